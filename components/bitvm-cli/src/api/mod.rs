@@ -0,0 +1,732 @@
+use crate::cli::parse_inputs;
+use crate::config::{ApiConfig, NetworkConfig};
+use crate::psbt::parse_role;
+use crate::service::Contracts;
+use bitcoin::secp256k1::{PublicKey, Secp256k1};
+use bitcoin::{Address, Network, Txid};
+use bitvm::chain::observer::ContractObserver;
+use bitvm::keys::ParticipantKeys;
+use bitvm::protocol::psbt::decode_psbt;
+use bitvm::protocol::{
+    ContractAction, ContractEvent, ContractMachine, ContractParameters, ContractSetup,
+    ContractTerms, Participants, PreSignature, Role, TransactionKind,
+};
+use bitvm::prover::{Prover, ProverSecrets};
+use bitvm::tapleaf::challenge_hashlock::ChallengeHashes;
+use bitvm::verifier::{Verifier, VerifierSecrets};
+use chainhook_sdk::utils::Context;
+use rocket::config::{self, LogLevel};
+use rocket::http::Status;
+use rocket::response::status::Custom;
+use rocket::serde::json::{json, Json, Value as JsonValue};
+use rocket::{Build, Rocket, Shutdown, State};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+type ApiResponse = Custom<Json<JsonValue>>;
+
+/// Why a request failed: unknown contracts are told apart from invalid requests.
+enum ApiError {
+    NotFound(String),
+    BadRequest(String),
+}
+
+impl From<String> for ApiError {
+    fn from(e: String) -> Self {
+        ApiError::BadRequest(e)
+    }
+}
+
+/// What the handlers share: the contracts played by the service, the keys new contracts are
+/// signed with, the network their addresses are encoded for, and the terms Vicky holds Paul
+/// to.
+pub struct ApiState {
+    pub contracts: Arc<Mutex<Contracts>>,
+    pub keys: Mutex<ParticipantKeys>,
+    pub network: Network,
+    pub terms: ContractTerms,
+    pub ctx: Context,
+}
+
+/// Challenges Vicky drew for a contract Paul has not set up yet, sealed in the vault.
+#[derive(Serialize, Deserialize)]
+struct PendingChallenges {
+    circuit_source: String,
+    secrets: VerifierSecrets,
+    /// Terms in force when the challenges were drawn.
+    #[serde(default)]
+    terms: ContractTerms,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewChallenges {
+    /// Bristol source of the circuit.
+    pub circuit: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewProverContract {
+    /// Bristol source of the circuit.
+    pub circuit: String,
+    /// Paul's inputs, as a string of bits ordered by input wire id.
+    pub inputs: String,
+    pub verifier: PublicKey,
+    pub challenge_hashes: ChallengeHashes,
+    pub parameters: ContractParameters,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct NewVerifierContract {
+    /// Public key returned when the challenges were drawn.
+    pub challenges_id: PublicKey,
+    pub setup: ContractSetup,
+}
+
+/// Serves the API on localhost until the returned handle is notified.
+pub fn start_api(
+    api_config: &ApiConfig,
+    network_config: &NetworkConfig,
+    terms: &ContractTerms,
+    contracts: Arc<Mutex<Contracts>>,
+    keys: ParticipantKeys,
+    ctx: &Context,
+) -> Result<Shutdown, String> {
+    let rocket = build_rocket(
+        api_config.http_port,
+        network_config.network(),
+        terms.clone(),
+        contracts,
+        keys,
+        ctx,
+    );
+    let ignite = hiro_system_kit::nestable_block_on(rocket.ignite())
+        .map_err(|e| format!("unable to start API: {}", e))?;
+    let shutdown = ignite.shutdown();
+    hiro_system_kit::thread_named("API")
+        .spawn(move || {
+            let _ = hiro_system_kit::nestable_block_on(ignite.launch());
+        })
+        .map_err(|e| format!("unable to start API: {}", e))?;
+    info!(
+        ctx.expect_logger(),
+        "API listening on http://127.0.0.1:{}", api_config.http_port
+    );
+    Ok(shutdown)
+}
+
+pub fn build_rocket(
+    http_port: u16,
+    network: Network,
+    terms: ContractTerms,
+    contracts: Arc<Mutex<Contracts>>,
+    keys: ParticipantKeys,
+    ctx: &Context,
+) -> Rocket<Build> {
+    let shutdown_config = config::Shutdown {
+        ctrlc: false,
+        grace: 0,
+        mercy: 0,
+        ..config::Shutdown::default()
+    };
+    let api_config = rocket::Config {
+        port: http_port,
+        address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+        log_level: LogLevel::Off,
+        cli_colors: false,
+        shutdown: shutdown_config,
+        ..rocket::Config::default()
+    };
+    rocket::custom(api_config)
+        .manage(ApiState {
+            contracts,
+            keys: Mutex::new(keys),
+            network,
+            terms,
+            ctx: ctx.clone(),
+        })
+        .mount(
+            "/",
+            rocket::routes![
+                handle_list_contracts,
+                handle_new_challenges,
+                handle_new_prover_contract,
+                handle_new_verifier_contract,
+                handle_get_contract,
+                handle_get_addresses,
+                handle_get_psbts,
+                handle_import_psbts,
+                handle_get_presignatures,
+                handle_submit_presignatures,
+                handle_get_transcript,
+            ],
+        )
+}
+
+#[rocket::get("/v1/contracts", format = "application/json")]
+pub fn handle_list_contracts(state: &State<ApiState>) -> ApiResponse {
+    respond(|| {
+        let contracts = lock(state)?;
+        let mut result = vec![];
+        for role in [Role::Prover, Role::Verifier] {
+            let observer = contracts.observer(role);
+            for contract_id in observer.contract_ids() {
+                result.push(contract_summary(observer, &contract_id)?);
+            }
+        }
+        Ok(json!(result))
+    })
+}
+
+#[rocket::post("/v1/challenges", format = "application/json", data = "<request>")]
+pub fn handle_new_challenges(state: &State<ApiState>, request: Json<NewChallenges>) -> ApiResponse {
+    respond(|| {
+        let contracts = lock(state)?;
+        let secp = Secp256k1::new();
+        let circuit = bitvm::bristol::parser::read_circuit(&request.circuit)?;
+        let (challenges_id, challenge_hashes) = lock_keys(state)?.verifier.with_next_contract_key(
+            &secp,
+            |key_source, secret_key| {
+                let mut secrets = VerifierSecrets::generate(&circuit, secret_key);
+                secrets.key_source = Some(key_source);
+                let challenges_id = secrets.secret_key.public_key(&secp);
+                let challenge_hashes = secrets.challenge_store.compute_hashes();
+                contracts.vault.write_secrets(
+                    &challenges_path(&contracts.working_dir, &challenges_id),
+                    &PendingChallenges {
+                        circuit_source: request.circuit.clone(),
+                        secrets,
+                        terms: state.terms.clone(),
+                    },
+                )?;
+                Ok((challenges_id, challenge_hashes))
+            },
+        )?;
+        Ok(json!({
+            "challenges_id": challenges_id,
+            "challenge_hashes": challenge_hashes,
+        }))
+    })
+}
+
+#[rocket::post(
+    "/v1/contracts/prover",
+    format = "application/json",
+    data = "<request>"
+)]
+pub fn handle_new_prover_contract(
+    state: &State<ApiState>,
+    request: Json<NewProverContract>,
+) -> ApiResponse {
+    respond(|| {
+        let mut contracts = lock(state)?;
+        let secp = Secp256k1::new();
+        let circuit = bitvm::bristol::parser::read_circuit(&request.circuit)?;
+        let inputs = parse_inputs(&circuit, &request.inputs)?;
+        // The key is only reserved once the contract is registered.
+        let (contract_id, setup) =
+            lock_keys(state)?
+                .prover
+                .with_next_contract_key(&secp, |key_source, secret_key| {
+                    let mut secrets = ProverSecrets::generate(&circuit, secret_key);
+                    secrets.key_source = Some(key_source);
+                    let setup = ContractSetup {
+                        circuit_source: request.circuit.clone(),
+                        participants: Participants {
+                            prover: secrets.secret_key.public_key(&secp),
+                            verifier: request.verifier,
+                        },
+                        parameters: request.parameters.clone(),
+                        commitments: secrets.compute_commitments(request.challenge_hashes.clone()),
+                    };
+                    let prover = Prover::new(
+                        &contracts.working_dir,
+                        &contracts.vault,
+                        setup.clone(),
+                        secrets,
+                        inputs,
+                    )?;
+                    let contract_id = prover.contract_id();
+                    contracts
+                        .observer_mut(Role::Prover)
+                        .register(Box::new(prover))?;
+                    Ok((contract_id, setup))
+                })?;
+        state.ctx.try_log(|logger| {
+            hiro_system_kit::slog::info!(logger, "Contract {} created as prover", contract_id)
+        });
+        Ok(json!({
+            "contract_id": contract_id,
+            "setup": setup,
+        }))
+    })
+}
+
+#[rocket::post(
+    "/v1/contracts/verifier",
+    format = "application/json",
+    data = "<request>"
+)]
+pub fn handle_new_verifier_contract(
+    state: &State<ApiState>,
+    request: Json<NewVerifierContract>,
+) -> ApiResponse {
+    respond(|| {
+        let mut contracts = lock(state)?;
+        let path = challenges_path(&contracts.working_dir, &request.challenges_id);
+        let pending: PendingChallenges = contracts.vault.read_secrets(&path)?;
+        if pending.circuit_source != request.setup.circuit_source {
+            return Err("setup is not for the circuit the challenges were drawn for"
+                .to_string()
+                .into());
+        }
+        if request.setup.participants.verifier != request.challenges_id
+            || request.setup.commitments.challenge_hashes
+                != pending.secrets.challenge_store.compute_hashes()
+        {
+            return Err("setup does not use these challenges".to_string().into());
+        }
+        pending
+            .terms
+            .check(&request.setup.parameters)
+            .map_err(|e| format!("setup does not meet the terms of these challenges: {}", e))?;
+        let verifier = Verifier::new(
+            &contracts.working_dir,
+            &contracts.vault,
+            request.setup.clone(),
+            pending.secrets,
+        )?;
+        let contract_id = verifier.contract_id();
+        contracts
+            .observer_mut(Role::Verifier)
+            .register(Box::new(verifier))?;
+        std::fs::remove_file(&path)
+            .map_err(|e| format!("unable to remove {}: {}", path.display(), e))?;
+        state.ctx.try_log(|logger| {
+            hiro_system_kit::slog::info!(logger, "Contract {} created as verifier", contract_id)
+        });
+        Ok(json!({ "contract_id": contract_id }))
+    })
+}
+
+#[rocket::get("/v1/contracts/<role>/<contract_id>", format = "application/json")]
+pub fn handle_get_contract(state: &State<ApiState>, role: &str, contract_id: &str) -> ApiResponse {
+    respond(|| {
+        let (role, contract_id) = parse_contract_path(role, contract_id)?;
+        let contracts = lock(state)?;
+        contract_summary(contracts.observer(role), &contract_id)
+    })
+}
+
+#[rocket::get(
+    "/v1/contracts/<role>/<contract_id>/addresses",
+    format = "application/json"
+)]
+pub fn handle_get_addresses(state: &State<ApiState>, role: &str, contract_id: &str) -> ApiResponse {
+    respond(|| {
+        let (role, contract_id) = parse_contract_path(role, contract_id)?;
+        let contracts = lock(state)?;
+        let machine = find_machine(contracts.observer(role), &contract_id)?;
+        let mut addresses = serde_json::Map::new();
+        for (stage, output) in machine.graph().outputs.iter() {
+            let address = Address::from_script(&output.txout.script_pubkey, state.network)
+                .map_err(|e| format!("unable to encode address of {:?}: {}", stage, e))?;
+            addresses.insert(format!("{:?}", stage), json!(address.to_string()));
+        }
+        Ok(JsonValue::Object(addresses))
+    })
+}
+
+#[rocket::get(
+    "/v1/contracts/<role>/<contract_id>/psbts",
+    format = "application/json"
+)]
+pub fn handle_get_psbts(state: &State<ApiState>, role: &str, contract_id: &str) -> ApiResponse {
+    respond(|| {
+        let (role, contract_id) = parse_contract_path(role, contract_id)?;
+        let contracts = lock(state)?;
+        let machine = find_machine(contracts.observer(role), &contract_id)?;
+        let mut psbts = serde_json::Map::new();
+        for (kind, psbt) in machine.export_psbts()? {
+            psbts.insert(kind.to_string(), json!(psbt.serialize_hex()));
+        }
+        Ok(JsonValue::Object(psbts))
+    })
+}
+
+#[rocket::get(
+    "/v1/contracts/<role>/<contract_id>/presignatures",
+    format = "application/json"
+)]
+pub fn handle_get_presignatures(
+    state: &State<ApiState>,
+    role: &str,
+    contract_id: &str,
+) -> ApiResponse {
+    respond(|| {
+        let (role, contract_id) = parse_contract_path(role, contract_id)?;
+        let contracts = lock(state)?;
+        let machine = find_machine(contracts.observer(role), &contract_id)?;
+        Ok(json!(machine.presignatures()))
+    })
+}
+
+/// Takes the other participant's pre-signatures. Once all of them are in, the setup is
+/// complete, and Paul gets the funding transaction to sign.
+#[rocket::post(
+    "/v1/contracts/<role>/<contract_id>/presignatures",
+    format = "application/json",
+    data = "<presignatures>"
+)]
+pub fn handle_submit_presignatures(
+    state: &State<ApiState>,
+    role: &str,
+    contract_id: &str,
+    presignatures: Json<Vec<PreSignature>>,
+) -> ApiResponse {
+    respond(|| {
+        let (role, contract_id) = parse_contract_path(role, contract_id)?;
+        let mut contracts = lock(state)?;
+        let observer = contracts.observer_mut(role);
+        let machine = observer
+            .machine_mut(&contract_id)
+            .ok_or(ApiError::NotFound(format!(
+                "contract {} not found",
+                contract_id
+            )))?;
+        machine.receive_presignatures(&presignatures)?;
+        complete_setup(machine)
+    })
+}
+
+/// Combines the other participant's signatures carried by hex encoded PSBTs, as signed by an
+/// external signer. Completes the setup like pre-signatures do.
+#[rocket::post(
+    "/v1/contracts/<role>/<contract_id>/psbts",
+    format = "application/json",
+    data = "<psbts>"
+)]
+pub fn handle_import_psbts(
+    state: &State<ApiState>,
+    role: &str,
+    contract_id: &str,
+    psbts: Json<Vec<String>>,
+) -> ApiResponse {
+    respond(|| {
+        let (role, contract_id) = parse_contract_path(role, contract_id)?;
+        let mut contracts = lock(state)?;
+        let observer = contracts.observer_mut(role);
+        let machine = observer
+            .machine_mut(&contract_id)
+            .ok_or(ApiError::NotFound(format!(
+                "contract {} not found",
+                contract_id
+            )))?;
+        for psbt in psbts.iter() {
+            machine.import_psbt(&decode_psbt(psbt.as_bytes())?)?;
+        }
+        complete_setup(machine)
+    })
+}
+
+/// Once every pre-signature is in, completes the setup, handing Paul the funding PSBT.
+fn complete_setup(machine: &mut dyn ContractMachine) -> Result<JsonValue, ApiError> {
+    let missing = machine.graph().count_missing_presignatures();
+    if missing > 0 {
+        return Ok(json!({ "missing_presignatures": missing }));
+    }
+    let mut funding_psbt = None;
+    for action in machine.handle(&ContractEvent::SetupCompleted)? {
+        if let ContractAction::Fund(_) = action {
+            let psbt = machine.export_psbt(&TransactionKind::Funding)?;
+            funding_psbt = Some(psbt.serialize_hex());
+        }
+    }
+    Ok(json!({
+        "missing_presignatures": 0,
+        "funding_psbt": funding_psbt,
+    }))
+}
+
+#[rocket::get(
+    "/v1/contracts/<role>/<contract_id>/transcript",
+    format = "application/json"
+)]
+pub fn handle_get_transcript(
+    state: &State<ApiState>,
+    role: &str,
+    contract_id: &str,
+) -> ApiResponse {
+    respond(|| {
+        let (role, contract_id) = parse_contract_path(role, contract_id)?;
+        let contracts = lock(state)?;
+        let machine = find_machine(contracts.observer(role), &contract_id)?;
+        Ok(json!(machine.transcript()))
+    })
+}
+
+fn respond<F>(handler: F) -> ApiResponse
+where
+    F: FnOnce() -> Result<JsonValue, ApiError>,
+{
+    let (status, e) = match handler() {
+        Ok(result) => {
+            return Custom(
+                Status::Ok,
+                Json(json!({
+                    "status": 200,
+                    "result": result,
+                })),
+            )
+        }
+        Err(ApiError::NotFound(e)) => (Status::NotFound, e),
+        Err(ApiError::BadRequest(e)) => (Status::BadRequest, e),
+    };
+    Custom(
+        status,
+        Json(json!({
+            "status": status.code,
+            "error": e,
+        })),
+    )
+}
+
+fn lock(state: &State<ApiState>) -> Result<MutexGuard<'_, Contracts>, String> {
+    state
+        .contracts
+        .lock()
+        .map_err(|_| "contracts lock poisoned".to_string())
+}
+
+fn lock_keys(state: &State<ApiState>) -> Result<MutexGuard<'_, ParticipantKeys>, String> {
+    state
+        .keys
+        .lock()
+        .map_err(|_| "keys lock poisoned".to_string())
+}
+
+fn parse_contract_path(role: &str, contract_id: &str) -> Result<(Role, Txid), String> {
+    let role = parse_role(role)?;
+    let contract_id = contract_id
+        .parse()
+        .map_err(|e| format!("invalid contract id {}: {}", contract_id, e))?;
+    Ok((role, contract_id))
+}
+
+fn find_machine<'a>(
+    observer: &'a ContractObserver,
+    contract_id: &Txid,
+) -> Result<&'a dyn ContractMachine, ApiError> {
+    observer
+        .machine(contract_id)
+        .ok_or(ApiError::NotFound(format!(
+            "contract {} not found",
+            contract_id
+        )))
+}
+
+fn contract_summary(
+    observer: &ContractObserver,
+    contract_id: &Txid,
+) -> Result<JsonValue, ApiError> {
+    let machine = find_machine(observer, contract_id)?;
+    Ok(json!({
+        "contract_id": contract_id,
+        "role": machine.role(),
+        "phase": machine.phase(),
+        "missing_presignatures": machine.graph().count_missing_presignatures(),
+    }))
+}
+
+fn challenges_path(working_dir: &Path, challenges_id: &PublicKey) -> PathBuf {
+    working_dir
+        .join("challenges")
+        .join(format!("{}.json", challenges_id))
+}
+
+#[test]
+fn test_api_sets_up_a_contract_between_two_services() {
+    use bitcoin::hashes::Hash;
+    use bitcoin::{Amount, OutPoint, ScriptBuf, TxOut};
+    use bitvm::protocol::{FundingInput, TimelockParameters};
+    use bitvm::vault::Vault;
+    use rocket::local::blocking::Client;
+
+    let circuit_source =
+        include_str!("../../../bitvm-core/src/bristol/fixtures/test_vector_1.bristol");
+    let circuit = bitvm::bristol::parser::read_circuit(circuit_source).unwrap();
+    let inputs = "1".repeat(circuit.collect_input_wires_ids().len());
+    let ctx = Context::empty();
+    let start_client = |name: &str| {
+        let working_dir = std::env::temp_dir().join(format!(
+            "bitvm-api-{}-{}",
+            name,
+            bitcoin::secp256k1::rand::random::<u64>()
+        ));
+        let vault = Vault::ephemeral();
+        let contracts = Arc::new(Mutex::new(
+            Contracts::restore(&working_dir, vault.clone()).unwrap(),
+        ));
+        let keys = ParticipantKeys::load_or_generate(
+            &working_dir.join("keys/prover.seed"),
+            &working_dir.join("keys/verifier.seed"),
+            &vault,
+            Network::Regtest,
+        )
+        .unwrap();
+        let rocket = build_rocket(
+            0,
+            Network::Regtest,
+            ContractTerms::default(),
+            contracts,
+            keys,
+            &ctx,
+        );
+        Client::tracked(rocket).unwrap()
+    };
+    let paul = start_client("prover");
+    let vicky = start_client("verifier");
+    let post = |client: &Client, uri: &str, body: JsonValue| -> JsonValue {
+        let response = client.post(uri.to_string()).json(&body).dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<JsonValue>().unwrap()["result"].clone()
+    };
+    let get = |client: &Client, uri: &str| -> JsonValue {
+        let response = client
+            .get(uri.to_string())
+            .header(rocket::http::ContentType::JSON)
+            .dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        response.into_json::<JsonValue>().unwrap()["result"].clone()
+    };
+
+    let challenges = post(
+        &vicky,
+        "/v1/challenges",
+        json!({ "circuit": circuit_source }),
+    );
+    let working_dir = |client: &Client| {
+        client
+            .rocket()
+            .state::<ApiState>()
+            .unwrap()
+            .contracts
+            .lock()
+            .unwrap()
+            .working_dir
+            .clone()
+    };
+    // Vicky's challenge preimages stay sealed until Paul sets the contract up.
+    let challenges_id = challenges["challenges_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    let pending =
+        std::fs::read_to_string(challenges_path(&working_dir(&vicky), &challenges_id)).unwrap();
+    assert!(!pending.contains("challenge_store"));
+    let parameters = ContractParameters {
+        amount: Amount::from_sat(1_000_000),
+        collateral: Amount::from_sat(100_000),
+        fee_per_transaction: Amount::from_sat(2_000),
+        timelocks: TimelockParameters {
+            response_timeout: 6,
+        },
+        funding_inputs: vec![FundingInput {
+            outpoint: OutPoint::new(Txid::all_zeros(), 0),
+            txout: TxOut {
+                value: 1_104_000,
+                script_pubkey: ScriptBuf::new(),
+            },
+        }],
+        change_script_pubkey: ScriptBuf::new(),
+    };
+    let created = post(
+        &paul,
+        "/v1/contracts/prover",
+        json!({
+            "circuit": circuit_source,
+            "inputs": inputs,
+            "verifier": challenges["challenges_id"],
+            "challenge_hashes": challenges["challenge_hashes"],
+            "parameters": parameters,
+        }),
+    );
+    let contract_id = created["contract_id"].as_str().unwrap().to_string();
+    // Vicky holds Paul to her terms, whatever he proposes.
+    let mut short_setup = created["setup"].clone();
+    short_setup["parameters"]["timelocks"]["response_timeout"] = json!(1);
+    let response = vicky
+        .post("/v1/contracts/verifier")
+        .json(&json!({
+            "challenges_id": challenges["challenges_id"],
+            "setup": short_setup,
+        }))
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+    assert_eq!(
+        response.into_json::<JsonValue>().unwrap()["error"],
+        json!("setup does not meet the terms of these challenges: response timeout of 1 blocks is below the minimum of 6")
+    );
+    let accepted = post(
+        &vicky,
+        "/v1/contracts/verifier",
+        json!({
+            "challenges_id": challenges["challenges_id"],
+            "setup": created["setup"],
+        }),
+    );
+    assert_eq!(accepted["contract_id"].as_str().unwrap(), contract_id);
+
+    let paul_uri = format!("/v1/contracts/prover/{}", contract_id);
+    let vicky_uri = format!("/v1/contracts/verifier/{}", contract_id);
+    let paul_presignatures = get(&paul, &format!("{}/presignatures", paul_uri));
+    let completed = post(
+        &vicky,
+        &format!("{}/presignatures", vicky_uri),
+        paul_presignatures,
+    );
+    assert_eq!(completed["missing_presignatures"], json!(0));
+    // Paul takes Vicky's signatures as PSBTs, as an external signer would hand them over.
+    let vicky_psbts = get(&vicky, &format!("{}/psbts", vicky_uri));
+    let completed = post(
+        &paul,
+        &format!("{}/psbts", paul_uri),
+        json!(vicky_psbts
+            .as_object()
+            .unwrap()
+            .values()
+            .collect::<Vec<_>>()),
+    );
+    assert_eq!(completed["missing_presignatures"], json!(0));
+    assert!(completed["funding_psbt"].is_string());
+
+    let contracts = get(&paul, "/v1/contracts");
+    assert_eq!(contracts.as_array().unwrap().len(), 1);
+    assert_eq!(contracts[0]["contract_id"].as_str().unwrap(), contract_id);
+    assert_eq!(contracts[0]["role"], json!("Prover"));
+    let addresses = get(&vicky, &format!("{}/addresses", vicky_uri));
+    assert!(addresses
+        .as_object()
+        .unwrap()
+        .values()
+        .all(|address| address.as_str().unwrap().starts_with("bcrt1p")));
+    let psbts = get(&paul, &format!("{}/psbts", paul_uri));
+    assert!(psbts.as_object().unwrap().contains_key("funding"));
+    assert_eq!(get(&paul, &format!("{}/transcript", paul_uri)), json!([]));
+
+    let response = paul
+        .get(format!("/v1/contracts/verifier/{}", contract_id))
+        .header(rocket::http::ContentType::JSON)
+        .dispatch();
+    assert_eq!(response.status(), Status::NotFound);
+    let response = paul
+        .get("/v1/contracts/prover/not-a-txid")
+        .header(rocket::http::ContentType::JSON)
+        .dispatch();
+    assert_eq!(response.status(), Status::BadRequest);
+
+    for client in [&paul, &vicky] {
+        std::fs::remove_dir_all(working_dir(client)).unwrap();
+    }
+}
@@ -1,15 +1,33 @@
+use crate::config::file::{ConfigFile, ENV_OVERRIDES};
 use crate::config::generator::generate_config;
-use crate::config::Config;
+use crate::config::{redact_url_credentials, BlockSignaling, Config};
+use crate::observer::new_bitcoind_client;
+use crate::psbt::{export_psbts, import_psbts};
+use crate::service::start_service;
+use crate::setup::{join_setup, open_setup, receive_setup_message};
+use crate::vault::{unlock_vault, PASSPHRASE_ENV};
+use bitcoin::secp256k1::{rand, PublicKey, Secp256k1, SecretKey};
+use bitcoin::{Network, ScriptBuf};
+use bitvm::cost::{estimate_dispute_cost, DisputeCost};
+use bitvm::fees::FeePolicy;
+use bitvm::keys::ParticipantKeys;
+use bitvm::protocol::contract::Contract;
+use bitvm::protocol::{Participants, Role};
+use bitvm::simulation::{run_simulation, Fault, SimulationOptions, SimulationReport};
 use bitvm::SerializedCircuit;
-use chainhook_sdk::bitcoincore_rpc::{Auth, Client, RpcApi};
-use chainhook_sdk::types::BitcoinNetwork;
+use bitvm_types::{Circuit, GateId, WireId};
+use chainhook_sdk::bitcoincore_rpc::json::GetBlockchainInfoResult;
+use chainhook_sdk::bitcoincore_rpc::RpcApi;
 use chainhook_sdk::utils::Context;
 use clap::{Parser, Subcommand};
 use hiro_system_kit;
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::io::Write;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[clap(author, version, about, long_about = None)]
@@ -26,6 +44,18 @@ enum Command {
     /// Circuits management
     #[clap(subcommand)]
     Circuits(CircuitsCommand),
+    /// Bitcoin node management
+    #[clap(subcommand)]
+    Node(NodeCommand),
+    /// Contracts watching service
+    #[clap(subcommand)]
+    Service(ServiceCommand),
+    /// Contract setup, by exchanging messages with the other participant
+    #[clap(subcommand)]
+    Setup(SetupCommand),
+    /// Contract files management
+    #[clap(subcommand)]
+    Contracts(ContractsCommand),
 }
 
 #[derive(Subcommand, PartialEq, Clone, Debug)]
@@ -34,6 +64,16 @@ enum ConfigCommand {
     /// Generate new config
     #[clap(name = "new", bin_name = "new", aliases = &["generate"])]
     New(NewConfig),
+    /// Validate config and print the effective settings, secrets redacted
+    #[clap(name = "check", bin_name = "check")]
+    Check(CheckConfig),
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct CheckConfig {
+    /// Load config file path
+    #[clap(long = "config", default_value = "BitVM.toml")]
+    pub config_path: String,
 }
 
 #[derive(Parser, PartialEq, Clone, Debug)]
@@ -42,6 +82,7 @@ struct NewConfig {
     #[clap(
         long = "regtest",
         conflicts_with = "testnet",
+        conflicts_with = "signet",
         conflicts_with = "mainnet"
     )]
     pub regtest: bool,
@@ -49,13 +90,26 @@ struct NewConfig {
     #[clap(
         long = "testnet",
         conflicts_with = "regtest",
+        conflicts_with = "signet",
         conflicts_with = "mainnet"
     )]
     pub testnet: bool,
+    /// Target Signet network
+    #[clap(
+        long = "signet",
+        conflicts_with = "regtest",
+        conflicts_with = "testnet",
+        conflicts_with = "mainnet"
+    )]
+    pub signet: bool,
+    /// Block challenge of a custom signet, in hex
+    #[clap(long = "signet-challenge")]
+    pub signet_challenge: Option<String>,
     /// Target Mainnet network
     #[clap(
         long = "mainnet",
         conflicts_with = "testnet",
+        conflicts_with = "signet",
         conflicts_with = "regtest"
     )]
     pub mainnet: bool,
@@ -72,7 +126,166 @@ enum CircuitsCommand {
     Check(CheckCircuit),
     /// Simulate Bristol file
     #[clap(name = "simulate", bin_name = "simulate")]
-    Simulate(SimulateCircuit),    
+    Simulate(SimulateCircuit),
+    /// Estimate the worst-case cost of a dispute on Bristol file
+    #[clap(name = "cost", bin_name = "cost")]
+    Cost(CircuitCost),
+}
+
+#[derive(Subcommand, PartialEq, Clone, Debug)]
+#[clap(bin_name = "node")]
+enum NodeCommand {
+    /// Report the height, network and block notifications of bitcoind
+    #[clap(name = "status", bin_name = "status")]
+    Status(NodeStatus),
+    /// Wait until bitcoind is synced with the network
+    #[clap(name = "wait-sync", bin_name = "wait-sync")]
+    WaitSync(WaitSync),
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct NodeStatus {
+    /// Load config file path
+    #[clap(long = "config", default_value = "BitVM.toml")]
+    pub config_path: String,
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct WaitSync {
+    /// Load config file path
+    #[clap(long = "config", default_value = "BitVM.toml")]
+    pub config_path: String,
+    /// Seconds between two checks
+    #[clap(long = "interval", default_value = "10")]
+    pub interval: u64,
+}
+
+#[derive(Subcommand, PartialEq, Clone, Debug)]
+#[clap(bin_name = "service")]
+enum ServiceCommand {
+    /// Watch the chain and play every active contract until Ctrl-C
+    #[clap(name = "start", bin_name = "start")]
+    Start(StartService),
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct StartService {
+    /// Load config file path
+    #[clap(long = "config", default_value = "BitVM.toml")]
+    pub config_path: String,
+    /// Replay the blocks from the given height before watching new ones
+    #[clap(long = "start-height")]
+    pub start_height: Option<u64>,
+}
+
+#[derive(Subcommand, PartialEq, Clone, Debug)]
+#[clap(bin_name = "setup")]
+enum SetupCommand {
+    /// Open a setup session as verifier, drawing the challenges to send to the prover
+    #[clap(name = "open", bin_name = "open")]
+    Open(OpenSetup),
+    /// Join a setup session as prover, answering the verifier's challenges with a proposal
+    #[clap(name = "join", bin_name = "join")]
+    Join(JoinSetup),
+    /// Process a message of the other participant
+    #[clap(name = "receive", bin_name = "receive")]
+    Receive(ReceiveSetupMessage),
+}
+
+#[derive(Subcommand, PartialEq, Clone, Debug)]
+#[clap(bin_name = "contracts", aliases = &["contract"])]
+enum ContractsCommand {
+    /// Check a contract file against its terms, and print them
+    #[clap(name = "verify", bin_name = "verify")]
+    Verify(VerifyContract),
+    /// Export every transaction of a contract as a PSBT, for external signers
+    #[clap(name = "export-psbts", bin_name = "export-psbts")]
+    ExportPsbts(ExportPsbts),
+    /// Combine the other participant's signatures from signed PSBTs
+    #[clap(name = "import-psbts", bin_name = "import-psbts")]
+    ImportPsbts(ImportPsbts),
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct VerifyContract {
+    /// Contract file path
+    pub contract_file_path: String,
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct ExportPsbts {
+    /// Contract id
+    pub contract_id: String,
+    /// Side of the contract played (prover or verifier)
+    #[clap(long = "role")]
+    pub role: String,
+    /// Load config file path
+    #[clap(long = "config", default_value = "BitVM.toml")]
+    pub config_path: String,
+    /// Directory to write the PSBTs to
+    #[clap(long = "output-dir", default_value = ".")]
+    pub output_dir: String,
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct ImportPsbts {
+    /// Contract id
+    pub contract_id: String,
+    /// PSBT files, binary or hex encoded
+    #[clap(required = true)]
+    pub psbt_files: Vec<String>,
+    /// Side of the contract played (prover or verifier)
+    #[clap(long = "role")]
+    pub role: String,
+    /// Load config file path
+    #[clap(long = "config", default_value = "BitVM.toml")]
+    pub config_path: String,
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct OpenSetup {
+    /// Bristol file path
+    pub bristol_file_path: String,
+    /// Load config file path
+    #[clap(long = "config", default_value = "BitVM.toml")]
+    pub config_path: String,
+    /// File to write the challenges to (stdout by default)
+    #[clap(long = "output", default_value = "-")]
+    pub output: String,
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct JoinSetup {
+    /// Bristol file path
+    pub bristol_file_path: String,
+    /// File to read the verifier's challenges from (stdin by default)
+    #[clap(long = "message", default_value = "-")]
+    pub message: String,
+    /// Paul's inputs, as a string of bits ordered by input wire id
+    #[clap(long = "inputs")]
+    pub inputs: String,
+    /// JSON file with the amounts, timelocks and funding inputs of the contract
+    #[clap(long = "parameters")]
+    pub parameters_path: String,
+    /// Load config file path
+    #[clap(long = "config", default_value = "BitVM.toml")]
+    pub config_path: String,
+    /// File to write the proposal to (stdout by default)
+    #[clap(long = "output", default_value = "-")]
+    pub output: String,
+}
+
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct ReceiveSetupMessage {
+    /// File to read the message from (stdin by default)
+    #[clap(long = "message", default_value = "-")]
+    pub message: String,
+    /// Load config file path
+    #[clap(long = "config", default_value = "BitVM.toml")]
+    pub config_path: String,
+    /// File to write the answer to (stdout by default)
+    #[clap(long = "output", default_value = "-")]
+    pub output: String,
 }
 
 #[derive(Parser, PartialEq, Clone, Debug)]
@@ -85,26 +298,57 @@ struct NewCircuit {
 struct CheckCircuit {
     /// Bristol file path
     pub bristol_file_path: String,
+    /// Check against the keys of the next contracts, as configured in the given config file
+    /// (ephemeral keys by default)
+    #[clap(long = "config")]
+    pub config_path: Option<String>,
 }
 
 #[derive(Parser, PartialEq, Clone, Debug)]
-struct SimulateCircuit {
+struct CircuitCost {
     /// Bristol file path
     pub bristol_file_path: String,
-    /// Simulate as prover
-    #[clap(
-        long = "prover",
-        conflicts_with = "verifier",
-    )]
-    pub prover: bool,
-    /// Simulate as verifier
-    #[clap(
-        long = "verifier",
-        conflicts_with = "prover",
-    )]
-    pub verifier: bool,   
+    /// Fee rate of the dispute transactions, in sat/vB
+    #[clap(long = "fee-rate", default_value = "10")]
+    pub fee_rate: u64,
+    /// Both parties bump every transaction through its anchor output, at the urgent rate of
+    /// the default fee policy
+    #[clap(long = "bump-fees")]
+    pub bump_fees: bool,
 }
 
+#[derive(Parser, PartialEq, Clone, Debug)]
+struct SimulateCircuit {
+    /// Bristol file path
+    pub bristol_file_path: String,
+    /// Paul flips the output of the given gate
+    #[clap(long = "flip-gate")]
+    pub flip_gates: Vec<GateId>,
+    /// Paul opens the opposite value of the given wire when a gate is challenged
+    #[clap(long = "equivocate")]
+    pub equivocate: Vec<WireId>,
+    /// Paul opens the opposite value of the given wire when a bisection round reveals it
+    #[clap(long = "equivocate-state")]
+    pub equivocate_states: Vec<WireId>,
+    /// Paul stops answering challenges after the given number of rounds
+    #[clap(long = "stop-after-round")]
+    pub stop_after_round: Option<usize>,
+    /// Paul commits to the opposite value of the given input wire
+    #[clap(long = "wrong-input")]
+    pub wrong_inputs: Vec<WireId>,
+    /// Paul's inputs, as a string of bits ordered by input wire id (random by default)
+    #[clap(long = "inputs")]
+    pub inputs: Option<String>,
+    /// Blocks each party has to make its next move
+    #[clap(long = "response-timeout", default_value = "6")]
+    pub response_timeout: u16,
+    /// Minimum fee rate of blocks from the given height on, as <height>:<sat/vB>
+    #[clap(long = "fee-rate")]
+    pub fee_rates: Vec<String>,
+    /// Both parties bump their stuck transactions through their anchor outputs
+    #[clap(long = "bump-fees")]
+    pub bump_fees: bool,
+}
 
 pub fn main() {
     let logger = hiro_system_kit::log::setup_logger();
@@ -122,28 +366,18 @@ pub fn main() {
         }
     };
 
-    match hiro_system_kit::nestable_block_on(handle_command(opts, &ctx)) {
-        Err(e) => {
-            error!(ctx.expect_logger(), "{e}");
-            std::thread::sleep(std::time::Duration::from_millis(500));
-            process::exit(1);
-        }
-        Ok(_) => {}
+    if let Err(e) = hiro_system_kit::nestable_block_on(handle_command(opts, &ctx)) {
+        error!(ctx.expect_logger(), "{e}");
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        process::exit(1);
     }
 }
 
-async fn handle_command(opts: Opts, _ctx: &Context) -> Result<(), String> {
+async fn handle_command(opts: Opts, ctx: &Context) -> Result<(), String> {
     match opts.command {
         Command::Config(subcmd) => match subcmd {
             ConfigCommand::New(cmd) => {
-                let bitcoin_network = match (cmd.mainnet, cmd.testnet, cmd.regtest) {
-                    (_, _, true) => BitcoinNetwork::Regtest,
-                    (_, true, _) => BitcoinNetwork::Testnet,
-                    (true, _, _) => BitcoinNetwork::Mainnet,
-                    _ => return Err("network.mode not supported".to_string()),
-                };
-
-                let config_content = generate_config(&bitcoin_network);
+                let config_content = new_config_content(&cmd)?;
                 let mut file_path = PathBuf::new();
                 file_path.push("BitVM.toml");
                 let mut file = File::create(&file_path)
@@ -153,6 +387,22 @@ async fn handle_command(opts: Opts, _ctx: &Context) -> Result<(), String> {
                 })?;
                 println!("Created file BitVM.toml");
             }
+            ConfigCommand::Check(cmd) => {
+                let config = ConfigFile::from_file_path(&cmd.config_path)?;
+                println!("{}", config);
+                println!();
+                for (name, field, _) in ENV_OVERRIDES.iter() {
+                    if std::env::var_os(name).is_some() {
+                        println!("# {} overridden by {}", field, name);
+                    }
+                }
+                if std::env::var_os(PASSPHRASE_ENV).is_some() {
+                    println!("# Vault passphrase read from {}", PASSPHRASE_ENV);
+                } else {
+                    println!("# Vault passphrase asked on the terminal");
+                }
+                println!("Config {} is valid", cmd.config_path);
+            }
         },
         Command::Circuits(subcmd) => match subcmd {
             CircuitsCommand::New(cmd) => {
@@ -168,63 +418,579 @@ async fn handle_command(opts: Opts, _ctx: &Context) -> Result<(), String> {
                 println!("Created circuit {}.bristol", cmd.bristol_file_path);
             }
             CircuitsCommand::Check(cmd) => {
-                let mut circuit_file: File = File::open(&cmd.bristol_file_path).map_err(|e| {
-                    format!("unable to open circuit {}\n{}", cmd.bristol_file_path, e)
-                })?;
-
-                let mut circuit_content = String::new();
-                circuit_file
-                    .read_to_string(&mut circuit_content)
-                    .map_err(|e| {
-                        format!("unable to read circuit {}\n{}", cmd.bristol_file_path, e)
-                    })?;
-                let circuit =
-                    bitvm::read_and_check_circuit(&SerializedCircuit::Bristol(&circuit_content))?;
+                let circuit_content = read_circuit_file(&cmd.bristol_file_path)?;
+                let participants = match cmd.config_path {
+                    Some(ref config_path) => {
+                        let config = ConfigFile::from_file_path(config_path)?;
+                        next_contract_participants(&config)?
+                    }
+                    None => ephemeral_participants(),
+                };
+                let circuit = bitvm::read_and_check_circuit(
+                    &SerializedCircuit::Bristol(&circuit_content),
+                    &participants,
+                )?;
                 println!("{}", circuit);
             }
+            CircuitsCommand::Cost(cmd) => {
+                let circuit_content = read_circuit_file(&cmd.bristol_file_path)?;
+                let circuit = bitvm::read_and_check_circuit(
+                    &SerializedCircuit::Bristol(&circuit_content),
+                    &ephemeral_participants(),
+                )?;
+                let cost = estimate_dispute_cost(
+                    &circuit,
+                    cmd.fee_rate,
+                    cmd.bump_fees.then(FeePolicy::default),
+                )?;
+                print_dispute_cost(&cost);
+            }
             CircuitsCommand::Simulate(cmd) => {
-                let mut circuit_file: File = File::open(&cmd.bristol_file_path).map_err(|e| {
-                    format!("unable to open circuit {}\n{}", cmd.bristol_file_path, e)
-                })?;
-
-                let mut circuit_content = String::new();
-                circuit_file
-                    .read_to_string(&mut circuit_content)
-                    .map_err(|e| {
-                        format!("unable to read circuit {}\n{}", cmd.bristol_file_path, e)
-                    })?;
-                let circuit =
-                    bitvm::read_and_check_circuit(&SerializedCircuit::Bristol(&circuit_content))?;
+                let circuit_content = read_circuit_file(&cmd.bristol_file_path)?;
+                let circuit = bitvm::read_and_check_circuit(
+                    &SerializedCircuit::Bristol(&circuit_content),
+                    &ephemeral_participants(),
+                )?;
                 println!("{}", circuit);
 
+                let mut options = SimulationOptions {
+                    response_timeout: cmd.response_timeout,
+                    fee_rates: parse_fee_rates(&cmd.fee_rates)?,
+                    fee_policy: cmd.bump_fees.then(FeePolicy::default),
+                    ..Default::default()
+                };
+                if let Some(ref bits) = cmd.inputs {
+                    options.inputs = Some(parse_inputs(&circuit, bits)?);
+                }
+                options.faults.extend(
+                    cmd.flip_gates
+                        .iter()
+                        .map(|gate_id| Fault::FlipGate(*gate_id)),
+                );
+                options.faults.extend(
+                    cmd.equivocate
+                        .iter()
+                        .map(|wire_id| Fault::EquivocateWire(*wire_id)),
+                );
+                options.faults.extend(
+                    cmd.equivocate_states
+                        .iter()
+                        .map(|wire_id| Fault::EquivocateState(*wire_id)),
+                );
+                options
+                    .faults
+                    .extend(cmd.stop_after_round.map(Fault::StopAfterRound));
+                options.faults.extend(
+                    cmd.wrong_inputs
+                        .iter()
+                        .map(|wire_id| Fault::WrongInput(*wire_id)),
+                );
+                let report = run_simulation(&circuit_content, &options);
+                let _ = std::fs::remove_dir_all(&options.working_dir);
+                let report = report?;
+
+                print_simulation_report(&report);
+                report.check_punishment()?;
+            }
+        },
+        Command::Node(subcmd) => match subcmd {
+            NodeCommand::Status(cmd) => {
+                let config = ConfigFile::from_file_path(&cmd.config_path)?;
+                let info = check_bitcoind_connection(&config).await?;
+                let mut issues = vec![];
+                println!(
+                    "Bitcoind: {}",
+                    redact_url_credentials(&config.network.bitcoind_rpc_url)
+                );
+                println!("Chain height: {} ({} headers)", info.blocks, info.headers);
+                match check_chain(&info, config.network.network()) {
+                    Ok(()) => println!("Network: {}", info.chain),
+                    Err(e) => {
+                        println!(
+                            "Network: {} (expected {})",
+                            info.chain,
+                            config.network.network().to_core_arg()
+                        );
+                        issues.push(e);
+                    }
+                }
+                if let Some(ref expected_challenge) = config.network.signet_challenge {
+                    match get_signet_challenge(&config)? {
+                        Some(challenge) if challenge == *expected_challenge => {
+                            println!("Signet challenge: {}", challenge.to_hex_string())
+                        }
+                        challenge => {
+                            let challenge = challenge
+                                .map_or("none".to_string(), |challenge| challenge.to_hex_string());
+                            println!(
+                                "Signet challenge: {} (expected {})",
+                                challenge,
+                                expected_challenge.to_hex_string()
+                            );
+                            issues.push(format!(
+                                "bitcoind runs the signet of challenge {}, while network.signet_challenge is {}",
+                                challenge,
+                                expected_challenge.to_hex_string()
+                            ));
+                        }
+                    }
+                }
+                match is_synced(&info) {
+                    true => println!("Synced: yes"),
+                    false => println!(
+                        "Synced: no ({:.2}% verified)",
+                        info.verification_progress * 100.0
+                    ),
+                }
+                match config.network.block_signaling {
+                    BlockSignaling::ZeroMQ(ref zmq_url) => match check_zmq_connection(zmq_url) {
+                        Ok(()) => println!("ZeroMQ: {} reachable", zmq_url),
+                        Err(e) => {
+                            println!("ZeroMQ: {} unreachable", zmq_url);
+                            issues.push(e);
+                        }
+                    },
+                    BlockSignaling::RpcPolling(interval) => {
+                        println!("ZeroMQ: disabled, polling every {}ms", interval.as_millis())
+                    }
+                }
+                if !issues.is_empty() {
+                    return Err(issues.join("\n"));
+                }
+            }
+            NodeCommand::WaitSync(cmd) => {
+                let config = ConfigFile::from_file_path(&cmd.config_path)?;
+                loop {
+                    let info = check_bitcoind_connection(&config).await?;
+                    if is_synced(&info) {
+                        println!("Bitcoind synced at height {}", info.blocks);
+                        break;
+                    }
+                    println!(
+                        "Waiting for bitcoind: {}/{} blocks, {:.2}% verified",
+                        info.blocks,
+                        info.headers,
+                        info.verification_progress * 100.0
+                    );
+                    std::thread::sleep(Duration::from_secs(cmd.interval));
+                }
+            }
+        },
+        Command::Service(subcmd) => match subcmd {
+            ServiceCommand::Start(cmd) => {
+                let config = ConfigFile::from_file_path(&cmd.config_path)?;
+                start_service(&config, cmd.start_height, ctx)?;
+            }
+        },
+        Command::Setup(subcmd) => match subcmd {
+            SetupCommand::Open(cmd) => {
+                let config = ConfigFile::from_file_path(&cmd.config_path)?;
+                let circuit_content = read_circuit_file(&cmd.bristol_file_path)?;
+                open_setup(&config, &circuit_content, &cmd.output)?;
+            }
+            SetupCommand::Join(cmd) => {
+                let config = ConfigFile::from_file_path(&cmd.config_path)?;
+                let circuit_content = read_circuit_file(&cmd.bristol_file_path)?;
+                join_setup(
+                    &config,
+                    &circuit_content,
+                    &cmd.message,
+                    &cmd.inputs,
+                    &cmd.parameters_path,
+                    &cmd.output,
+                )?;
+            }
+            SetupCommand::Receive(cmd) => {
+                let config = ConfigFile::from_file_path(&cmd.config_path)?;
+                receive_setup_message(&config, &cmd.message, &cmd.output)?;
+            }
+        },
+        Command::Contracts(subcmd) => match subcmd {
+            ContractsCommand::Verify(cmd) => {
+                let contract: Contract =
+                    bitvm::storage::read_json(Path::new(&cmd.contract_file_path))?;
+                let graph = contract.verify(&Secp256k1::new())?;
+                print_contract(&contract, graph.count_missing_presignatures());
+            }
+            ContractsCommand::ExportPsbts(cmd) => {
+                let config = ConfigFile::from_file_path(&cmd.config_path)?;
+                export_psbts(&config, &cmd.role, &cmd.contract_id, &cmd.output_dir)?;
+            }
+            ContractsCommand::ImportPsbts(cmd) => {
+                let config = ConfigFile::from_file_path(&cmd.config_path)?;
+                import_psbts(&config, &cmd.role, &cmd.contract_id, &cmd.psbt_files)?;
             }
         },
     }
     Ok(())
 }
 
-pub async fn check_bitcoind_connection(config: &Config) -> Result<u64, String> {
-    let auth = Auth::UserPass(
-        config.network.bitcoind_rpc_username.clone(),
-        config.network.bitcoind_rpc_password.clone(),
-    );
+fn read_circuit_file(bristol_file_path: &str) -> Result<String, String> {
+    std::fs::read_to_string(bristol_file_path)
+        .map_err(|e| format!("unable to read circuit {}\n{}", bristol_file_path, e))
+}
+
+fn is_synced(info: &GetBlockchainInfoResult) -> bool {
+    !info.initial_block_download && info.blocks == info.headers
+}
+
+/// Checks that bitcoind runs the chain of `network`, named as in `getblockchaininfo`.
+fn check_chain(info: &GetBlockchainInfoResult, network: Network) -> Result<(), String> {
+    let expected_chain = network.to_core_arg();
+    match info.chain == expected_chain {
+        true => Ok(()),
+        false => Err(format!(
+            "bitcoind is running on {}, while network.mode is {}",
+            info.chain, expected_chain
+        )),
+    }
+}
 
-    let bitcoin_rpc = match Client::new(&config.network.bitcoind_rpc_url, auth) {
-        Ok(con) => con,
-        Err(message) => {
-            return Err(format!(
-                "unable to connect to bitcoind: {}",
-                message.to_string()
-            ));
+/// Checks that something listens on the ZeroMQ endpoint bitcoind publishes blocks to.
+fn check_zmq_connection(zmq_url: &str) -> Result<(), String> {
+    if let Some(path) = zmq_url.strip_prefix("ipc://") {
+        return match std::path::Path::new(path).exists() {
+            true => Ok(()),
+            false => Err(format!("ZeroMQ socket {} not found", path)),
+        };
+    }
+    let address = zmq_url.strip_prefix("tcp://").unwrap_or(zmq_url);
+    let socket_addresses = address
+        .to_socket_addrs()
+        .map_err(|e| format!("unable to resolve ZeroMQ address {}: {}", zmq_url, e))?;
+    for socket_address in socket_addresses {
+        if TcpStream::connect_timeout(&socket_address, Duration::from_secs(5)).is_ok() {
+            return Ok(());
         }
+    }
+    Err(format!("unable to connect to ZeroMQ endpoint {}", zmq_url))
+}
+
+/// Contents of the `BitVM.toml` requested by `config new`.
+fn new_config_content(cmd: &NewConfig) -> Result<String, String> {
+    let bitcoin_network = match (cmd.mainnet, cmd.testnet, cmd.signet, cmd.regtest) {
+        (_, _, _, true) => Network::Regtest,
+        (_, _, true, _) => Network::Signet,
+        (_, true, _, _) => Network::Testnet,
+        (true, _, _, _) => Network::Bitcoin,
+        _ => return Err("network.mode not supported".to_string()),
+    };
+    if let Some(ref challenge) = cmd.signet_challenge {
+        if bitcoin_network != Network::Signet {
+            return Err("--signet-challenge requires --signet".to_string());
+        }
+        let script = ScriptBuf::from_hex(challenge)
+            .map_err(|e| format!("invalid signet challenge {}: {}", challenge, e))?;
+        if script.is_empty() {
+            return Err("--signet-challenge cannot be empty".to_string());
+        }
+    }
+    Ok(generate_config(
+        bitcoin_network,
+        cmd.signet_challenge.as_deref(),
+    ))
+}
+
+/// Keys the next contracts of this node would be set up with, on both sides.
+fn next_contract_participants(config: &Config) -> Result<Participants, String> {
+    let secp = Secp256k1::new();
+    let keys = ParticipantKeys::load(
+        &config.keys.prover_seed_path,
+        &config.keys.verifier_seed_path,
+        &unlock_vault(config)?,
+        config.network.network(),
+    )?;
+    let public_key = |role| -> Result<PublicKey, String> {
+        let store = keys.store(role);
+        let secret_key = store.derive(&secp, &store.next_contract_path()?)?;
+        Ok(secret_key.public_key(&secp))
     };
+    Ok(Participants {
+        prover: public_key(Role::Prover)?,
+        verifier: public_key(Role::Verifier)?,
+    })
+}
 
-    let end_block = match bitcoin_rpc.get_blockchain_info() {
-        Ok(result) => result.blocks,
-        Err(e) => {
-            return Err(format!("unable to connect to bitcoind: {}", e.to_string()));
+/// Throwaway keys, for checks that do not depend on who the participants are.
+fn ephemeral_participants() -> Participants {
+    let secp = Secp256k1::new();
+    let mut rng = rand::thread_rng();
+    Participants {
+        prover: SecretKey::new(&mut rng).public_key(&secp),
+        verifier: SecretKey::new(&mut rng).public_key(&secp),
+    }
+}
+
+/// Maps a string of bits to the input wires of `circuit`, in wire id order.
+pub fn parse_inputs(circuit: &Circuit, bits: &str) -> Result<BTreeMap<WireId, bool>, String> {
+    let wires = circuit.collect_input_wires_ids();
+    if bits.len() != wires.len() {
+        return Err(format!(
+            "expected {} input bits, got {}",
+            wires.len(),
+            bits.len()
+        ));
+    }
+    wires
+        .into_iter()
+        .zip(bits.chars())
+        .map(|(wire_id, bit)| match bit {
+            '0' => Ok((*wire_id, false)),
+            '1' => Ok((*wire_id, true)),
+            _ => Err(format!("invalid input bit '{}'", bit)),
+        })
+        .collect()
+}
+
+fn parse_fee_rates(fee_rates: &[String]) -> Result<BTreeMap<u32, u64>, String> {
+    fee_rates
+        .iter()
+        .map(|entry| {
+            let (height, fee_rate) = entry
+                .split_once(':')
+                .ok_or(format!("expected <height>:<sat/vB>, got {}", entry))?;
+            Ok((
+                height
+                    .parse()
+                    .map_err(|e| format!("invalid height {}: {}", height, e))?,
+                fee_rate
+                    .parse()
+                    .map_err(|e| format!("invalid fee rate {}: {}", fee_rate, e))?,
+            ))
+        })
+        .collect()
+}
+
+fn role_name(role: Role) -> &'static str {
+    match role {
+        Role::Prover => "Paul (prover)",
+        Role::Verifier => "Vicky (verifier)",
+    }
+}
+
+fn print_contract(contract: &Contract, missing_presignatures: usize) {
+    println!("Contract {}", contract.contract_id);
+    println!("Circuit: {}", contract.circuit_hash);
+    println!(
+        "{}: {}",
+        role_name(Role::Prover),
+        contract.participants.prover
+    );
+    println!(
+        "{}: {}",
+        role_name(Role::Verifier),
+        contract.participants.verifier
+    );
+    println!(
+        "Amount: {} sats, collateral: {} sats, fee per transaction: {} sats",
+        contract.parameters.amount.to_sat(),
+        contract.parameters.collateral.to_sat(),
+        contract.parameters.fee_per_transaction.to_sat()
+    );
+    println!(
+        "Response timeout: {} blocks",
+        contract.parameters.timelocks.response_timeout
+    );
+    println!(
+        "Transactions: {}, outputs: {}, missing pre-signatures: {}",
+        contract.transactions.len(),
+        contract.outputs.len(),
+        missing_presignatures
+    );
+}
+
+fn print_dispute_cost(cost: &DisputeCost) {
+    println!(
+        "Bisection rounds: {}, fee rate: {} sat/vB",
+        cost.rounds, cost.fee_rate
+    );
+    match cost.fee_policy {
+        Some(_) => println!(
+            "Every transaction is bumped by a child spending its anchor and one fee wallet coin"
+        ),
+        None => println!(
+            "Fee bumps not included: a transaction stuck below the market rate also needs a child spending its anchor (see --bump-fees)"
+        ),
+    }
+    let last = cost
+        .honest
+        .transactions
+        .last()
+        .expect("disputes are not empty");
+    for tx in cost.cheating.transactions.iter().chain([last]) {
+        println!(
+            "{:<24} by {:<16} vsize={} child_vsize={} fee={}",
+            tx.kind.to_string(),
+            role_name(tx.broadcaster),
+            tx.vsize,
+            tx.child_vsize,
+            tx.fee.to_sat()
+        );
+    }
+    for (name, path) in [("honest", &cost.honest), ("cheating", &cost.cheating)] {
+        println!(
+            "Worst case with {} Paul: {} vbytes, {} sats ({} by Paul, {} by Vicky)",
+            name,
+            path.vsize(),
+            path.fee().to_sat(),
+            path.fee_paid_by(Role::Prover).to_sat(),
+            path.fee_paid_by(Role::Verifier).to_sat()
+        );
+    }
+}
+
+fn print_simulation_report(report: &SimulationReport) {
+    let inputs = report
+        .inputs
+        .values()
+        .map(|bit| if *bit { '1' } else { '0' })
+        .collect::<String>();
+    println!("Inputs: {}, bisection rounds: {}", inputs, report.rounds);
+    for tx in report.transactions.iter() {
+        println!(
+            "[block {:>5}] {:<24} by {:<16} {} vsize={} witness={}B fee={} cpfp={}",
+            tx.height,
+            tx.kind.to_string(),
+            role_name(tx.broadcaster),
+            tx.txid,
+            tx.vsize,
+            tx.witness_size,
+            tx.fee.to_sat(),
+            tx.cpfp_fee.to_sat()
+        );
+    }
+    for participant in [Role::Prover, Role::Verifier] {
+        println!(
+            "Fees paid by {}: {} sats",
+            role_name(participant),
+            report.total_fees(participant).to_sat()
+        );
+    }
+    println!(
+        "Amount at stake claimed by {} through {}",
+        role_name(report.winner),
+        report.outcome
+    );
+    println!(
+        "Collateral claimed by {}",
+        role_name(report.collateral_winner)
+    );
+    if report.false_claim {
+        println!("Paul's claim was false");
+    }
+    if report.equivocated {
+        println!("Paul equivocated");
+    }
+}
+
+/// Block challenge of the signet bitcoind runs, if any. Not part of the typed
+/// `getblockchaininfo` result.
+fn get_signet_challenge(config: &Config) -> Result<Option<ScriptBuf>, String> {
+    let bitcoin_rpc = new_bitcoind_client(config)?;
+    let info: serde_json::Value = bitcoin_rpc
+        .call("getblockchaininfo", &[])
+        .map_err(|e| format!("unable to connect to bitcoind: {}", e))?;
+    info.get("signet_challenge")
+        .and_then(|challenge| challenge.as_str())
+        .map(|challenge| {
+            ScriptBuf::from_hex(challenge)
+                .map_err(|e| format!("invalid signet challenge {}: {}", challenge, e))
+        })
+        .transpose()
+}
+
+pub async fn check_bitcoind_connection(config: &Config) -> Result<GetBlockchainInfoResult, String> {
+    let bitcoin_rpc = new_bitcoind_client(config)?;
+
+    match bitcoin_rpc.get_blockchain_info() {
+        Ok(result) => Ok(result),
+        Err(e) => Err(format!("unable to connect to bitcoind: {}", e)),
+    }
+}
+
+#[test]
+fn test_config_new_for_custom_signets() {
+    let new_config = |args: &[&str]| {
+        let args = ["bitvm", "config", "new"].iter().chain(args);
+        match Opts::try_parse_from(args).unwrap().command {
+            Command::Config(ConfigCommand::New(cmd)) => new_config_content(&cmd),
+            command => panic!("unexpected command {:?}", command),
         }
     };
 
-    Ok(end_block)
+    let content = new_config(&["--signet", "--signet-challenge", "51"]).unwrap();
+    let config = ConfigFile::from_config_file(toml::from_str(&content).unwrap()).unwrap();
+    assert_eq!(config.network.network(), Network::Signet);
+    assert_eq!(
+        config.network.signet_challenge,
+        Some(ScriptBuf::from_hex("51").unwrap())
+    );
+    let content = new_config(&["--signet"]).unwrap();
+    let config = ConfigFile::from_config_file(toml::from_str(&content).unwrap()).unwrap();
+    assert_eq!(config.network.signet_challenge, None);
+
+    assert_eq!(
+        new_config(&["--testnet", "--signet-challenge", "51"]).unwrap_err(),
+        "--signet-challenge requires --signet"
+    );
+    assert!(new_config(&["--signet", "--signet-challenge", "5"])
+        .unwrap_err()
+        .starts_with("invalid signet challenge 5"));
+    assert_eq!(
+        new_config(&["--signet", "--signet-challenge", ""]).unwrap_err(),
+        "--signet-challenge cannot be empty"
+    );
+}
+
+#[test]
+fn test_node_status_checks() {
+    let blockchain_info = |chain: &str, blocks: u64, initial_block_download: bool| {
+        serde_json::from_value::<GetBlockchainInfoResult>(serde_json::json!({
+            "chain": chain,
+            "blocks": blocks,
+            "headers": 120,
+            "bestblockhash": "0000000000000000000000000000000000000000000000000000000000000000",
+            "difficulty": 1.0,
+            "mediantime": 0,
+            "verificationprogress": 1.0,
+            "initialblockdownload": initial_block_download,
+            "chainwork": "00",
+            "size_on_disk": 0,
+            "pruned": false,
+            "warnings": "",
+        }))
+        .unwrap()
+    };
+
+    assert!(is_synced(&blockchain_info("regtest", 120, false)));
+    assert!(!is_synced(&blockchain_info("regtest", 119, false)));
+    assert!(!is_synced(&blockchain_info("regtest", 120, true)));
+
+    let info = blockchain_info("test", 120, false);
+    assert!(check_chain(&info, Network::Testnet).is_ok());
+    assert_eq!(
+        check_chain(&info, Network::Bitcoin).unwrap_err(),
+        "bitcoind is running on test, while network.mode is main"
+    );
+    assert!(check_chain(&blockchain_info("main", 120, false), Network::Bitcoin).is_ok());
+    assert!(check_chain(&blockchain_info("signet", 120, false), Network::Signet).is_ok());
+    assert!(check_chain(&blockchain_info("regtest", 120, false), Network::Signet).is_err());
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let zmq_url = format!("tcp://{}", listener.local_addr().unwrap());
+    assert!(check_zmq_connection(&zmq_url).is_ok());
+    drop(listener);
+    assert_eq!(
+        check_zmq_connection(&zmq_url).unwrap_err(),
+        format!("unable to connect to ZeroMQ endpoint {}", zmq_url)
+    );
+    let socket_path = std::env::temp_dir().join(format!("bitvm-zmq-{}", rand::random::<u64>()));
+    let zmq_url = format!("ipc://{}", socket_path.display());
+    assert_eq!(
+        check_zmq_connection(&zmq_url).unwrap_err(),
+        format!("ZeroMQ socket {} not found", socket_path.display())
+    );
+    File::create(&socket_path).unwrap();
+    assert!(check_zmq_connection(&zmq_url).is_ok());
+    std::fs::remove_file(&socket_path).unwrap();
 }
@@ -1,23 +1,48 @@
-use chainhook_sdk::types::BitcoinNetwork;
+use bitcoin::Network;
 
-pub fn generate_config(network: &BitcoinNetwork) -> String {
-    let network = format!("{:?}", network);
-    let conf = format!(
+/// Contents of a new `BitVM.toml` for `network`, running the custom signet of
+/// `signet_challenge` if any.
+pub fn generate_config(network: Network, signet_challenge: Option<&str>) -> String {
+    let mode = match network {
+        Network::Bitcoin => "mainnet",
+        Network::Testnet => "testnet",
+        Network::Signet => "signet",
+        _ => "regtest",
+    };
+    let signet_challenge = match signet_challenge {
+        Some(challenge) => format!("signet_challenge = \"{}\"\n", challenge),
+        None => String::new(),
+    };
+    format!(
         r#"[storage]
 working_dir = "bitvm"
 
 [network]
-mode = "{network}"
-bitcoind_rpc_url = "http://0.0.0.0:8332"
+mode = "{mode}"
+{signet_challenge}bitcoind_rpc_url = "http://0.0.0.0:8332"
 bitcoind_rpc_username = "devnet"
 bitcoind_rpc_password = "devnet"
 bitcoind_zmq_url = "tcp://0.0.0.0:18543"
+# Without bitcoind_zmq_url, new blocks are polled over RPC instead
+# bitcoind_rpc_polling_interval_ms = 5000
+
+[api]
+http_port = 20456
+
+[verifier]
+# Least accepted of the contracts proposed by provers
+min_amount_sats = 10000
+min_collateral_sats = 10000
+min_response_timeout = 6
+
+[keys]
+# BIP32 seeds of each role, sealed by the vault and generated on first use
+prover_seed_path = "bitvm/keys/prover.seed"
+verifier_seed_path = "bitvm/keys/verifier.seed"
 
 [logs]
 bitvm_internals = true
 chainhook_internals = true
 "#,
-        network = network.to_lowercase(),
-    );
-    return conf;
+    )
 }
@@ -1,4 +1,9 @@
-use chainhook_sdk::indexer::IndexerConfig;
+use bitcoin::{Network, ScriptBuf};
+use bitvm::protocol::ContractTerms;
+use chainhook_sdk::types::BitcoinNetwork;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
 
 pub mod file;
 pub mod generator;
@@ -6,8 +11,155 @@ pub mod generator;
 #[derive(Clone, Debug)]
 pub struct Config {
     pub storage: StorageConfig,
-    pub network: IndexerConfig,
+    pub network: NetworkConfig,
     pub logs: LogConfig,
+    pub api: Option<ApiConfig>,
+    pub keys: KeysConfig,
+    /// Least accepted of the contracts Paul proposes, when playing as verifier.
+    pub terms: ContractTerms,
+}
+
+/// Printed in place of secrets.
+pub const REDACTED: &str = "<redacted>";
+
+#[derive(Clone)]
+pub struct NetworkConfig {
+    pub bitcoind_rpc_url: String,
+    pub bitcoind_rpc_username: String,
+    pub bitcoind_rpc_password: String,
+    pub bitcoin_network: Network,
+    /// Block challenge of a custom signet, which bitcoind must run. None on the default
+    /// signet, and on other networks.
+    pub signet_challenge: Option<ScriptBuf>,
+    pub block_signaling: BlockSignaling,
+}
+
+impl NetworkConfig {
+    /// Network addresses and extended keys are encoded for.
+    pub fn network(&self) -> Network {
+        self.bitcoin_network
+    }
+
+    /// Network handed to chainhook, which does not know signet: its addresses are encoded
+    /// like testnet ones. When parsing blocks, chainhook only uses the network to tag them
+    /// and to look for Stacks operations, with testnet magic bytes on signet. Blocks and
+    /// transactions are taken as bitcoind returns them, without checking the signet challenge,
+    /// so contracts, matched by txid and outpoint, are followed as on any other network.
+    pub fn chainhook_network(&self) -> BitcoinNetwork {
+        match self.bitcoin_network {
+            Network::Bitcoin => BitcoinNetwork::Mainnet,
+            Network::Testnet | Network::Signet => BitcoinNetwork::Testnet,
+            _ => BitcoinNetwork::Regtest,
+        }
+    }
+}
+
+impl fmt::Debug for NetworkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NetworkConfig")
+            .field(
+                "bitcoind_rpc_url",
+                &redact_url_credentials(&self.bitcoind_rpc_url),
+            )
+            .field("bitcoind_rpc_username", &self.bitcoind_rpc_username)
+            .field("bitcoind_rpc_password", &REDACTED)
+            .field("bitcoin_network", &self.bitcoin_network)
+            .field("signet_challenge", &self.signet_challenge)
+            .field("block_signaling", &self.block_signaling)
+            .finish()
+    }
+}
+
+/// `url` with the `user:pass@` credentials it may embed redacted.
+pub(crate) fn redact_url_credentials(url: &str) -> String {
+    let (scheme, rest) = match url.split_once("://") {
+        Some((scheme, rest)) => (format!("{}://", scheme), rest),
+        None => (String::new(), url),
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    match authority.rfind('@') {
+        Some(at) => format!("{}{}@{}", scheme, REDACTED, &rest[at + 1..]),
+        None => url.to_string(),
+    }
+}
+
+/// Effective settings, in the layout of the config file, with secrets redacted.
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[storage]")?;
+        writeln!(f, "working_dir = {:?}", self.storage.working_dir)?;
+        writeln!(f)?;
+        writeln!(f, "[network]")?;
+        let mode = match self.network.bitcoin_network {
+            Network::Bitcoin => "mainnet".to_string(),
+            network => network.to_string(),
+        };
+        writeln!(f, "mode = {:?}", mode)?;
+        writeln!(
+            f,
+            "bitcoind_rpc_url = {:?}",
+            redact_url_credentials(&self.network.bitcoind_rpc_url)
+        )?;
+        writeln!(
+            f,
+            "bitcoind_rpc_username = {:?}",
+            self.network.bitcoind_rpc_username
+        )?;
+        writeln!(f, "bitcoind_rpc_password = {:?}", REDACTED)?;
+        match self.network.block_signaling {
+            BlockSignaling::ZeroMQ(ref zmq_url) => writeln!(f, "bitcoind_zmq_url = {:?}", zmq_url)?,
+            BlockSignaling::RpcPolling(interval) => writeln!(
+                f,
+                "bitcoind_rpc_polling_interval_ms = {}",
+                interval.as_millis()
+            )?,
+        }
+        if let Some(ref challenge) = self.network.signet_challenge {
+            writeln!(f, "signet_challenge = {:?}", challenge.to_hex_string())?;
+        }
+        writeln!(f)?;
+        writeln!(f, "[logs]")?;
+        writeln!(f, "bitvm_internals = {}", self.logs.bitvm_internals)?;
+        writeln!(f, "chainhook_internals = {}", self.logs.chainhook_internals)?;
+        if let Some(ref api) = self.api {
+            writeln!(f)?;
+            writeln!(f, "[api]")?;
+            writeln!(f, "http_port = {}", api.http_port)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "[verifier]")?;
+        writeln!(f, "min_amount_sats = {}", self.terms.min_amount.to_sat())?;
+        writeln!(
+            f,
+            "min_collateral_sats = {}",
+            self.terms.min_collateral.to_sat()
+        )?;
+        writeln!(
+            f,
+            "min_response_timeout = {}",
+            self.terms.min_response_timeout
+        )?;
+        writeln!(f)?;
+        writeln!(f, "[keys]")?;
+        writeln!(
+            f,
+            "prover_seed_path = {:?}",
+            self.keys.prover_seed_path.display().to_string()
+        )?;
+        write!(
+            f,
+            "verifier_seed_path = {:?}",
+            self.keys.verifier_seed_path.display().to_string()
+        )
+    }
+}
+
+/// How new blocks are announced by bitcoind.
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockSignaling {
+    ZeroMQ(String),
+    /// Ask bitcoind for its best block at the given interval, for nodes without ZeroMQ.
+    RpcPolling(Duration),
 }
 
 #[derive(Clone, Debug)]
@@ -16,6 +168,19 @@ pub struct LogConfig {
     pub chainhook_internals: bool,
 }
 
+/// Local HTTP API of the service.
+#[derive(Clone, Debug)]
+pub struct ApiConfig {
+    pub http_port: u16,
+}
+
+/// Seeds the participant keys of each role are derived from.
+#[derive(Clone, Debug)]
+pub struct KeysConfig {
+    pub prover_seed_path: PathBuf,
+    pub verifier_seed_path: PathBuf,
+}
+
 #[derive(Clone, Debug)]
 pub struct StorageConfig {
     pub working_dir: String,
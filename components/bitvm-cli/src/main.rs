@@ -4,8 +4,14 @@ extern crate serde_derive;
 #[macro_use]
 extern crate hiro_system_kit;
 
+pub mod api;
 pub mod cli;
 pub mod config;
+pub mod observer;
+pub mod psbt;
+pub mod service;
+pub mod setup;
+pub mod vault;
 
 fn main() {
     cli::main();
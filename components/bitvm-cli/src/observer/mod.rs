@@ -0,0 +1,583 @@
+use crate::config::{BlockSignaling, Config};
+use bitcoin::consensus::deserialize;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::{Block, BlockHash, OutPoint, ScriptBuf, Transaction, Txid};
+use bitvm::chain::observer::{ContractObserver, MAX_REORG_DEPTH};
+use bitvm::chain::{ChainBlock, ChainEvent};
+use bitvm::protocol::ContractAction;
+use chainhook_sdk::bitcoincore_rpc::bitcoin as rpc_bitcoin;
+use chainhook_sdk::bitcoincore_rpc::{Auth, Client, RpcApi};
+use chainhook_sdk::indexer::bitcoin::{
+    build_http_client, download_and_parse_block_with_retry, BitcoinBlockFullBreakdown,
+};
+use chainhook_sdk::indexer::fork_scratch_pad::ForkScratchPad;
+use chainhook_sdk::observer::{
+    start_event_observer, start_observer_commands_handler, ChainhookStore, EventObserverConfig,
+    ObserverCommand, ObserverEvent, ObserverMetrics,
+};
+use chainhook_sdk::types::{
+    BitcoinBlockData, BitcoinBlockSignaling, BitcoinChainEvent, StacksNetwork, StacksNodeConfig,
+};
+use chainhook_sdk::utils::Context;
+use crossbeam_channel::Receiver;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+/// Starts chainhook's block observer, returning the channel to stop it and the stream of
+/// chain updates.
+pub fn start_chain_observer(
+    config: &Config,
+    ctx: &Context,
+) -> Result<(Sender<ObserverCommand>, Receiver<ObserverEvent>), String> {
+    let event_observer_config = EventObserverConfig {
+        chainhook_config: None,
+        bitcoin_rpc_proxy_enabled: false,
+        ingestion_port: 0,
+        bitcoind_rpc_username: config.network.bitcoind_rpc_username.clone(),
+        bitcoind_rpc_password: config.network.bitcoind_rpc_password.clone(),
+        bitcoind_rpc_url: config.network.bitcoind_rpc_url.clone(),
+        // Chainhook requires a signaling, which only its ZeroMQ runloop and Stacks ingestion
+        // server read: neither is started when polling.
+        bitcoin_block_signaling: match config.network.block_signaling {
+            BlockSignaling::ZeroMQ(ref zmq_url) => BitcoinBlockSignaling::ZeroMQ(zmq_url.clone()),
+            BlockSignaling::RpcPolling(_) => {
+                BitcoinBlockSignaling::Stacks(StacksNodeConfig::default_localhost(0))
+            }
+        },
+        display_logs: config.logs.chainhook_internals,
+        cache_path: config.storage.working_dir.clone(),
+        bitcoin_network: config.network.chainhook_network(),
+        stacks_network: StacksNetwork::Devnet,
+        data_handler_tx: None,
+    };
+    let (observer_commands_tx, observer_commands_rx) = std::sync::mpsc::channel();
+    let (observer_events_tx, observer_events_rx) = crossbeam_channel::unbounded();
+    match config.network.block_signaling {
+        BlockSignaling::ZeroMQ(_) => start_event_observer(
+            event_observer_config,
+            observer_commands_tx.clone(),
+            observer_commands_rx,
+            Some(observer_events_tx),
+            None,
+            ctx.clone(),
+        )
+        .map_err(|e| format!("unable to start chain observer: {}", e))?,
+        BlockSignaling::RpcPolling(interval) => {
+            ctx.try_log(|logger| {
+                hiro_system_kit::slog::info!(
+                    logger,
+                    "Observing Bitcoin chain events by polling bitcoind every {}ms",
+                    interval.as_millis()
+                )
+            });
+            let config_moved = event_observer_config.clone();
+            let ctx_moved = ctx.clone();
+            hiro_system_kit::thread_named("Chain observer commands handler")
+                .spawn(move || {
+                    let future = start_observer_commands_handler(
+                        config_moved,
+                        ChainhookStore::new(),
+                        observer_commands_rx,
+                        Some(observer_events_tx),
+                        None,
+                        Arc::new(RwLock::new(ObserverMetrics::default())),
+                        None,
+                        ctx_moved,
+                    );
+                    let _ = hiro_system_kit::nestable_block_on(future);
+                })
+                .map_err(|e| format!("unable to start chain observer: {}", e))?;
+            let commands_tx_moved = observer_commands_tx.clone();
+            let ctx_moved = ctx.clone();
+            hiro_system_kit::thread_named("RPC block poller")
+                .spawn(move || {
+                    let future = start_rpc_polling_runloop(
+                        &event_observer_config,
+                        interval,
+                        commands_tx_moved,
+                        &ctx_moved,
+                    );
+                    hiro_system_kit::nestable_block_on(future);
+                })
+                .map_err(|e| format!("unable to start chain observer: {}", e))?;
+        }
+    }
+    Ok((observer_commands_tx, observer_events_rx))
+}
+
+/// Stand-in for chainhook's ZeroMQ runloop: asks bitcoind for its best block every
+/// `interval`, and submits the blocks leading to it to chainhook, which tracks forks.
+async fn start_rpc_polling_runloop(
+    config: &EventObserverConfig,
+    interval: Duration,
+    observer_commands_tx: Sender<ObserverCommand>,
+    ctx: &Context,
+) {
+    let bitcoin_config = config.get_bitcoin_config();
+    let http_client = build_http_client();
+    let bitcoin_rpc = match Client::new(
+        &config.bitcoind_rpc_url,
+        Auth::UserPass(
+            config.bitcoind_rpc_username.clone(),
+            config.bitcoind_rpc_password.clone(),
+        ),
+    ) {
+        Ok(bitcoin_rpc) => bitcoin_rpc,
+        Err(e) => {
+            ctx.try_log(|logger| {
+                hiro_system_kit::slog::error!(logger, "unable to connect to bitcoind: {}", e)
+            });
+            return;
+        }
+    };
+    let mut poller = RpcPoller::new();
+    loop {
+        let block_hash = match bitcoin_rpc.get_best_block_hash() {
+            Ok(block_hash) => block_hash.to_string(),
+            Err(e) => {
+                ctx.try_log(|logger| {
+                    hiro_system_kit::slog::warn!(logger, "unable to retrieve best block: {}", e)
+                });
+                std::thread::sleep(interval);
+                continue;
+            }
+        };
+        let download = |hash: String| {
+            let (http_client, bitcoin_config) = (&http_client, &bitcoin_config);
+            async move {
+                download_and_parse_block_with_retry(http_client, &hash, bitcoin_config, ctx).await
+            }
+        };
+        if let Err(e) = poller
+            .poll(block_hash, download, &observer_commands_tx, ctx)
+            .await
+        {
+            ctx.try_log(|logger| {
+                hiro_system_kit::slog::warn!(logger, "unable to download block: {}", e)
+            });
+        }
+        std::thread::sleep(interval);
+    }
+}
+
+/// Blocks the RPC poller submitted to chainhook, by height, over the last `MAX_REORG_DEPTH`
+/// heights: contracts cannot follow deeper reorgs.
+struct RpcPoller {
+    bitcoin_blocks_pool: ForkScratchPad,
+    known_blocks: BTreeMap<u64, Vec<String>>,
+    best_block_hash: Option<String>,
+}
+
+impl RpcPoller {
+    fn new() -> RpcPoller {
+        RpcPoller {
+            bitcoin_blocks_pool: ForkScratchPad::new(),
+            known_blocks: BTreeMap::new(),
+            best_block_hash: None,
+        }
+    }
+
+    /// Submits the blocks leading to `block_hash`, walking back from it to a block chainhook
+    /// knows, or a single block on the first poll. The tip is only recorded once all of them
+    /// were submitted: if a download fails, the whole walk is retried on the next poll.
+    async fn poll<F, Fut>(
+        &mut self,
+        block_hash: String,
+        mut download: F,
+        observer_commands_tx: &Sender<ObserverCommand>,
+        ctx: &Context,
+    ) -> Result<(), String>
+    where
+        F: FnMut(String) -> Fut,
+        Fut: Future<Output = Result<BitcoinBlockFullBreakdown, String>>,
+    {
+        if self.best_block_hash.as_ref() == Some(&block_hash) {
+            return Ok(());
+        }
+
+        // Blocks at or below the oldest known one cannot lead back to it: a reorg that deep
+        // is submitted from there, for chainhook to reject.
+        let oldest_height = self.known_blocks.keys().next().copied();
+        let mut blocks = vec![];
+        let mut next_hash = Some(block_hash.clone());
+        while let Some(hash) = next_hash.take() {
+            let block = download(hash).await?;
+            let header = block.get_block_header();
+            let parent = &header.parent_block_identifier;
+            let known_parent = self
+                .known_blocks
+                .get(&parent.index)
+                .is_some_and(|hashes| hashes.contains(&parent.hash));
+            match oldest_height {
+                Some(height) if !known_parent && header.block_identifier.index > height => {
+                    next_hash = Some(
+                        header
+                            .parent_block_identifier
+                            .get_hash_bytes_str()
+                            .to_string(),
+                    );
+                }
+                _ => {}
+            }
+            blocks.push(block);
+        }
+
+        for block in blocks.into_iter().rev() {
+            let header = block.get_block_header();
+            ctx.try_log(|logger| {
+                hiro_system_kit::slog::info!(
+                    logger,
+                    "Bitcoin block #{} dispatched for processing",
+                    block.height
+                )
+            });
+            let _ = observer_commands_tx.send(ObserverCommand::ProcessBitcoinBlock(block));
+            if !self.bitcoin_blocks_pool.can_process_header(&header) {
+                continue;
+            }
+            self.remember(header.block_identifier.index, &header.block_identifier.hash);
+            match self.bitcoin_blocks_pool.process_header(header, ctx) {
+                Ok(Some(event)) => {
+                    let _ = observer_commands_tx
+                        .send(ObserverCommand::PropagateBitcoinChainEvent(event));
+                }
+                Ok(None) => {}
+                Err(e) => ctx.try_log(|logger| {
+                    hiro_system_kit::slog::warn!(logger, "Unable to append block: {:?}", e)
+                }),
+            }
+        }
+        self.best_block_hash = Some(block_hash);
+        Ok(())
+    }
+
+    fn remember(&mut self, height: u64, hash: &str) {
+        let hashes = self.known_blocks.entry(height).or_default();
+        if !hashes.iter().any(|known| known == hash) {
+            hashes.push(hash.to_string());
+        }
+        while let (Some((&oldest, _)), Some((&newest, _))) = (
+            self.known_blocks.first_key_value(),
+            self.known_blocks.last_key_value(),
+        ) {
+            if newest - oldest < MAX_REORG_DEPTH as u64 {
+                break;
+            }
+            self.known_blocks.pop_first();
+        }
+    }
+}
+
+pub fn new_bitcoind_client(config: &Config) -> Result<Client, String> {
+    let auth = Auth::UserPass(
+        config.network.bitcoind_rpc_username.clone(),
+        config.network.bitcoind_rpc_password.clone(),
+    );
+    Client::new(&config.network.bitcoind_rpc_url, auth)
+        .map_err(|e| format!("unable to connect to bitcoind: {}", e))
+}
+
+/// Feeds a chainhook update to every observer, and publishes the transactions their
+/// contracts ask for. The update is converted for every observer before any is fed, so that
+/// it can be processed again as a whole when bitcoind fails to provide its transactions.
+/// Failures past that point are logged: contracts are fed their events again with the next
+/// block.
+pub fn process_chain_event(
+    observers: &mut [ContractObserver],
+    event: &BitcoinChainEvent,
+    bitcoin_rpc: &Client,
+    ctx: &Context,
+) -> Result<(), String> {
+    let chain_events = observers
+        .iter()
+        .map(|observer| convert_chain_event(event, observer, bitcoin_rpc))
+        .collect::<Result<Vec<_>, String>>()?;
+    for (observer, chain_event) in observers.iter_mut().zip(chain_events.iter()) {
+        apply_chain_event(observer, chain_event, bitcoin_rpc, ctx);
+    }
+    Ok(())
+}
+
+/// Feeds `chain_event` to `observer`, logging what its contracts failed to handle.
+fn apply_chain_event(
+    observer: &mut ContractObserver,
+    chain_event: &ChainEvent,
+    bitcoin_rpc: &Client,
+    ctx: &Context,
+) {
+    let update = match observer.handle_chain_event(chain_event) {
+        Ok(update) => update,
+        Err(e) => {
+            ctx.try_log(|logger| {
+                hiro_system_kit::slog::warn!(logger, "unable to apply chain update: {}", e)
+            });
+            return;
+        }
+    };
+    for (contract_id, e) in update.failures.iter() {
+        ctx.try_log(|logger| {
+            hiro_system_kit::slog::warn!(
+                logger,
+                "contract {}: {}, retrying with the next block",
+                contract_id,
+                e
+            )
+        });
+    }
+    execute_actions(&update.actions, bitcoin_rpc, ctx);
+}
+
+/// Replays the blocks from `start_height` to bitcoind's tip, for contracts to catch up on
+/// what happened while they were not observed. Returns the height of the tip.
+pub fn catch_up(
+    observers: &mut [ContractObserver],
+    start_height: u64,
+    bitcoin_rpc: &Client,
+    ctx: &Context,
+) -> Result<u64, String> {
+    let tip_height = bitcoin_rpc
+        .get_block_count()
+        .map_err(|e| format!("unable to retrieve block count: {}", e))?;
+    for height in start_height..=tip_height {
+        let block = fetch_block(height, bitcoin_rpc)?;
+        for observer in observers.iter_mut() {
+            let new_block = ChainBlock {
+                height: height as u32,
+                hash: block.block_hash(),
+                parent_hash: block.header.prev_blockhash,
+                transactions: block
+                    .txdata
+                    .iter()
+                    .filter(|tx| {
+                        tx.input
+                            .iter()
+                            .any(|input| observer.watches_outpoint(&input.previous_output))
+                            || tx
+                                .output
+                                .iter()
+                                .any(|output| observer.watches_script_pubkey(&output.script_pubkey))
+                    })
+                    .cloned()
+                    .collect(),
+            };
+            let chain_event = ChainEvent::ChainUpdatedWithBlocks {
+                new_blocks: vec![new_block],
+            };
+            apply_chain_event(observer, &chain_event, bitcoin_rpc, ctx);
+        }
+    }
+    Ok(tip_height)
+}
+
+/// Publishes the transactions requested by contracts. Failures are logged: the transaction
+/// is requested again on the next block.
+pub fn execute_actions(actions: &[(Txid, ContractAction)], bitcoin_rpc: &Client, ctx: &Context) {
+    for (contract_id, action) in actions.iter() {
+        match action {
+            ContractAction::Fund(tx) => ctx.try_log(|logger| {
+                hiro_system_kit::slog::info!(
+                    logger,
+                    "contract {}: funding transaction {} ready to be signed by the wallet: {}",
+                    contract_id,
+                    tx.txid(),
+                    bitcoin::consensus::encode::serialize_hex(tx)
+                )
+            }),
+            ContractAction::Broadcast { kind, tx } => {
+                let tx_hex = bitcoin::consensus::encode::serialize_hex(tx);
+                match bitcoin_rpc.send_raw_transaction(tx_hex) {
+                    Ok(txid) => ctx.try_log(|logger| {
+                        hiro_system_kit::slog::info!(
+                            logger,
+                            "contract {}: broadcast {} {}",
+                            contract_id,
+                            kind,
+                            txid
+                        )
+                    }),
+                    Err(e) => ctx.try_log(|logger| {
+                        hiro_system_kit::slog::warn!(
+                            logger,
+                            "contract {}: unable to broadcast {}: {}",
+                            contract_id,
+                            kind,
+                            e
+                        )
+                    }),
+                }
+            }
+        }
+    }
+}
+
+/// Converts a chainhook update, keeping only the transactions `observer` watches. Those are
+/// fetched from bitcoind, as chainhook's standardized blocks cannot be turned back into
+/// transactions.
+pub fn convert_chain_event(
+    event: &BitcoinChainEvent,
+    observer: &ContractObserver,
+    bitcoin_rpc: &Client,
+) -> Result<ChainEvent, String> {
+    let convert_blocks = |blocks: &[BitcoinBlockData]| {
+        blocks
+            .iter()
+            .map(|block| convert_block(block, observer, bitcoin_rpc))
+            .collect::<Result<Vec<_>, String>>()
+    };
+    match event {
+        BitcoinChainEvent::ChainUpdatedWithBlocks(data) => Ok(ChainEvent::ChainUpdatedWithBlocks {
+            new_blocks: convert_blocks(&data.new_blocks)?,
+        }),
+        BitcoinChainEvent::ChainUpdatedWithReorg(data) => {
+            let mut blocks_to_rollback = convert_blocks(&data.blocks_to_rollback)?;
+            blocks_to_rollback.sort_by_key(|block| std::cmp::Reverse(block.height));
+            let mut blocks_to_apply = convert_blocks(&data.blocks_to_apply)?;
+            blocks_to_apply.sort_by_key(|block| block.height);
+            Ok(ChainEvent::ChainUpdatedWithReorg {
+                blocks_to_rollback,
+                blocks_to_apply,
+            })
+        }
+    }
+}
+
+fn convert_block(
+    block: &BitcoinBlockData,
+    observer: &ContractObserver,
+    bitcoin_rpc: &Client,
+) -> Result<ChainBlock, String> {
+    let hash = parse_hash::<BlockHash>(&block.block_identifier.hash)?;
+    let mut transactions = vec![];
+    for tx in block.transactions.iter() {
+        let spends_watched_output = tx.metadata.inputs.iter().any(|input| {
+            parse_hash::<Txid>(&input.previous_output.txid.hash).is_ok_and(|txid| {
+                observer.watches_outpoint(&OutPoint::new(txid, input.previous_output.vout))
+            })
+        });
+        let pays_watched_script = tx.metadata.outputs.iter().any(|output| {
+            ScriptBuf::from_hex(output.script_pubkey.trim_start_matches("0x"))
+                .is_ok_and(|script_pubkey| observer.watches_script_pubkey(&script_pubkey))
+        });
+        if spends_watched_output || pays_watched_script {
+            transactions.push(fetch_transaction(
+                &tx.transaction_identifier.hash,
+                &block.block_identifier.hash,
+                bitcoin_rpc,
+            )?);
+        }
+    }
+    Ok(ChainBlock {
+        height: block.block_identifier.index as u32,
+        hash,
+        parent_hash: parse_hash(&block.parent_block_identifier.hash)?,
+        transactions,
+    })
+}
+
+fn fetch_block(height: u64, bitcoin_rpc: &Client) -> Result<Block, String> {
+    let block_hash = bitcoin_rpc
+        .get_block_hash(height)
+        .map_err(|e| format!("unable to retrieve block #{}: {}", height, e))?;
+    let block_hex = bitcoin_rpc
+        .get_block_hex(&block_hash)
+        .map_err(|e| format!("unable to retrieve block #{}: {}", height, e))?;
+    let bytes =
+        Vec::<u8>::from_hex(&block_hex).map_err(|e| format!("invalid block #{}: {}", height, e))?;
+    deserialize(&bytes).map_err(|e| format!("invalid block #{}: {}", height, e))
+}
+
+fn fetch_transaction(
+    txid: &str,
+    block_hash: &str,
+    bitcoin_rpc: &Client,
+) -> Result<Transaction, String> {
+    let txid = txid.trim_start_matches("0x");
+    let rpc_txid =
+        rpc_bitcoin::Txid::from_str(txid).map_err(|e| format!("invalid txid {}: {}", txid, e))?;
+    let rpc_block_hash = rpc_bitcoin::BlockHash::from_str(block_hash.trim_start_matches("0x"))
+        .map_err(|e| format!("invalid block hash {}: {}", block_hash, e))?;
+    let tx_hex = bitcoin_rpc
+        .get_raw_transaction_hex(&rpc_txid, Some(&rpc_block_hash))
+        .map_err(|e| format!("unable to fetch transaction {}: {}", txid, e))?;
+    let bytes =
+        Vec::<u8>::from_hex(&tx_hex).map_err(|e| format!("invalid transaction {}: {}", txid, e))?;
+    deserialize(&bytes).map_err(|e| format!("invalid transaction {}: {}", txid, e))
+}
+
+fn parse_hash<T: FromStr>(hash: &str) -> Result<T, String> {
+    T::from_str(hash.trim_start_matches("0x")).map_err(|_| format!("invalid hash {}", hash))
+}
+
+#[cfg(test)]
+fn stub_block(height: usize) -> BitcoinBlockFullBreakdown {
+    BitcoinBlockFullBreakdown {
+        hash: format!("{:064x}", height),
+        height,
+        tx: vec![],
+        time: 0,
+        nonce: 0,
+        previousblockhash: Some(format!("{:064x}", height - 1)),
+        confirmations: 1,
+    }
+}
+
+#[test]
+fn test_rpc_poller_submits_every_block_up_to_the_tip() {
+    let ctx = Context::empty();
+    let chain = (1..=20)
+        .map(|height| (format!("{:064x}", height), stub_block(height)))
+        .collect::<std::collections::HashMap<_, _>>();
+    let unavailable = std::cell::RefCell::new(std::collections::HashSet::new());
+    let download = |hash: String| {
+        let block = match unavailable.borrow().contains(&hash) {
+            true => Err(format!("block {} unavailable", hash)),
+            false => Ok(chain[&hash].clone()),
+        };
+        std::future::ready(block)
+    };
+    let (observer_commands_tx, observer_commands_rx) = std::sync::mpsc::channel();
+    let submitted_heights = || {
+        observer_commands_rx
+            .try_iter()
+            .filter_map(|command| match command {
+                ObserverCommand::ProcessBitcoinBlock(block) => Some(block.height),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+    };
+    let mut poller = RpcPoller::new();
+    let mut poll = |height: usize| {
+        hiro_system_kit::nestable_block_on(poller.poll(
+            format!("{:064x}", height),
+            download,
+            &observer_commands_tx,
+            &ctx,
+        ))
+    };
+
+    // The first poll starts from the tip.
+    poll(1).unwrap();
+    assert_eq!(submitted_heights(), vec![1]);
+
+    // A block missing on the way back leaves the whole batch for the next poll.
+    unavailable.borrow_mut().insert(format!("{:064x}", 10));
+    assert!(poll(20).is_err());
+    assert_eq!(submitted_heights(), Vec::<usize>::new());
+
+    // The walk back is not bounded by the reorg depth: every missed block is submitted.
+    unavailable.borrow_mut().clear();
+    poll(20).unwrap();
+    assert_eq!(submitted_heights(), (2..=20).collect::<Vec<_>>());
+    poll(20).unwrap();
+    assert_eq!(submitted_heights(), Vec::<usize>::new());
+
+    // Only the blocks a reorg can reach are remembered.
+    assert_eq!(
+        poller.known_blocks.keys().copied().collect::<Vec<_>>(),
+        (9..=20).collect::<Vec<_>>()
+    );
+}
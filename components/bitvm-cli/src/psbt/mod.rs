@@ -0,0 +1,102 @@
+use crate::config::Config;
+use crate::vault::unlock_vault;
+use bitcoin::Txid;
+use bitvm::protocol::psbt::decode_psbt;
+use bitvm::protocol::{ContractEvent, ContractMachine, Role};
+use bitvm::prover::Prover;
+use bitvm::verifier::Verifier;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Writes every transaction of the contract to `output_dir` as a BIP174 `<kind>.psbt` file,
+/// for external signers.
+pub fn export_psbts(
+    config: &Config,
+    role: &str,
+    contract_id: &str,
+    output_dir: &str,
+) -> Result<(), String> {
+    let machine = restore_machine(config, role, contract_id)?;
+    let output_dir = Path::new(output_dir);
+    fs::create_dir_all(output_dir)
+        .map_err(|e| format!("unable to create {}: {}", output_dir.display(), e))?;
+    let psbts = machine.export_psbts()?;
+    for (kind, psbt) in psbts.iter() {
+        let path = output_dir.join(format!("{}.psbt", kind));
+        fs::write(&path, psbt.serialize())
+            .map_err(|e| format!("unable to write {}: {}", path.display(), e))?;
+    }
+    eprintln!(
+        "Exported {} PSBTs of contract {} to {}",
+        psbts.len(),
+        machine.contract_id(),
+        output_dir.display()
+    );
+    Ok(())
+}
+
+/// Combines the other participant's signatures carried by the PSBT `files` into the graph.
+/// The contract must not be played by a running service at the same time, which would
+/// overwrite them: use the API instead.
+pub fn import_psbts(
+    config: &Config,
+    role: &str,
+    contract_id: &str,
+    files: &[String],
+) -> Result<(), String> {
+    let mut machine = restore_machine(config, role, contract_id)?;
+    let mut imported = 0;
+    for file in files.iter() {
+        let bytes = fs::read(file).map_err(|e| format!("unable to read {}: {}", file, e))?;
+        imported += machine
+            .import_psbt(&decode_psbt(&bytes)?)
+            .map_err(|e| format!("unable to import {}: {}", file, e))?;
+    }
+    let missing = machine.graph().count_missing_presignatures();
+    eprintln!(
+        "Imported {} signatures, {} pre-signatures missing",
+        imported, missing
+    );
+    if missing == 0 {
+        machine.handle(&ContractEvent::SetupCompleted)?;
+    }
+    Ok(())
+}
+
+fn restore_machine(
+    config: &Config,
+    role: &str,
+    contract_id: &str,
+) -> Result<Box<dyn ContractMachine>, String> {
+    let working_dir = PathBuf::from(&config.storage.working_dir);
+    bitvm::storage::open(&working_dir)?;
+    let contract_id: Txid = contract_id
+        .parse()
+        .map_err(|e| format!("invalid contract id {}: {}", contract_id, e))?;
+    match parse_role(role)? {
+        Role::Prover => {
+            let vault = unlock_vault(config)?;
+            Ok(Box::new(Prover::restore(
+                &working_dir,
+                &vault,
+                &contract_id,
+            )?))
+        }
+        Role::Verifier => {
+            let vault = unlock_vault(config)?;
+            Ok(Box::new(Verifier::restore(
+                &working_dir,
+                &vault,
+                &contract_id,
+            )?))
+        }
+    }
+}
+
+pub(crate) fn parse_role(role: &str) -> Result<Role, String> {
+    match role {
+        "prover" => Ok(Role::Prover),
+        "verifier" => Ok(Role::Verifier),
+        _ => Err(format!("unknown role {}", role)),
+    }
+}
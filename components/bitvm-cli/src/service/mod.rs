@@ -0,0 +1,299 @@
+use crate::api::start_api;
+use crate::config::Config;
+use crate::observer::{catch_up, new_bitcoind_client, process_chain_event, start_chain_observer};
+use crate::vault::unlock_vault;
+use bitvm::chain::observer::ContractObserver;
+use bitvm::keys::ParticipantKeys;
+use bitvm::protocol::Role;
+use bitvm::prover::Prover;
+use bitvm::storage;
+use bitvm::vault::Vault;
+use bitvm::verifier::Verifier;
+use chainhook_sdk::bitcoincore_rpc::RpcApi;
+use chainhook_sdk::observer::{ObserverCommand, ObserverEvent};
+use chainhook_sdk::types::BitcoinChainEvent;
+use chainhook_sdk::utils::Context;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+const SERVICE_STATE_FILE: &str = "service.json";
+
+/// Persisted progress of the service, to catch up on missed blocks after a restart.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct ServiceState {
+    /// Last block fed to the contracts.
+    tip_height: u64,
+}
+
+/// Contracts played by the service, shared by the chain observer and the API. Paul's and
+/// Vicky's contracts are fed by distinct observers, as both sides of a contract share its id.
+pub struct Contracts {
+    pub working_dir: PathBuf,
+    /// Seals the secrets of the contracts played, and of the challenges drawn for new ones.
+    pub vault: Vault,
+    pub observers: [ContractObserver; 2],
+}
+
+impl Contracts {
+    /// Restores every active contract of `working_dir`, unsealing their secrets with `vault`.
+    pub fn restore(working_dir: &Path, vault: Vault) -> Result<Contracts, String> {
+        bitvm::storage::open(working_dir)?;
+        let mut prover_observer = ContractObserver::new();
+        for prover in Prover::restore_active(working_dir, &vault)? {
+            prover_observer.register(Box::new(prover))?;
+        }
+        let mut verifier_observer = ContractObserver::new();
+        for verifier in Verifier::restore_active(working_dir, &vault)? {
+            verifier_observer.register(Box::new(verifier))?;
+        }
+        Ok(Contracts {
+            working_dir: working_dir.to_path_buf(),
+            vault,
+            observers: [prover_observer, verifier_observer],
+        })
+    }
+
+    pub fn observer(&self, role: Role) -> &ContractObserver {
+        match role {
+            Role::Prover => &self.observers[0],
+            Role::Verifier => &self.observers[1],
+        }
+    }
+
+    pub fn observer_mut(&mut self, role: Role) -> &mut ContractObserver {
+        match role {
+            Role::Prover => &mut self.observers[0],
+            Role::Verifier => &mut self.observers[1],
+        }
+    }
+}
+
+/// Watches the chain on behalf of every active contract of `working_dir`, until Ctrl-C.
+/// Blocks mined since the service last stopped are replayed first, starting at
+/// `start_height` when given.
+pub fn start_service(
+    config: &Config,
+    start_height: Option<u64>,
+    ctx: &Context,
+) -> Result<(), String> {
+    let working_dir = PathBuf::from(&config.storage.working_dir);
+    let vault = unlock_vault(config)?;
+    let contracts = Contracts::restore(&working_dir, vault.clone())?;
+    info!(
+        ctx.expect_logger(),
+        "Restored {} contracts as prover, {} as verifier",
+        contracts.observer(Role::Prover).contract_ids().len(),
+        contracts.observer(Role::Verifier).contract_ids().len()
+    );
+    let contracts = Arc::new(Mutex::new(contracts));
+
+    let bitcoin_rpc = new_bitcoind_client(config)?;
+    let (observer_commands_tx, observer_events_rx) = start_chain_observer(config, ctx)?;
+    let terminate_tx = observer_commands_tx.clone();
+    ctrlc::set_handler(move || {
+        let _ = terminate_tx.send(ObserverCommand::Terminate);
+    })
+    .map_err(|e| format!("unable to set Ctrl-C handler: {}", e))?;
+    let api_shutdown = match config.api {
+        Some(ref api_config) => {
+            let keys = ParticipantKeys::load_or_generate(
+                &config.keys.prover_seed_path,
+                &config.keys.verifier_seed_path,
+                &vault,
+                config.network.network(),
+            )?;
+            Some(start_api(
+                api_config,
+                &config.network,
+                &config.terms,
+                contracts.clone(),
+                keys,
+                ctx,
+            )?)
+        }
+        None => None,
+    };
+
+    let state_path = working_dir.join(SERVICE_STATE_FILE);
+    let start_height = match (start_height, read_service_state(&state_path)?) {
+        (Some(start_height), _) => Some(start_height),
+        (None, Some(state)) => Some(state.tip_height + 1),
+        (None, None) => None,
+    };
+    let tip_height = match start_height {
+        Some(start_height) => {
+            info!(
+                ctx.expect_logger(),
+                "Catching up from block #{}", start_height
+            );
+            let mut contracts = contracts.lock().map_err(|_| "contracts lock poisoned")?;
+            catch_up(&mut contracts.observers, start_height, &bitcoin_rpc, ctx)?
+        }
+        None => bitcoin_rpc
+            .get_block_count()
+            .map_err(|e| format!("unable to connect to bitcoind: {}", e))?,
+    };
+    storage::write_json(&state_path, &ServiceState { tip_height })?;
+    info!(
+        ctx.expect_logger(),
+        "Watching the chain from block #{}", tip_height
+    );
+
+    let mut follower = ChainFollower::new(tip_height);
+    while let Ok(event) = observer_events_rx.recv() {
+        match event {
+            ObserverEvent::BitcoinChainEvent((chain_event, _)) => {
+                let mut contracts = contracts.lock().map_err(|_| "contracts lock poisoned")?;
+                let process = |chain_event: &BitcoinChainEvent| {
+                    process_chain_event(&mut contracts.observers, chain_event, &bitcoin_rpc, ctx)
+                };
+                if !follower.follow(chain_event, process, ctx) {
+                    continue;
+                }
+                let state = ServiceState {
+                    tip_height: follower.tip_height,
+                };
+                if let Err(e) = storage::write_json(&state_path, &state) {
+                    warn!(ctx.expect_logger(), "Unable to save service state: {}", e);
+                }
+            }
+            ObserverEvent::Error(e) => warn!(ctx.expect_logger(), "Chain observer: {}", e),
+            ObserverEvent::Fatal(e) => return Err(format!("chain observer failed: {}", e)),
+            ObserverEvent::Terminate => break,
+            _ => {}
+        }
+    }
+    if let Some(api_shutdown) = api_shutdown {
+        api_shutdown.notify();
+    }
+    info!(
+        ctx.expect_logger(),
+        "Service stopped at block #{}", follower.tip_height
+    );
+    Ok(())
+}
+
+/// Feeds the chain updates announced by chainhook to the contracts, in order. An update
+/// that cannot be processed is held back, and processed again along with the next one.
+struct ChainFollower {
+    /// Last block fed to the contracts.
+    tip_height: u64,
+    held_back: VecDeque<BitcoinChainEvent>,
+}
+
+impl ChainFollower {
+    fn new(tip_height: u64) -> ChainFollower {
+        ChainFollower {
+            tip_height,
+            held_back: VecDeque::new(),
+        }
+    }
+
+    /// Hands the updates held back, then `chain_event`, to `process`, up to the first one
+    /// failing. Returns whether the tip moved.
+    fn follow<F>(
+        &mut self,
+        mut chain_event: BitcoinChainEvent,
+        mut process: F,
+        ctx: &Context,
+    ) -> bool
+    where
+        F: FnMut(&BitcoinChainEvent) -> Result<(), String>,
+    {
+        // Blocks replayed while catching up may be announced again.
+        if let BitcoinChainEvent::ChainUpdatedWithBlocks(ref mut data) = chain_event {
+            let tip_height = self.tip_height;
+            data.new_blocks
+                .retain(|block| block.block_identifier.index > tip_height);
+        }
+        self.held_back.push_back(chain_event);
+        let tip_height = self.tip_height;
+        while let Some(chain_event) = self.held_back.front() {
+            if let Err(e) = process(chain_event) {
+                ctx.try_log(|logger| {
+                    hiro_system_kit::slog::warn!(
+                        logger,
+                        "Unable to process chain update: {}, retrying with the next block",
+                        e
+                    )
+                });
+                break;
+            }
+            let new_tip = match chain_event {
+                BitcoinChainEvent::ChainUpdatedWithBlocks(data) => &data.new_blocks,
+                BitcoinChainEvent::ChainUpdatedWithReorg(data) => &data.blocks_to_apply,
+            }
+            .iter()
+            .map(|block| block.block_identifier.index)
+            .max();
+            if let Some(new_tip) = new_tip {
+                self.tip_height = new_tip;
+            }
+            self.held_back.pop_front();
+        }
+        self.tip_height != tip_height
+    }
+}
+
+fn read_service_state(path: &Path) -> Result<Option<ServiceState>, String> {
+    match path.exists() {
+        true => storage::read_json(path).map(Some),
+        false => Ok(None),
+    }
+}
+
+#[test]
+fn test_chain_follower_holds_back_failed_updates() {
+    use chainhook_sdk::types::{
+        BitcoinBlockData, BitcoinBlockMetadata, BitcoinChainUpdatedWithBlocksData, BitcoinNetwork,
+        BlockIdentifier,
+    };
+
+    let ctx = Context::empty();
+    let block_identifier = |index: u64| BlockIdentifier {
+        index,
+        hash: format!("0x{:064x}", index),
+    };
+    let chain_event = |heights: &[u64]| {
+        BitcoinChainEvent::ChainUpdatedWithBlocks(BitcoinChainUpdatedWithBlocksData {
+            new_blocks: heights
+                .iter()
+                .map(|height| BitcoinBlockData {
+                    block_identifier: block_identifier(*height),
+                    parent_block_identifier: block_identifier(height - 1),
+                    timestamp: 0,
+                    transactions: vec![],
+                    metadata: BitcoinBlockMetadata {
+                        network: BitcoinNetwork::Regtest,
+                    },
+                })
+                .collect(),
+            confirmed_blocks: vec![],
+        })
+    };
+    let mut processed = vec![];
+    let failing = std::cell::Cell::new(false);
+    let mut process = |chain_event: &BitcoinChainEvent| {
+        if failing.get() {
+            return Err("bitcoind unreachable".to_string());
+        }
+        if let BitcoinChainEvent::ChainUpdatedWithBlocks(data) = chain_event {
+            processed.extend(data.new_blocks.iter().map(|b| b.block_identifier.index));
+        }
+        Ok(())
+    };
+    let mut follower = ChainFollower::new(1);
+
+    // Blocks replayed while catching up are skipped.
+    assert!(!follower.follow(chain_event(&[1]), &mut process, &ctx));
+    // A failing update neither stops the service nor moves the tip...
+    failing.set(true);
+    assert!(!follower.follow(chain_event(&[2]), &mut process, &ctx));
+    assert_eq!(follower.tip_height, 1);
+    // ...and is processed again, before the next one.
+    failing.set(false);
+    assert!(follower.follow(chain_event(&[3]), &mut process, &ctx));
+    assert_eq!(follower.tip_height, 3);
+    assert_eq!(processed, vec![2, 3]);
+}
@@ -0,0 +1,163 @@
+use crate::cli::parse_inputs;
+use crate::config::Config;
+use crate::vault::unlock_vault;
+use bitcoin::secp256k1::Secp256k1;
+use bitvm::keys::ParticipantKeys;
+use bitvm::protocol::{
+    ContractAction, ContractEvent, ContractMachine, ContractParameters, Role, TransactionKind,
+};
+use bitvm::prover::{Prover, ProverSecrets};
+use bitvm::setup::{SetupEnvelope, SetupSession};
+use bitvm::vault::Vault;
+use bitvm::verifier::VerifierSecrets;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Path standing for stdin or stdout.
+pub const STDIO_PATH: &str = "-";
+
+/// Opens a setup session as Vicky, and writes the challenges to send to Paul to `output`.
+pub fn open_setup(config: &Config, circuit_source: &str, output: &str) -> Result<(), String> {
+    let secp = Secp256k1::new();
+    let working_dir = open_storage(config)?;
+    let circuit = bitvm::bristol::parser::read_circuit(circuit_source)?;
+    let vault = unlock_vault(config)?;
+    let (key_source, secret_key) = load_keys(config, &vault)?
+        .verifier
+        .next_contract_key(&secp)?;
+    let mut secrets = VerifierSecrets::generate(&circuit, secret_key);
+    secrets.key_source = Some(key_source);
+    let (session, challenges) =
+        SetupSession::open(&secp, &vault, circuit_source, secrets, config.terms.clone())?;
+    session.save(&working_dir)?;
+    write_message(output, &challenges)?;
+    eprintln!("Opened setup session {}", session.session_id);
+    Ok(())
+}
+
+/// Joins the session opened by the challenges read from `input` as Paul, and writes his
+/// proposal to `output`.
+pub fn join_setup(
+    config: &Config,
+    circuit_source: &str,
+    input: &str,
+    inputs: &str,
+    parameters_path: &str,
+    output: &str,
+) -> Result<(), String> {
+    let secp = Secp256k1::new();
+    let working_dir = open_storage(config)?;
+    let challenges = read_message(input)?;
+    let circuit = bitvm::bristol::parser::read_circuit(circuit_source)?;
+    let inputs = parse_inputs(&circuit, inputs)?;
+    let parameters: ContractParameters = bitvm::storage::read_json(Path::new(parameters_path))?;
+    let vault = unlock_vault(config)?;
+    let (key_source, secret_key) = load_keys(config, &vault)?.prover.next_contract_key(&secp)?;
+    let mut secrets = ProverSecrets::generate(&circuit, secret_key);
+    secrets.key_source = Some(key_source);
+    let (session, prover, proposal) = SetupSession::join(
+        &secp,
+        &working_dir,
+        &vault,
+        &challenges,
+        circuit_source,
+        secrets,
+        inputs,
+        parameters,
+    )?;
+    session.save(&working_dir)?;
+    write_message(output, &proposal)?;
+    eprintln!(
+        "Joined setup session {} as prover of contract {}",
+        session.session_id,
+        prover.contract_id()
+    );
+    Ok(())
+}
+
+/// Feeds the message read from `input` to the session it belongs to. Vicky answers a
+/// proposal with her acceptance, written to `output`; Paul's setup completes on acceptance,
+/// and the funding PSBT is written to `output` for him to sign.
+pub fn receive_setup_message(config: &Config, input: &str, output: &str) -> Result<(), String> {
+    let secp = Secp256k1::new();
+    let working_dir = open_storage(config)?;
+    let message = read_message(input)?;
+    let mut session = SetupSession::restore(&working_dir, &message.session_id)?;
+    match session.role {
+        Role::Verifier => {
+            let (mut verifier, acceptance) =
+                session.receive_proposal(&secp, &working_dir, &unlock_vault(config)?, &message)?;
+            verifier.handle(&ContractEvent::SetupCompleted)?;
+            session.save(&working_dir)?;
+            write_message(output, &acceptance)?;
+            eprintln!("Accepted contract {} as verifier", verifier.contract_id());
+        }
+        Role::Prover => {
+            let contract_id = session
+                .contract_id
+                .ok_or("setup session has no contract".to_string())?;
+            let mut prover = Prover::restore(&working_dir, &unlock_vault(config)?, &contract_id)?;
+            session.receive_acceptance(&secp, &message, &mut prover)?;
+            let actions = prover.handle(&ContractEvent::SetupCompleted)?;
+            session.save(&working_dir)?;
+            if actions
+                .iter()
+                .any(|action| matches!(action, ContractAction::Fund(_)))
+            {
+                let psbt = prover.export_psbt(&TransactionKind::Funding)?;
+                write_output(output, &psbt.serialize_hex())?;
+            }
+            eprintln!(
+                "Setup of contract {} finalized, fund it to start",
+                contract_id
+            );
+        }
+    }
+    Ok(())
+}
+
+fn open_storage(config: &Config) -> Result<PathBuf, String> {
+    let working_dir = PathBuf::from(&config.storage.working_dir);
+    bitvm::storage::open(&working_dir)?;
+    Ok(working_dir)
+}
+
+fn load_keys(config: &Config, vault: &Vault) -> Result<ParticipantKeys, String> {
+    ParticipantKeys::load_or_generate(
+        &config.keys.prover_seed_path,
+        &config.keys.verifier_seed_path,
+        vault,
+        config.network.network(),
+    )
+}
+
+fn read_message(input: &str) -> Result<SetupEnvelope, String> {
+    let mut json = String::new();
+    match input {
+        STDIO_PATH => {
+            std::io::stdin()
+                .read_to_string(&mut json)
+                .map_err(|e| format!("unable to read stdin: {}", e))?;
+        }
+        path => {
+            json = fs::read_to_string(path)
+                .map_err(|e| format!("unable to read message {}: {}", path, e))?;
+        }
+    }
+    SetupEnvelope::from_json(&json)
+}
+
+fn write_message(output: &str, message: &SetupEnvelope) -> Result<(), String> {
+    write_output(output, &message.to_json()?)
+}
+
+fn write_output(output: &str, content: &str) -> Result<(), String> {
+    match output {
+        STDIO_PATH => {
+            let mut stdout = std::io::stdout();
+            writeln!(stdout, "{}", content).map_err(|e| format!("unable to write stdout: {}", e))
+        }
+        path => fs::write(path, content).map_err(|e| format!("unable to write {}: {}", path, e)),
+    }
+}
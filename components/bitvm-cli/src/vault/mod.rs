@@ -0,0 +1,45 @@
+use crate::config::Config;
+use bitvm::vault::{KdfParams, Vault};
+use std::path::PathBuf;
+use zeroize::Zeroizing;
+
+/// Environment variable holding the passphrase, for running unattended.
+pub const PASSPHRASE_ENV: &str = "BITVM_PASSPHRASE";
+
+/// Unlocks the vault sealing the prover's secrets and the key seeds, asking for its passphrase
+/// on the terminal unless `BITVM_PASSPHRASE` is set. The vault is created on first use.
+pub fn unlock_vault(config: &Config) -> Result<Vault, String> {
+    let working_dir = PathBuf::from(&config.storage.working_dir);
+    let from_env = std::env::var(PASSPHRASE_ENV).ok().map(Zeroizing::new);
+    if Vault::exists(&working_dir) {
+        let passphrase = match from_env {
+            Some(passphrase) => passphrase,
+            None => read_passphrase("Vault passphrase: ")?,
+        };
+        return Vault::unlock(&working_dir, &passphrase);
+    }
+    let passphrase = match from_env {
+        Some(passphrase) => passphrase,
+        None => {
+            eprintln!(
+                "Creating a vault in {} to encrypt secrets",
+                working_dir.display()
+            );
+            let passphrase = read_passphrase("New vault passphrase: ")?;
+            if *read_passphrase("Confirm passphrase: ")? != *passphrase {
+                return Err("passphrases do not match".to_string());
+            }
+            passphrase
+        }
+    };
+    if passphrase.is_empty() {
+        return Err("vault passphrase cannot be empty".to_string());
+    }
+    Vault::create(&working_dir, &passphrase, KdfParams::default())
+}
+
+fn read_passphrase(prompt: &str) -> Result<Zeroizing<String>, String> {
+    rpassword::prompt_password(prompt)
+        .map(Zeroizing::new)
+        .map_err(|e| format!("unable to read passphrase: {}", e))
+}
@@ -0,0 +1,547 @@
+use std::collections::BTreeMap;
+
+use bitvm_types::{BitCommitmentPreimages, Circuit, CommitmentSet, ExecutionTrace, GateId, WireId};
+use sha2::{Digest, Sha256};
+
+/// Number of challenge-response rounds needed to isolate a single step in a trace of
+/// `trace_len` gates. The trace is padded to the next power of two, so that every dispute
+/// takes the same number of rounds and the transaction graph can be pre-signed.
+pub fn compute_bisection_rounds(trace_len: usize) -> usize {
+    let mut rounds = 0;
+    while (1 << rounds) < trace_len {
+        rounds += 1;
+    }
+    rounds
+}
+
+/// Midpoint challenged in round `verdicts.len()` of a bisection over `rounds` rounds, once
+/// Vicky gave `verdicts` on the previous rounds.
+pub fn compute_midpoint(rounds: usize, verdicts: &[bool]) -> usize {
+    let (mut lo, mut hi) = (0, 1 << rounds);
+    for agreed in verdicts.iter() {
+        let midpoint = (lo + hi) / 2;
+        match agreed {
+            true => lo = midpoint,
+            false => hi = midpoint,
+        }
+    }
+    (lo + hi) / 2
+}
+
+/// Wires holding the state of an evaluation between two steps: the values computed so far
+/// that a later step, or the outputs, still read. Paul opens them with his wire commitments,
+/// so the inputs and output of the disputed gate are bound to the states on either side of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateWires {
+    /// First and last state each wire is part of.
+    lifetimes: BTreeMap<WireId, (usize, usize)>,
+}
+
+impl StateWires {
+    pub fn new(circuit: &Circuit, order: &[GateId]) -> StateWires {
+        let mut lifetimes = BTreeMap::new();
+        for wire_id in circuit.collect_input_wires_ids() {
+            lifetimes.insert(*wire_id, (0, 0));
+        }
+        for (position, gate_id) in order.iter().enumerate() {
+            lifetimes.insert(*gate_id, (position + 1, position + 1));
+            let Some(gate) = circuit.gates.get(gate_id) else {
+                continue;
+            };
+            for input in gate.inputs() {
+                if let Some(lifetime) = lifetimes.get_mut(&input) {
+                    lifetime.1 = position;
+                }
+            }
+        }
+        for wire_id in circuit.collect_output_wires_ids() {
+            if let Some(lifetime) = lifetimes.get_mut(&wire_id) {
+                lifetime.1 = usize::MAX;
+            }
+        }
+        StateWires { lifetimes }
+    }
+
+    /// Wires of the state before step `position`, by increasing id. States past the end of
+    /// the trace hold the outputs.
+    pub fn at(&self, position: usize) -> Vec<WireId> {
+        self.lifetimes
+            .iter()
+            .filter(|(_, (first, last))| *first <= position && position <= *last)
+            .map(|(wire_id, _)| *wire_id)
+            .collect()
+    }
+}
+
+/// Shared view of a bisection: Paul and Vicky agree on state `lo` and disagree on state `hi`.
+/// Each round halves the interval, until a single step remains.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bisection {
+    pub rounds: usize,
+    pub lo: usize,
+    pub hi: usize,
+    pub transcript: Vec<BisectionRound>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BisectionRound {
+    pub midpoint: usize,
+    /// Values Paul opened for the state wires at `midpoint`.
+    pub state: BTreeMap<WireId, bool>,
+    pub agreed: Option<bool>,
+}
+
+impl Bisection {
+    pub fn new(trace_len: usize) -> Result<Bisection, String> {
+        if trace_len == 0 {
+            return Err("unable to bisect an empty trace".to_string());
+        }
+        let rounds = compute_bisection_rounds(trace_len);
+        Ok(Bisection {
+            rounds,
+            lo: 0,
+            hi: 1 << rounds,
+            transcript: vec![],
+        })
+    }
+
+    pub fn current_round(&self) -> usize {
+        self.transcript.len()
+    }
+
+    /// Verdicts given so far, selecting the leaf of the next response.
+    pub fn verdicts(&self) -> Vec<bool> {
+        self.transcript
+            .iter()
+            .filter_map(|round| round.agreed)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.hi - self.lo == 1
+    }
+
+    pub fn midpoint(&self) -> Option<usize> {
+        match self.is_complete() {
+            true => None,
+            false => Some((self.lo + self.hi) / 2),
+        }
+    }
+
+    /// Records Paul's answer for the current midpoint.
+    pub fn record_response(&mut self, state: BTreeMap<WireId, bool>) -> Result<(), String> {
+        let midpoint = self
+            .midpoint()
+            .ok_or("bisection already complete".to_string())?;
+        if let Some(round) = self.transcript.last() {
+            if round.agreed.is_none() {
+                return Err("previous response not judged yet".to_string());
+            }
+        }
+        self.transcript.push(BisectionRound {
+            midpoint,
+            state,
+            agreed: None,
+        });
+        Ok(())
+    }
+
+    /// Records Vicky's verdict on the last response, and narrows the interval accordingly.
+    pub fn record_verdict(&mut self, agreed: bool) -> Result<(), String> {
+        let round = self
+            .transcript
+            .last_mut()
+            .ok_or("no response to judge".to_string())?;
+        if round.agreed.is_some() {
+            return Err("last response already judged".to_string());
+        }
+        round.agreed = Some(agreed);
+        match agreed {
+            true => self.lo = round.midpoint,
+            false => self.hi = round.midpoint,
+        }
+        Ok(())
+    }
+
+    /// Position of the step under dispute, once the bisection is complete.
+    pub fn disputed_step(&self) -> Option<usize> {
+        match self.is_complete() {
+            true => Some(self.lo),
+            false => None,
+        }
+    }
+}
+
+/// Message sent by Vicky in the challenge transaction of each round.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Challenge {
+    /// Verdict on the previous response (none in the first round), selecting the next midpoint.
+    Bisect {
+        round: usize,
+        verdict: Option<bool>,
+        midpoint: usize,
+    },
+    /// Final verdict, selecting the single gate Paul must open. `gate_id` is none when the
+    /// disputed step falls in the padding past the end of the trace: nothing left to challenge.
+    Gate {
+        verdict: Option<bool>,
+        position: usize,
+        gate_id: Option<GateId>,
+    },
+}
+
+/// Message sent by Paul in the response transaction of each round.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub round: usize,
+    /// Preimages opening the state wires at the midpoint, by increasing wire id.
+    pub preimages: Vec<[u8; 32]>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum BisectionOutcome {
+    /// The step at `position` is the first one Paul and Vicky disagree on.
+    FaultyGate { position: usize, gate_id: GateId },
+    /// Paul's trace matched Vicky's on every state she was shown.
+    NoFaultFound,
+}
+
+/// Paul's side: answers each challenge by opening the state wires of his trace at the midpoint.
+pub struct BisectionProver {
+    pub bisection: Bisection,
+    state_wires: StateWires,
+    values: BTreeMap<WireId, bool>,
+    wire_preimages: BTreeMap<WireId, BitCommitmentPreimages>,
+}
+
+impl BisectionProver {
+    pub fn new(
+        circuit: &Circuit,
+        trace: &ExecutionTrace,
+        wire_preimages: BTreeMap<WireId, BitCommitmentPreimages>,
+    ) -> Result<BisectionProver, String> {
+        Ok(BisectionProver {
+            bisection: Bisection::new(trace.len())?,
+            state_wires: StateWires::new(circuit, &trace.order),
+            values: trace.values.clone(),
+            wire_preimages,
+        })
+    }
+
+    /// Applies the verdict carried by `challenge`, and answers it if it is a bisection step.
+    pub fn respond(&mut self, challenge: &Challenge) -> Result<Option<Response>, String> {
+        let (verdict, expected_midpoint) = match challenge {
+            Challenge::Bisect {
+                verdict, midpoint, ..
+            } => (verdict, Some(*midpoint)),
+            Challenge::Gate { verdict, .. } => (verdict, None),
+        };
+        if let Some(agreed) = verdict {
+            self.bisection.record_verdict(*agreed)?;
+        }
+        if self.bisection.midpoint() != expected_midpoint {
+            return Err("challenge inconsistent with the bisection transcript".to_string());
+        }
+        let Some(midpoint) = expected_midpoint else {
+            return Ok(None);
+        };
+
+        let round = self.bisection.current_round();
+        let mut state = BTreeMap::new();
+        let mut preimages = vec![];
+        for wire_id in self.state_wires.at(midpoint) {
+            let value = *self
+                .values
+                .get(&wire_id)
+                .ok_or(format!("no value for wire {}", wire_id))?;
+            let wire_preimages = self
+                .wire_preimages
+                .get(&wire_id)
+                .ok_or(format!("no preimages for wire {}", wire_id))?;
+            state.insert(wire_id, value);
+            preimages.push(*wire_preimages.preimage_for_bit(value));
+        }
+        self.bisection.record_response(state)?;
+        Ok(Some(Response { round, preimages }))
+    }
+}
+
+/// Vicky's side: compares the states Paul opens against her own evaluation of the circuit.
+pub struct BisectionVerifier {
+    pub bisection: Bisection,
+    order: Vec<GateId>,
+    state_wires: StateWires,
+    values: BTreeMap<WireId, bool>,
+    commitment_set: CommitmentSet,
+    /// Every value Paul opened so far, starting with his commitment.
+    opened: BTreeMap<WireId, bool>,
+    /// Whether the outputs Paul committed to differ from Vicky's: the state past the last
+    /// step, never opened during the bisection.
+    outputs_disputed: bool,
+    pending_verdict: Option<bool>,
+}
+
+impl BisectionVerifier {
+    /// Bisects `trace`, Vicky's evaluation of the circuit on the inputs Paul committed to along
+    /// with the values in `committed`.
+    pub fn new(
+        circuit: &Circuit,
+        trace: &ExecutionTrace,
+        commitment_set: &CommitmentSet,
+        committed: &BTreeMap<WireId, bool>,
+    ) -> Result<BisectionVerifier, String> {
+        let outputs_disputed = circuit
+            .collect_output_wires_ids()
+            .iter()
+            .any(|wire_id| committed.get(wire_id) != trace.values.get(wire_id));
+        Ok(BisectionVerifier {
+            bisection: Bisection::new(trace.len())?,
+            order: trace.order.clone(),
+            state_wires: StateWires::new(circuit, &trace.order),
+            values: trace.values.clone(),
+            commitment_set: commitment_set.clone(),
+            opened: committed.clone(),
+            outputs_disputed,
+            pending_verdict: None,
+        })
+    }
+
+    pub fn next_challenge(&mut self) -> Result<Challenge, String> {
+        let verdict = self.pending_verdict.take();
+        if let Some(agreed) = verdict {
+            self.bisection.record_verdict(agreed)?;
+        }
+        match self.bisection.midpoint() {
+            Some(midpoint) => Ok(Challenge::Bisect {
+                round: self.bisection.current_round(),
+                verdict,
+                midpoint,
+            }),
+            None => {
+                let position = self.bisection.lo;
+                Ok(Challenge::Gate {
+                    verdict,
+                    position,
+                    gate_id: self.order.get(position).cloned(),
+                })
+            }
+        }
+    }
+
+    /// Decodes the state Paul opened, and decides whether the next challenge agrees with it.
+    /// Vicky agrees only with her own values, opened consistently with everything Paul
+    /// opened before: a state contradicting an earlier opening is disputed.
+    pub fn receive_response(&mut self, response: &Response) -> Result<bool, String> {
+        if response.round != self.bisection.current_round() {
+            return Err(format!("unexpected response for round {}", response.round));
+        }
+        let midpoint = self
+            .bisection
+            .midpoint()
+            .ok_or("bisection already complete".to_string())?;
+        let wires = self.state_wires.at(midpoint);
+        if response.preimages.len() != wires.len() {
+            return Err(format!(
+                "expected {} preimages, got {}",
+                wires.len(),
+                response.preimages.len()
+            ));
+        }
+        let mut state = BTreeMap::new();
+        let mut agreed = true;
+        for (wire_id, preimage) in wires.iter().zip(response.preimages.iter()) {
+            let hashes = self
+                .commitment_set
+                .get(wire_id)
+                .ok_or(format!("wire {} missing from the commitment set", wire_id))?;
+            let hash: [u8; 32] = Sha256::digest(preimage).into();
+            let value = match hash {
+                hash if hash == hashes.0 => false,
+                hash if hash == hashes.1 => true,
+                _ => {
+                    return Err(format!(
+                        "preimage for wire {} does not match its commitment",
+                        wire_id
+                    ))
+                }
+            };
+            agreed &= self.values.get(wire_id) == Some(&value);
+            agreed &= *self.opened.entry(*wire_id).or_insert(value) == value;
+            state.insert(*wire_id, value);
+        }
+        self.bisection.record_response(state)?;
+        self.pending_verdict = Some(agreed);
+        Ok(agreed)
+    }
+
+    /// The step disputed once the bisection is complete. When Vicky agreed with every state,
+    /// `hi` is still the padded end of the trace: the last step is only at fault if the
+    /// outputs Paul committed to differ from hers.
+    pub fn outcome(&self) -> Option<BisectionOutcome> {
+        let position = self.bisection.disputed_step()?;
+        if self.bisection.hi == 1 << self.bisection.rounds && !self.outputs_disputed {
+            return Some(BisectionOutcome::NoFaultFound);
+        }
+        match self.order.get(position) {
+            Some(gate_id) => Some(BisectionOutcome::FaultyGate {
+                position,
+                gate_id: *gate_id,
+            }),
+            None => Some(BisectionOutcome::NoFaultFound),
+        }
+    }
+}
+
+/// Plays a full bisection between `prover` and `verifier`, off-chain.
+pub fn run_bisection(
+    prover: &mut BisectionProver,
+    verifier: &mut BisectionVerifier,
+) -> Result<BisectionOutcome, String> {
+    loop {
+        let challenge = verifier.next_challenge()?;
+        match prover.respond(&challenge)? {
+            Some(response) => {
+                verifier.receive_response(&response)?;
+            }
+            None => break,
+        }
+    }
+    verifier
+        .outcome()
+        .ok_or("bisection ended before isolating a step".to_string())
+}
+
+#[cfg(test)]
+fn setup_bisection(
+    circuit: &Circuit,
+    trace: &ExecutionTrace,
+    honest_trace: &ExecutionTrace,
+) -> (BisectionProver, BisectionVerifier) {
+    let wire_preimages = circuit
+        .gates_bit_commitments_preimages
+        .iter()
+        .map(|(wire_id, preimages)| (*wire_id, preimages.clone()))
+        .collect();
+    let committed = crate::protocol::collect_committed_wires_ids(circuit)
+        .into_iter()
+        .map(|wire_id| (wire_id, trace.values[&wire_id]))
+        .collect();
+    (
+        BisectionProver::new(circuit, trace, wire_preimages).unwrap(),
+        BisectionVerifier::new(
+            circuit,
+            honest_trace,
+            &circuit.compute_commitment_set(),
+            &committed,
+        )
+        .unwrap(),
+    )
+}
+
+#[test]
+fn test_state_wires_hold_what_later_steps_read() {
+    let circuit = crate::bristol::parser::read_circuit(include_str!(
+        "../bristol/fixtures/test_vector_1.bristol"
+    ))
+    .unwrap();
+    let order = circuit.topological_order().unwrap();
+    let state_wires = StateWires::new(&circuit, &order);
+    let inputs = circuit
+        .collect_input_wires_ids()
+        .into_iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    assert_eq!(state_wires.at(0), inputs);
+    assert_eq!(
+        state_wires.at(order.len()),
+        circuit.collect_output_wires_ids()
+    );
+    assert_eq!(state_wires.at(1 << 10), circuit.collect_output_wires_ids());
+    for (position, gate_id) in order.iter().enumerate() {
+        // A gate reads its inputs from the state before it, and writes to the state after it.
+        let before = state_wires.at(position);
+        for input in circuit.gates[gate_id].inputs() {
+            assert!(before.contains(&input));
+        }
+        assert!(state_wires.at(position + 1).contains(gate_id));
+    }
+}
+
+#[test]
+fn test_bisection_isolates_flipped_gate() {
+    for source in [
+        include_str!("../bristol/fixtures/test_vector_1.bristol"),
+        include_str!("../bristol/fixtures/test_vector_2.bristol"),
+    ] {
+        let circuit = crate::bristol::parser::read_circuit(source).unwrap();
+        let inputs = circuit
+            .collect_input_wires_ids()
+            .into_iter()
+            .map(|wire_id| (*wire_id, wire_id % 3 == 0))
+            .collect::<BTreeMap<_, _>>();
+        let honest_trace = circuit.evaluate(&inputs).unwrap();
+        let outputs = circuit.collect_output_wires_ids();
+
+        for (position, faulty_gate) in honest_trace.order.iter().enumerate() {
+            let trace = circuit
+                .evaluate_with(&inputs, |gate_id, value| value ^ (gate_id == *faulty_gate))
+                .unwrap();
+            let (mut prover, mut verifier) = setup_bisection(&circuit, &trace, &honest_trace);
+            let outcome = run_bisection(&mut prover, &mut verifier).unwrap();
+            let faulty = BisectionOutcome::FaultyGate {
+                position,
+                gate_id: *faulty_gate,
+            };
+            assert_eq!(
+                verifier.bisection.transcript.len(),
+                verifier.bisection.rounds
+            );
+            assert_eq!(prover.bisection, verifier.bisection);
+            if outputs
+                .iter()
+                .any(|o| trace.values[o] != honest_trace.values[o])
+            {
+                assert_eq!(outcome, faulty);
+            } else {
+                // A flip masked before the outputs may go unnoticed, but never blames another gate.
+                assert!(outcome == faulty || outcome == BisectionOutcome::NoFaultFound);
+            }
+        }
+
+        let (mut prover, mut verifier) = setup_bisection(&circuit, &honest_trace, &honest_trace);
+        assert_eq!(
+            run_bisection(&mut prover, &mut verifier),
+            Ok(BisectionOutcome::NoFaultFound)
+        );
+    }
+}
+
+#[test]
+fn test_bisection_over_power_of_two_trace() {
+    // Four gates: the bisection never opens the state past the last one.
+    let source = "2 1 2 0 1 AND\n2 1 3 0 2 XOR\n1 1 4 3 INV\n2 1 5 4 1 AND\n";
+    let circuit = crate::bristol::parser::read_circuit(source).unwrap();
+    let inputs = BTreeMap::from([(0, true), (1, true)]);
+    let honest_trace = circuit.evaluate(&inputs).unwrap();
+    assert_eq!(honest_trace.len(), 4);
+
+    let (mut prover, mut verifier) = setup_bisection(&circuit, &honest_trace, &honest_trace);
+    assert_eq!(
+        run_bisection(&mut prover, &mut verifier),
+        Ok(BisectionOutcome::NoFaultFound)
+    );
+
+    for (position, faulty_gate) in honest_trace.order.iter().enumerate() {
+        let trace = circuit
+            .evaluate_with(&inputs, |gate_id, value| value ^ (gate_id == *faulty_gate))
+            .unwrap();
+        let (mut prover, mut verifier) = setup_bisection(&circuit, &trace, &honest_trace);
+        assert_eq!(
+            run_bisection(&mut prover, &mut verifier),
+            Ok(BisectionOutcome::FaultyGate {
+                position,
+                gate_id: *faulty_gate,
+            })
+        );
+    }
+}
@@ -1,5 +1,5 @@
 pub fn create_template() -> String {
-    format!(
+    String::from(
         r#"# Bristol format for NAND gate
 # Number of gates: 1
 # Number of inputs: 2
@@ -7,7 +7,7 @@ use pest_derive::Parser;
 struct BristolParser;
 
 pub fn read_circuit(circuit_source: &str) -> Result<Circuit, String> {
-    let file = match BristolParser::parse(Rule::file, &circuit_source) {
+    let file = match BristolParser::parse(Rule::file, circuit_source) {
         Ok(ref mut r) => r.next().unwrap(),
         Err(e) => {
             println!("{e}");
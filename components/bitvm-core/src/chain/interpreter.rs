@@ -0,0 +1,272 @@
+use bitcoin::opcodes::all::*;
+use bitcoin::script::Instruction;
+use bitcoin::secp256k1::{self, Message, Secp256k1, XOnlyPublicKey};
+use bitcoin::sighash::{Prevouts, SighashCache};
+use bitcoin::taproot::{ControlBlock, LeafVersion, TapLeafHash, TAPROOT_ANNEX_PREFIX};
+use bitcoin::{Script, Sequence, Transaction, TxOut};
+use sha2::{Digest, Sha256};
+
+const MAX_STACK_ELEMENT_SIZE: usize = 520;
+
+/// Checks that input `input_index` of `tx` satisfies the taproot output it spends, through
+/// either its key path or one of its script leaves. `prevouts` are the outputs spent by
+/// every input of `tx`, in order.
+///
+/// Only the opcodes BitVM scripts are made of are interpreted; any other opcode fails the
+/// spend.
+pub fn verify_input(
+    secp: &Secp256k1<secp256k1::All>,
+    tx: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+) -> Result<(), String> {
+    let input = tx
+        .input
+        .get(input_index)
+        .ok_or(format!("no input {}", input_index))?;
+    let prevout = prevouts
+        .get(input_index)
+        .ok_or(format!("no prevout for input {}", input_index))?;
+    if !prevout.script_pubkey.is_v1_p2tr() {
+        return Err("only taproot outputs can be spent".to_string());
+    }
+    let output_key = XOnlyPublicKey::from_slice(&prevout.script_pubkey.as_bytes()[2..])
+        .map_err(|e| format!("invalid output key: {}", e))?;
+
+    let mut elements = input.witness.to_vec();
+    let has_annex = elements
+        .last()
+        .is_some_and(|annex| annex.first() == Some(&TAPROOT_ANNEX_PREFIX));
+    if elements.len() >= 2 && has_annex {
+        return Err("annexes are not supported".to_string());
+    }
+    let prevouts = Prevouts::All(prevouts);
+
+    if elements.len() == 1 {
+        let signature = bitcoin::taproot::Signature::from_slice(&elements[0])
+            .map_err(|e| format!("invalid key path signature: {}", e))?;
+        let sighash = SighashCache::new(tx)
+            .taproot_key_spend_signature_hash(input_index, &prevouts, signature.hash_ty)
+            .map_err(|e| format!("unable to compute sighash: {}", e))?;
+        return secp
+            .verify_schnorr(&signature.sig, &Message::from(sighash), &output_key)
+            .map_err(|_| "invalid key path signature".to_string());
+    }
+
+    let control_block = elements.pop().ok_or("empty witness".to_string())?;
+    let script = elements.pop().ok_or("missing leaf script".to_string())?;
+    let control_block = ControlBlock::decode(&control_block)
+        .map_err(|e| format!("invalid control block: {}", e))?;
+    let script = Script::from_bytes(&script);
+    if control_block.leaf_version != LeafVersion::TapScript {
+        return Err("unsupported leaf version".to_string());
+    }
+    if !control_block.verify_taproot_commitment(secp, output_key, script) {
+        return Err("leaf script not committed to by the output".to_string());
+    }
+
+    let leaf_hash = TapLeafHash::from_script(script, LeafVersion::TapScript);
+    let check_signature = |signature: &[u8], public_key: &XOnlyPublicKey| {
+        let signature = bitcoin::taproot::Signature::from_slice(signature)
+            .map_err(|e| format!("invalid signature: {}", e))?;
+        let sighash = SighashCache::new(tx)
+            .taproot_script_spend_signature_hash(
+                input_index,
+                &prevouts,
+                leaf_hash,
+                signature.hash_ty,
+            )
+            .map_err(|e| format!("unable to compute sighash: {}", e))?;
+        Ok(secp
+            .verify_schnorr(&signature.sig, &Message::from(sighash), public_key)
+            .is_ok())
+    };
+    execute_tapscript(
+        script,
+        elements,
+        tx.version,
+        input.sequence,
+        check_signature,
+    )
+}
+
+/// Runs `script` on `stack`, which must end up holding a single true element.
+fn execute_tapscript<F>(
+    script: &Script,
+    mut stack: Vec<Vec<u8>>,
+    tx_version: i32,
+    sequence: Sequence,
+    check_signature: F,
+) -> Result<(), String>
+where
+    F: Fn(&[u8], &XOnlyPublicKey) -> Result<bool, String>,
+{
+    for element in stack.iter() {
+        if element.len() > MAX_STACK_ELEMENT_SIZE {
+            return Err("witness element too large".to_string());
+        }
+    }
+    for instruction in script.instructions() {
+        let instruction = instruction.map_err(|e| format!("invalid script: {}", e))?;
+        let opcode = match instruction {
+            Instruction::PushBytes(bytes) => {
+                stack.push(bytes.as_bytes().to_vec());
+                continue;
+            }
+            Instruction::Op(opcode) => opcode,
+        };
+        match opcode {
+            OP_PUSHNUM_NEG1 => stack.push(encode_script_num(-1)),
+            _ if (OP_PUSHNUM_1.to_u8()..=OP_PUSHNUM_16.to_u8()).contains(&opcode.to_u8()) => {
+                let value = (opcode.to_u8() - OP_PUSHNUM_1.to_u8() + 1) as i64;
+                stack.push(encode_script_num(value));
+            }
+            OP_DROP => {
+                pop(&mut stack)?;
+            }
+            OP_DUP => {
+                let top = stack.last().ok_or("stack underflow".to_string())?.clone();
+                stack.push(top);
+            }
+            OP_SWAP => {
+                let len = stack.len();
+                if len < 2 {
+                    return Err("stack underflow".to_string());
+                }
+                stack.swap(len - 1, len - 2);
+            }
+            OP_SHA256 => {
+                let top = pop(&mut stack)?;
+                stack.push(Sha256::digest(top).to_vec());
+            }
+            OP_EQUAL | OP_EQUALVERIFY => {
+                let a = pop(&mut stack)?;
+                let b = pop(&mut stack)?;
+                stack.push(encode_bool(a == b));
+                if opcode == OP_EQUALVERIFY {
+                    verify(&mut stack, "OP_EQUALVERIFY")?;
+                }
+            }
+            OP_BOOLOR => {
+                let a = decode_script_num(&pop(&mut stack)?, 4)?;
+                let b = decode_script_num(&pop(&mut stack)?, 4)?;
+                stack.push(encode_bool(a != 0 || b != 0));
+            }
+            OP_VERIFY => verify(&mut stack, "OP_VERIFY")?,
+            OP_CHECKSIG | OP_CHECKSIGVERIFY => {
+                let public_key = pop(&mut stack)?;
+                let signature = pop(&mut stack)?;
+                let valid = match (public_key.len(), signature.is_empty()) {
+                    (0, _) => return Err("empty public key".to_string()),
+                    (_, true) => false,
+                    (32, false) => {
+                        let public_key = XOnlyPublicKey::from_slice(&public_key)
+                            .map_err(|e| format!("invalid public key: {}", e))?;
+                        if !check_signature(&signature, &public_key)? {
+                            return Err("invalid signature".to_string());
+                        }
+                        true
+                    }
+                    // Unknown public key types are reserved for soft forks.
+                    (_, false) => true,
+                };
+                stack.push(encode_bool(valid));
+                if opcode == OP_CHECKSIGVERIFY {
+                    verify(&mut stack, "OP_CHECKSIGVERIFY")?;
+                }
+            }
+            OP_CSV => {
+                let top = stack.last().ok_or("stack underflow".to_string())?;
+                check_sequence(decode_script_num(top, 5)?, tx_version, sequence)?;
+            }
+            _ => return Err(format!("unsupported opcode {}", opcode)),
+        }
+    }
+    match stack.len() {
+        1 if cast_to_bool(&stack[0]) => Ok(()),
+        1 => Err("script evaluated to false".to_string()),
+        _ => Err("script did not leave a single element on the stack".to_string()),
+    }
+}
+
+/// BIP112: the input's relative timelock must be at least the one required by the script.
+fn check_sequence(required: i64, tx_version: i32, sequence: Sequence) -> Result<(), String> {
+    const DISABLE_FLAG: u32 = 1 << 31;
+    const TYPE_FLAG: u32 = 1 << 22;
+    const VALUE_MASK: u32 = 0xffff;
+
+    if required < 0 {
+        return Err("negative relative timelock".to_string());
+    }
+    let required = required as u32;
+    if required & DISABLE_FLAG != 0 {
+        return Ok(());
+    }
+    let sequence = sequence.to_consensus_u32();
+    if tx_version < 2 || sequence & DISABLE_FLAG != 0 {
+        return Err("relative timelock not enforced by the input".to_string());
+    }
+    if required & TYPE_FLAG != sequence & TYPE_FLAG || required & VALUE_MASK > sequence & VALUE_MASK
+    {
+        return Err("relative timelock not satisfied".to_string());
+    }
+    Ok(())
+}
+
+fn pop(stack: &mut Vec<Vec<u8>>) -> Result<Vec<u8>, String> {
+    stack.pop().ok_or("stack underflow".to_string())
+}
+
+fn verify(stack: &mut Vec<Vec<u8>>, opcode: &str) -> Result<(), String> {
+    match cast_to_bool(&pop(stack)?) {
+        true => Ok(()),
+        false => Err(format!("{} failed", opcode)),
+    }
+}
+
+fn cast_to_bool(element: &[u8]) -> bool {
+    match element.split_last() {
+        None => false,
+        Some((last, rest)) => rest.iter().any(|byte| *byte != 0) || (*last & 0x7f) != 0,
+    }
+}
+
+fn encode_bool(value: bool) -> Vec<u8> {
+    match value {
+        true => vec![1],
+        false => vec![],
+    }
+}
+
+fn encode_script_num(value: i64) -> Vec<u8> {
+    let mut bytes = vec![];
+    let mut magnitude = value.unsigned_abs();
+    while magnitude > 0 {
+        bytes.push((magnitude & 0xff) as u8);
+        magnitude >>= 8;
+    }
+    match bytes.last() {
+        Some(last) if last & 0x80 != 0 => bytes.push(if value < 0 { 0x80 } else { 0 }),
+        Some(_) if value < 0 => *bytes.last_mut().unwrap() |= 0x80,
+        _ => {}
+    }
+    bytes
+}
+
+fn decode_script_num(bytes: &[u8], max_len: usize) -> Result<i64, String> {
+    if bytes.len() > max_len {
+        return Err("script number overflow".to_string());
+    }
+    let Some((last, _)) = bytes.split_last() else {
+        return Ok(0);
+    };
+    let mut value = 0i64;
+    for (i, byte) in bytes.iter().enumerate() {
+        value |= (*byte as i64) << (8 * i);
+    }
+    if last & 0x80 != 0 {
+        value &= !(0x80i64 << (8 * (bytes.len() - 1)));
+        value = -value;
+    }
+    Ok(value)
+}
@@ -0,0 +1,446 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use bitcoin::absolute::LockTime;
+use bitcoin::block::{Block, Header, Version};
+use bitcoin::hash_types::TxMerkleNode;
+use bitcoin::hashes::Hash;
+use bitcoin::pow::CompactTarget;
+use bitcoin::relative;
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::{Amount, BlockHash, OutPoint, Transaction, TxOut, Txid};
+
+use super::interpreter::verify_input;
+use super::{ChainBlock, ChainEvent};
+
+/// Outputs spent by a block, kept to undo it on reorg.
+type SpentOutputs = Vec<(OutPoint, (TxOut, u32))>;
+
+/// In-process stand-in for bitcoind: validates transactions, including their taproot
+/// witnesses and relative timelocks, mines them into blocks on demand, and notifies
+/// subscribers of every chain update. Like miners, blocks leave out transactions paying
+/// less than the minimum fee rate, unless a child pays for them.
+pub struct MockChain {
+    secp: Secp256k1<secp256k1::All>,
+    utxos: HashMap<OutPoint, (TxOut, u32)>,
+    mempool: Vec<Transaction>,
+    fees: HashMap<Txid, Amount>,
+    min_fee_rate: u64,
+    blocks: Vec<(ChainBlock, SpentOutputs)>,
+    subscribers: Vec<Sender<ChainEvent>>,
+    minted: u32,
+    nonce: u32,
+}
+
+impl Default for MockChain {
+    fn default() -> Self {
+        MockChain::new()
+    }
+}
+
+impl MockChain {
+    pub fn new() -> MockChain {
+        MockChain {
+            secp: Secp256k1::new(),
+            utxos: HashMap::new(),
+            mempool: vec![],
+            fees: HashMap::new(),
+            min_fee_rate: 0,
+            blocks: vec![],
+            subscribers: vec![],
+            minted: 0,
+            nonce: 0,
+        }
+    }
+
+    /// Height of the tip. The chain starts at height 0, with no block.
+    pub fn height(&self) -> u32 {
+        self.blocks.len() as u32
+    }
+
+    pub fn tip_hash(&self) -> BlockHash {
+        self.blocks
+            .last()
+            .map_or(BlockHash::all_zeros(), |(block, _)| block.hash)
+    }
+
+    /// Chain updates, from the next block on.
+    pub fn subscribe(&mut self) -> Receiver<ChainEvent> {
+        let (sender, receiver) = channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Creates a confirmed output out of thin air, standing in for a wallet UTXO.
+    pub fn mint(&mut self, txout: TxOut) -> OutPoint {
+        self.minted += 1;
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::from_consensus(self.minted),
+            input: vec![],
+            output: vec![txout.clone()],
+        };
+        let outpoint = OutPoint::new(tx.txid(), 0);
+        self.utxos.insert(outpoint, (txout, self.height()));
+        outpoint
+    }
+
+    pub fn get_txout(&self, outpoint: &OutPoint) -> Option<&TxOut> {
+        self.utxos.get(outpoint).map(|(txout, _)| txout)
+    }
+
+    pub fn confirmation_height(&self, outpoint: &OutPoint) -> Option<u32> {
+        self.utxos.get(outpoint).map(|(_, height)| *height)
+    }
+
+    pub fn is_unspent(&self, outpoint: &OutPoint) -> bool {
+        self.utxos.contains_key(outpoint)
+    }
+
+    pub fn mempool(&self) -> &[Transaction] {
+        &self.mempool
+    }
+
+    /// Fee paid by a transaction of the mempool.
+    pub fn mempool_fee(&self, txid: &Txid) -> Option<Amount> {
+        self.fees.get(txid).cloned()
+    }
+
+    pub fn min_fee_rate(&self) -> u64 {
+        self.min_fee_rate
+    }
+
+    /// Rate, in sat/vB, below which transactions and their ancestors are left out of blocks.
+    pub fn set_min_fee_rate(&mut self, fee_rate: u64) {
+        self.min_fee_rate = fee_rate;
+    }
+
+    /// Output created by a confirmed transaction, or by one of the mempool.
+    fn find_prevout(&self, outpoint: &OutPoint) -> Option<(TxOut, Option<u32>)> {
+        if let Some((txout, height)) = self.utxos.get(outpoint) {
+            return Some((txout.clone(), Some(*height)));
+        }
+        self.mempool
+            .iter()
+            .find(|pending| pending.txid() == outpoint.txid)
+            .and_then(|pending| pending.output.get(outpoint.vout as usize))
+            .map(|txout| (txout.clone(), None))
+    }
+
+    /// Whether `tx` spends an output that is already spent, or was never created.
+    pub fn conflicts(&self, tx: &Transaction) -> bool {
+        tx.input.iter().any(|input| {
+            self.find_prevout(&input.previous_output).is_none()
+                || self.mempool.iter().any(|pending| {
+                    pending
+                        .input
+                        .iter()
+                        .any(|other| other.previous_output == input.previous_output)
+                })
+        })
+    }
+
+    /// Accepts `tx` for the next block, returning the fee it pays.
+    pub fn broadcast(&mut self, tx: Transaction) -> Result<Amount, String> {
+        let txid = tx.txid();
+        if self.mempool.iter().any(|pending| pending.txid() == txid) {
+            return Err(format!("{} already in mempool", txid));
+        }
+        if self.conflicts(&tx) {
+            return Err(format!("{} spends a missing or already spent output", txid));
+        }
+        let mut prevouts = vec![];
+        for input in tx.input.iter() {
+            let (prevout, height) = self
+                .find_prevout(&input.previous_output)
+                .expect("conflicts checked above");
+            if let Some(relative::LockTime::Blocks(blocks)) = input.sequence.to_relative_lock_time()
+            {
+                // Unconfirmed outputs are at least one block away from maturity.
+                let height = height.unwrap_or(self.height() + 1);
+                if tx.version >= 2 && self.height() + 1 < height + blocks.value() as u32 {
+                    return Err(format!("{} spends a timelocked output too early", txid));
+                }
+            }
+            prevouts.push(prevout);
+        }
+        for input_index in 0..tx.input.len() {
+            verify_input(&self.secp, &tx, input_index, &prevouts)
+                .map_err(|e| format!("{} input {}: {}", txid, input_index, e))?;
+        }
+        let value_in: u64 = prevouts.iter().map(|prevout| prevout.value).sum();
+        let value_out: u64 = tx.output.iter().map(|output| output.value).sum();
+        let fee = value_in
+            .checked_sub(value_out)
+            .ok_or(format!("{} spends more than its inputs", txid))?;
+        self.fees.insert(txid, Amount::from_sat(fee));
+        self.mempool.push(tx);
+        Ok(Amount::from_sat(fee))
+    }
+
+    /// Confirms the mempool in a new block, but for transactions paying less than the
+    /// minimum fee rate.
+    pub fn mine_block(&mut self) -> ChainBlock {
+        let block = self.connect_block();
+        self.notify(ChainEvent::ChainUpdatedWithBlocks {
+            new_blocks: vec![block.clone()],
+        });
+        block
+    }
+
+    pub fn mine_blocks(&mut self, count: u32) -> Vec<ChainBlock> {
+        (0..count).map(|_| self.mine_block()).collect()
+    }
+
+    /// Replaces the last `depth` blocks with `depth + 1` new ones. Transactions of the
+    /// orphaned blocks go back to the mempool, and are mined again if still valid.
+    pub fn reorg(&mut self, depth: u32) -> Result<Vec<ChainBlock>, String> {
+        if depth > self.height() {
+            return Err(format!("unable to reorg {} blocks", depth));
+        }
+        let mut blocks_to_rollback = vec![];
+        let mut orphaned = vec![];
+        for _ in 0..depth {
+            let (block, spent) = self.blocks.pop().expect("depth checked above");
+            for tx in block.transactions.iter() {
+                let txid = tx.txid();
+                for vout in 0..tx.output.len() {
+                    self.utxos.remove(&OutPoint::new(txid, vout as u32));
+                }
+            }
+            self.utxos.extend(spent);
+            orphaned.splice(0..0, block.transactions.iter().cloned());
+            blocks_to_rollback.push(block);
+        }
+        let pending = std::mem::take(&mut self.mempool);
+        self.fees.clear();
+        for tx in orphaned.into_iter().chain(pending) {
+            let _ = self.broadcast(tx);
+        }
+        let blocks_to_apply = (0..=depth)
+            .map(|_| self.connect_block())
+            .collect::<Vec<_>>();
+        self.notify(ChainEvent::ChainUpdatedWithReorg {
+            blocks_to_rollback,
+            blocks_to_apply: blocks_to_apply.clone(),
+        });
+        Ok(blocks_to_apply)
+    }
+
+    /// Transactions of the mempool paying, along with their unconfirmed ancestors, the
+    /// minimum fee rate, in mempool order.
+    fn select_transactions(&self) -> Vec<Transaction> {
+        let mut selected: HashSet<Txid> = HashSet::new();
+        loop {
+            let mut progress = false;
+            for tx in self.mempool.iter() {
+                let txid = tx.txid();
+                if selected.contains(&txid) {
+                    continue;
+                }
+                let package = self.unselected_ancestors(tx, &selected);
+                let (fee, vsize) = self
+                    .mempool
+                    .iter()
+                    .filter(|pending| package.contains(&pending.txid()))
+                    .fold((0, 0), |(fee, vsize), pending| {
+                        (
+                            fee + self.fees[&pending.txid()].to_sat(),
+                            vsize + pending.vsize() as u64,
+                        )
+                    });
+                if fee >= self.min_fee_rate * vsize {
+                    selected.extend(package);
+                    progress = true;
+                }
+            }
+            if !progress {
+                break;
+            }
+        }
+        self.mempool
+            .iter()
+            .filter(|tx| selected.contains(&tx.txid()))
+            .cloned()
+            .collect()
+    }
+
+    /// `tx` and the mempool transactions it depends on, but for those in `selected`.
+    fn unselected_ancestors(&self, tx: &Transaction, selected: &HashSet<Txid>) -> HashSet<Txid> {
+        let mut package = HashSet::from([tx.txid()]);
+        let mut queue = vec![tx];
+        while let Some(tx) = queue.pop() {
+            for input in tx.input.iter() {
+                let parent_txid = input.previous_output.txid;
+                if selected.contains(&parent_txid) || package.contains(&parent_txid) {
+                    continue;
+                }
+                if let Some(parent) = self
+                    .mempool
+                    .iter()
+                    .find(|pending| pending.txid() == parent_txid)
+                {
+                    package.insert(parent_txid);
+                    queue.push(parent);
+                }
+            }
+        }
+        package
+    }
+
+    /// Confirms the mempool in a new block, without notifying subscribers.
+    fn connect_block(&mut self) -> ChainBlock {
+        let transactions = self.select_transactions();
+        self.mempool.retain(|tx| !transactions.contains(tx));
+        for tx in transactions.iter() {
+            self.fees.remove(&tx.txid());
+        }
+        let height = self.height() + 1;
+        let parent_hash = self.tip_hash();
+        let mut spent = vec![];
+        for tx in transactions.iter() {
+            for input in tx.input.iter() {
+                if let Some(entry) = self.utxos.remove(&input.previous_output) {
+                    spent.push((input.previous_output, entry));
+                }
+            }
+            let txid = tx.txid();
+            for (vout, output) in tx.output.iter().enumerate() {
+                self.utxos
+                    .insert(OutPoint::new(txid, vout as u32), (output.clone(), height));
+            }
+        }
+
+        self.nonce += 1;
+        let mut block = Block {
+            header: Header {
+                version: Version::TWO,
+                prev_blockhash: parent_hash,
+                merkle_root: TxMerkleNode::all_zeros(),
+                time: height,
+                bits: CompactTarget::from_consensus(0x207fffff),
+                nonce: self.nonce,
+            },
+            txdata: transactions,
+        };
+        if let Some(merkle_root) = block.compute_merkle_root() {
+            block.header.merkle_root = merkle_root;
+        }
+        let block = ChainBlock {
+            height,
+            hash: block.block_hash(),
+            parent_hash,
+            transactions: block.txdata,
+        };
+        self.blocks.push((block.clone(), spent));
+        block
+    }
+
+    fn notify(&mut self, event: ChainEvent) {
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+    }
+}
+
+#[test]
+fn test_mock_chain_checks_scripts_and_timelocks() {
+    use bitcoin::script::Builder;
+    use bitcoin::secp256k1::{KeyPair, Message};
+    use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+    use bitcoin::taproot::{LeafVersion, TapLeafHash};
+    use bitcoin::{opcodes, ScriptBuf, Sequence, TxIn, Witness};
+    use sha2::{Digest, Sha256};
+
+    use crate::tapleaf::challenge_hashlock::augment_with_hashlock;
+    use crate::tapleaf::{augment_with_timelock, build_taproot_spend_info};
+
+    let secp = Secp256k1::new();
+    let keypair = KeyPair::from_seckey_slice(&secp, &[5; 32]).unwrap();
+    let preimage = [9u8; 32];
+    let script = augment_with_timelock(
+        augment_with_hashlock(Builder::new(), &Sha256::digest(preimage).into()),
+        3,
+    )
+    .push_slice(keypair.x_only_public_key().0.serialize())
+    .push_opcode(opcodes::all::OP_CHECKSIG)
+    .into_script();
+    let spend_info = build_taproot_spend_info(&secp, vec![script.clone()]).unwrap();
+    let control_block = spend_info
+        .control_block(&(script.clone(), LeafVersion::TapScript))
+        .unwrap();
+    let txout = TxOut {
+        value: 10_000,
+        script_pubkey: ScriptBuf::new_v1_p2tr_tweaked(spend_info.output_key()),
+    };
+
+    let mut chain = MockChain::new();
+    let events = chain.subscribe();
+    let outpoint = chain.mint(txout.clone());
+    let mut tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            sequence: Sequence::from_height(3),
+            ..Default::default()
+        }],
+        output: vec![TxOut {
+            value: 9_000,
+            script_pubkey: ScriptBuf::new(),
+        }],
+    };
+    let sighash = SighashCache::new(&tx)
+        .taproot_script_spend_signature_hash(
+            0,
+            &Prevouts::All(&[&txout]),
+            TapLeafHash::from_script(&script, LeafVersion::TapScript),
+            TapSighashType::Default,
+        )
+        .unwrap();
+    let signature = secp.sign_schnorr(&Message::from(sighash), &keypair);
+    let witness = |preimage: &[u8]| {
+        Witness::from_slice(&[
+            signature.as_ref().to_vec(),
+            preimage.to_vec(),
+            script.to_bytes(),
+            control_block.serialize(),
+        ])
+    };
+
+    tx.input[0].witness = witness(&preimage);
+    assert!(chain.broadcast(tx.clone()).is_err());
+    chain.mine_blocks(2);
+    tx.input[0].witness = witness(&[8; 32]);
+    assert!(chain.broadcast(tx.clone()).is_err());
+    tx.input[0].sequence = Sequence::from_height(2);
+    tx.input[0].witness = witness(&preimage);
+    assert!(chain.broadcast(tx.clone()).is_err());
+    tx.input[0].sequence = Sequence::from_height(3);
+    assert_eq!(chain.broadcast(tx.clone()), Ok(Amount::from_sat(1_000)));
+
+    let block = chain.mine_block();
+    assert_eq!(block.height, 3);
+    assert!(!chain.is_unspent(&outpoint));
+    let new_blocks = events
+        .try_iter()
+        .flat_map(|event| match event {
+            ChainEvent::ChainUpdatedWithBlocks { new_blocks } => new_blocks,
+            ChainEvent::ChainUpdatedWithReorg { .. } => panic!("unexpected reorg"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(new_blocks.len(), 3);
+    assert_eq!(new_blocks[2].transactions, vec![tx.clone()]);
+    assert_eq!(new_blocks[2].parent_hash, new_blocks[1].hash);
+
+    // The spend survives the reorg, and is confirmed again on the new branch.
+    let blocks_to_apply = chain.reorg(1).unwrap();
+    assert_eq!(blocks_to_apply[0].transactions, vec![tx]);
+    assert_eq!(chain.height(), 4);
+    let ChainEvent::ChainUpdatedWithReorg {
+        blocks_to_rollback, ..
+    } = events.try_recv().unwrap()
+    else {
+        panic!("expected a reorg");
+    };
+    assert_eq!(blocks_to_rollback, vec![block]);
+}
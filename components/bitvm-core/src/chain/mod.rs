@@ -0,0 +1,29 @@
+use bitcoin::{BlockHash, Transaction};
+
+pub mod interpreter;
+pub mod mock;
+pub mod observer;
+
+/// A block, reduced to what contracts look at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainBlock {
+    pub height: u32,
+    pub hash: BlockHash,
+    pub parent_hash: BlockHash,
+    pub transactions: Vec<Transaction>,
+}
+
+/// Updates of the best chain, shaped after chainhook's `BitcoinChainEvent`. Observers of a
+/// live node and the mock chain both emit them, so contracts are driven the same way online
+/// and in tests.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChainEvent {
+    ChainUpdatedWithBlocks {
+        new_blocks: Vec<ChainBlock>,
+    },
+    /// `blocks_to_rollback` are ordered from the tip down, `blocks_to_apply` from the fork up.
+    ChainUpdatedWithReorg {
+        blocks_to_rollback: Vec<ChainBlock>,
+        blocks_to_apply: Vec<ChainBlock>,
+    },
+}
@@ -0,0 +1,434 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+use bitcoin::{BlockHash, OutPoint, Script, ScriptBuf, Transaction, Txid};
+
+use super::{ChainBlock, ChainEvent};
+use crate::protocol::{
+    ContractAction, ContractEvent, ContractMachine, StageOutput, TransactionKind,
+};
+
+/// Blocks the observer can roll back.
+pub const MAX_REORG_DEPTH: usize = 12;
+
+/// A contract watched by the observer.
+struct WatchedContract {
+    machine: Box<dyn ContractMachine>,
+    funding_txid: Txid,
+    /// Outputs of the graph, with the move they wait for and its timeout.
+    outputs: HashMap<OutPoint, (StageOutput, u16)>,
+    script_pubkeys: HashSet<ScriptBuf>,
+    /// Wallet outputs spent by the funding transaction.
+    funding_inputs: HashSet<OutPoint>,
+    /// Unspent outputs of the graph, with the height they confirmed at.
+    confirmed: HashMap<OutPoint, u32>,
+    /// Events the machine failed to handle, fed to it again with the next block.
+    retry: Vec<ContractEvent>,
+}
+
+impl WatchedContract {
+    fn is_relevant(&self, tx: &Transaction) -> bool {
+        tx.input.iter().any(|input| {
+            self.outputs.contains_key(&input.previous_output)
+                || self.funding_inputs.contains(&input.previous_output)
+        }) || tx
+            .output
+            .iter()
+            .any(|output| self.script_pubkeys.contains(&output.script_pubkey))
+    }
+}
+
+/// State of a contract before a block was applied.
+#[derive(Clone)]
+struct ContractSnapshot {
+    machine: serde_json::Value,
+    confirmed: HashMap<OutPoint, u32>,
+    retry: Vec<ContractEvent>,
+}
+
+/// State of every contract before a block was applied.
+struct BlockRecord {
+    hash: BlockHash,
+    snapshots: BTreeMap<Txid, ContractSnapshot>,
+}
+
+/// Outcome of a chain update: the actions requested by each contract, and the contracts
+/// that failed to handle it. Those are left as they were before the failing block, and fed
+/// its events again with the next one.
+#[derive(Debug, Default)]
+pub struct ChainUpdate {
+    pub actions: Vec<(Txid, ContractAction)>,
+    pub failures: Vec<(Txid, String)>,
+}
+
+/// Turns chain updates into contract events: registers the outputs and scriptpubkeys of
+/// each contract, feeds the spends and confirmations it sees to the contract's state
+/// machine, and restores the machines to their previous state when blocks are reorged out.
+#[derive(Default)]
+pub struct ContractObserver {
+    contracts: BTreeMap<Txid, WatchedContract>,
+    history: VecDeque<BlockRecord>,
+}
+
+impl ContractObserver {
+    pub fn new() -> ContractObserver {
+        ContractObserver::default()
+    }
+
+    pub fn register(&mut self, machine: Box<dyn ContractMachine>) -> Result<(), String> {
+        let contract_id = machine.contract_id();
+        if self.contracts.contains_key(&contract_id) {
+            return Err(format!("contract {} already observed", contract_id));
+        }
+        let graph = machine.graph();
+        let mut outputs = HashMap::new();
+        let mut script_pubkeys = HashSet::new();
+        for (stage, output) in graph.outputs.iter() {
+            outputs.insert(output.outpoint, (*stage, graph.output_timeout(stage)?));
+            script_pubkeys.insert(output.txout.script_pubkey.clone());
+        }
+        let funding = &graph.transaction(&TransactionKind::Funding)?.tx;
+        let contract = WatchedContract {
+            funding_txid: funding.txid(),
+            funding_inputs: funding
+                .input
+                .iter()
+                .map(|input| input.previous_output)
+                .collect(),
+            outputs,
+            script_pubkeys,
+            confirmed: HashMap::new(),
+            retry: vec![],
+            machine,
+        };
+        self.contracts.insert(contract_id, contract);
+        Ok(())
+    }
+
+    pub fn contract_ids(&self) -> Vec<Txid> {
+        self.contracts.keys().cloned().collect()
+    }
+
+    pub fn machine(&self, contract_id: &Txid) -> Option<&(dyn ContractMachine + 'static)> {
+        self.contracts
+            .get(contract_id)
+            .map(|contract| contract.machine.as_ref())
+    }
+
+    pub fn machine_mut(
+        &mut self,
+        contract_id: &Txid,
+    ) -> Option<&mut (dyn ContractMachine + 'static)> {
+        self.contracts
+            .get_mut(contract_id)
+            .map(|contract| contract.machine.as_mut())
+    }
+
+    /// Whether spending `outpoint` concerns a watched contract.
+    pub fn watches_outpoint(&self, outpoint: &OutPoint) -> bool {
+        self.contracts.values().any(|contract| {
+            contract.outputs.contains_key(outpoint) || contract.funding_inputs.contains(outpoint)
+        })
+    }
+
+    /// Whether paying to `script_pubkey` concerns a watched contract.
+    pub fn watches_script_pubkey(&self, script_pubkey: &Script) -> bool {
+        self.contracts
+            .values()
+            .any(|contract| contract.script_pubkeys.contains(script_pubkey))
+    }
+
+    /// Applies a chain update, returning the actions requested by each contract. A contract
+    /// failing to handle it does not hold the others back.
+    pub fn handle_chain_event(&mut self, event: &ChainEvent) -> Result<ChainUpdate, String> {
+        let blocks_to_apply = match event {
+            ChainEvent::ChainUpdatedWithBlocks { new_blocks } => new_blocks,
+            ChainEvent::ChainUpdatedWithReorg {
+                blocks_to_rollback,
+                blocks_to_apply,
+            } => {
+                if let Some(fork) = blocks_to_rollback.last() {
+                    self.rollback(&fork.hash)?;
+                }
+                blocks_to_apply
+            }
+        };
+        let mut update = ChainUpdate::default();
+        for block in blocks_to_apply.iter() {
+            self.apply_block(block, &mut update)?;
+        }
+        Ok(update)
+    }
+
+    fn apply_block(&mut self, block: &ChainBlock, update: &mut ChainUpdate) -> Result<(), String> {
+        let mut snapshots = BTreeMap::new();
+        for (contract_id, contract) in self.contracts.iter() {
+            let snapshot = ContractSnapshot {
+                machine: contract.machine.snapshot()?,
+                confirmed: contract.confirmed.clone(),
+                retry: contract.retry.clone(),
+            };
+            snapshots.insert(*contract_id, snapshot);
+        }
+
+        for (contract_id, contract) in self.contracts.iter_mut() {
+            let mut events = std::mem::take(&mut contract.retry);
+            for tx in block.transactions.iter() {
+                if !contract.is_relevant(tx) {
+                    continue;
+                }
+                for input in tx.input.iter() {
+                    contract.confirmed.remove(&input.previous_output);
+                }
+                let txid = tx.txid();
+                for vout in 0..tx.output.len() {
+                    let outpoint = OutPoint::new(txid, vout as u32);
+                    if contract.outputs.contains_key(&outpoint) {
+                        contract.confirmed.insert(outpoint, block.height);
+                    }
+                }
+                events.push(ContractEvent::SpendObserved(tx.clone()));
+                if txid == contract.funding_txid {
+                    events.push(ContractEvent::FundingConfirmed);
+                }
+            }
+            for (outpoint, confirmed) in contract.confirmed.iter() {
+                let (output, timeout) = contract.outputs[outpoint];
+                events.push(ContractEvent::TimeoutNear {
+                    output,
+                    blocks_left: (confirmed + timeout as u32).saturating_sub(block.height + 1),
+                });
+            }
+            let mut actions = vec![];
+            let handled = events.iter().try_for_each(|event| {
+                for action in contract.machine.handle(event)? {
+                    actions.push((*contract_id, action));
+                }
+                Ok::<(), String>(())
+            });
+            match handled {
+                Ok(()) => update.actions.append(&mut actions),
+                Err(mut e) => {
+                    let snapshot = snapshots[contract_id].machine.clone();
+                    if let Err(rollback_error) = contract.machine.rollback(snapshot) {
+                        e = format!("{}, then {}", e, rollback_error);
+                    }
+                    contract.retry = events
+                        .into_iter()
+                        .filter(|event| !matches!(event, ContractEvent::TimeoutNear { .. }))
+                        .collect();
+                    update.failures.push((*contract_id, e));
+                }
+            }
+        }
+
+        self.history.push_back(BlockRecord {
+            hash: block.hash,
+            snapshots,
+        });
+        if self.history.len() > MAX_REORG_DEPTH {
+            self.history.pop_front();
+        }
+        Ok(())
+    }
+
+    /// Restores every contract to its state before the block `fork` was applied.
+    fn rollback(&mut self, fork: &BlockHash) -> Result<(), String> {
+        let position = self
+            .history
+            .iter()
+            .rposition(|record| record.hash == *fork)
+            .ok_or(format!(
+                "block {} is deeper than the last {} blocks observed",
+                fork, MAX_REORG_DEPTH
+            ))?;
+        let record = self
+            .history
+            .split_off(position)
+            .pop_front()
+            .expect("fork found in history");
+        for (contract_id, snapshot) in record.snapshots.into_iter() {
+            if let Some(contract) = self.contracts.get_mut(&contract_id) {
+                contract.machine.rollback(snapshot.machine)?;
+                contract.confirmed = snapshot.confirmed;
+                contract.retry = snapshot.retry;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_observer_feeds_contracts_and_rolls_back_reorgs() {
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::{KeyPair, Secp256k1};
+
+    use crate::protocol::build_test_setup;
+    use crate::prover::{Prover, ProverSecrets};
+    use crate::vault::Vault;
+    use crate::verifier::{Verifier, VerifierPhase, VerifierSecrets};
+
+    let secp = Secp256k1::new();
+    let prover_keypair = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+    let verifier_keypair = KeyPair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let (circuit, setup, challenge_store) = build_test_setup(
+        include_str!("../bristol/fixtures/test_vector_1.bristol"),
+        &prover_keypair,
+        &verifier_keypair,
+    );
+    let prover_secrets = ProverSecrets::generate(&circuit, prover_keypair.secret_key());
+    let mut setup = setup;
+    setup.commitments = prover_secrets.compute_commitments(challenge_store.compute_hashes());
+    let inputs = circuit
+        .collect_input_wires_ids()
+        .into_iter()
+        .map(|wire_id| (*wire_id, false))
+        .collect();
+    let working_dir = std::env::temp_dir().join(format!("bitvm-obs-{}", rand::random::<u64>()));
+    let prover_dir = working_dir.join("prover");
+    let verifier_dir = working_dir.join("verifier");
+
+    let vault = Vault::ephemeral();
+    let mut prover =
+        Prover::new(&prover_dir, &vault, setup.clone(), prover_secrets, inputs).unwrap();
+    let verifier_secrets = VerifierSecrets {
+        secret_key: verifier_keypair.secret_key(),
+        key_source: None,
+        challenge_store,
+    };
+    let mut verifier = Verifier::new(&verifier_dir, &vault, setup, verifier_secrets).unwrap();
+    prover
+        .receive_presignatures(&verifier.presignatures())
+        .unwrap();
+    verifier
+        .receive_presignatures(&prover.presignatures())
+        .unwrap();
+    let [ContractAction::Fund(funding)] =
+        &prover.handle(&ContractEvent::SetupCompleted).unwrap()[..]
+    else {
+        panic!("expected the funding transaction");
+    };
+    let funding = funding.clone();
+    verifier.handle(&ContractEvent::SetupCompleted).unwrap();
+    let contract_id = prover.contract_id();
+
+    let mut prover_observer = ContractObserver::new();
+    prover_observer.register(Box::new(prover)).unwrap();
+    let mut verifier_observer = ContractObserver::new();
+    verifier_observer.register(Box::new(verifier)).unwrap();
+    assert!(prover_observer.watches_outpoint(&funding.input[0].previous_output));
+    assert!(verifier_observer.watches_script_pubkey(&funding.output[0].script_pubkey));
+
+    let block = |height: u32, branch: u8, transactions: Vec<Transaction>| ChainBlock {
+        height,
+        hash: BlockHash::hash(&[height as u8, branch]),
+        parent_hash: BlockHash::hash(&[height as u8 - 1, branch]),
+        transactions,
+    };
+    let mut apply = |block: &ChainBlock| {
+        let event = ChainEvent::ChainUpdatedWithBlocks {
+            new_blocks: vec![block.clone()],
+        };
+        (
+            prover_observer.handle_chain_event(&event).unwrap().actions,
+            verifier_observer
+                .handle_chain_event(&event)
+                .unwrap()
+                .actions,
+        )
+    };
+
+    let (prover_actions, _) = apply(&block(1, 0, vec![funding]));
+    // Paul commits once funded, and keeps rebroadcasting until the commit confirms.
+    let Some((id, ContractAction::Broadcast { kind, tx: commit })) = prover_actions.first() else {
+        panic!("expected the commit transaction");
+    };
+    assert_eq!((*id, *kind), (contract_id, TransactionKind::Commit));
+    let commit = commit.clone();
+    assert!(prover_actions.iter().all(|(_, action)| matches!(
+        action,
+        ContractAction::Broadcast { tx, .. } if *tx == commit
+    )));
+    let unrelated = Transaction {
+        version: 2,
+        lock_time: bitcoin::absolute::LockTime::ZERO,
+        input: vec![],
+        output: vec![],
+    };
+    let committed = block(2, 0, vec![unrelated, commit.clone()]);
+    apply(&committed);
+    let restore_verifier = || Verifier::restore(&verifier_dir, &vault, &contract_id).unwrap();
+    assert_eq!(restore_verifier().state.phase, VerifierPhase::Accepted);
+
+    // The commit transaction is reorged out: Vicky waits for it again, and Paul
+    // rebroadcasts it.
+    let reorged = block(2, 1, vec![]);
+    let event = ChainEvent::ChainUpdatedWithReorg {
+        blocks_to_rollback: vec![committed],
+        blocks_to_apply: vec![reorged.clone()],
+    };
+    let prover_actions = prover_observer.handle_chain_event(&event).unwrap().actions;
+    verifier_observer.handle_chain_event(&event).unwrap();
+    assert_eq!(
+        restore_verifier().state.phase,
+        VerifierPhase::AwaitingCommit
+    );
+    assert!(prover_actions.iter().any(|(_, action)| matches!(
+        action,
+        ContractAction::Broadcast { tx, .. } if *tx == commit
+    )));
+
+    // A contract failing on a block, here unable to persist its state, does not hold the
+    // others back, and is fed the same transactions again with the next block.
+    let contract_dir = crate::storage::contract_dir(&verifier_dir, &contract_id);
+    let moved_dir = working_dir.join("moved");
+    std::fs::rename(&contract_dir, &moved_dir).unwrap();
+    std::fs::write(&contract_dir, b"").unwrap();
+    let event = ChainEvent::ChainUpdatedWithBlocks {
+        new_blocks: vec![block(3, 1, vec![commit.clone()])],
+    };
+    let update = verifier_observer.handle_chain_event(&event).unwrap();
+    assert_eq!(update.failures.len(), 1);
+    assert_eq!(update.failures[0].0, contract_id);
+    let phase = |observer: &ContractObserver| observer.machine(&contract_id).unwrap().phase();
+    assert_eq!(phase(&verifier_observer), "AwaitingCommit");
+    assert!(prover_observer
+        .handle_chain_event(&event)
+        .unwrap()
+        .failures
+        .is_empty());
+    std::fs::remove_file(&contract_dir).unwrap();
+    std::fs::rename(&moved_dir, &contract_dir).unwrap();
+    let event = ChainEvent::ChainUpdatedWithBlocks {
+        new_blocks: vec![block(4, 1, vec![])],
+    };
+    let update = verifier_observer.handle_chain_event(&event).unwrap();
+    assert!(update.failures.is_empty());
+    assert_eq!(phase(&verifier_observer), "Accepted");
+
+    let rollback_too_deep = ChainEvent::ChainUpdatedWithReorg {
+        blocks_to_rollback: vec![block(2, 7, vec![])],
+        blocks_to_apply: vec![],
+    };
+    let history = |observer: &ContractObserver| {
+        observer
+            .history
+            .iter()
+            .map(|record| record.hash)
+            .collect::<Vec<_>>()
+    };
+    let history_before = history(&verifier_observer);
+    assert!(!history_before.is_empty());
+    assert!(verifier_observer
+        .handle_chain_event(&rollback_too_deep)
+        .is_err());
+    assert_eq!(history(&verifier_observer), history_before);
+    assert_eq!(phase(&verifier_observer), "Accepted");
+    assert_eq!(restore_verifier().state.phase, VerifierPhase::Accepted);
+    let restored = Prover::restore_active(&prover_dir, &vault).unwrap();
+    assert_eq!(restored.len(), 1);
+    assert_eq!(restored[0].contract_id(), contract_id);
+    assert!(Prover::restore_active(&verifier_dir, &vault)
+        .unwrap()
+        .is_empty());
+    std::fs::remove_dir_all(&working_dir).unwrap();
+}
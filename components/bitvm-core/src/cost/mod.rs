@@ -0,0 +1,270 @@
+use bitcoin::blockdata::opcodes;
+use bitcoin::hashes::Hash;
+use bitcoin::script::Instruction;
+use bitcoin::secp256k1::{Secp256k1, SecretKey};
+use bitcoin::{Amount, OutPoint, ScriptBuf, TxOut, Txid, Witness};
+use bitvm_types::Circuit;
+use rand::thread_rng;
+
+use crate::bisection::compute_bisection_rounds;
+use crate::fees::{cpfp_vsize, FeePolicy};
+use crate::protocol::{
+    ContractCommitments, ContractParameters, FundingInput, Participants, Role, TimelockParameters,
+    TransactionGraph, TransactionKind,
+};
+use crate::tapleaf::challenge_hashlock::ChallengeStore;
+
+/// Size of the preimages opened by leaf conditions, one per `OP_SHA256`.
+const PREIMAGE_SIZE: usize = 32;
+
+const SIGNATURE_SIZE: usize = 64;
+
+/// Worst-case size of a transaction of the dispute, and the fee it takes at the estimated
+/// fee rate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionCost {
+    pub kind: TransactionKind,
+    pub broadcaster: Role,
+    pub vsize: usize,
+    /// Size of the child bumping the transaction through its anchor, 0 when not bumped.
+    pub child_vsize: usize,
+    /// Fee of the transaction, and of its child if any.
+    pub fee: Amount,
+}
+
+/// Every transaction of a dispute settled on-chain, from the commitment to the judgement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathCost {
+    pub transactions: Vec<TransactionCost>,
+}
+
+impl PathCost {
+    pub fn vsize(&self) -> usize {
+        self.transactions
+            .iter()
+            .map(|tx| tx.vsize + tx.child_vsize)
+            .sum()
+    }
+
+    pub fn fee(&self) -> Amount {
+        self.transactions.iter().map(|tx| tx.fee).sum()
+    }
+
+    pub fn fee_paid_by(&self, role: Role) -> Amount {
+        self.transactions
+            .iter()
+            .filter(|tx| tx.broadcaster == role)
+            .map(|tx| tx.fee)
+            .sum()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DisputeCost {
+    /// Rate every transaction pays, in sat/vB.
+    pub fee_rate: u64,
+    /// Policy bumping every transaction through its anchor, if any.
+    pub fee_policy: Option<FeePolicy>,
+    pub rounds: usize,
+    /// Paul's claim holds, and he takes the amount back once Vicky fails to prove the opened
+    /// gate wrong.
+    pub honest: PathCost,
+    /// Paul's claim is false, and Vicky slashes him on the opened gate.
+    pub cheating: PathCost,
+}
+
+/// Estimates what disputing a claim on `circuit` costs at `fee_rate` sat/vB, when every
+/// bisection round is played and each transaction spends its largest leaf. With a
+/// `fee_policy`, every transaction is bumped by a child at the policy's urgent rate, as when
+/// its deadline is about to pass.
+pub fn estimate_dispute_cost(
+    circuit: &Circuit,
+    fee_rate: u64,
+    fee_policy: Option<FeePolicy>,
+) -> Result<DisputeCost, String> {
+    let fee_rate = match fee_policy {
+        Some(policy) => policy.fee_rate(fee_rate, 0),
+        None => fee_rate,
+    };
+    let graph = build_estimation_graph(circuit)?;
+    let mut dispute = vec![TransactionKind::Commit];
+    for round in 0..graph.rounds {
+        dispute.push(TransactionKind::Challenge(round));
+        dispute.push(TransactionKind::Response(round));
+    }
+    dispute.push(TransactionKind::GateChallenge);
+    dispute.push(TransactionKind::GateResponse);
+
+    let estimate_path = |last: TransactionKind| {
+        dispute
+            .iter()
+            .chain([&last])
+            .map(|kind| estimate_transaction_cost(&graph, kind, fee_rate, fee_policy.is_some()))
+            .collect::<Result<Vec<_>, String>>()
+            .map(|transactions| PathCost { transactions })
+    };
+    Ok(DisputeCost {
+        fee_rate,
+        fee_policy,
+        rounds: graph.rounds,
+        honest: estimate_path(TransactionKind::SlashTimeout)?,
+        cheating: estimate_path(TransactionKind::Slash)?,
+    })
+}
+
+/// Size of `kind` once its witness is complete, through its largest leaf. When `bumped`, a
+/// child spends its anchor and a single coin of the broadcaster's fee wallet.
+pub fn estimate_transaction_cost(
+    graph: &TransactionGraph,
+    kind: &TransactionKind,
+    fee_rate: u64,
+    bumped: bool,
+) -> Result<TransactionCost, String> {
+    let transaction = graph.transaction(kind)?;
+    let spent = transaction
+        .spent_output
+        .ok_or(format!("{} is not spent through a leaf", kind))?;
+    let spend_info = &graph.output(&spent)?.spend_info;
+    // Leaves of equal weight may land at any depth of the tree: assume the deepest.
+    let control_block_size = spend_info
+        .as_script_map()
+        .keys()
+        .filter_map(|script| spend_info.control_block(script))
+        .map(|control_block| control_block.size())
+        .max()
+        .ok_or(format!("no leaf in the output spent by {}", kind))?;
+    let mut vsize = 0;
+    for leaf in transaction.leaves.iter() {
+        let mut witness = Witness::new();
+        for _ in transaction.signers.iter() {
+            witness.push([0; SIGNATURE_SIZE]);
+        }
+        for _ in 0..count_opened_preimages(leaf) {
+            witness.push([0; PREIMAGE_SIZE]);
+        }
+        witness.push(leaf.as_bytes());
+        witness.push(vec![0; control_block_size]);
+        let mut tx = transaction.tx.clone();
+        tx.input[0].witness = witness;
+        vsize = vsize.max(tx.vsize());
+    }
+    let child_vsize = match bumped {
+        true => cpfp_vsize(&graph.anchor(kind)?.1.script_pubkey, 1),
+        false => 0,
+    };
+    Ok(TransactionCost {
+        kind: *kind,
+        broadcaster: kind.broadcaster(),
+        vsize,
+        child_vsize,
+        fee: Amount::from_sat((vsize + child_vsize) as u64 * fee_rate),
+    })
+}
+
+fn count_opened_preimages(leaf: &ScriptBuf) -> usize {
+    leaf.instructions()
+        .filter(|instruction| {
+            matches!(instruction, Ok(Instruction::Op(op)) if *op == opcodes::all::OP_SHA256)
+        })
+        .count()
+}
+
+/// Graph of a contract on `circuit` between throwaway keys. Its transactions have the size
+/// of those of any contract on the same circuit.
+fn build_estimation_graph(circuit: &Circuit) -> Result<TransactionGraph, String> {
+    let secp = Secp256k1::new();
+    let mut rng = thread_rng();
+    let participants = Participants {
+        prover: SecretKey::new(&mut rng).public_key(&secp),
+        verifier: SecretKey::new(&mut rng).public_key(&secp),
+    };
+    let rounds = compute_bisection_rounds(circuit.gates.len());
+    let mut gates_ids = circuit.gates.keys().cloned().collect::<Vec<_>>();
+    gates_ids.sort();
+    let commitments = ContractCommitments {
+        commitment_set: circuit.compute_commitment_set(),
+        challenge_hashes: ChallengeStore::new(&gates_ids, rounds).compute_hashes(),
+    };
+    let script_pubkey =
+        ScriptBuf::new_v1_p2tr(&secp, participants.prover.x_only_public_key().0, None);
+    let parameters = ContractParameters {
+        amount: Amount::ONE_BTC,
+        collateral: Amount::from_sat(100_000),
+        fee_per_transaction: Amount::from_sat(2_000),
+        timelocks: TimelockParameters::default(),
+        funding_inputs: vec![FundingInput {
+            outpoint: OutPoint::new(Txid::all_zeros(), 0),
+            txout: TxOut {
+                value: Amount::ONE_BTC.to_sat() * 2,
+                script_pubkey: script_pubkey.clone(),
+            },
+        }],
+        change_script_pubkey: script_pubkey,
+    };
+    TransactionGraph::build(&secp, circuit, &commitments, &participants, &parameters)
+}
+
+#[test]
+fn test_dispute_cost_covers_the_longest_dispute() {
+    let source = include_str!("../bristol/fixtures/test_vector_1.bristol");
+    let circuit = crate::bristol::parser::read_circuit(source).unwrap();
+    let cost = estimate_dispute_cost(&circuit, 10, None).unwrap();
+    assert_eq!(cost.rounds, compute_bisection_rounds(circuit.gates.len()));
+    assert_eq!(cost.honest.transactions.len(), 2 * cost.rounds + 4);
+    assert_eq!(cost.cheating.transactions.len(), 2 * cost.rounds + 4);
+    assert_eq!(
+        cost.cheating.transactions.last().unwrap().kind,
+        TransactionKind::Slash
+    );
+    assert_eq!(
+        cost.cheating.fee(),
+        Amount::from_sat(cost.cheating.vsize() as u64 * 10)
+    );
+    assert_eq!(
+        cost.honest.fee_paid_by(Role::Prover) + cost.honest.fee_paid_by(Role::Verifier),
+        cost.honest.fee()
+    );
+
+    // Bumping adds a child to every transaction, paid at the urgent rate.
+    let bumped = estimate_dispute_cost(&circuit, 10, Some(FeePolicy::default())).unwrap();
+    assert_eq!(bumped.fee_rate, FeePolicy::default().urgent_fee_rate);
+    for (tx, bumped_tx) in cost
+        .cheating
+        .transactions
+        .iter()
+        .zip(bumped.cheating.transactions.iter())
+    {
+        assert_eq!(tx.child_vsize, 0);
+        assert!(bumped_tx.child_vsize > 0);
+        assert_eq!(
+            bumped_tx.fee,
+            Amount::from_sat((tx.vsize + bumped_tx.child_vsize) as u64 * bumped.fee_rate)
+        );
+    }
+
+    // The estimates bound the transactions of a simulated dispute.
+    let options = crate::simulation::SimulationOptions {
+        faults: vec![crate::simulation::Fault::FlipGate(
+            circuit.collect_output_wires_ids()[0],
+        )],
+        ..Default::default()
+    };
+    let report = crate::simulation::run_simulation(source, &options).unwrap();
+    std::fs::remove_dir_all(&options.working_dir).unwrap();
+    for tx in report.transactions.iter() {
+        if let Some(estimate) = cost
+            .cheating
+            .transactions
+            .iter()
+            .find(|e| e.kind == tx.kind)
+        {
+            assert!(
+                tx.vsize <= estimate.vsize,
+                "{} is larger than estimated: {} > {}",
+                tx.kind,
+                tx.vsize,
+                estimate.vsize
+            );
+        }
+    }
+}
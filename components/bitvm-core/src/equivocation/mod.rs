@@ -0,0 +1,216 @@
+use std::collections::{BTreeMap, HashMap};
+
+use bitcoin::absolute::LockTime;
+use bitcoin::secp256k1::{self, KeyPair, Message, Secp256k1};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{LeafVersion, TapLeafHash, TaprootSpendInfo};
+use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+use bitvm_types::{BitCommitmentPreimages, CommitmentSet, WireId};
+use sha2::{Digest, Sha256};
+
+use crate::tapleaf::anti_contradiction_address::build_anti_contradiciton_leaf;
+
+/// Preimages revealed so far for a wire, for bit values 0 and 1.
+type RevealedPreimages = (Option<[u8; 32]>, Option<[u8; 32]>);
+
+/// Both preimages of the same wire, revealed by the prover.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Equivocation {
+    pub wire_id: WireId,
+    pub preimages: BitCommitmentPreimages,
+}
+
+/// Matches preimages revealed on-chain against the public commitment set, and reports
+/// wires for which both bit values have been opened.
+pub struct EquivocationDetector {
+    reverse_index: HashMap<[u8; 32], (WireId, bool)>,
+    revealed: BTreeMap<WireId, RevealedPreimages>,
+}
+
+impl EquivocationDetector {
+    pub fn new(commitment_set: &CommitmentSet) -> EquivocationDetector {
+        EquivocationDetector {
+            reverse_index: commitment_set.build_reverse_index(),
+            revealed: BTreeMap::new(),
+        }
+    }
+
+    /// Records a candidate preimage. Returns an equivocation the first time the opposite
+    /// value of an already opened wire shows up.
+    pub fn observe_preimage(&mut self, candidate: &[u8]) -> Option<Equivocation> {
+        let preimage: [u8; 32] = candidate.try_into().ok()?;
+        let hash: [u8; 32] = Sha256::digest(preimage).into();
+        let (wire_id, bit) = self.reverse_index.get(&hash)?;
+
+        let entry = self.revealed.entry(*wire_id).or_default();
+        let was_complete = entry.0.is_some() && entry.1.is_some();
+        if *bit {
+            entry.1 = Some(preimage);
+        } else {
+            entry.0 = Some(preimage);
+        }
+
+        match (was_complete, entry) {
+            (false, (Some(preimage_0), Some(preimage_1))) => Some(Equivocation {
+                wire_id: *wire_id,
+                preimages: BitCommitmentPreimages(*preimage_0, *preimage_1),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Scans every witness element of every input of `tx`.
+    pub fn observe_transaction(&mut self, tx: &Transaction) -> Vec<Equivocation> {
+        let mut equivocations = vec![];
+        for input in tx.input.iter() {
+            for element in input.witness.iter() {
+                if let Some(equivocation) = self.observe_preimage(element) {
+                    equivocations.push(equivocation);
+                }
+            }
+        }
+        equivocations
+    }
+
+    pub fn revealed_bit(&self, wire_id: &WireId) -> Option<bool> {
+        match self.revealed.get(wire_id)? {
+            (Some(_), None) => Some(false),
+            (None, Some(_)) => Some(true),
+            _ => None,
+        }
+    }
+}
+
+/// Builds and signs the transaction sweeping the anti-contradiction output to the verifier's key,
+/// using the leaf of the wire Paul equivocated on.
+pub fn build_slashing_transaction(
+    secp: &Secp256k1<secp256k1::All>,
+    equivocation: &Equivocation,
+    anti_contradiction_spend_info: &TaprootSpendInfo,
+    anti_contradiction_outpoint: OutPoint,
+    anti_contradiction_output: &TxOut,
+    verifier_keypair: &KeyPair,
+    fee: Amount,
+) -> Result<Transaction, String> {
+    let verifier_public_key = verifier_keypair.public_key();
+    let hashes = equivocation.preimages.compute_bit_commitment_hashes();
+    let leaf_script = build_anti_contradiciton_leaf(&verifier_public_key, &hashes);
+    let control_block = anti_contradiction_spend_info
+        .control_block(&(leaf_script.clone(), LeafVersion::TapScript))
+        .ok_or(format!(
+            "wire {} is not committed in the anti-contradiction output",
+            equivocation.wire_id
+        ))?;
+
+    let value = anti_contradiction_output
+        .value
+        .checked_sub(fee.to_sat())
+        .ok_or("fee exceeds the anti-contradiction output value".to_string())?;
+
+    let mut tx = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: anti_contradiction_outpoint,
+            script_sig: ScriptBuf::new(),
+            sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+            witness: Witness::new(),
+        }],
+        output: vec![TxOut {
+            value,
+            script_pubkey: ScriptBuf::new_v1_p2tr(
+                secp,
+                verifier_keypair.x_only_public_key().0,
+                None,
+            ),
+        }],
+    };
+
+    let leaf_hash = TapLeafHash::from_script(&leaf_script, LeafVersion::TapScript);
+    let sighash = SighashCache::new(&tx)
+        .taproot_script_spend_signature_hash(
+            0,
+            &Prevouts::All(&[anti_contradiction_output]),
+            leaf_hash,
+            TapSighashType::Default,
+        )
+        .map_err(|e| format!("unable to compute sighash: {}", e))?;
+    let signature = secp.sign_schnorr(&Message::from(sighash), verifier_keypair);
+
+    let mut witness = Witness::new();
+    witness.push(signature.as_ref());
+    witness.push(equivocation.preimages.1);
+    witness.push(equivocation.preimages.0);
+    witness.push(leaf_script.as_bytes());
+    witness.push(control_block.serialize());
+    tx.input[0].witness = witness;
+    Ok(tx)
+}
+
+#[test]
+fn test_equivocation_detection_and_slashing() {
+    use crate::tapleaf::anti_contradiction_address::compute_anti_contradiction_address;
+    use bitcoin::hashes::Hash;
+
+    let circuit = crate::bristol::parser::read_circuit(include_str!(
+        "../bristol/fixtures/test_vector_1.bristol"
+    ))
+    .expect("unable to parse bristol");
+    let commitment_set = circuit.compute_commitment_set();
+
+    let secp = Secp256k1::new();
+    let prover_keypair = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+    let verifier_keypair = KeyPair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let spend_info = compute_anti_contradiction_address(
+        &commitment_set,
+        &secp,
+        &prover_keypair.public_key(),
+        &verifier_keypair.public_key(),
+        144,
+    )
+    .unwrap();
+
+    let wire_id = 106;
+    let preimages = circuit
+        .gates_bit_commitments_preimages
+        .get(&wire_id)
+        .unwrap();
+    let mut detector = EquivocationDetector::new(&commitment_set);
+    assert_eq!(detector.observe_preimage(&[0; 32]), None);
+    assert_eq!(detector.observe_preimage(&preimages.1), None);
+    assert_eq!(detector.observe_preimage(&preimages.1), None);
+    assert_eq!(detector.revealed_bit(&wire_id), Some(true));
+    let equivocation = detector
+        .observe_preimage(&preimages.0)
+        .expect("equivocation expected");
+    assert_eq!(equivocation.wire_id, wire_id);
+    assert_eq!(detector.observe_preimage(&preimages.0), None);
+
+    let anti_contradiction_output = TxOut {
+        value: 100_000,
+        script_pubkey: ScriptBuf::new_v1_p2tr_tweaked(spend_info.output_key()),
+    };
+    let outpoint = OutPoint::new(bitcoin::Txid::all_zeros(), 0);
+    let tx = build_slashing_transaction(
+        &secp,
+        &equivocation,
+        &spend_info,
+        outpoint,
+        &anti_contradiction_output,
+        &verifier_keypair,
+        Amount::from_sat(1_000),
+    )
+    .unwrap();
+
+    assert_eq!(tx.output[0].value, 99_000);
+    let witness = &tx.input[0].witness;
+    assert_eq!(witness.len(), 5);
+    assert_eq!(witness.nth(1).unwrap(), &preimages.1);
+    assert_eq!(witness.nth(2).unwrap(), &preimages.0);
+    let control_block = bitcoin::taproot::ControlBlock::decode(witness.nth(4).unwrap()).unwrap();
+    assert!(control_block.verify_taproot_commitment(
+        &secp,
+        spend_info.output_key().to_inner(),
+        bitcoin::Script::from_bytes(witness.nth(3).unwrap()),
+    ));
+}
@@ -0,0 +1,298 @@
+use bitcoin::absolute::LockTime;
+use bitcoin::key::TapTweak;
+use bitcoin::secp256k1::{self, KeyPair, Message, Secp256k1};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Witness};
+
+use crate::protocol::DUST_LIMIT;
+
+/// Size of a key path spend signature, with the default sighash type.
+const KEY_SPEND_SIGNATURE_SIZE: usize = 64;
+
+/// Fee rates, in sat/vB, a participant is willing to pay to get its transactions confirmed
+/// before the other one can claim the funds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FeePolicy {
+    /// Minimum rate of a bumped transaction.
+    pub target_fee_rate: u64,
+    /// Minimum rate once the deadline is `urgent_blocks` away or less.
+    pub urgent_fee_rate: u64,
+    pub urgent_blocks: u32,
+    /// Rate never exceeded, whatever the market.
+    pub max_fee_rate: u64,
+}
+
+impl Default for FeePolicy {
+    fn default() -> Self {
+        FeePolicy {
+            target_fee_rate: 2,
+            urgent_fee_rate: 20,
+            urgent_blocks: 3,
+            max_fee_rate: 500,
+        }
+    }
+}
+
+impl FeePolicy {
+    /// Rate to bump to, given the rate the market currently confirms and the blocks left
+    /// before the deadline.
+    pub fn fee_rate(&self, market_fee_rate: u64, blocks_left: u32) -> u64 {
+        let floor = if blocks_left <= self.urgent_blocks {
+            self.urgent_fee_rate
+        } else {
+            self.target_fee_rate
+        };
+        market_fee_rate.max(floor).min(self.max_fee_rate)
+    }
+}
+
+/// Rate paid by `fee` over `vsize`, rounded down.
+pub fn fee_rate(fee: Amount, vsize: usize) -> u64 {
+    fee.to_sat() / vsize.max(1) as u64
+}
+
+/// Coins of a single taproot key, set aside to pay for fee bumps.
+pub struct FeeWallet {
+    keypair: KeyPair,
+    script_pubkey: ScriptBuf,
+    utxos: Vec<(OutPoint, TxOut)>,
+}
+
+impl FeeWallet {
+    pub fn new(secp: &Secp256k1<secp256k1::All>, keypair: KeyPair) -> FeeWallet {
+        FeeWallet {
+            script_pubkey: ScriptBuf::new_v1_p2tr(secp, keypair.x_only_public_key().0, None),
+            keypair,
+            utxos: vec![],
+        }
+    }
+
+    pub fn script_pubkey(&self) -> &ScriptBuf {
+        &self.script_pubkey
+    }
+
+    pub fn add_utxo(&mut self, outpoint: OutPoint, txout: TxOut) {
+        self.utxos.push((outpoint, txout));
+    }
+
+    pub fn utxos(&self) -> &[(OutPoint, TxOut)] {
+        &self.utxos
+    }
+
+    pub fn balance(&self) -> Amount {
+        Amount::from_sat(self.utxos.iter().map(|(_, txout)| txout.value).sum())
+    }
+}
+
+/// Builds a child of `parent` spending its `anchor` output, owned by `anchor_keypair`, and as
+/// many coins of `wallet` as needed for both transactions to pay `fee_rate` together. The
+/// change goes back to the wallet, which can spend it before it confirms.
+pub fn build_cpfp(
+    secp: &Secp256k1<secp256k1::All>,
+    parent: &Transaction,
+    parent_fee: Amount,
+    anchor: (OutPoint, TxOut),
+    anchor_keypair: &KeyPair,
+    fee_rate: u64,
+    wallet: &mut FeeWallet,
+) -> Result<Transaction, String> {
+    if anchor.0.txid != parent.txid() {
+        return Err(format!(
+            "{} is not an output of {}",
+            anchor.0,
+            parent.txid()
+        ));
+    }
+    let mut candidates = wallet.utxos.clone();
+    candidates.sort_by_key(|(_, txout)| std::cmp::Reverse(txout.value));
+    let mut candidates = candidates.into_iter();
+    let mut inputs = vec![anchor];
+    let (mut tx, prevouts) = loop {
+        let outpoints = inputs
+            .iter()
+            .map(|(outpoint, _)| *outpoint)
+            .collect::<Vec<_>>();
+        let mut tx = cpfp_template(&outpoints, &wallet.script_pubkey);
+        let package_fee = fee_rate * (parent.vsize() + tx.vsize()) as u64;
+        let child_fee = package_fee.saturating_sub(parent_fee.to_sat());
+        let value_in: u64 = inputs.iter().map(|(_, txout)| txout.value).sum();
+        if value_in > child_fee + DUST_LIMIT {
+            tx.output[0].value = value_in - child_fee;
+            break (tx, inputs);
+        }
+        inputs.push(candidates.next().ok_or(format!(
+            "fee wallet holds {} sats, {} needed to bump {} to {} sat/vB",
+            wallet.balance().to_sat(),
+            child_fee + DUST_LIMIT,
+            parent.txid(),
+            fee_rate
+        ))?);
+    };
+
+    let prevouts_txouts = prevouts
+        .iter()
+        .map(|(_, txout)| txout.clone())
+        .collect::<Vec<_>>();
+    let mut witnesses = vec![];
+    for input_index in 0..tx.input.len() {
+        let keypair = if input_index == 0 {
+            anchor_keypair
+        } else {
+            &wallet.keypair
+        };
+        witnesses.push(sign_key_spend(
+            secp,
+            &tx,
+            input_index,
+            &prevouts_txouts,
+            keypair,
+        )?);
+    }
+    for (input, witness) in tx.input.iter_mut().zip(witnesses) {
+        input.witness = witness;
+    }
+
+    wallet
+        .utxos
+        .retain(|(outpoint, _)| !prevouts.iter().any(|(spent, _)| spent == outpoint));
+    wallet.add_utxo(OutPoint::new(tx.txid(), 0), tx.output[0].clone());
+    Ok(tx)
+}
+
+/// Size of a child spending an anchor and `wallet_inputs` coins of a fee wallet paying to
+/// `script_pubkey`, once signed.
+pub fn cpfp_vsize(script_pubkey: &ScriptBuf, wallet_inputs: usize) -> usize {
+    cpfp_template(&vec![OutPoint::null(); wallet_inputs + 1], script_pubkey).vsize()
+}
+
+/// Child spending `outpoints` through their key path, with placeholder signatures, to a
+/// single output of no value yet.
+fn cpfp_template(outpoints: &[OutPoint], script_pubkey: &ScriptBuf) -> Transaction {
+    Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: outpoints
+            .iter()
+            .map(|outpoint| TxIn {
+                previous_output: *outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                witness: Witness::from_slice(&[[0; KEY_SPEND_SIGNATURE_SIZE]]),
+            })
+            .collect(),
+        output: vec![TxOut {
+            value: 0,
+            script_pubkey: script_pubkey.clone(),
+        }],
+    }
+}
+
+/// Signs input `input_index` of `tx` through the key path of an output paying to `keypair`,
+/// with no script tree.
+pub fn sign_key_spend(
+    secp: &Secp256k1<secp256k1::All>,
+    tx: &Transaction,
+    input_index: usize,
+    prevouts: &[TxOut],
+    keypair: &KeyPair,
+) -> Result<Witness, String> {
+    let sighash = SighashCache::new(tx)
+        .taproot_key_spend_signature_hash(
+            input_index,
+            &Prevouts::All(prevouts),
+            TapSighashType::Default,
+        )
+        .map_err(|e| format!("unable to compute sighash: {}", e))?;
+    let tweaked = keypair.tap_tweak(secp, None).to_inner();
+    let signature = bitcoin::taproot::Signature {
+        sig: secp.sign_schnorr(&Message::from(sighash), &tweaked),
+        hash_ty: TapSighashType::Default,
+    };
+    Ok(Witness::from_slice(&[signature.to_vec()]))
+}
+
+#[test]
+fn test_cpfp_gets_a_stuck_transaction_mined() {
+    use crate::chain::mock::MockChain;
+    use crate::protocol::ANCHOR_VALUE;
+
+    assert_eq!(FeePolicy::default().fee_rate(1, 10), 2);
+    assert_eq!(FeePolicy::default().fee_rate(1, 3), 20);
+    assert_eq!(FeePolicy::default().fee_rate(40, 10), 40);
+    assert_eq!(FeePolicy::default().fee_rate(1_000, 0), 500);
+
+    let secp = Secp256k1::new();
+    let keypair = KeyPair::from_seckey_slice(&secp, &[5; 32]).unwrap();
+    let mut wallet = FeeWallet::new(&secp, KeyPair::from_seckey_slice(&secp, &[6; 32]).unwrap());
+    let mut chain = MockChain::new();
+    let script_pubkey = ScriptBuf::new_v1_p2tr(&secp, keypair.x_only_public_key().0, None);
+    let funding = TxOut {
+        value: 10_000,
+        script_pubkey: script_pubkey.clone(),
+    };
+    let outpoint = chain.mint(funding.clone());
+    let wallet_txout = TxOut {
+        value: 5_000,
+        script_pubkey: wallet.script_pubkey().clone(),
+    };
+    wallet.add_utxo(chain.mint(wallet_txout.clone()), wallet_txout);
+
+    let mut parent = Transaction {
+        version: 2,
+        lock_time: LockTime::ZERO,
+        input: vec![TxIn {
+            previous_output: outpoint,
+            ..Default::default()
+        }],
+        output: vec![
+            TxOut {
+                value: 10_000 - ANCHOR_VALUE - 100,
+                script_pubkey: script_pubkey.clone(),
+            },
+            TxOut {
+                value: ANCHOR_VALUE,
+                script_pubkey,
+            },
+        ],
+    };
+    parent.input[0].witness = sign_key_spend(&secp, &parent, 0, &[funding], &keypair).unwrap();
+    chain.set_min_fee_rate(10);
+    let parent_fee = chain.broadcast(parent.clone()).unwrap();
+    assert!(fee_rate(parent_fee, parent.vsize()) < 10);
+    assert!(chain.mine_block().transactions.is_empty());
+
+    let anchor = (OutPoint::new(parent.txid(), 1), parent.output[1].clone());
+    let child = build_cpfp(
+        &secp,
+        &parent,
+        parent_fee,
+        anchor.clone(),
+        &keypair,
+        10,
+        &mut wallet,
+    )
+    .unwrap();
+    let child_fee = chain.broadcast(child.clone()).unwrap();
+    assert!(fee_rate(parent_fee + child_fee, parent.vsize() + child.vsize()) >= 10);
+    assert_eq!(
+        wallet.utxos(),
+        &[(OutPoint::new(child.txid(), 0), child.output[0].clone())]
+    );
+    assert_eq!(
+        wallet.balance(),
+        Amount::from_sat(5_000 + ANCHOR_VALUE) - child_fee
+    );
+    assert_eq!(chain.mine_block().transactions, vec![parent.clone(), child]);
+
+    // The wallet is too poor for the next bump.
+    assert!(build_cpfp(
+        &secp,
+        &parent,
+        parent_fee,
+        anchor,
+        &keypair,
+        1_000,
+        &mut wallet
+    )
+    .is_err());
+}
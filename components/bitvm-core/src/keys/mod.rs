@@ -0,0 +1,277 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use bitcoin::bip32::{ChildNumber, DerivationPath, ExtendedPrivKey, Fingerprint, KeySource};
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::secp256k1::{self, rand, Secp256k1, SecretKey};
+use bitcoin::Network;
+use zeroize::Zeroizing;
+
+use crate::protocol::Role;
+use crate::storage;
+use crate::vault::Vault;
+
+/// BIP43 purpose of the contract keys, as they sign taproot script spends.
+const PURPOSE: u32 = 86;
+
+const SEED_LENGTH: usize = 32;
+
+/// Index of the next contract key, persisted next to the seed.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct KeyStoreState {
+    next_index: u32,
+}
+
+/// A participant's keys for one role: a BIP32 seed, from which a fresh key is derived for
+/// every contract at `m/86'/<coin>'/<role>'/<index>'`.
+pub struct KeyStore {
+    role: Role,
+    master: ExtendedPrivKey,
+    state_path: PathBuf,
+    state: KeyStoreState,
+}
+
+impl KeyStore {
+    /// Loads the seed sealed by `vault` at `seed_path`, or generates one when the file is
+    /// missing.
+    pub fn load_or_generate(
+        role: Role,
+        seed_path: &Path,
+        vault: &Vault,
+        network: Network,
+    ) -> Result<KeyStore, String> {
+        if !seed_path.exists() {
+            let seed = Zeroizing::new(rand::random::<[u8; SEED_LENGTH]>());
+            write_seed(seed_path, vault, seed.as_slice())?;
+        }
+        KeyStore::load(role, seed_path, vault, network)
+    }
+
+    /// Loads the seed sealed by `vault` at `seed_path`, which must exist.
+    pub fn load(
+        role: Role,
+        seed_path: &Path,
+        vault: &Vault,
+        network: Network,
+    ) -> Result<KeyStore, String> {
+        if !seed_path.exists() {
+            return Err(format!("seed {} not found", seed_path.display()));
+        }
+        let hex: Zeroizing<String> = Zeroizing::new(vault.read_secrets(seed_path)?);
+        let seed = Zeroizing::new(
+            Vec::<u8>::from_hex(&hex)
+                .map_err(|e| format!("invalid seed {}: {}", seed_path.display(), e))?,
+        );
+        let master = ExtendedPrivKey::new_master(network, &seed)
+            .map_err(|e| format!("invalid seed {}: {}", seed_path.display(), e))?;
+        let state_path = seed_path.with_extension("json");
+        let state = match state_path.exists() {
+            true => storage::read_json(&state_path)?,
+            false => KeyStoreState::default(),
+        };
+        Ok(KeyStore {
+            role,
+            master,
+            state_path,
+            state,
+        })
+    }
+
+    pub fn role(&self) -> Role {
+        self.role
+    }
+
+    pub fn fingerprint<C: secp256k1::Signing>(&self, secp: &Secp256k1<C>) -> Fingerprint {
+        self.master.fingerprint(secp)
+    }
+
+    /// Derivation path of the `index`-th contract key.
+    pub fn contract_path(&self, index: u32) -> Result<DerivationPath, String> {
+        let coin_type = match self.master.network {
+            Network::Bitcoin => 0,
+            _ => 1,
+        };
+        let role = match self.role {
+            Role::Prover => 0,
+            Role::Verifier => 1,
+        };
+        [PURPOSE, coin_type, role, index]
+            .into_iter()
+            .map(|index| {
+                ChildNumber::from_hardened_idx(index)
+                    .map_err(|e| format!("invalid derivation index {}: {}", index, e))
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .map(DerivationPath::from)
+    }
+
+    /// Path of the key the next contract will use, without reserving it.
+    pub fn next_contract_path(&self) -> Result<DerivationPath, String> {
+        self.contract_path(self.state.next_index)
+    }
+
+    pub fn derive<C: secp256k1::Signing>(
+        &self,
+        secp: &Secp256k1<C>,
+        path: &DerivationPath,
+    ) -> Result<SecretKey, String> {
+        self.master
+            .derive_priv(secp, path)
+            .map(|key| key.private_key)
+            .map_err(|e| format!("unable to derive key {}: {}", path, e))
+    }
+
+    /// Reserves the key of a new contract. The index is persisted before the key is returned,
+    /// so a key is never handed out twice. Other processes sharing the seed may reserve keys
+    /// too: the index is read again and bumped under a lock on the state file.
+    pub fn next_contract_key<C: secp256k1::Signing>(
+        &mut self,
+        secp: &Secp256k1<C>,
+    ) -> Result<(KeySource, SecretKey), String> {
+        self.with_next_contract_key(secp, |key_source, secret_key| Ok((key_source, secret_key)))
+    }
+
+    /// Hands the key of a new contract to `use_key`, and reserves it only if `use_key`
+    /// succeeds, so that failed setups do not burn indexes. The state file stays locked
+    /// meanwhile.
+    pub fn with_next_contract_key<C: secp256k1::Signing, T>(
+        &mut self,
+        secp: &Secp256k1<C>,
+        use_key: impl FnOnce(KeySource, SecretKey) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let lock_path = self.state_path.with_extension("lock");
+        let lock = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .map_err(|e| format!("unable to open {}: {}", lock_path.display(), e))?;
+        lock.lock()
+            .map_err(|e| format!("unable to lock {}: {}", lock_path.display(), e))?;
+        if self.state_path.exists() {
+            self.state = storage::read_json(&self.state_path)?;
+        }
+        let path = self.next_contract_path()?;
+        let secret_key = self.derive(secp, &path)?;
+        let result = use_key((self.fingerprint(secp), path), secret_key)?;
+        self.state.next_index += 1;
+        storage::write_json(&self.state_path, &self.state)?;
+        Ok(result)
+    }
+}
+
+/// Key stores of both roles, as a node may play either side of a contract.
+pub struct ParticipantKeys {
+    pub prover: KeyStore,
+    pub verifier: KeyStore,
+}
+
+impl ParticipantKeys {
+    pub fn load_or_generate(
+        prover_seed_path: &Path,
+        verifier_seed_path: &Path,
+        vault: &Vault,
+        network: Network,
+    ) -> Result<ParticipantKeys, String> {
+        Ok(ParticipantKeys {
+            prover: KeyStore::load_or_generate(Role::Prover, prover_seed_path, vault, network)?,
+            verifier: KeyStore::load_or_generate(
+                Role::Verifier,
+                verifier_seed_path,
+                vault,
+                network,
+            )?,
+        })
+    }
+
+    /// Loads the seeds of both roles, which must exist.
+    pub fn load(
+        prover_seed_path: &Path,
+        verifier_seed_path: &Path,
+        vault: &Vault,
+        network: Network,
+    ) -> Result<ParticipantKeys, String> {
+        Ok(ParticipantKeys {
+            prover: KeyStore::load(Role::Prover, prover_seed_path, vault, network)?,
+            verifier: KeyStore::load(Role::Verifier, verifier_seed_path, vault, network)?,
+        })
+    }
+
+    pub fn store(&self, role: Role) -> &KeyStore {
+        match role {
+            Role::Prover => &self.prover,
+            Role::Verifier => &self.verifier,
+        }
+    }
+
+    pub fn store_mut(&mut self, role: Role) -> &mut KeyStore {
+        match role {
+            Role::Prover => &mut self.prover,
+            Role::Verifier => &mut self.verifier,
+        }
+    }
+}
+
+/// Seals the hex encoded `seed` at `path`, which must not exist yet.
+fn write_seed(path: &Path, vault: &Vault, seed: &[u8]) -> Result<(), String> {
+    if path.exists() {
+        return Err(format!("{} already exists", path.display()));
+    }
+    let hex = Zeroizing::new(
+        seed.iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<String>(),
+    );
+    vault.write_secrets(path, &*hex)
+}
+
+#[test]
+fn test_contract_keys_are_distinct_and_survive_reloads() {
+    let secp = Secp256k1::new();
+    let keys_dir = std::env::temp_dir().join(format!("bitvm-keys-{}", rand::random::<u64>()));
+    let prover_seed = keys_dir.join("prover.seed");
+    let verifier_seed = keys_dir.join("verifier.seed");
+
+    let vault = Vault::ephemeral();
+    let mut keys =
+        ParticipantKeys::load_or_generate(&prover_seed, &verifier_seed, &vault, Network::Regtest)
+            .unwrap();
+    let (prover_source, prover_key) = keys.prover.next_contract_key(&secp).unwrap();
+    let (verifier_source, verifier_key) = keys.verifier.next_contract_key(&secp).unwrap();
+    assert_ne!(prover_key, verifier_key);
+    assert_eq!(prover_source.1.to_string(), "m/86'/1'/0'/0'");
+    assert_eq!(verifier_source.1.to_string(), "m/86'/1'/1'/0'");
+    let (_, second_key) = keys.prover.next_contract_key(&secp).unwrap();
+    assert_ne!(prover_key, second_key);
+
+    let mut reloaded =
+        ParticipantKeys::load_or_generate(&prover_seed, &verifier_seed, &vault, Network::Regtest)
+            .unwrap();
+    assert_eq!(reloaded.prover.fingerprint(&secp), prover_source.0);
+    assert_eq!(
+        reloaded.prover.derive(&secp, &prover_source.1).unwrap(),
+        prover_key
+    );
+    let (third_source, _) = reloaded.prover.next_contract_key(&secp).unwrap();
+    assert_eq!(third_source.1.to_string(), "m/86'/1'/0'/2'");
+    // A store loaded before `reloaded` reserved its key does not hand it out again.
+    let (fourth_source, _) = keys.prover.next_contract_key(&secp).unwrap();
+    assert_eq!(fourth_source.1.to_string(), "m/86'/1'/0'/3'");
+    // A key whose contract failed to be set up is handed out again.
+    let failed = keys
+        .prover
+        .with_next_contract_key(&secp, |_, _| Err::<(), _>("invalid setup".to_string()));
+    assert!(failed.is_err());
+    let (fifth_source, _) = keys.prover.next_contract_key(&secp).unwrap();
+    assert_eq!(fifth_source.1.to_string(), "m/86'/1'/0'/4'");
+
+    // Seeds are only readable through the vault that sealed them.
+    assert!(storage::read_json::<crate::vault::Sealed>(&prover_seed).is_ok());
+    let other_vault = Vault::ephemeral();
+    assert!(KeyStore::load(Role::Prover, &prover_seed, &other_vault, Network::Regtest).is_err());
+
+    let missing_seed = keys_dir.join("missing.seed");
+    assert!(KeyStore::load(Role::Prover, &missing_seed, &vault, Network::Regtest).is_err());
+    assert!(!missing_seed.exists());
+    let _ = fs::remove_dir_all(&keys_dir);
+}
@@ -1,36 +1,56 @@
-use bitcoin::secp256k1::SecretKey;
 use bitcoin::secp256k1::{self, Secp256k1};
 use bitvm_types::Circuit;
+use protocol::Participants;
 use tapleaf::commitment_address::compute_commitment_address;
-use sha2::{Digest, Sha256};
-
 
+#[macro_use]
+extern crate serde_derive;
 extern crate pest;
 
+pub mod bisection;
 pub mod bristol;
+pub mod chain;
 pub mod circuit;
+pub mod cost;
+pub mod equivocation;
+pub mod fees;
+pub mod keys;
+pub mod protocol;
+pub mod prover;
+pub mod setup;
+#[cfg(any(test, feature = "simulation"))]
+pub mod simulation;
+pub mod storage;
 pub mod tapleaf;
+pub mod vault;
+pub mod verifier;
+pub mod witness;
 
 pub enum SerializedCircuit<'a> {
     Bristol(&'a str),
 }
 
-pub fn read_and_check_circuit(serialized_circuit: &SerializedCircuit) -> Result<Circuit, String> {
+/// Parses a circuit and checks its commitments fit in a funding output locked to
+/// `participants`.
+pub fn read_and_check_circuit(
+    serialized_circuit: &SerializedCircuit,
+    participants: &Participants,
+) -> Result<Circuit, String> {
     let circuit = match serialized_circuit {
         SerializedCircuit::Bristol(src) => bristol::parser::read_circuit(src)?,
     };
 
     let secp: Secp256k1<secp256k1::All> = Secp256k1::new();
 
-    let paul_secret = {
-        let seed = Sha256::digest(&[0]);
-        SecretKey::from_slice(&seed).unwrap()
-    };
-    let vicky_secret = {
-        let seed = Sha256::digest(&[0]);
-        SecretKey::from_slice(&seed).unwrap()
-    };
-
-    compute_commitment_address(&circuit, &secp, &paul_secret.public_key(&secp), &vicky_secret.public_key(&secp));
+    let commitment_set = circuit.compute_commitment_set();
+    let committed_wires = protocol::collect_committed_wires_ids(&circuit);
+    compute_commitment_address(
+        &commitment_set,
+        &committed_wires,
+        &secp,
+        &participants.prover,
+        &participants.verifier,
+        10,
+    )?;
     Ok(circuit)
 }
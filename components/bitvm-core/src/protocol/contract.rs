@@ -0,0 +1,184 @@
+use std::path::{Path, PathBuf};
+
+use bitcoin::hashes::sha256;
+use bitcoin::key::XOnlyPublicKey;
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::taproot::TapNodeHash;
+use bitcoin::{OutPoint, Transaction, TxOut, Txid};
+
+use super::{
+    ContractCommitments, ContractParameters, ContractSetup, Participants, PreSignature, Role,
+    StageOutput, TransactionGraph, TransactionKind,
+};
+use crate::setup::hash_circuit;
+use crate::storage;
+
+/// Version of the contract file format. Files of any other version are rejected.
+pub const CONTRACT_FORMAT_VERSION: u16 = 1;
+
+pub const CONTRACT_FILE: &str = "contract.json";
+
+/// A taproot output of the graph.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractOutput {
+    pub stage: StageOutput,
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+    pub internal_key: XOnlyPublicKey,
+    pub merkle_root: Option<TapNodeHash>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractTransaction {
+    pub kind: TransactionKind,
+    pub tx: Transaction,
+    pub spent_output: Option<StageOutput>,
+}
+
+/// Everything public about a contract: the terms both parties agreed on, and the graph they
+/// pre-signed. It holds none of Paul's or Vicky's secrets, so it can be shared, and either
+/// party can check it by rebuilding the graph from the terms.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Contract {
+    pub version: u16,
+    pub contract_id: Txid,
+    pub circuit_hash: sha256::Hash,
+    /// Bristol source of the circuit, which `circuit_hash` commits to.
+    pub circuit_source: String,
+    pub participants: Participants,
+    pub parameters: ContractParameters,
+    pub commitments: ContractCommitments,
+    pub outputs: Vec<ContractOutput>,
+    pub transactions: Vec<ContractTransaction>,
+    pub prover_presignatures: Vec<PreSignature>,
+    pub verifier_presignatures: Vec<PreSignature>,
+}
+
+impl Contract {
+    pub fn from_graph(setup: &ContractSetup, graph: &TransactionGraph) -> Result<Contract, String> {
+        Ok(Contract {
+            version: CONTRACT_FORMAT_VERSION,
+            contract_id: graph.transaction(&TransactionKind::Funding)?.tx.txid(),
+            circuit_hash: hash_circuit(&setup.circuit_source),
+            circuit_source: setup.circuit_source.clone(),
+            participants: setup.participants.clone(),
+            parameters: setup.parameters.clone(),
+            commitments: setup.commitments.clone(),
+            outputs: graph
+                .outputs
+                .iter()
+                .map(|(stage, output)| ContractOutput {
+                    stage: *stage,
+                    outpoint: output.outpoint,
+                    txout: output.txout.clone(),
+                    internal_key: output.spend_info.internal_key(),
+                    merkle_root: output.spend_info.merkle_root(),
+                })
+                .collect(),
+            transactions: graph
+                .transactions
+                .iter()
+                .map(|(kind, transaction)| ContractTransaction {
+                    kind: *kind,
+                    tx: transaction.tx.clone(),
+                    spent_output: transaction.spent_output,
+                })
+                .collect(),
+            prover_presignatures: graph.collect_presignatures(Role::Prover),
+            verifier_presignatures: graph.collect_presignatures(Role::Verifier),
+        })
+    }
+
+    pub fn setup(&self) -> ContractSetup {
+        ContractSetup {
+            circuit_source: self.circuit_source.clone(),
+            participants: self.participants.clone(),
+            parameters: self.parameters.clone(),
+            commitments: self.commitments.clone(),
+        }
+    }
+
+    /// Rebuilds the graph from the terms of the contract, checks the outputs, transactions
+    /// and pre-signatures of the file against it, and returns it.
+    pub fn verify(&self, secp: &Secp256k1<secp256k1::All>) -> Result<TransactionGraph, String> {
+        if self.version != CONTRACT_FORMAT_VERSION {
+            return Err(format!(
+                "unsupported contract format version {} (expected {})",
+                self.version, CONTRACT_FORMAT_VERSION
+            ));
+        }
+        if hash_circuit(&self.circuit_source) != self.circuit_hash {
+            return Err("circuit source does not match the circuit hash".to_string());
+        }
+        let setup = self.setup();
+        let mut graph = setup.build_graph(secp, &setup.read_circuit()?)?;
+        let rebuilt = Contract::from_graph(&setup, &graph)?;
+        if rebuilt.contract_id != self.contract_id {
+            return Err(format!(
+                "contract id {} does not match the funding transaction {}",
+                self.contract_id, rebuilt.contract_id
+            ));
+        }
+        if rebuilt.outputs != self.outputs {
+            return Err("outputs do not match the terms of the contract".to_string());
+        }
+        if rebuilt.transactions != self.transactions {
+            return Err("transactions do not match the terms of the contract".to_string());
+        }
+        graph.add_presignatures(secp, Role::Prover, &self.prover_presignatures)?;
+        graph.add_presignatures(secp, Role::Verifier, &self.verifier_presignatures)?;
+        Ok(graph)
+    }
+
+    pub fn save(&self, working_dir: &Path) -> Result<(), String> {
+        storage::write_json(&contract_path(working_dir, &self.contract_id), self)
+    }
+
+    pub fn restore(working_dir: &Path, contract_id: &Txid) -> Result<Contract, String> {
+        storage::read_json(&contract_path(working_dir, contract_id))
+    }
+}
+
+pub fn contract_path(working_dir: &Path, contract_id: &Txid) -> PathBuf {
+    storage::contract_dir(working_dir, contract_id).join(CONTRACT_FILE)
+}
+
+#[test]
+fn test_contract_file_is_verified_against_its_terms() {
+    use bitcoin::secp256k1::KeyPair;
+
+    let secp = Secp256k1::new();
+    let prover_keypair = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+    let verifier_keypair = KeyPair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let (circuit, setup, _) = super::build_test_setup(
+        include_str!("../bristol/fixtures/test_vector_1.bristol"),
+        &prover_keypair,
+        &verifier_keypair,
+    );
+    let mut graph = setup.build_graph(&secp, &circuit).unwrap();
+    for keypair in [&prover_keypair, &verifier_keypair] {
+        let role = match keypair == &prover_keypair {
+            true => Role::Prover,
+            false => Role::Verifier,
+        };
+        let signatures = graph.presign(&secp, keypair).unwrap();
+        graph.add_presignatures(&secp, role, &signatures).unwrap();
+    }
+
+    let contract = Contract::from_graph(&setup, &graph).unwrap();
+    let json = serde_json::to_string(&contract).unwrap();
+    let contract: Contract = serde_json::from_str(&json).unwrap();
+    let verified = contract.verify(&secp).unwrap();
+    assert_eq!(verified.count_missing_presignatures(), 0);
+    assert!(!json.contains("preimage"));
+
+    let mut tampered = contract.clone();
+    tampered.parameters.amount = bitcoin::Amount::from_sat(1);
+    assert!(tampered.verify(&secp).is_err());
+    let mut tampered = contract.clone();
+    tampered.outputs[0].txout.value += 1;
+    assert!(tampered.verify(&secp).is_err());
+    let mut tampered = contract;
+    tampered.verifier_presignatures[0].signature = tampered.prover_presignatures[0].signature;
+    assert!(tampered.verify(&secp).is_err());
+}
@@ -0,0 +1,1465 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bitcoin::absolute::LockTime;
+use bitcoin::bip32::KeySource;
+use bitcoin::psbt::PartiallySignedTransaction;
+use bitcoin::secp256k1::{self, schnorr, KeyPair, Message, PublicKey, Secp256k1};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::taproot::{LeafVersion, TapLeafHash, TaprootSpendInfo};
+use bitcoin::{Amount, OutPoint, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+use bitvm_types::{Circuit, CommitmentSet, GateId, WireId};
+
+use crate::bisection::{compute_bisection_rounds, compute_midpoint, StateWires};
+use crate::fees::{build_cpfp, FeeWallet};
+use crate::tapleaf::anti_contradiction_address::{
+    build_equivocation_condition, build_reclaim_leaf, compute_anti_contradiction_address,
+};
+use crate::tapleaf::challenge_address::{
+    build_tap_scripts_for_defectuous_gate, compute_challenge_address,
+};
+use crate::tapleaf::challenge_hashlock::{
+    build_challenge_condition, build_gate_response_condition, build_state_response_condition,
+    ChallengeHashes,
+};
+use crate::tapleaf::commitment_address::{
+    build_commit_leaf, build_leaf_1, build_leaf_2, compute_commitment_address,
+};
+use crate::tapleaf::{
+    augment_with_multisig, augment_with_timelock, build_taproot_spend_info, seal_with_multisig,
+};
+use crate::witness::WitnessParser;
+
+pub mod contract;
+pub mod psbt;
+
+/// Outputs below this value are not relayed.
+pub(crate) const DUST_LIMIT: u64 = 330;
+
+/// Value of the output each transaction carries for its broadcaster to bump its fee, by
+/// spending it in a child paying for both.
+pub const ANCHOR_VALUE: u64 = 330;
+
+/// Output of a graph transaction reserved for fee bumping.
+pub const ANCHOR_VOUT: u32 = 1;
+
+/// Wires whose values Paul reveals in the commit transaction: the inputs, then the outputs.
+pub fn collect_committed_wires_ids(circuit: &Circuit) -> Vec<WireId> {
+    let mut committed_wires = circuit
+        .collect_input_wires_ids()
+        .into_iter()
+        .cloned()
+        .collect::<Vec<_>>();
+    committed_wires.extend(circuit.collect_output_wires_ids());
+    committed_wires
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Role {
+    Prover,
+    Verifier,
+}
+
+impl Role {
+    pub fn counterparty(&self) -> Role {
+        match self {
+            Role::Prover => Role::Verifier,
+            Role::Verifier => Role::Prover,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Participants {
+    pub prover: PublicKey,
+    pub verifier: PublicKey,
+}
+
+impl Participants {
+    pub fn public_key(&self, role: Role) -> &PublicKey {
+        match role {
+            Role::Prover => &self.prover,
+            Role::Verifier => &self.verifier,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TimelockParameters {
+    /// Blocks each party has to publish its next move before the other one can claim the funds.
+    pub response_timeout: u16,
+}
+
+impl Default for TimelockParameters {
+    fn default() -> Self {
+        TimelockParameters {
+            response_timeout: 144,
+        }
+    }
+}
+
+impl TimelockParameters {
+    /// Blocks Paul's collateral stays locked: long enough for the slowest possible dispute to
+    /// reach its last reveal, plus one window for Vicky to punish an equivocation.
+    pub fn compute_reclaim_timeout(&self, rounds: usize) -> Result<u16, String> {
+        let windows = 2 * rounds as u64 + 5;
+        u16::try_from(windows * self.response_timeout as u64)
+            .map_err(|_| "response timeout too long for the number of rounds".to_string())
+    }
+}
+
+/// A wallet output used to fund the contract.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FundingInput {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractParameters {
+    /// Amount at stake in the dispute.
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub amount: Amount,
+    /// Paul's collateral, forfeited to Vicky if he ever equivocates.
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub collateral: Amount,
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub fee_per_transaction: Amount,
+    pub timelocks: TimelockParameters,
+    pub funding_inputs: Vec<FundingInput>,
+    pub change_script_pubkey: ScriptBuf,
+}
+
+/// Least Vicky accepts of the parameters Paul proposes: enough at stake for the contract to
+/// be worth watching, and enough time to answer each of his moves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractTerms {
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub min_amount: Amount,
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub min_collateral: Amount,
+    pub min_response_timeout: u16,
+}
+
+impl Default for ContractTerms {
+    fn default() -> Self {
+        ContractTerms {
+            min_amount: Amount::from_sat(10_000),
+            min_collateral: Amount::from_sat(10_000),
+            min_response_timeout: 6,
+        }
+    }
+}
+
+impl ContractTerms {
+    /// Checks `parameters` meet the terms, reporting every shortfall at once.
+    pub fn check(&self, parameters: &ContractParameters) -> Result<(), String> {
+        let mut errors = vec![];
+        if parameters.amount < self.min_amount {
+            errors.push(format!(
+                "amount {} is below the minimum of {}",
+                parameters.amount, self.min_amount
+            ));
+        }
+        if parameters.collateral < self.min_collateral {
+            errors.push(format!(
+                "collateral {} is below the minimum of {}",
+                parameters.collateral, self.min_collateral
+            ));
+        }
+        if parameters.timelocks.response_timeout < self.min_response_timeout {
+            errors.push(format!(
+                "response timeout of {} blocks is below the minimum of {}",
+                parameters.timelocks.response_timeout, self.min_response_timeout
+            ));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("; "))
+        }
+    }
+}
+
+/// Public commitments exchanged during setup.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractCommitments {
+    /// Paul's bit commitments for every wire, opened in the commitment, the bisection
+    /// responses and the gate response alike.
+    pub commitment_set: CommitmentSet,
+    /// Vicky's challenge hashlocks.
+    pub challenge_hashes: ChallengeHashes,
+}
+
+/// Everything both parties agreed on during setup. The transaction graph is rebuilt from it
+/// rather than stored.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ContractSetup {
+    /// Bristol source of the circuit.
+    pub circuit_source: String,
+    pub participants: Participants,
+    pub parameters: ContractParameters,
+    pub commitments: ContractCommitments,
+}
+
+impl ContractSetup {
+    pub fn read_circuit(&self) -> Result<Circuit, String> {
+        crate::bristol::parser::read_circuit(&self.circuit_source)
+    }
+
+    pub fn build_graph(
+        &self,
+        secp: &Secp256k1<secp256k1::All>,
+        circuit: &Circuit,
+    ) -> Result<TransactionGraph, String> {
+        TransactionGraph::build(
+            secp,
+            circuit,
+            &self.commitments,
+            &self.participants,
+            &self.parameters,
+        )
+    }
+}
+
+/// What the participants' state machines react to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractEvent {
+    /// Both parties exchanged their pre-signatures.
+    SetupCompleted,
+    /// The funding transaction confirmed.
+    FundingConfirmed,
+    /// A transaction spending one of the contract outputs was seen on chain.
+    SpendObserved(Transaction),
+    /// The timelock of an unspent contract output expires in `blocks_left` blocks.
+    TimeoutNear {
+        output: StageOutput,
+        blocks_left: u32,
+    },
+}
+
+/// What the participants' state machines ask their host to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ContractAction {
+    /// The funding transaction, for the wallet to sign and broadcast.
+    Fund(Transaction),
+    Broadcast {
+        kind: TransactionKind,
+        tx: Transaction,
+    },
+}
+
+/// A contract transaction seen on chain.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TranscriptEntry {
+    pub kind: TransactionKind,
+    pub txid: Txid,
+}
+
+/// A participant's state machine for one contract, as driven by chain observers. Machines are
+/// `Send` so a service can share them with its API.
+pub trait ContractMachine: Send {
+    fn contract_id(&self) -> Txid;
+    fn role(&self) -> Role;
+    /// Current phase of the contract, for display.
+    fn phase(&self) -> String;
+    fn graph(&self) -> &TransactionGraph;
+    /// This participant's pre-signatures, to be sent to the other one.
+    fn presignatures(&self) -> Vec<PreSignature>;
+    fn receive_presignatures(&mut self, presignatures: &[PreSignature]) -> Result<(), String>;
+    /// Origin of this participant's key, when it was derived from a key store.
+    fn key_source(&self) -> Option<&KeySource>;
+    /// Contract transactions seen on chain so far, in order.
+    fn transcript(&self) -> &[TranscriptEntry];
+    fn handle(&mut self, event: &ContractEvent) -> Result<Vec<ContractAction>, String>;
+    /// Builds a child of `parent`, a transaction this participant broadcast paying
+    /// `parent_fee`, so that both pay `fee_rate` out of `wallet`.
+    fn bump_fee(
+        &self,
+        parent: &Transaction,
+        parent_fee: Amount,
+        fee_rate: u64,
+        wallet: &mut FeeWallet,
+    ) -> Result<Transaction, String>;
+    /// Copy of the persisted state, to roll back to when blocks are reorged out.
+    fn snapshot(&self) -> Result<serde_json::Value, String>;
+    fn rollback(&mut self, snapshot: serde_json::Value) -> Result<(), String>;
+
+    /// Exports `kind` as a PSBT, with the BIP32 derivation of this participant's key.
+    fn export_psbt(&self, kind: &TransactionKind) -> Result<PartiallySignedTransaction, String> {
+        self.graph().export_psbt(kind, &self.key_origins())
+    }
+
+    fn export_psbts(&self) -> Result<Vec<(TransactionKind, PartiallySignedTransaction)>, String> {
+        self.graph().export_psbts(&self.key_origins())
+    }
+
+    fn key_origins(&self) -> Vec<(Role, KeySource)> {
+        self.key_source()
+            .map(|key_source| (self.role(), key_source.clone()))
+            .into_iter()
+            .collect()
+    }
+
+    /// Combines the other participant's signatures carried by `psbt` into the graph, and
+    /// returns how many were not known yet.
+    fn import_psbt(&mut self, psbt: &PartiallySignedTransaction) -> Result<usize, String> {
+        let counterparty = self.role().counterparty();
+        let graph = self.graph();
+        let presignatures = graph
+            .read_psbt_presignatures(&Secp256k1::verification_only(), psbt, counterparty)?
+            .into_iter()
+            .filter(|presignature| {
+                graph.transactions[&presignature.kind]
+                    .signatures
+                    .get(&presignature.leaf_hash)
+                    .and_then(|signatures| signatures.get(counterparty))
+                    .is_none()
+            })
+            .collect::<Vec<_>>();
+        if !presignatures.is_empty() {
+            self.receive_presignatures(&presignatures)?;
+        }
+        Ok(presignatures.len())
+    }
+}
+
+/// Outputs of the graph, named after the move they are waiting for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum StageOutput {
+    /// Waiting for Paul to commit to the inputs and outputs of the circuit.
+    Funding,
+    /// Paul's collateral, claimable by Vicky on equivocation.
+    Collateral,
+    /// Waiting for Vicky's challenge of the given round. The last round selects a gate.
+    Challenge(usize),
+    /// Waiting for Paul's response to the given bisection round.
+    Response(usize),
+    /// Waiting for Paul to open the challenged gate.
+    GateResponse,
+    /// Waiting for Vicky to prove the opened gate inconsistent.
+    Judgement,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum TransactionKind {
+    Funding,
+    Commit,
+    CommitTimeout,
+    CooperativeClose,
+    CollateralReclaim,
+    /// Vicky's claim on the collateral after an equivocation. Built when it happens, rather
+    /// than pre-signed.
+    CollateralSlash,
+    Challenge(usize),
+    ChallengeTimeout(usize),
+    Response(usize),
+    ResponseTimeout(usize),
+    GateChallenge,
+    GateResponse,
+    GateResponseTimeout,
+    Slash,
+    SlashTimeout,
+}
+
+impl TransactionKind {
+    /// Party who broadcasts the transaction, and owns its anchor output.
+    pub fn broadcaster(&self) -> Role {
+        match self {
+            TransactionKind::Funding
+            | TransactionKind::Commit
+            | TransactionKind::CooperativeClose
+            | TransactionKind::CollateralReclaim
+            | TransactionKind::ChallengeTimeout(_)
+            | TransactionKind::Response(_)
+            | TransactionKind::GateResponse
+            | TransactionKind::SlashTimeout => Role::Prover,
+            TransactionKind::CommitTimeout
+            | TransactionKind::CollateralSlash
+            | TransactionKind::Challenge(_)
+            | TransactionKind::ResponseTimeout(_)
+            | TransactionKind::GateChallenge
+            | TransactionKind::GateResponseTimeout
+            | TransactionKind::Slash => Role::Verifier,
+        }
+    }
+}
+
+impl fmt::Display for TransactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TransactionKind::Funding => write!(f, "funding"),
+            TransactionKind::Commit => write!(f, "commit"),
+            TransactionKind::CommitTimeout => write!(f, "commit-timeout"),
+            TransactionKind::CooperativeClose => write!(f, "cooperative-close"),
+            TransactionKind::CollateralReclaim => write!(f, "collateral-reclaim"),
+            TransactionKind::CollateralSlash => write!(f, "collateral-slash"),
+            TransactionKind::Challenge(round) => write!(f, "challenge-{round}"),
+            TransactionKind::ChallengeTimeout(round) => write!(f, "challenge-timeout-{round}"),
+            TransactionKind::Response(round) => write!(f, "response-{round}"),
+            TransactionKind::ResponseTimeout(round) => write!(f, "response-timeout-{round}"),
+            TransactionKind::GateChallenge => write!(f, "gate-challenge"),
+            TransactionKind::GateResponse => write!(f, "gate-response"),
+            TransactionKind::GateResponseTimeout => write!(f, "gate-response-timeout"),
+            TransactionKind::Slash => write!(f, "slash"),
+            TransactionKind::SlashTimeout => write!(f, "slash-timeout"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolOutput {
+    pub outpoint: OutPoint,
+    pub txout: TxOut,
+    pub spend_info: TaprootSpendInfo,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LeafSignatures {
+    pub prover: Option<schnorr::Signature>,
+    pub verifier: Option<schnorr::Signature>,
+}
+
+impl LeafSignatures {
+    pub fn get(&self, role: Role) -> Option<&schnorr::Signature> {
+        match role {
+            Role::Prover => self.prover.as_ref(),
+            Role::Verifier => self.verifier.as_ref(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GraphTransaction {
+    pub tx: Transaction,
+    /// Output spent by the single input of the transaction. None for the funding transaction.
+    pub spent_output: Option<StageOutput>,
+    /// Leaves of the spent output this transaction may use.
+    pub leaves: Vec<ScriptBuf>,
+    /// Keys checked by these leaves, in the order their signatures sit on the witness stack.
+    pub signers: Vec<Role>,
+    /// Whether both parties sign this transaction during setup, before funding.
+    pub presigned: bool,
+    pub signatures: BTreeMap<TapLeafHash, LeafSignatures>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreSignature {
+    pub kind: TransactionKind,
+    pub leaf_hash: TapLeafHash,
+    pub signature: schnorr::Signature,
+}
+
+/// Every transaction of a BitVM contract, linked by outpoints.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransactionGraph {
+    pub participants: Participants,
+    pub rounds: usize,
+    pub timelocks: TimelockParameters,
+    /// Gates Vicky may challenge, in the order of the gate challenge and response leaves.
+    pub gates_ids: Vec<GateId>,
+    pub outputs: BTreeMap<StageOutput, ProtocolOutput>,
+    pub transactions: BTreeMap<TransactionKind, GraphTransaction>,
+}
+
+impl TransactionGraph {
+    pub fn build(
+        secp: &Secp256k1<secp256k1::All>,
+        circuit: &Circuit,
+        commitments: &ContractCommitments,
+        participants: &Participants,
+        parameters: &ContractParameters,
+    ) -> Result<TransactionGraph, String> {
+        let rounds = compute_bisection_rounds(circuit.gates.len());
+        if commitments.challenge_hashes.verdicts.len() != rounds {
+            return Err(format!(
+                "expected {} verdict hashlocks, got {}",
+                rounds,
+                commitments.challenge_hashes.verdicts.len()
+            ));
+        }
+        let fee = parameters.fee_per_transaction.to_sat();
+        let longest_path = 2 * rounds as u64 + 4;
+        if parameters.amount.to_sat() < (fee + ANCHOR_VALUE) * longest_path + DUST_LIMIT {
+            return Err(format!(
+                "amount too low to cover the fees of {} transactions",
+                longest_path
+            ));
+        }
+
+        let (prover, verifier) = (&participants.prover, &participants.verifier);
+        let timeout = parameters.timelocks.response_timeout;
+        let reclaim_timeout = parameters.timelocks.compute_reclaim_timeout(rounds)?;
+        let commitment_set = &commitments.commitment_set;
+
+        let mut graph = TransactionGraph {
+            participants: participants.clone(),
+            rounds,
+            timelocks: parameters.timelocks.clone(),
+            gates_ids: commitments.challenge_hashes.gates.keys().cloned().collect(),
+            outputs: BTreeMap::new(),
+            transactions: BTreeMap::new(),
+        };
+
+        // Funding: locks the amount at stake and Paul's collateral.
+        let committed_wires = collect_committed_wires_ids(circuit);
+        let funding_spend_info = compute_commitment_address(
+            commitment_set,
+            &committed_wires,
+            secp,
+            prover,
+            verifier,
+            timeout,
+        )?;
+        let collateral_spend_info = compute_anti_contradiction_address(
+            commitment_set,
+            secp,
+            prover,
+            verifier,
+            reclaim_timeout,
+        )?;
+        graph.add_funding(parameters, &funding_spend_info, &collateral_spend_info)?;
+
+        // Commit: Paul reveals the inputs and outputs of the circuit.
+        let challenge_0 = graph.build_stage_output(secp, 0, commitments, circuit)?;
+        graph.add_spend(
+            TransactionKind::Commit,
+            StageOutput::Funding,
+            vec![build_commit_leaf(
+                commitment_set,
+                &committed_wires,
+                prover,
+                verifier,
+            )?],
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            Some((StageOutput::Challenge(0), challenge_0)),
+            None,
+            fee,
+        )?;
+        graph.add_spend(
+            TransactionKind::CommitTimeout,
+            StageOutput::Funding,
+            vec![build_leaf_1(prover, verifier, timeout)],
+            Sequence::from_height(timeout),
+            None,
+            Some(verifier),
+            fee,
+        )?;
+        graph.add_spend(
+            TransactionKind::CooperativeClose,
+            StageOutput::Funding,
+            vec![build_leaf_2(prover, verifier)],
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            None,
+            Some(prover),
+            fee,
+        )?;
+        graph.add_spend(
+            TransactionKind::CollateralReclaim,
+            StageOutput::Collateral,
+            vec![build_reclaim_leaf(prover, reclaim_timeout)],
+            Sequence::from_height(reclaim_timeout),
+            None,
+            Some(prover),
+            fee,
+        )?;
+
+        // Bisection rounds: Vicky challenges a midpoint, Paul reveals his state there.
+        let state_wires = StateWires::new(circuit, &circuit.topological_order()?);
+        for round in 0..rounds {
+            let verdict = round
+                .checked_sub(1)
+                .map(|r| &commitments.challenge_hashes.verdicts[r]);
+            let challenge_leaf =
+                seal_with_multisig(build_challenge_condition(verdict, None), prover, verifier);
+            let response_leaves = graph.build_response_leaves(round, &state_wires, commitments)?;
+            let mut response_scripts = response_leaves.clone();
+            response_scripts.push(build_timeout_leaf(participants, timeout));
+            let response_output = build_taproot_spend_info(secp, response_scripts)?;
+            graph.add_spend(
+                TransactionKind::Challenge(round),
+                StageOutput::Challenge(round),
+                vec![challenge_leaf],
+                Sequence::ENABLE_RBF_NO_LOCKTIME,
+                Some((StageOutput::Response(round), response_output)),
+                None,
+                fee,
+            )?;
+            graph.add_timeout(
+                TransactionKind::ChallengeTimeout(round),
+                StageOutput::Challenge(round),
+                Role::Prover,
+                fee,
+            )?;
+
+            let next_challenge = graph.build_stage_output(secp, round + 1, commitments, circuit)?;
+            graph.add_spend(
+                TransactionKind::Response(round),
+                StageOutput::Response(round),
+                response_leaves,
+                Sequence::ENABLE_RBF_NO_LOCKTIME,
+                Some((StageOutput::Challenge(round + 1), next_challenge)),
+                None,
+                fee,
+            )?;
+            graph.add_timeout(
+                TransactionKind::ResponseTimeout(round),
+                StageOutput::Response(round),
+                Role::Verifier,
+                fee,
+            )?;
+        }
+
+        // Single gate challenge: Vicky selects a gate, Paul opens it, Vicky proves it wrong or
+        // that he opened both values of a wire along the way.
+        let mut gate_challenge_leaves = vec![];
+        let mut gate_response_leaves = vec![];
+        let mut slash_leaves = vec![];
+        let verdict = rounds
+            .checked_sub(1)
+            .map(|r| &commitments.challenge_hashes.verdicts[r]);
+        for (gate_id, gate_hash) in commitments.challenge_hashes.gates.iter() {
+            let gate = circuit
+                .gates
+                .get(gate_id)
+                .ok_or(format!("challenge hashlock for unknown gate {}", gate_id))?;
+            gate_challenge_leaves.push(seal_with_multisig(
+                build_challenge_condition(verdict, Some(gate_hash)),
+                prover,
+                verifier,
+            ));
+            let mut wires = gate.inputs();
+            wires.push(*gate_id);
+            let wires_bit_commitments = wires
+                .iter()
+                .map(|wire_id| {
+                    commitment_set
+                        .get(wire_id)
+                        .ok_or(format!("wire {} missing from the commitment set", wire_id))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            gate_response_leaves.push(seal_with_multisig(
+                build_gate_response_condition(gate_hash, &wires_bit_commitments),
+                prover,
+                verifier,
+            ));
+            for (_, _, condition) in
+                build_tap_scripts_for_defectuous_gate(*gate_id, gate, commitment_set)?
+            {
+                slash_leaves.push(seal_with_multisig(condition, prover, verifier));
+            }
+        }
+        for bit_commitment in commitment_set.hashes.values() {
+            slash_leaves.push(seal_with_multisig(
+                build_equivocation_condition(bit_commitment),
+                prover,
+                verifier,
+            ));
+        }
+        let mut gate_response_scripts = gate_response_leaves.clone();
+        gate_response_scripts.push(build_timeout_leaf(participants, timeout));
+        let gate_response_output = build_taproot_spend_info(secp, gate_response_scripts)?;
+        graph.add_spend(
+            TransactionKind::GateChallenge,
+            StageOutput::Challenge(rounds),
+            gate_challenge_leaves,
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            Some((StageOutput::GateResponse, gate_response_output)),
+            None,
+            fee,
+        )?;
+        graph.add_timeout(
+            TransactionKind::ChallengeTimeout(rounds),
+            StageOutput::Challenge(rounds),
+            Role::Prover,
+            fee,
+        )?;
+
+        let judgement_output =
+            compute_challenge_address(circuit, commitment_set, secp, prover, verifier, timeout)?;
+        graph.add_spend(
+            TransactionKind::GateResponse,
+            StageOutput::GateResponse,
+            gate_response_leaves,
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            Some((StageOutput::Judgement, judgement_output)),
+            None,
+            fee,
+        )?;
+        graph.add_timeout(
+            TransactionKind::GateResponseTimeout,
+            StageOutput::GateResponse,
+            Role::Verifier,
+            fee,
+        )?;
+
+        graph.add_spend(
+            TransactionKind::Slash,
+            StageOutput::Judgement,
+            slash_leaves,
+            Sequence::ENABLE_RBF_NO_LOCKTIME,
+            None,
+            Some(verifier),
+            fee,
+        )?;
+        graph.add_timeout(
+            TransactionKind::SlashTimeout,
+            StageOutput::Judgement,
+            Role::Prover,
+            fee,
+        )?;
+
+        Ok(graph)
+    }
+
+    /// Output waiting for Vicky's challenge of `round`: bisection leaves before the last
+    /// round, then one leaf per gate.
+    fn build_stage_output(
+        &self,
+        secp: &Secp256k1<secp256k1::All>,
+        round: usize,
+        commitments: &ContractCommitments,
+        circuit: &Circuit,
+    ) -> Result<TaprootSpendInfo, String> {
+        let (prover, verifier) = (&self.participants.prover, &self.participants.verifier);
+        let verdict = round
+            .checked_sub(1)
+            .map(|r| &commitments.challenge_hashes.verdicts[r]);
+        let mut scripts = vec![];
+        if round < self.rounds {
+            scripts.push(seal_with_multisig(
+                build_challenge_condition(verdict, None),
+                prover,
+                verifier,
+            ));
+        } else {
+            for (gate_id, gate_hash) in commitments.challenge_hashes.gates.iter() {
+                if !circuit.gates.contains_key(gate_id) {
+                    return Err(format!("challenge hashlock for unknown gate {}", gate_id));
+                }
+                scripts.push(seal_with_multisig(
+                    build_challenge_condition(verdict, Some(gate_hash)),
+                    prover,
+                    verifier,
+                ));
+            }
+        }
+        scripts.push(build_timeout_leaf(
+            &self.participants,
+            self.timelocks.response_timeout,
+        ));
+        build_taproot_spend_info(secp, scripts)
+    }
+
+    /// Leaves through which Paul answers the challenge of `round`, one per sequence of verdicts
+    /// on the previous rounds, in the order of [`TransactionGraph::response_leaf`]. Each one
+    /// opens the state wires at the midpoint those verdicts select.
+    fn build_response_leaves(
+        &self,
+        round: usize,
+        state_wires: &StateWires,
+        commitments: &ContractCommitments,
+    ) -> Result<Vec<ScriptBuf>, String> {
+        let (prover, verifier) = (&self.participants.prover, &self.participants.verifier);
+        let mut leaves = vec![];
+        for path in 0..1usize << round {
+            let verdicts = (0..round).map(|r| (path >> r) & 1 == 1).collect::<Vec<_>>();
+            let verdict_hashes = verdicts
+                .iter()
+                .zip(commitments.challenge_hashes.verdicts.iter())
+                .map(|(agreed, hashes)| hashes.hash_for_bit(*agreed))
+                .collect::<Vec<_>>();
+            let wires_bit_commitments = state_wires
+                .at(compute_midpoint(self.rounds, &verdicts))
+                .iter()
+                .map(|wire_id| {
+                    commitments
+                        .commitment_set
+                        .get(wire_id)
+                        .ok_or(format!("wire {} missing from the commitment set", wire_id))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            leaves.push(seal_with_multisig(
+                build_state_response_condition(&verdict_hashes, &wires_bit_commitments),
+                prover,
+                verifier,
+            ));
+        }
+        Ok(leaves)
+    }
+
+    fn add_funding(
+        &mut self,
+        parameters: &ContractParameters,
+        funding_spend_info: &TaprootSpendInfo,
+        collateral_spend_info: &TaprootSpendInfo,
+    ) -> Result<(), String> {
+        let total_in: u64 = parameters
+            .funding_inputs
+            .iter()
+            .map(|input| input.txout.value)
+            .sum();
+        let required = parameters.amount.to_sat()
+            + parameters.collateral.to_sat()
+            + parameters.fee_per_transaction.to_sat();
+        if total_in < required {
+            return Err(format!(
+                "funding inputs hold {} sats, {} required",
+                total_in, required
+            ));
+        }
+        let mut output = vec![
+            TxOut {
+                value: parameters.amount.to_sat(),
+                script_pubkey: ScriptBuf::new_v1_p2tr_tweaked(funding_spend_info.output_key()),
+            },
+            TxOut {
+                value: parameters.collateral.to_sat(),
+                script_pubkey: ScriptBuf::new_v1_p2tr_tweaked(collateral_spend_info.output_key()),
+            },
+        ];
+        if total_in - required > DUST_LIMIT {
+            output.push(TxOut {
+                value: total_in - required,
+                script_pubkey: parameters.change_script_pubkey.clone(),
+            });
+        }
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: parameters
+                .funding_inputs
+                .iter()
+                .map(|input| TxIn {
+                    previous_output: input.outpoint,
+                    script_sig: ScriptBuf::new(),
+                    sequence: Sequence::ENABLE_RBF_NO_LOCKTIME,
+                    witness: Witness::new(),
+                })
+                .collect(),
+            output,
+        };
+        let txid = tx.txid();
+        for (vout, (stage, spend_info)) in [
+            (StageOutput::Funding, funding_spend_info),
+            (StageOutput::Collateral, collateral_spend_info),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            self.outputs.insert(
+                stage,
+                ProtocolOutput {
+                    outpoint: OutPoint::new(txid, vout as u32),
+                    txout: tx.output[vout].clone(),
+                    spend_info: spend_info.clone(),
+                },
+            );
+        }
+        self.transactions.insert(
+            TransactionKind::Funding,
+            GraphTransaction {
+                tx,
+                spent_output: None,
+                leaves: vec![],
+                signers: vec![],
+                presigned: false,
+                signatures: BTreeMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Adds a transaction spending `spent` through `leaves`, paying either to the next stage
+    /// output, or to the key of the party closing the contract. A second output anchors the
+    /// fee bumps of its broadcaster.
+    #[allow(clippy::too_many_arguments)]
+    fn add_spend(
+        &mut self,
+        kind: TransactionKind,
+        spent: StageOutput,
+        leaves: Vec<ScriptBuf>,
+        sequence: Sequence,
+        next_stage: Option<(StageOutput, TaprootSpendInfo)>,
+        beneficiary: Option<&PublicKey>,
+        fee: u64,
+    ) -> Result<(), String> {
+        let spent_output = self
+            .outputs
+            .get(&spent)
+            .ok_or(format!("{:?} output not built yet", spent))?;
+        let value = spent_output
+            .txout
+            .value
+            .checked_sub(fee + ANCHOR_VALUE)
+            .filter(|value| *value > DUST_LIMIT)
+            .ok_or(format!("{} output below dust", kind))?;
+        let secp = Secp256k1::verification_only();
+        let script_pubkey = match (&next_stage, beneficiary) {
+            (Some((_, spend_info)), _) => ScriptBuf::new_v1_p2tr_tweaked(spend_info.output_key()),
+            (None, Some(public_key)) => {
+                ScriptBuf::new_v1_p2tr(&secp, public_key.x_only_public_key().0, None)
+            }
+            (None, None) => return Err(format!("{} pays to nobody", kind)),
+        };
+        let anchor_key = self.participants.public_key(kind.broadcaster());
+        let tx = Transaction {
+            version: 2,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: spent_output.outpoint,
+                script_sig: ScriptBuf::new(),
+                sequence,
+                witness: Witness::new(),
+            }],
+            output: vec![
+                TxOut {
+                    value,
+                    script_pubkey,
+                },
+                TxOut {
+                    value: ANCHOR_VALUE,
+                    script_pubkey: ScriptBuf::new_v1_p2tr(
+                        &secp,
+                        anchor_key.x_only_public_key().0,
+                        None,
+                    ),
+                },
+            ],
+        };
+        if let Some((stage, spend_info)) = next_stage {
+            self.outputs.insert(
+                stage,
+                ProtocolOutput {
+                    outpoint: OutPoint::new(tx.txid(), 0),
+                    txout: tx.output[0].clone(),
+                    spend_info,
+                },
+            );
+        }
+        let (signers, presigned) = match kind {
+            TransactionKind::CollateralReclaim => (vec![Role::Prover], false),
+            TransactionKind::CooperativeClose => (vec![Role::Verifier, Role::Prover], false),
+            _ => (vec![Role::Verifier, Role::Prover], true),
+        };
+        self.transactions.insert(
+            kind,
+            GraphTransaction {
+                tx,
+                spent_output: Some(spent),
+                leaves,
+                signers,
+                presigned,
+                signatures: BTreeMap::new(),
+            },
+        );
+        Ok(())
+    }
+
+    fn add_timeout(
+        &mut self,
+        kind: TransactionKind,
+        spent: StageOutput,
+        beneficiary: Role,
+        fee: u64,
+    ) -> Result<(), String> {
+        let timeout = self.timelocks.response_timeout;
+        let beneficiary = *self.participants.public_key(beneficiary);
+        let leaf = build_timeout_leaf(&self.participants, timeout);
+        self.add_spend(
+            kind,
+            spent,
+            vec![leaf],
+            Sequence::from_height(timeout),
+            None,
+            Some(&beneficiary),
+            fee,
+        )
+    }
+
+    /// Recognizes a transaction of the graph. Witnesses are not covered by the txid, so this
+    /// holds whichever leaf was used.
+    pub fn identify_transaction(&self, tx: &Transaction) -> Option<TransactionKind> {
+        let txid = tx.txid();
+        self.transactions
+            .iter()
+            .find(|(_, transaction)| transaction.tx.txid() == txid)
+            .map(|(kind, _)| *kind)
+    }
+
+    /// Parser recognizing spends of every output of the graph, labelled by output.
+    pub fn build_witness_parser(
+        &self,
+        commitments: &ContractCommitments,
+    ) -> WitnessParser<StageOutput> {
+        let mut parser = WitnessParser::new(&commitments.commitment_set);
+        parser.register_challenge_hashes(&commitments.challenge_hashes);
+        for (stage, output) in self.outputs.iter() {
+            parser.register_output(&output.spend_info, |_| *stage);
+        }
+        parser
+    }
+
+    /// Leaf of the gate challenge or gate response transaction dedicated to `gate_id`.
+    pub fn gate_leaf(
+        &self,
+        kind: &TransactionKind,
+        gate_id: &GateId,
+    ) -> Result<&ScriptBuf, String> {
+        if !matches!(
+            kind,
+            TransactionKind::GateChallenge | TransactionKind::GateResponse
+        ) {
+            return Err(format!("{} has no per-gate leaves", kind));
+        }
+        let position = self
+            .gates_ids
+            .iter()
+            .position(|id| id == gate_id)
+            .ok_or(format!("gate {} can not be challenged", gate_id))?;
+        Ok(&self.transaction(kind)?.leaves[position])
+    }
+
+    /// Leaf of the response of `round` opening the midpoint selected by `verdicts`, Vicky's
+    /// verdicts on the previous rounds.
+    pub fn response_leaf(&self, round: usize, verdicts: &[bool]) -> Result<&ScriptBuf, String> {
+        if verdicts.len() != round {
+            return Err(format!(
+                "expected {} verdicts, got {}",
+                round,
+                verdicts.len()
+            ));
+        }
+        let kind = TransactionKind::Response(round);
+        let position = verdicts
+            .iter()
+            .enumerate()
+            .map(|(r, agreed)| (*agreed as usize) << r)
+            .sum::<usize>();
+        self.transaction(&kind)?
+            .leaves
+            .get(position)
+            .ok_or(format!("no leaf for these verdicts in {}", kind))
+    }
+
+    /// Signatures `role` contributed so far, as they would be sent to the other party.
+    pub fn collect_presignatures(&self, role: Role) -> Vec<PreSignature> {
+        let mut presignatures = vec![];
+        for (kind, transaction) in self.transactions.iter() {
+            for (leaf_hash, signatures) in transaction.signatures.iter() {
+                if let Some(signature) = signatures.get(role) {
+                    presignatures.push(PreSignature {
+                        kind: *kind,
+                        leaf_hash: *leaf_hash,
+                        signature: *signature,
+                    });
+                }
+            }
+        }
+        presignatures
+    }
+
+    pub fn transaction(&self, kind: &TransactionKind) -> Result<&GraphTransaction, String> {
+        self.transactions
+            .get(kind)
+            .ok_or(format!("no {} transaction in the graph", kind))
+    }
+
+    pub fn output(&self, stage: &StageOutput) -> Result<&ProtocolOutput, String> {
+        self.outputs
+            .get(stage)
+            .ok_or(format!("no {:?} output in the graph", stage))
+    }
+
+    /// Child of `parent`, a graph transaction broadcast by `keypair`'s owner `role`, paying
+    /// for both at `fee_rate` through its anchor.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build_cpfp(
+        &self,
+        secp: &Secp256k1<secp256k1::All>,
+        role: Role,
+        keypair: &KeyPair,
+        parent: &Transaction,
+        parent_fee: Amount,
+        fee_rate: u64,
+        wallet: &mut FeeWallet,
+    ) -> Result<Transaction, String> {
+        let kind = self
+            .identify_transaction(parent)
+            .ok_or(format!("{} is not a contract transaction", parent.txid()))?;
+        if kind.broadcaster() != role {
+            return Err(format!("{} is not broadcast by the {:?}", kind, role));
+        }
+        build_cpfp(
+            secp,
+            parent,
+            parent_fee,
+            self.anchor(&kind)?,
+            keypair,
+            fee_rate,
+            wallet,
+        )
+    }
+
+    /// Anchor output of `kind`, spendable by its broadcaster's key to bump its fee.
+    pub fn anchor(&self, kind: &TransactionKind) -> Result<(OutPoint, TxOut), String> {
+        let transaction = self.transaction(kind)?;
+        let txout = transaction
+            .tx
+            .output
+            .get(ANCHOR_VOUT as usize)
+            .filter(|_| transaction.spent_output.is_some())
+            .ok_or(format!("{} has no anchor output", kind))?;
+        Ok((
+            OutPoint::new(transaction.tx.txid(), ANCHOR_VOUT),
+            txout.clone(),
+        ))
+    }
+
+    /// Blocks after which the move expected on `stage` times out.
+    pub fn output_timeout(&self, stage: &StageOutput) -> Result<u16, String> {
+        match stage {
+            StageOutput::Collateral => self.timelocks.compute_reclaim_timeout(self.rounds),
+            _ => Ok(self.timelocks.response_timeout),
+        }
+    }
+
+    /// Signature hash of `kind` when spending through the leaf `leaf_hash`.
+    pub fn compute_sighash(
+        &self,
+        kind: &TransactionKind,
+        leaf_hash: TapLeafHash,
+    ) -> Result<Message, String> {
+        let transaction = self.transaction(kind)?;
+        let spent = transaction
+            .spent_output
+            .ok_or(format!("{} is not signed through a leaf", kind))?;
+        let prevout = &self.output(&spent)?.txout;
+        let sighash = SighashCache::new(&transaction.tx)
+            .taproot_script_spend_signature_hash(
+                0,
+                &Prevouts::All(&[prevout]),
+                leaf_hash,
+                TapSighashType::Default,
+            )
+            .map_err(|e| format!("unable to compute sighash: {}", e))?;
+        Ok(Message::from(sighash))
+    }
+
+    /// Signs every leaf of `kind` checking `keypair`.
+    pub fn sign_transaction(
+        &self,
+        secp: &Secp256k1<secp256k1::All>,
+        keypair: &KeyPair,
+        kind: &TransactionKind,
+    ) -> Result<Vec<PreSignature>, String> {
+        let transaction = self.transaction(kind)?;
+        let mut presignatures = vec![];
+        for leaf in transaction.leaves.iter() {
+            let leaf_hash = TapLeafHash::from_script(leaf, LeafVersion::TapScript);
+            let message = self.compute_sighash(kind, leaf_hash)?;
+            presignatures.push(PreSignature {
+                kind: *kind,
+                leaf_hash,
+                signature: secp.sign_schnorr(&message, keypair),
+            });
+        }
+        Ok(presignatures)
+    }
+
+    /// Signatures a participant hands over during setup: every leaf of every pre-signed transaction.
+    pub fn presign(
+        &self,
+        secp: &Secp256k1<secp256k1::All>,
+        keypair: &KeyPair,
+    ) -> Result<Vec<PreSignature>, String> {
+        let mut presignatures = vec![];
+        for (kind, transaction) in self.transactions.iter() {
+            if transaction.presigned {
+                presignatures.append(&mut self.sign_transaction(secp, keypair, kind)?);
+            }
+        }
+        Ok(presignatures)
+    }
+
+    /// Checks that `presignature` is a valid signature of `role` for a leaf of the graph.
+    pub fn verify_presignature<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        role: Role,
+        presignature: &PreSignature,
+    ) -> Result<(), String> {
+        let public_key = self.participants.public_key(role).x_only_public_key().0;
+        let transaction = self.transaction(&presignature.kind)?;
+        if !transaction.signers.contains(&role) {
+            return Err(format!("{} is not signed by {:?}", presignature.kind, role));
+        }
+        let known_leaf = transaction.leaves.iter().any(|leaf| {
+            TapLeafHash::from_script(leaf, LeafVersion::TapScript) == presignature.leaf_hash
+        });
+        if !known_leaf {
+            return Err(format!("unknown leaf for {}", presignature.kind));
+        }
+        let message = self.compute_sighash(&presignature.kind, presignature.leaf_hash)?;
+        secp.verify_schnorr(&presignature.signature, &message, &public_key)
+            .map_err(|_| format!("invalid {:?} signature for {}", role, presignature.kind))
+    }
+
+    /// Verifies and records signatures produced by `role`.
+    pub fn add_presignatures(
+        &mut self,
+        secp: &Secp256k1<secp256k1::All>,
+        role: Role,
+        presignatures: &[PreSignature],
+    ) -> Result<(), String> {
+        for presignature in presignatures.iter() {
+            self.verify_presignature(secp, role, presignature)?;
+
+            let transaction = self.transactions.get_mut(&presignature.kind).unwrap();
+            let signatures = transaction
+                .signatures
+                .entry(presignature.leaf_hash)
+                .or_default();
+            match role {
+                Role::Prover => signatures.prover = Some(presignature.signature),
+                Role::Verifier => signatures.verifier = Some(presignature.signature),
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of signatures still missing before the graph can be funded safely.
+    pub fn count_missing_presignatures(&self) -> usize {
+        self.transactions
+            .values()
+            .filter(|transaction| transaction.presigned)
+            .map(|transaction| {
+                transaction
+                    .leaves
+                    .iter()
+                    .map(|leaf| {
+                        let leaf_hash = TapLeafHash::from_script(leaf, LeafVersion::TapScript);
+                        let signatures = transaction.signatures.get(&leaf_hash);
+                        transaction
+                            .signers
+                            .iter()
+                            .filter(|role| signatures.and_then(|s| s.get(**role)).is_none())
+                            .count()
+                    })
+                    .sum::<usize>()
+            })
+            .sum()
+    }
+
+    /// Inverse of [`TransactionGraph::finalize_transaction`]: the elements a spend of `kind`
+    /// fed to its leaf condition, in consumption order. `stack` excludes the script and
+    /// control block.
+    pub fn extract_conditions(
+        &self,
+        kind: &TransactionKind,
+        stack: &[Vec<u8>],
+    ) -> Result<Vec<Vec<u8>>, String> {
+        let signers = self.transaction(kind)?.signers.len();
+        if stack.len() < signers {
+            return Err(format!("{} witness is missing signatures", kind));
+        }
+        Ok(stack[signers..].iter().rev().cloned().collect())
+    }
+
+    /// Completes the witness of `kind`, spending through `leaf`. `conditions` holds the stack
+    /// elements consumed by the leaf condition, in the order the script consumes them.
+    pub fn finalize_transaction(
+        &self,
+        kind: &TransactionKind,
+        leaf: &ScriptBuf,
+        conditions: Vec<Vec<u8>>,
+    ) -> Result<Transaction, String> {
+        let transaction = self.transaction(kind)?;
+        let spent = transaction
+            .spent_output
+            .ok_or(format!("{} is not spent through a leaf", kind))?;
+        if !transaction.leaves.contains(leaf) {
+            return Err(format!("leaf not available to {}", kind));
+        }
+        let leaf_hash = TapLeafHash::from_script(leaf, LeafVersion::TapScript);
+        let control_block = self
+            .output(&spent)?
+            .spend_info
+            .control_block(&(leaf.clone(), LeafVersion::TapScript))
+            .ok_or(format!(
+                "leaf not committed in the output spent by {}",
+                kind
+            ))?;
+        let signatures = transaction.signatures.get(&leaf_hash);
+
+        let mut witness = Witness::new();
+        for role in transaction.signers.iter() {
+            let signature = signatures
+                .and_then(|s| s.get(*role))
+                .ok_or(format!("missing {:?} signature for {}", role, kind))?;
+            witness.push(signature.as_ref());
+        }
+        for element in conditions.iter().rev() {
+            witness.push(element);
+        }
+        witness.push(leaf.as_bytes());
+        witness.push(control_block.serialize());
+
+        let mut tx = transaction.tx.clone();
+        tx.input[0].witness = witness;
+        Ok(tx)
+    }
+}
+
+fn build_timeout_leaf(participants: &Participants, timeout: u16) -> ScriptBuf {
+    augment_with_multisig(
+        augment_with_timelock(bitcoin::script::Builder::new(), timeout),
+        &participants.prover,
+        &participants.verifier,
+    )
+    .into_script()
+}
+
+#[cfg(test)]
+pub fn build_test_setup(
+    circuit_source: &str,
+    prover_keypair: &KeyPair,
+    verifier_keypair: &KeyPair,
+) -> (
+    Circuit,
+    ContractSetup,
+    crate::tapleaf::challenge_hashlock::ChallengeStore,
+) {
+    use crate::tapleaf::challenge_hashlock::ChallengeStore;
+    use bitcoin::hashes::Hash;
+
+    let secp = Secp256k1::new();
+    let circuit = crate::bristol::parser::read_circuit(circuit_source).unwrap();
+    let rounds = compute_bisection_rounds(circuit.gates.len());
+    let mut gates_ids = circuit.gates.keys().cloned().collect::<Vec<_>>();
+    gates_ids.sort();
+    let challenge_store = ChallengeStore::new(&gates_ids, rounds);
+    let commitments = ContractCommitments {
+        commitment_set: circuit.compute_commitment_set(),
+        challenge_hashes: challenge_store.compute_hashes(),
+    };
+    let participants = Participants {
+        prover: prover_keypair.public_key(),
+        verifier: verifier_keypair.public_key(),
+    };
+    let parameters = ContractParameters {
+        amount: Amount::from_sat(1_000_000),
+        collateral: Amount::from_sat(100_000),
+        fee_per_transaction: Amount::from_sat(2_000),
+        timelocks: TimelockParameters {
+            response_timeout: 6,
+        },
+        funding_inputs: vec![FundingInput {
+            outpoint: OutPoint::new(bitcoin::Txid::all_zeros(), 0),
+            txout: TxOut {
+                value: 2_000_000,
+                script_pubkey: ScriptBuf::new_v1_p2tr(
+                    &secp,
+                    prover_keypair.x_only_public_key().0,
+                    None,
+                ),
+            },
+        }],
+        change_script_pubkey: ScriptBuf::new_v1_p2tr(
+            &secp,
+            prover_keypair.x_only_public_key().0,
+            None,
+        ),
+    };
+    let setup = ContractSetup {
+        circuit_source: circuit_source.to_string(),
+        participants,
+        parameters,
+        commitments,
+    };
+    (circuit, setup, challenge_store)
+}
+
+#[cfg(test)]
+pub fn build_test_graph(
+    circuit_source: &str,
+    prover_keypair: &KeyPair,
+    verifier_keypair: &KeyPair,
+) -> (Circuit, TransactionGraph) {
+    let (circuit, setup, _) = build_test_setup(circuit_source, prover_keypair, verifier_keypair);
+    let graph = setup.build_graph(&Secp256k1::new(), &circuit).unwrap();
+    (circuit, graph)
+}
+
+#[test]
+fn test_transaction_graph_is_linked_and_presigned() {
+    let secp = Secp256k1::new();
+    let prover_keypair = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+    let verifier_keypair = KeyPair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let (_, mut graph) = build_test_graph(
+        include_str!("../bristol/fixtures/test_vector_1.bristol"),
+        &prover_keypair,
+        &verifier_keypair,
+    );
+
+    assert_eq!(graph.rounds, 4);
+    // One response leaf per midpoint Vicky may select.
+    for round in 0..graph.rounds {
+        let response = graph
+            .transaction(&TransactionKind::Response(round))
+            .unwrap();
+        assert_eq!(response.leaves.len(), 1 << round);
+    }
+    // Every transaction spends the output its predecessor created.
+    for (kind, transaction) in graph.transactions.iter() {
+        let Some(spent) = transaction.spent_output else {
+            continue;
+        };
+        let output = graph.output(&spent).unwrap();
+        assert_eq!(
+            transaction.tx.input[0].previous_output, output.outpoint,
+            "{}",
+            kind
+        );
+        for leaf in transaction.leaves.iter() {
+            assert!(output
+                .spend_info
+                .control_block(&(leaf.clone(), LeafVersion::TapScript))
+                .is_some());
+        }
+    }
+    let response = graph.transaction(&TransactionKind::Response(1)).unwrap();
+    assert_eq!(
+        response.tx.output[0].script_pubkey,
+        graph
+            .output(&StageOutput::Challenge(2))
+            .unwrap()
+            .txout
+            .script_pubkey
+    );
+
+    let missing = graph.count_missing_presignatures();
+    assert!(missing > 0);
+    let prover_signatures = graph.presign(&secp, &prover_keypair).unwrap();
+    let verifier_signatures = graph.presign(&secp, &verifier_keypair).unwrap();
+    assert!(graph
+        .add_presignatures(&secp, Role::Prover, &verifier_signatures)
+        .is_err());
+    graph
+        .add_presignatures(&secp, Role::Prover, &prover_signatures)
+        .unwrap();
+    graph
+        .add_presignatures(&secp, Role::Verifier, &verifier_signatures)
+        .unwrap();
+    assert_eq!(graph.count_missing_presignatures(), 0);
+
+    let timeout = graph
+        .transaction(&TransactionKind::ResponseTimeout(0))
+        .unwrap();
+    let leaf = timeout.leaves[0].clone();
+    let tx = graph
+        .finalize_transaction(&TransactionKind::ResponseTimeout(0), &leaf, vec![])
+        .unwrap();
+    assert_eq!(tx.input[0].sequence, Sequence::from_height(6));
+    assert_eq!(tx.input[0].witness.len(), 4);
+}
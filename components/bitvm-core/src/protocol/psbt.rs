@@ -0,0 +1,243 @@
+use bitcoin::bip32::KeySource;
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::psbt::{PartiallySignedTransaction, PsbtSighashType};
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::sighash::TapSighashType;
+use bitcoin::taproot::{self, LeafVersion, TapLeafHash};
+
+use super::{PreSignature, Role, TransactionGraph, TransactionKind};
+
+impl TransactionGraph {
+    /// Exports `kind` as a PSBT carrying the spent output, its tap leaves and control blocks,
+    /// and the signatures collected so far. Keys listed in `key_origins` get their BIP32
+    /// derivation, so that external signers recognize them.
+    pub fn export_psbt(
+        &self,
+        kind: &TransactionKind,
+        key_origins: &[(Role, KeySource)],
+    ) -> Result<PartiallySignedTransaction, String> {
+        let transaction = self.transaction(kind)?;
+        let mut psbt = PartiallySignedTransaction::from_unsigned_tx(transaction.tx.clone())
+            .map_err(|e| format!("unable to build psbt for {}: {}", kind, e))?;
+        let Some(spent) = transaction.spent_output else {
+            return Ok(psbt);
+        };
+        let output = self.output(&spent)?;
+        let input = &mut psbt.inputs[0];
+        input.witness_utxo = Some(output.txout.clone());
+        input.sighash_type = Some(PsbtSighashType::from(TapSighashType::Default));
+        input.tap_internal_key = Some(output.spend_info.internal_key());
+        input.tap_merkle_root = output.spend_info.merkle_root();
+        let mut leaf_hashes = vec![];
+        for leaf in transaction.leaves.iter() {
+            let script_ver = (leaf.clone(), LeafVersion::TapScript);
+            let control_block = output.spend_info.control_block(&script_ver).ok_or(format!(
+                "leaf not committed in the output spent by {}",
+                kind
+            ))?;
+            input.tap_scripts.insert(control_block, script_ver);
+
+            let leaf_hash = TapLeafHash::from_script(leaf, LeafVersion::TapScript);
+            leaf_hashes.push(leaf_hash);
+            let Some(signatures) = transaction.signatures.get(&leaf_hash) else {
+                continue;
+            };
+            for role in [Role::Prover, Role::Verifier] {
+                if let Some(signature) = signatures.get(role) {
+                    let public_key = self.participants.public_key(role).x_only_public_key().0;
+                    input.tap_script_sigs.insert(
+                        (public_key, leaf_hash),
+                        taproot::Signature {
+                            sig: *signature,
+                            hash_ty: TapSighashType::Default,
+                        },
+                    );
+                }
+            }
+        }
+        for (role, key_source) in key_origins.iter() {
+            if !transaction.signers.contains(role) {
+                continue;
+            }
+            let public_key = self.participants.public_key(*role).x_only_public_key().0;
+            input
+                .tap_key_origins
+                .insert(public_key, (leaf_hashes.clone(), key_source.clone()));
+        }
+        Ok(psbt)
+    }
+
+    /// Exports every transaction of the graph.
+    pub fn export_psbts(
+        &self,
+        key_origins: &[(Role, KeySource)],
+    ) -> Result<Vec<(TransactionKind, PartiallySignedTransaction)>, String> {
+        self.transactions
+            .keys()
+            .map(|kind| Ok((*kind, self.export_psbt(kind, key_origins)?)))
+            .collect()
+    }
+
+    /// Signatures of `role` carried by `psbt`, which must be one of the transactions of the
+    /// graph. Each of them is checked against the graph before being returned.
+    pub fn read_psbt_presignatures<C: secp256k1::Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        psbt: &PartiallySignedTransaction,
+        role: Role,
+    ) -> Result<Vec<PreSignature>, String> {
+        let txid = psbt.unsigned_tx.txid();
+        let kind = self
+            .transactions
+            .iter()
+            .find(|(_, transaction)| transaction.tx.txid() == txid)
+            .map(|(kind, _)| *kind)
+            .ok_or(format!(
+                "psbt {} is not a transaction of the contract",
+                txid
+            ))?;
+        let public_key = self.participants.public_key(role).x_only_public_key().0;
+        let mut presignatures = vec![];
+        for input in psbt.inputs.iter() {
+            for ((signer, leaf_hash), signature) in input.tap_script_sigs.iter() {
+                if *signer != public_key {
+                    continue;
+                }
+                if signature.hash_ty != TapSighashType::Default {
+                    return Err(format!(
+                        "unexpected sighash type {} in {} psbt",
+                        signature.hash_ty, kind
+                    ));
+                }
+                presignatures.push(PreSignature {
+                    kind,
+                    leaf_hash: *leaf_hash,
+                    signature: signature.sig,
+                });
+            }
+        }
+        for presignature in presignatures.iter() {
+            self.verify_presignature(secp, role, presignature)?;
+        }
+        Ok(presignatures)
+    }
+}
+
+/// Magic bytes opening a serialized PSBT.
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+/// Reads a PSBT serialized as in BIP174 files, or hex encoded.
+pub fn decode_psbt(bytes: &[u8]) -> Result<PartiallySignedTransaction, String> {
+    let decoded;
+    let bytes = match bytes.starts_with(PSBT_MAGIC) {
+        true => bytes,
+        false => {
+            let hex =
+                std::str::from_utf8(bytes).map_err(|_| "invalid psbt encoding".to_string())?;
+            decoded =
+                Vec::<u8>::from_hex(hex.trim()).map_err(|e| format!("invalid psbt hex: {}", e))?;
+            &decoded
+        }
+    };
+    PartiallySignedTransaction::deserialize(bytes).map_err(|e| format!("invalid psbt: {}", e))
+}
+
+#[test]
+fn test_export_psbt_carries_leaves_and_signatures() {
+    use bitcoin::secp256k1::{KeyPair, Secp256k1};
+
+    let secp = Secp256k1::new();
+    let prover_keypair = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+    let verifier_keypair = KeyPair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let (circuit, mut graph) = super::build_test_graph(
+        include_str!("../bristol/fixtures/test_vector_1.bristol"),
+        &prover_keypair,
+        &verifier_keypair,
+    );
+    let signatures = graph.presign(&secp, &prover_keypair).unwrap();
+    graph
+        .add_presignatures(&secp, Role::Prover, &signatures)
+        .unwrap();
+
+    let psbts = graph.export_psbts(&[]).unwrap();
+    assert_eq!(psbts.len(), graph.transactions.len());
+    let psbt = graph
+        .export_psbt(&TransactionKind::GateChallenge, &[])
+        .unwrap();
+    let input = &psbt.inputs[0];
+    assert_eq!(input.tap_scripts.len(), circuit.gates.len());
+    assert_eq!(input.tap_script_sigs.len(), circuit.gates.len());
+    for (control_block, (script, _)) in input.tap_scripts.iter() {
+        assert!(control_block.verify_taproot_commitment(
+            &secp,
+            input
+                .witness_utxo
+                .as_ref()
+                .unwrap()
+                .script_pubkey
+                .as_bytes()[2..]
+                .try_into()
+                .map(|bytes: [u8; 32]| bitcoin::key::XOnlyPublicKey::from_slice(&bytes).unwrap())
+                .unwrap(),
+            script
+        ));
+    }
+}
+
+#[test]
+fn test_psbt_signatures_are_combined_into_the_graph() {
+    use bitcoin::bip32::{DerivationPath, Fingerprint};
+    use bitcoin::secp256k1::KeyPair;
+    use std::str::FromStr;
+
+    let secp = Secp256k1::new();
+    let prover_keypair = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+    let verifier_keypair = KeyPair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let (_, mut graph) = super::build_test_graph(
+        include_str!("../bristol/fixtures/test_vector_1.bristol"),
+        &prover_keypair,
+        &verifier_keypair,
+    );
+    let mut verifier_graph = graph.clone();
+    let signatures = verifier_graph.presign(&secp, &verifier_keypair).unwrap();
+    verifier_graph
+        .add_presignatures(&secp, Role::Verifier, &signatures)
+        .unwrap();
+
+    // Vicky's signer hands back signed PSBTs, with the origin of her key.
+    let key_source = (
+        Fingerprint::from([1, 2, 3, 4]),
+        DerivationPath::from_str("m/86'/1'/1'/0'").unwrap(),
+    );
+    let psbt = verifier_graph
+        .export_psbt(
+            &TransactionKind::Commit,
+            &[(Role::Verifier, key_source.clone())],
+        )
+        .unwrap();
+    let verifier_key = verifier_keypair.x_only_public_key().0;
+    assert_eq!(psbt.inputs[0].tap_key_origins[&verifier_key].1, key_source);
+    let psbt = decode_psbt(psbt.serialize_hex().as_bytes()).unwrap();
+    let presignatures = graph
+        .read_psbt_presignatures(&secp, &psbt, Role::Verifier)
+        .unwrap();
+    assert!(!presignatures.is_empty());
+    assert!(graph
+        .read_psbt_presignatures(&secp, &psbt, Role::Prover)
+        .unwrap()
+        .is_empty());
+    graph
+        .add_presignatures(&secp, Role::Verifier, &presignatures)
+        .unwrap();
+    assert_eq!(
+        graph.transactions[&TransactionKind::Commit].signatures,
+        verifier_graph.transactions[&TransactionKind::Commit].signatures
+    );
+
+    // Signatures over another transaction are rejected.
+    let mut tampered = psbt;
+    tampered.unsigned_tx.lock_time = bitcoin::absolute::LockTime::from_consensus(1);
+    assert!(graph
+        .read_psbt_presignatures(&secp, &tampered, Role::Verifier)
+        .is_err());
+}
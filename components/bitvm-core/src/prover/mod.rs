@@ -0,0 +1,873 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use bitcoin::bip32::KeySource;
+use bitcoin::secp256k1::{self, KeyPair, Secp256k1, SecretKey};
+use bitcoin::{Amount, Transaction, Txid};
+use bitvm_types::{BitCommitmentPreimages, Circuit, ExecutionTrace, WireId};
+
+use crate::bisection::{Bisection, BisectionProver, Challenge};
+use crate::fees::FeeWallet;
+use crate::protocol::contract::Contract;
+use crate::protocol::{
+    collect_committed_wires_ids, ContractAction, ContractCommitments, ContractEvent,
+    ContractMachine, ContractSetup, PreSignature, Role, StageOutput, TransactionGraph,
+    TransactionKind, TranscriptEntry,
+};
+#[cfg(any(test, feature = "simulation"))]
+use crate::simulation::Fault;
+use crate::storage;
+use crate::tapleaf::challenge_hashlock::{ChallengeHashes, RevealedChallenge};
+use crate::vault::Vault;
+use crate::witness::{LeafSpend, WitnessParser};
+
+const PROVER_STATE_FILE: &str = "prover.json";
+const PROVER_SECRETS_FILE: &str = "prover-secrets.json";
+
+/// Paul's secrets: his signing key, and the preimages opening his wire commitments.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProverSecrets {
+    pub secret_key: SecretKey,
+    /// Origin of `secret_key` when it was derived from a key store.
+    #[serde(default)]
+    pub key_source: Option<KeySource>,
+    pub wire_preimages: BTreeMap<WireId, BitCommitmentPreimages>,
+}
+
+impl Drop for ProverSecrets {
+    fn drop(&mut self) {
+        self.secret_key.non_secure_erase();
+    }
+}
+
+impl ProverSecrets {
+    /// Keeps the wire preimages drawn when `circuit` was parsed.
+    pub fn generate(circuit: &Circuit, secret_key: SecretKey) -> ProverSecrets {
+        ProverSecrets {
+            secret_key,
+            key_source: None,
+            wire_preimages: circuit
+                .gates_bit_commitments_preimages
+                .iter()
+                .map(|(wire_id, preimages)| (*wire_id, preimages.clone()))
+                .collect(),
+        }
+    }
+
+    /// Commitments of the contract, once Vicky shared her challenge hashes.
+    pub fn compute_commitments(&self, challenge_hashes: ChallengeHashes) -> ContractCommitments {
+        let mut commitment_set = bitvm_types::CommitmentSet::new();
+        for (wire_id, preimages) in self.wire_preimages.iter() {
+            commitment_set
+                .hashes
+                .insert(*wire_id, preimages.compute_bit_commitment_hashes());
+        }
+        ContractCommitments {
+            commitment_set,
+            challenge_hashes,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ProverPhase {
+    /// Collecting Vicky's pre-signatures.
+    Setup,
+    /// Funding transaction handed to the wallet.
+    AwaitingFunding,
+    /// Waiting for Vicky's challenge of the given round. The last round selects a gate.
+    AwaitingChallenge(usize),
+    /// Challenged gate opened, waiting for Vicky to give up.
+    AwaitingJudgement,
+    /// The amount at stake was claimed by the given transaction.
+    Closed(TransactionKind),
+}
+
+/// Persisted state of a contract, from Paul's side.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ProverState {
+    pub setup: ContractSetup,
+    /// Inputs Paul committed to.
+    pub inputs: BTreeMap<WireId, bool>,
+    pub verifier_presignatures: Vec<PreSignature>,
+    pub phase: ProverPhase,
+    pub bisection: Bisection,
+    /// Vicky's verdicts revealed so far, unlocking the response leaves of the later rounds.
+    #[serde(default)]
+    pub verdict_preimages: Vec<Vec<u8>>,
+    /// Contract transactions seen on chain so far.
+    #[serde(default)]
+    pub transcript: Vec<TranscriptEntry>,
+    /// Last transaction Paul broadcast, kept to rebroadcast it until it confirms.
+    pub last_broadcast: Option<(TransactionKind, Transaction)>,
+    pub collateral_spent: bool,
+}
+
+/// Paul's side of a contract: answers every challenge with the execution trace of the
+/// circuit on his inputs, and claims the funds when Vicky stops playing.
+pub struct Prover {
+    pub state: ProverState,
+    secrets: ProverSecrets,
+    working_dir: PathBuf,
+    secp: Secp256k1<secp256k1::All>,
+    keypair: KeyPair,
+    circuit: Circuit,
+    trace: ExecutionTrace,
+    graph: TransactionGraph,
+    bisection: BisectionProver,
+    parser: WitnessParser<StageOutput>,
+    /// Deviations from the protocol, when rehearsing a dishonest prover. Never persisted: a
+    /// restored prover always plays honestly.
+    #[cfg(any(test, feature = "simulation"))]
+    faults: Vec<Fault>,
+}
+
+impl Prover {
+    pub fn new(
+        working_dir: &Path,
+        vault: &Vault,
+        setup: ContractSetup,
+        secrets: ProverSecrets,
+        inputs: BTreeMap<WireId, bool>,
+    ) -> Result<Prover, String> {
+        let circuit = setup.read_circuit()?;
+        let trace = circuit.evaluate(&inputs)?;
+        let state = ProverState {
+            setup,
+            inputs,
+            verifier_presignatures: vec![],
+            phase: ProverPhase::Setup,
+            bisection: Bisection::new(trace.len())?,
+            verdict_preimages: vec![],
+            transcript: vec![],
+            last_broadcast: None,
+            collateral_spent: false,
+        };
+        let prover = Prover::from_state(working_dir, state, secrets)?;
+        vault.write_secrets(&prover.contract_file(PROVER_SECRETS_FILE), &prover.secrets)?;
+        prover.save()?;
+        Ok(prover)
+    }
+
+    /// A prover deviating from the protocol as scripted by `faults`, for rehearsing disputes.
+    #[cfg(any(test, feature = "simulation"))]
+    pub fn new_dishonest(
+        working_dir: &Path,
+        vault: &Vault,
+        setup: ContractSetup,
+        secrets: ProverSecrets,
+        inputs: BTreeMap<WireId, bool>,
+        faults: Vec<Fault>,
+    ) -> Result<Prover, String> {
+        let mut prover = Prover::new(working_dir, vault, setup, secrets, inputs)?;
+        prover.trace = crate::simulation::evaluate_with_faults(
+            &prover.circuit,
+            &prover.state.inputs,
+            &faults,
+        )?;
+        let mut opened_trace = prover.trace.clone();
+        for fault in faults.iter() {
+            if let Fault::EquivocateState(wire_id) = fault {
+                if let Some(value) = opened_trace.values.get_mut(wire_id) {
+                    *value = !*value;
+                }
+            }
+        }
+        prover.bisection = BisectionProver::new(
+            &prover.circuit,
+            &opened_trace,
+            prover.secrets.wire_preimages.clone(),
+        )?;
+        prover.bisection.bisection = prover.state.bisection.clone();
+        prover.faults = faults;
+        Ok(prover)
+    }
+
+    /// Resumes a contract from its persisted state, with the secrets sealed in `vault`.
+    pub fn restore(
+        working_dir: &Path,
+        vault: &Vault,
+        contract_id: &Txid,
+    ) -> Result<Prover, String> {
+        let contract_dir = storage::contract_dir(working_dir, contract_id);
+        Prover::from_state(
+            working_dir,
+            storage::read_json(&contract_dir.join(PROVER_STATE_FILE))?,
+            vault.read_secrets(&contract_dir.join(PROVER_SECRETS_FILE))?,
+        )
+    }
+
+    /// Resumes every contract of `working_dir` this party still has to play.
+    pub fn restore_active(working_dir: &Path, vault: &Vault) -> Result<Vec<Prover>, String> {
+        let mut contracts = vec![];
+        for contract_id in storage::list_contracts(working_dir)? {
+            let path = storage::contract_dir(working_dir, &contract_id).join(PROVER_STATE_FILE);
+            if !path.exists() {
+                continue;
+            }
+            let contract = Prover::restore(working_dir, vault, &contract_id)?;
+            if contract.is_active() {
+                contracts.push(contract);
+            }
+        }
+        Ok(contracts)
+    }
+
+    fn from_state(
+        working_dir: &Path,
+        state: ProverState,
+        secrets: ProverSecrets,
+    ) -> Result<Prover, String> {
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::from_secret_key(&secp, &secrets.secret_key);
+        if keypair.public_key() != state.setup.participants.prover {
+            return Err("secret key does not match the prover of the contract".to_string());
+        }
+        let mut circuit = state.setup.read_circuit()?;
+        circuit.gates_bit_commitments_preimages = secrets
+            .wire_preimages
+            .iter()
+            .map(|(wire_id, preimages)| (*wire_id, preimages.clone()))
+            .collect();
+        let commitments =
+            secrets.compute_commitments(state.setup.commitments.challenge_hashes.clone());
+        if commitments != state.setup.commitments {
+            return Err("secrets do not match the commitments of the contract".to_string());
+        }
+        let trace = circuit.evaluate(&state.inputs)?;
+
+        let mut graph = state.setup.build_graph(&secp, &circuit)?;
+        let presignatures = graph.presign(&secp, &keypair)?;
+        graph.add_presignatures(&secp, Role::Prover, &presignatures)?;
+        graph.add_presignatures(&secp, Role::Verifier, &state.verifier_presignatures)?;
+
+        let mut bisection = BisectionProver::new(&circuit, &trace, secrets.wire_preimages.clone())?;
+        bisection.bisection = state.bisection.clone();
+        let parser = graph.build_witness_parser(&state.setup.commitments);
+
+        Ok(Prover {
+            state,
+            secrets,
+            working_dir: working_dir.to_path_buf(),
+            secp,
+            keypair,
+            circuit,
+            trace,
+            graph,
+            bisection,
+            parser,
+            #[cfg(any(test, feature = "simulation"))]
+            faults: vec![],
+        })
+    }
+
+    /// Whether the contract still needs watching: until the amount at stake is claimed and the collateral spent.
+    pub fn is_active(&self) -> bool {
+        !(matches!(self.state.phase, ProverPhase::Closed(_)) && self.state.collateral_spent)
+    }
+
+    /// Contracts are identified by the txid of their funding transaction.
+    pub fn contract_id(&self) -> Txid {
+        self.graph.transactions[&TransactionKind::Funding].tx.txid()
+    }
+
+    pub fn graph(&self) -> &TransactionGraph {
+        &self.graph
+    }
+
+    pub fn trace(&self) -> &ExecutionTrace {
+        &self.trace
+    }
+
+    /// Persists the state of the contract. Secrets are sealed once, when the contract is
+    /// created, and kept apart from the state.
+    pub fn save(&self) -> Result<(), String> {
+        storage::write_json(&self.contract_file(PROVER_STATE_FILE), &self.state)
+    }
+
+    fn contract_file(&self, name: &str) -> PathBuf {
+        storage::contract_dir(&self.working_dir, &self.contract_id()).join(name)
+    }
+
+    /// Paul's pre-signatures, to be sent to Vicky.
+    pub fn presignatures(&self) -> Vec<PreSignature> {
+        self.graph.collect_presignatures(Role::Prover)
+    }
+
+    pub fn receive_presignatures(&mut self, presignatures: &[PreSignature]) -> Result<(), String> {
+        self.graph
+            .add_presignatures(&self.secp, Role::Verifier, presignatures)?;
+        self.state
+            .verifier_presignatures
+            .extend(presignatures.iter().cloned());
+        self.save()
+    }
+
+    /// Advances the contract, returning the transactions to publish. The new state is
+    /// persisted before returning.
+    pub fn handle(&mut self, event: &ContractEvent) -> Result<Vec<ContractAction>, String> {
+        let actions = match event {
+            ContractEvent::SetupCompleted => self.complete_setup()?,
+            ContractEvent::FundingConfirmed => match self.state.phase {
+                ProverPhase::AwaitingFunding => self.commit()?,
+                _ => vec![],
+            },
+            ContractEvent::SpendObserved(tx) => self.observe_spend(tx)?,
+            ContractEvent::TimeoutNear {
+                output,
+                blocks_left,
+            } => self.handle_timeout(output, *blocks_left)?,
+        };
+        self.save()?;
+        Ok(actions)
+    }
+
+    fn complete_setup(&mut self) -> Result<Vec<ContractAction>, String> {
+        if self.state.phase != ProverPhase::Setup {
+            return Ok(vec![]);
+        }
+        let missing = self.graph.count_missing_presignatures();
+        if missing > 0 {
+            return Err(format!(
+                "{} pre-signatures missing, refusing to fund",
+                missing
+            ));
+        }
+        self.state.phase = ProverPhase::AwaitingFunding;
+        Contract::from_graph(&self.state.setup, &self.graph)?.save(&self.working_dir)?;
+        let funding = self.graph.transaction(&TransactionKind::Funding)?;
+        Ok(vec![ContractAction::Fund(funding.tx.clone())])
+    }
+
+    /// Opens the inputs and outputs of the circuit.
+    fn commit(&mut self) -> Result<Vec<ContractAction>, String> {
+        let conditions = collect_committed_wires_ids(&self.circuit)
+            .iter()
+            .map(|wire_id| self.open_wire(wire_id))
+            .collect::<Result<Vec<_>, String>>()?;
+        self.state.phase = ProverPhase::AwaitingChallenge(0);
+        self.broadcast_through_first_leaf(TransactionKind::Commit, conditions)
+    }
+
+    fn observe_spend(&mut self, tx: &Transaction) -> Result<Vec<ContractAction>, String> {
+        let collateral = self.graph.output(&StageOutput::Collateral)?.outpoint;
+        if tx
+            .input
+            .iter()
+            .any(|input| input.previous_output == collateral)
+        {
+            self.state.collateral_spent = true;
+        }
+        let Some(kind) = self.graph.identify_transaction(tx) else {
+            return Ok(vec![]);
+        };
+        let txid = tx.txid();
+        if !self.state.transcript.iter().any(|entry| entry.txid == txid) {
+            storage::write_transaction(&self.working_dir, &self.contract_id(), tx)?;
+            self.state.transcript.push(TranscriptEntry { kind, txid });
+        }
+        match kind {
+            TransactionKind::Challenge(round) => {
+                if self.state.phase != ProverPhase::AwaitingChallenge(round) {
+                    return Ok(vec![]);
+                }
+                self.respond_to_challenge(round, tx)
+            }
+            TransactionKind::GateChallenge => {
+                if self.state.phase != ProverPhase::AwaitingChallenge(self.graph.rounds) {
+                    return Ok(vec![]);
+                }
+                self.open_gate(tx)
+            }
+            TransactionKind::Funding
+            | TransactionKind::CollateralReclaim
+            | TransactionKind::CollateralSlash
+            | TransactionKind::Commit
+            | TransactionKind::Response(_)
+            | TransactionKind::GateResponse => Ok(vec![]),
+            TransactionKind::CommitTimeout
+            | TransactionKind::CooperativeClose
+            | TransactionKind::ChallengeTimeout(_)
+            | TransactionKind::ResponseTimeout(_)
+            | TransactionKind::GateResponseTimeout
+            | TransactionKind::Slash
+            | TransactionKind::SlashTimeout => {
+                self.state.phase = ProverPhase::Closed(kind);
+                self.state.last_broadcast = None;
+                Ok(vec![])
+            }
+        }
+    }
+
+    /// Reveals the state at the midpoint Vicky selected with her verdict, through the leaf her
+    /// verdicts so far unlock.
+    fn respond_to_challenge(
+        &mut self,
+        round: usize,
+        tx: &Transaction,
+    ) -> Result<Vec<ContractAction>, String> {
+        if self.stops_at(round) {
+            return Ok(vec![]);
+        }
+        let spend = self.parse_spend(tx)?;
+        let mut verdict_preimages = self.state.verdict_preimages.clone();
+        let verdict = match self.extract_verdict(&spend, round)? {
+            Some((agreed, preimage)) => {
+                verdict_preimages.push(preimage);
+                Some(agreed)
+            }
+            None => None,
+        };
+        if verdict_preimages.len() != round {
+            return Err(format!("missing verdicts before round {}", round));
+        }
+        let mut bisection = self.bisection.bisection.clone();
+        if let Some(agreed) = verdict {
+            bisection.record_verdict(agreed)?;
+        }
+        let midpoint = bisection
+            .midpoint()
+            .ok_or("bisection complete before the last round".to_string())?;
+        let kind = TransactionKind::Response(round);
+        let leaf = self
+            .graph
+            .response_leaf(round, &bisection.verdicts())?
+            .clone();
+        let response = self
+            .bisection
+            .respond(&Challenge::Bisect {
+                round,
+                verdict,
+                midpoint,
+            })?
+            .ok_or("no response to a bisection challenge".to_string())?;
+        self.state.bisection = self.bisection.bisection.clone();
+        self.state.phase = ProverPhase::AwaitingChallenge(round + 1);
+        let mut conditions = verdict_preimages.clone();
+        conditions.extend(response.preimages.iter().map(|p| p.to_vec()));
+        self.state.verdict_preimages = verdict_preimages;
+        let tx = self.graph.finalize_transaction(&kind, &leaf, conditions)?;
+        Ok(vec![self.broadcast(kind, tx)])
+    }
+
+    /// Opens the inputs and output of the gate Vicky challenged.
+    fn open_gate(&mut self, tx: &Transaction) -> Result<Vec<ContractAction>, String> {
+        if self.stops_at(self.graph.rounds) {
+            return Ok(vec![]);
+        }
+        let spend = self.parse_spend(tx)?;
+        let verdict = self
+            .extract_verdict(&spend, self.graph.rounds)?
+            .map(|(agreed, _)| agreed);
+        let (gate_id, gate_preimage) = spend
+            .stack
+            .iter()
+            .find_map(|element| {
+                match self
+                    .state
+                    .setup
+                    .commitments
+                    .challenge_hashes
+                    .identify(element)
+                {
+                    Some(RevealedChallenge::Gate(gate_id)) => Some((gate_id, element.clone())),
+                    _ => None,
+                }
+            })
+            .ok_or("gate challenge without gate selector".to_string())?;
+        let mut bisection = self.bisection.bisection.clone();
+        if let Some(agreed) = verdict {
+            bisection.record_verdict(agreed)?;
+        }
+        self.bisection.respond(&Challenge::Gate {
+            verdict,
+            position: bisection.lo,
+            gate_id: Some(gate_id),
+        })?;
+        self.state.bisection = self.bisection.bisection.clone();
+
+        let gate = self
+            .circuit
+            .gates
+            .get(&gate_id)
+            .ok_or(format!("unknown gate {}", gate_id))?;
+        let mut wires = gate.inputs();
+        wires.push(gate_id);
+        let mut conditions = vec![gate_preimage];
+        for wire_id in wires.iter() {
+            let equivocates = self.equivocates(wire_id);
+            conditions.push(self.open_wire_as(wire_id, |value| value ^ equivocates)?);
+        }
+        let kind = TransactionKind::GateResponse;
+        let leaf = self.graph.gate_leaf(&kind, &gate_id)?.clone();
+        let tx = self.graph.finalize_transaction(&kind, &leaf, conditions)?;
+        self.state.phase = ProverPhase::AwaitingJudgement;
+        Ok(vec![self.broadcast(kind, tx)])
+    }
+
+    fn handle_timeout(
+        &mut self,
+        output: &StageOutput,
+        blocks_left: u32,
+    ) -> Result<Vec<ContractAction>, String> {
+        let expired = blocks_left == 0;
+        match (output, &self.state.phase) {
+            (StageOutput::Collateral, _) if expired && !self.state.collateral_spent => {
+                self.reclaim_collateral()
+            }
+            (StageOutput::Challenge(round), ProverPhase::AwaitingChallenge(current))
+                if expired && round == current =>
+            {
+                self.broadcast_through_first_leaf(TransactionKind::ChallengeTimeout(*round), vec![])
+            }
+            (StageOutput::Judgement, ProverPhase::AwaitingJudgement) if expired => {
+                self.broadcast_through_first_leaf(TransactionKind::SlashTimeout, vec![])
+            }
+            (StageOutput::Funding | StageOutput::Response(_) | StageOutput::GateResponse, _) => {
+                Ok(self.rebroadcast(output))
+            }
+            _ => Ok(vec![]),
+        }
+    }
+
+    fn reclaim_collateral(&mut self) -> Result<Vec<ContractAction>, String> {
+        let kind = TransactionKind::CollateralReclaim;
+        let signatures = self
+            .graph
+            .sign_transaction(&self.secp, &self.keypair, &kind)?;
+        self.graph
+            .add_presignatures(&self.secp, Role::Prover, &signatures)?;
+        let leaf = self.graph.transaction(&kind)?.leaves[0].clone();
+        let tx = self.graph.finalize_transaction(&kind, &leaf, vec![])?;
+        Ok(vec![ContractAction::Broadcast { kind, tx }])
+    }
+
+    /// Paul's pending move, if it spends `output` and has not confirmed yet.
+    fn rebroadcast(&self, output: &StageOutput) -> Vec<ContractAction> {
+        match self.state.last_broadcast {
+            Some((kind, ref tx))
+                if self.graph.transactions[&kind].spent_output == Some(*output) =>
+            {
+                vec![ContractAction::Broadcast {
+                    kind,
+                    tx: tx.clone(),
+                }]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn broadcast_through_first_leaf(
+        &mut self,
+        kind: TransactionKind,
+        conditions: Vec<Vec<u8>>,
+    ) -> Result<Vec<ContractAction>, String> {
+        let leaf = self.graph.transaction(&kind)?.leaves[0].clone();
+        let tx = self.graph.finalize_transaction(&kind, &leaf, conditions)?;
+        Ok(vec![self.broadcast(kind, tx)])
+    }
+
+    fn broadcast(&mut self, kind: TransactionKind, tx: Transaction) -> ContractAction {
+        self.state.last_broadcast = Some((kind, tx.clone()));
+        ContractAction::Broadcast { kind, tx }
+    }
+
+    /// Whether Paul gives up answering the challenge of `round`.
+    #[cfg(any(test, feature = "simulation"))]
+    fn stops_at(&self, round: usize) -> bool {
+        self.faults
+            .iter()
+            .any(|fault| matches!(fault, Fault::StopAfterRound(last) if round >= *last))
+    }
+
+    #[cfg(not(any(test, feature = "simulation")))]
+    fn stops_at(&self, _round: usize) -> bool {
+        false
+    }
+
+    /// Whether Paul opens the opposite value of `wire_id` when a gate is challenged.
+    #[cfg(any(test, feature = "simulation"))]
+    fn equivocates(&self, wire_id: &WireId) -> bool {
+        self.faults.contains(&Fault::EquivocateWire(*wire_id))
+    }
+
+    #[cfg(not(any(test, feature = "simulation")))]
+    fn equivocates(&self, _wire_id: &WireId) -> bool {
+        false
+    }
+
+    fn open_wire(&self, wire_id: &WireId) -> Result<Vec<u8>, String> {
+        self.open_wire_as(wire_id, |value| value)
+    }
+
+    /// Opens `wire_id`, letting `tamper` replace the value of the trace.
+    fn open_wire_as<F>(&self, wire_id: &WireId, tamper: F) -> Result<Vec<u8>, String>
+    where
+        F: Fn(bool) -> bool,
+    {
+        let value = tamper(
+            self.trace
+                .value(wire_id)
+                .ok_or(format!("no value for wire {}", wire_id))?,
+        );
+        let preimages = self
+            .secrets
+            .wire_preimages
+            .get(wire_id)
+            .ok_or(format!("no preimages for wire {}", wire_id))?;
+        Ok(preimages.preimage_for_bit(value).to_vec())
+    }
+
+    /// Vicky's verdict on the round preceding `round`, revealed by her challenge, along with
+    /// its preimage.
+    fn extract_verdict(
+        &self,
+        spend: &LeafSpend<StageOutput>,
+        round: usize,
+    ) -> Result<Option<(bool, Vec<u8>)>, String> {
+        let Some(judged_round) = round.checked_sub(1) else {
+            return Ok(None);
+        };
+        let challenge_hashes = &self.state.setup.commitments.challenge_hashes;
+        spend
+            .stack
+            .iter()
+            .find_map(|element| match challenge_hashes.identify(element) {
+                Some(RevealedChallenge::Verdict { round, agreed }) if round == judged_round => {
+                    Some(Some((agreed, element.clone())))
+                }
+                _ => None,
+            })
+            .ok_or(format!(
+                "challenge without verdict on round {}",
+                judged_round
+            ))
+    }
+
+    fn parse_spend(&self, tx: &Transaction) -> Result<LeafSpend<StageOutput>, String> {
+        self.parser
+            .parse_transaction(tx)
+            .into_iter()
+            .next()
+            .ok_or(format!("{} does not spend a contract output", tx.txid()))
+    }
+}
+
+impl ContractMachine for Prover {
+    fn contract_id(&self) -> Txid {
+        Prover::contract_id(self)
+    }
+
+    fn role(&self) -> Role {
+        Role::Prover
+    }
+
+    fn phase(&self) -> String {
+        format!("{:?}", self.state.phase)
+    }
+
+    fn graph(&self) -> &TransactionGraph {
+        &self.graph
+    }
+
+    fn presignatures(&self) -> Vec<PreSignature> {
+        Prover::presignatures(self)
+    }
+
+    fn receive_presignatures(&mut self, presignatures: &[PreSignature]) -> Result<(), String> {
+        Prover::receive_presignatures(self, presignatures)
+    }
+
+    fn key_source(&self) -> Option<&KeySource> {
+        self.secrets.key_source.as_ref()
+    }
+
+    fn transcript(&self) -> &[TranscriptEntry] {
+        &self.state.transcript
+    }
+
+    fn bump_fee(
+        &self,
+        parent: &Transaction,
+        parent_fee: Amount,
+        fee_rate: u64,
+        wallet: &mut FeeWallet,
+    ) -> Result<Transaction, String> {
+        self.graph.build_cpfp(
+            &self.secp,
+            Role::Prover,
+            &self.keypair,
+            parent,
+            parent_fee,
+            fee_rate,
+            wallet,
+        )
+    }
+
+    fn handle(&mut self, event: &ContractEvent) -> Result<Vec<ContractAction>, String> {
+        Prover::handle(self, event)
+    }
+
+    fn snapshot(&self) -> Result<serde_json::Value, String> {
+        serde_json::to_value(&self.state).map_err(|e| format!("unable to snapshot state: {}", e))
+    }
+
+    fn rollback(&mut self, snapshot: serde_json::Value) -> Result<(), String> {
+        let state: ProverState = serde_json::from_value(snapshot)
+            .map_err(|e| format!("unable to restore snapshot: {}", e))?;
+        self.bisection.bisection = state.bisection.clone();
+        self.state = state;
+        self.save()
+    }
+}
+
+#[test]
+fn test_prover_answers_challenges_and_resumes_from_disk() {
+    use crate::bisection::BisectionVerifier;
+    use crate::protocol::build_test_setup;
+
+    let secp = Secp256k1::new();
+    let prover_keypair = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+    let verifier_keypair = KeyPair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let (circuit, setup, challenge_store) = build_test_setup(
+        include_str!("../bristol/fixtures/test_vector_1.bristol"),
+        &prover_keypair,
+        &verifier_keypair,
+    );
+    let secrets = ProverSecrets {
+        secret_key: prover_keypair.secret_key(),
+        key_source: None,
+        wire_preimages: circuit
+            .gates_bit_commitments_preimages
+            .iter()
+            .map(|(wire_id, preimages)| (*wire_id, preimages.clone()))
+            .collect(),
+    };
+    let inputs = circuit
+        .collect_input_wires_ids()
+        .into_iter()
+        .map(|wire_id| (*wire_id, wire_id % 2 == 0))
+        .collect::<BTreeMap<_, _>>();
+    let working_dir = std::env::temp_dir().join(format!("bitvm-prover-{}", rand::random::<u64>()));
+    let vault = Vault::ephemeral();
+
+    let mut prover =
+        Prover::new(&working_dir, &vault, setup.clone(), secrets, inputs.clone()).unwrap();
+    let mut graph = setup.build_graph(&secp, &circuit).unwrap();
+    let verifier_presignatures = graph.presign(&secp, &verifier_keypair).unwrap();
+    graph
+        .add_presignatures(&secp, Role::Verifier, &verifier_presignatures)
+        .unwrap();
+    graph
+        .add_presignatures(&secp, Role::Prover, &prover.presignatures())
+        .unwrap();
+    assert!(prover.handle(&ContractEvent::SetupCompleted).is_err());
+    prover
+        .receive_presignatures(&verifier_presignatures)
+        .unwrap();
+
+    let actions = prover.handle(&ContractEvent::SetupCompleted).unwrap();
+    assert!(matches!(actions[..], [ContractAction::Fund(_)]));
+    let actions = prover.handle(&ContractEvent::FundingConfirmed).unwrap();
+    assert!(matches!(
+        actions[..],
+        [ContractAction::Broadcast {
+            kind: TransactionKind::Commit,
+            ..
+        }]
+    ));
+
+    // Vicky wrongly believes the last gate outputs the opposite value.
+    let last_gate = *prover.trace().order.last().unwrap();
+    let verifier_trace = circuit
+        .evaluate_with(&inputs, |gate_id, value| value ^ (gate_id == last_gate))
+        .unwrap();
+    let committed = collect_committed_wires_ids(&circuit)
+        .into_iter()
+        .map(|wire_id| (wire_id, prover.trace().values[&wire_id]))
+        .collect();
+    let mut verifier = BisectionVerifier::new(
+        &circuit,
+        &verifier_trace,
+        &setup.commitments.commitment_set,
+        &committed,
+    )
+    .unwrap();
+    let contract_id = prover.contract_id();
+    for round in 0..graph.rounds {
+        let challenge = verifier.next_challenge().unwrap();
+        let kind = TransactionKind::Challenge(round);
+        let leaf = graph.transaction(&kind).unwrap().leaves[0].clone();
+        let preimages = challenge_store.reveal(&challenge).unwrap();
+        let tx = graph
+            .finalize_transaction(&kind, &leaf, preimages.iter().map(|p| p.to_vec()).collect())
+            .unwrap();
+
+        // Restart between rounds: the dispute resumes from the working directory.
+        drop(prover);
+        prover = Prover::restore(&working_dir, &vault, &contract_id).unwrap();
+        let actions = prover.handle(&ContractEvent::SpendObserved(tx)).unwrap();
+        let [ContractAction::Broadcast { kind, ref tx }] = actions[..] else {
+            panic!("expected a response");
+        };
+        assert_eq!(kind, TransactionKind::Response(round));
+        let stack = tx.input[0].witness.to_vec();
+        // Vicky's verdicts so far, then the state at the midpoint.
+        let preimages = stack[2..stack.len() - 2]
+            .iter()
+            .rev()
+            .skip(round)
+            .map(|element| <[u8; 32]>::try_from(element.as_slice()).unwrap())
+            .collect::<Vec<_>>();
+        verifier
+            .receive_response(&crate::bisection::Response { round, preimages })
+            .unwrap();
+    }
+
+    let challenge = verifier.next_challenge().unwrap();
+    let Challenge::Gate {
+        gate_id: Some(gate_id),
+        ..
+    } = challenge
+    else {
+        panic!("bisection should isolate a gate");
+    };
+    assert_eq!(gate_id, last_gate);
+    let kind = TransactionKind::GateChallenge;
+    let leaf = graph.gate_leaf(&kind, &gate_id).unwrap().clone();
+    let preimages = challenge_store.reveal(&challenge).unwrap();
+    let tx = graph
+        .finalize_transaction(&kind, &leaf, preimages.iter().map(|p| p.to_vec()).collect())
+        .unwrap();
+    let actions = prover.handle(&ContractEvent::SpendObserved(tx)).unwrap();
+    assert!(matches!(
+        actions[..],
+        [ContractAction::Broadcast {
+            kind: TransactionKind::GateResponse,
+            ..
+        }]
+    ));
+    assert_eq!(prover.state.phase, ProverPhase::AwaitingJudgement);
+
+    // Paul's gate is consistent: Vicky can not slash, and Paul claims the funds.
+    let actions = prover
+        .handle(&ContractEvent::TimeoutNear {
+            output: StageOutput::Judgement,
+            blocks_left: 0,
+        })
+        .unwrap();
+    let [ContractAction::Broadcast { kind, ref tx }] = actions[..] else {
+        panic!("expected the slash timeout");
+    };
+    assert_eq!(kind, TransactionKind::SlashTimeout);
+    let tx = tx.clone();
+    prover.handle(&ContractEvent::SpendObserved(tx)).unwrap();
+    assert_eq!(
+        Prover::restore(&working_dir, &vault, &contract_id)
+            .unwrap()
+            .state
+            .phase,
+        ProverPhase::Closed(TransactionKind::SlashTimeout)
+    );
+    std::fs::remove_dir_all(&working_dir).unwrap();
+}
@@ -0,0 +1,500 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use bitcoin::hashes::{sha256, Hash};
+use bitcoin::secp256k1::{self, rand, schnorr, KeyPair, Message, PublicKey, Secp256k1};
+use bitcoin::Txid;
+use bitvm_types::WireId;
+
+use crate::protocol::{
+    ContractParameters, ContractSetup, ContractTerms, Participants, PreSignature, Role,
+};
+use crate::prover::{Prover, ProverSecrets};
+use crate::storage;
+use crate::tapleaf::challenge_hashlock::ChallengeHashes;
+use crate::vault::{Sealed, Vault};
+use crate::verifier::{Verifier, VerifierSecrets};
+
+/// Version of the setup messages. Envelopes of any other version are rejected.
+pub const SETUP_PROTOCOL_VERSION: u16 = 1;
+
+const SETUP_DIR: &str = "setup";
+
+/// Prefix of the signed digest, so setup signatures can't be replayed as anything else.
+const SIGNATURE_TAG: &[u8] = b"BitVM/setup";
+
+/// What Paul and Vicky tell each other while setting up a contract.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum SetupPayload {
+    /// Vicky opens a session with the hashes of the challenges she may reveal.
+    Challenges {
+        circuit_hash: sha256::Hash,
+        verifier: PublicKey,
+        challenge_hashes: ChallengeHashes,
+    },
+    /// Paul proposes the terms of the contract, with his commitments and pre-signatures.
+    Proposal {
+        setup: Box<ContractSetup>,
+        presignatures: Vec<PreSignature>,
+    },
+    /// Vicky agrees to the proposal, and returns her pre-signatures.
+    Acceptance {
+        contract_id: Txid,
+        presignatures: Vec<PreSignature>,
+    },
+}
+
+/// A setup message, signed by its sender. Envelopes are plain JSON and carry everything
+/// needed to check them, so they can be exchanged over any transport.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SetupEnvelope {
+    pub version: u16,
+    /// Random nonce drawn by Vicky, binding every message to one setup.
+    pub session_id: sha256::Hash,
+    pub sender: PublicKey,
+    pub payload: SetupPayload,
+    pub signature: schnorr::Signature,
+}
+
+impl SetupEnvelope {
+    pub fn sign<C: secp256k1::Signing>(
+        secp: &Secp256k1<C>,
+        session_id: sha256::Hash,
+        keypair: &KeyPair,
+        payload: SetupPayload,
+    ) -> Result<SetupEnvelope, String> {
+        let sender = keypair.public_key();
+        let digest = signature_digest(SETUP_PROTOCOL_VERSION, &session_id, &sender, &payload)?;
+        Ok(SetupEnvelope {
+            version: SETUP_PROTOCOL_VERSION,
+            session_id,
+            sender,
+            signature: secp.sign_schnorr(&digest, keypair),
+            payload,
+        })
+    }
+
+    /// Checks the version and the sender's signature.
+    pub fn verify<C: secp256k1::Verification>(&self, secp: &Secp256k1<C>) -> Result<(), String> {
+        if self.version != SETUP_PROTOCOL_VERSION {
+            return Err(format!(
+                "unsupported setup protocol version {} (expected {})",
+                self.version, SETUP_PROTOCOL_VERSION
+            ));
+        }
+        let digest = signature_digest(self.version, &self.session_id, &self.sender, &self.payload)?;
+        secp.verify_schnorr(&self.signature, &digest, &self.sender.x_only_public_key().0)
+            .map_err(|e| format!("invalid signature of setup message: {}", e))
+    }
+
+    pub fn to_json(&self) -> Result<String, String> {
+        serde_json::to_string_pretty(self)
+            .map_err(|e| format!("unable to serialize setup message: {}", e))
+    }
+
+    pub fn from_json(json: &str) -> Result<SetupEnvelope, String> {
+        serde_json::from_str(json).map_err(|e| format!("malformed setup message: {}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SetupStep {
+    /// Vicky sent her challenges, and waits for Paul's proposal.
+    AwaitingProposal,
+    /// Paul sent his proposal, and waits for Vicky's pre-signatures.
+    AwaitingAcceptance,
+    /// Both parties hold every pre-signature: the contract can be funded.
+    Finalized,
+}
+
+/// One side of a contract setup, persisted between messages so the exchange can span
+/// several runs of the CLI.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SetupSession {
+    pub session_id: sha256::Hash,
+    pub role: Role,
+    pub circuit_hash: sha256::Hash,
+    pub step: SetupStep,
+    pub counterparty: Option<PublicKey>,
+    pub contract_id: Option<Txid>,
+    /// Vicky's secrets, sealed until she accepts a proposal and hands them to her contract.
+    pending_secrets: Option<Sealed>,
+    /// Terms of the contract, once agreed on.
+    setup: Option<ContractSetup>,
+    /// Least Vicky accepts of Paul's proposal.
+    #[serde(default)]
+    terms: ContractTerms,
+}
+
+impl SetupSession {
+    /// Opens a session as Vicky, returning the challenges to send to Paul.
+    pub fn open(
+        secp: &Secp256k1<secp256k1::All>,
+        vault: &Vault,
+        circuit_source: &str,
+        secrets: VerifierSecrets,
+        terms: ContractTerms,
+    ) -> Result<(SetupSession, SetupEnvelope), String> {
+        let session_id = sha256::Hash::from_byte_array(rand::random());
+        let keypair = KeyPair::from_secret_key(secp, &secrets.secret_key);
+        let circuit_hash = hash_circuit(circuit_source);
+        let envelope = SetupEnvelope::sign(
+            secp,
+            session_id,
+            &keypair,
+            SetupPayload::Challenges {
+                circuit_hash,
+                verifier: keypair.public_key(),
+                challenge_hashes: secrets.challenge_store.compute_hashes(),
+            },
+        )?;
+        let session = SetupSession {
+            session_id,
+            role: Role::Verifier,
+            circuit_hash,
+            step: SetupStep::AwaitingProposal,
+            counterparty: None,
+            contract_id: None,
+            pending_secrets: Some(vault.seal(&secrets)?),
+            setup: None,
+            terms,
+        };
+        Ok((session, envelope))
+    }
+
+    /// Joins Vicky's session as Paul: builds his side of the contract on her challenges, and
+    /// returns the proposal to send back.
+    #[allow(clippy::too_many_arguments)]
+    pub fn join(
+        secp: &Secp256k1<secp256k1::All>,
+        working_dir: &Path,
+        vault: &Vault,
+        challenges: &SetupEnvelope,
+        circuit_source: &str,
+        secrets: ProverSecrets,
+        inputs: BTreeMap<WireId, bool>,
+        parameters: ContractParameters,
+    ) -> Result<(SetupSession, Prover, SetupEnvelope), String> {
+        challenges.verify(secp)?;
+        let SetupPayload::Challenges {
+            circuit_hash,
+            verifier,
+            challenge_hashes,
+        } = &challenges.payload
+        else {
+            return Err("expected the verifier's challenges".to_string());
+        };
+        if challenges.sender != *verifier {
+            return Err("challenges are not signed by the verifier".to_string());
+        }
+        if hash_circuit(circuit_source) != *circuit_hash {
+            return Err("challenges were drawn for another circuit".to_string());
+        }
+        let keypair = KeyPair::from_secret_key(secp, &secrets.secret_key);
+        let setup = ContractSetup {
+            circuit_source: circuit_source.to_string(),
+            participants: Participants {
+                prover: keypair.public_key(),
+                verifier: *verifier,
+            },
+            parameters,
+            commitments: secrets.compute_commitments(challenge_hashes.clone()),
+        };
+        let prover = Prover::new(working_dir, vault, setup.clone(), secrets, inputs)?;
+        let envelope = SetupEnvelope::sign(
+            secp,
+            challenges.session_id,
+            &keypair,
+            SetupPayload::Proposal {
+                setup: Box::new(setup.clone()),
+                presignatures: prover.presignatures(),
+            },
+        )?;
+        let session = SetupSession {
+            session_id: challenges.session_id,
+            role: Role::Prover,
+            circuit_hash: *circuit_hash,
+            step: SetupStep::AwaitingAcceptance,
+            counterparty: Some(*verifier),
+            contract_id: Some(prover.contract_id()),
+            pending_secrets: None,
+            setup: Some(setup),
+            terms: ContractTerms::default(),
+        };
+        Ok((session, prover, envelope))
+    }
+
+    /// Checks Paul's proposal against Vicky's challenges, and sets up her side of the
+    /// contract. Returns the acceptance to send back; Vicky's setup is then complete.
+    pub fn receive_proposal(
+        &mut self,
+        secp: &Secp256k1<secp256k1::All>,
+        working_dir: &Path,
+        vault: &Vault,
+        proposal: &SetupEnvelope,
+    ) -> Result<(Verifier, SetupEnvelope), String> {
+        self.check_envelope(secp, proposal, Role::Verifier, SetupStep::AwaitingProposal)?;
+        let SetupPayload::Proposal {
+            setup,
+            presignatures,
+        } = &proposal.payload
+        else {
+            return Err("expected the prover's proposal".to_string());
+        };
+        let secrets: VerifierSecrets = vault.open(
+            self.pending_secrets
+                .as_ref()
+                .ok_or("verifier secrets already used".to_string())?,
+        )?;
+        let keypair = KeyPair::from_secret_key(secp, &secrets.secret_key);
+        if proposal.sender != setup.participants.prover {
+            return Err("proposal is not signed by the prover".to_string());
+        }
+        if hash_circuit(&setup.circuit_source) != self.circuit_hash {
+            return Err("proposal is for another circuit".to_string());
+        }
+        if setup.participants.verifier != keypair.public_key()
+            || setup.commitments.challenge_hashes != secrets.challenge_store.compute_hashes()
+        {
+            return Err("proposal does not use the challenges of this session".to_string());
+        }
+        self.terms
+            .check(&setup.parameters)
+            .map_err(|e| format!("proposal does not meet the terms of this session: {}", e))?;
+        let mut verifier = Verifier::new(working_dir, vault, setup.as_ref().clone(), secrets)?;
+        verifier.receive_presignatures(presignatures)?;
+        let missing = verifier.graph().count_missing_presignatures();
+        if missing > 0 {
+            return Err(format!("proposal lacks {} pre-signatures", missing));
+        }
+        let envelope = SetupEnvelope::sign(
+            secp,
+            self.session_id,
+            &keypair,
+            SetupPayload::Acceptance {
+                contract_id: verifier.contract_id(),
+                presignatures: verifier.presignatures(),
+            },
+        )?;
+        self.pending_secrets = None;
+        self.counterparty = Some(proposal.sender);
+        self.contract_id = Some(verifier.contract_id());
+        self.setup = Some(setup.as_ref().clone());
+        self.step = SetupStep::Finalized;
+        Ok((verifier, envelope))
+    }
+
+    /// Takes Vicky's pre-signatures, completing Paul's setup.
+    pub fn receive_acceptance(
+        &mut self,
+        secp: &Secp256k1<secp256k1::All>,
+        acceptance: &SetupEnvelope,
+        prover: &mut Prover,
+    ) -> Result<(), String> {
+        self.check_envelope(
+            secp,
+            acceptance,
+            Role::Prover,
+            SetupStep::AwaitingAcceptance,
+        )?;
+        let SetupPayload::Acceptance {
+            contract_id,
+            presignatures,
+        } = &acceptance.payload
+        else {
+            return Err("expected the verifier's acceptance".to_string());
+        };
+        if Some(*contract_id) != self.contract_id || prover.contract_id() != *contract_id {
+            return Err(format!(
+                "acceptance is for another contract {}",
+                contract_id
+            ));
+        }
+        prover.receive_presignatures(presignatures)?;
+        let missing = prover.graph().count_missing_presignatures();
+        if missing > 0 {
+            return Err(format!("acceptance lacks {} pre-signatures", missing));
+        }
+        self.step = SetupStep::Finalized;
+        Ok(())
+    }
+
+    /// Terms of the contract, once both parties hold every pre-signature.
+    pub fn finalized_setup(&self) -> Option<&ContractSetup> {
+        match self.step {
+            SetupStep::Finalized => self.setup.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn save(&self, working_dir: &Path) -> Result<(), String> {
+        storage::write_json(&session_path(working_dir, &self.session_id), self)
+    }
+
+    pub fn restore(working_dir: &Path, session_id: &sha256::Hash) -> Result<SetupSession, String> {
+        storage::read_json(&session_path(working_dir, session_id))
+    }
+
+    fn check_envelope(
+        &self,
+        secp: &Secp256k1<secp256k1::All>,
+        envelope: &SetupEnvelope,
+        role: Role,
+        step: SetupStep,
+    ) -> Result<(), String> {
+        envelope.verify(secp)?;
+        if envelope.session_id != self.session_id {
+            return Err(format!("message is for session {}", envelope.session_id));
+        }
+        if self.role != role || self.step != step {
+            return Err(format!(
+                "unexpected message for a {:?} session in step {:?}",
+                self.role, self.step
+            ));
+        }
+        match self.counterparty {
+            Some(counterparty) if counterparty != envelope.sender => {
+                Err("message is not signed by the other participant".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn hash_circuit(circuit_source: &str) -> sha256::Hash {
+    sha256::Hash::hash(circuit_source.as_bytes())
+}
+
+fn session_path(working_dir: &Path, session_id: &sha256::Hash) -> std::path::PathBuf {
+    working_dir
+        .join(SETUP_DIR)
+        .join(format!("{}.json", session_id))
+}
+
+fn signature_digest(
+    version: u16,
+    session_id: &sha256::Hash,
+    sender: &PublicKey,
+    payload: &SetupPayload,
+) -> Result<Message, String> {
+    let payload = serde_json::to_vec(payload)
+        .map_err(|e| format!("unable to serialize setup message: {}", e))?;
+    let mut engine = sha256::Hash::engine();
+    bitcoin::hashes::HashEngine::input(&mut engine, SIGNATURE_TAG);
+    bitcoin::hashes::HashEngine::input(&mut engine, &version.to_be_bytes());
+    bitcoin::hashes::HashEngine::input(&mut engine, session_id.as_byte_array());
+    bitcoin::hashes::HashEngine::input(&mut engine, &sender.serialize());
+    bitcoin::hashes::HashEngine::input(&mut engine, &payload);
+    let digest = sha256::Hash::from_engine(engine);
+    Message::from_slice(digest.as_byte_array())
+        .map_err(|e| format!("unable to hash setup message: {}", e))
+}
+
+#[test]
+fn test_setup_completes_over_serialized_messages() {
+    use crate::protocol::{build_test_setup, ContractEvent};
+    use bitcoin::secp256k1::SecretKey;
+
+    let secp = Secp256k1::new();
+    let circuit_source = include_str!("../bristol/fixtures/test_vector_1.bristol");
+    let prover_keypair = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+    let verifier_keypair = KeyPair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let (circuit, test_setup, _) =
+        build_test_setup(circuit_source, &prover_keypair, &verifier_keypair);
+    let working_dir = std::env::temp_dir().join(format!("bitvm-setup-{}", rand::random::<u64>()));
+    let prover_dir = working_dir.join("prover");
+    let verifier_dir = working_dir.join("verifier");
+    let vault = Vault::ephemeral();
+    let transmit =
+        |envelope: &SetupEnvelope| SetupEnvelope::from_json(&envelope.to_json().unwrap()).unwrap();
+
+    let verifier_secrets =
+        VerifierSecrets::generate(&circuit, SecretKey::from_slice(&[7; 32]).unwrap());
+    let (verifier_session, challenges) = SetupSession::open(
+        &secp,
+        &vault,
+        circuit_source,
+        verifier_secrets,
+        ContractTerms::default(),
+    )
+    .unwrap();
+    verifier_session.save(&verifier_dir).unwrap();
+    let saved = std::fs::read_to_string(session_path(&verifier_dir, &challenges.session_id));
+    assert!(!saved.unwrap().contains("challenge_store"));
+
+    let prover_secrets = ProverSecrets::generate(&circuit, prover_keypair.secret_key());
+    let inputs = circuit
+        .collect_input_wires_ids()
+        .into_iter()
+        .map(|wire_id| (*wire_id, true))
+        .collect::<BTreeMap<_, _>>();
+    let (mut prover_session, mut prover, proposal) = SetupSession::join(
+        &secp,
+        &prover_dir,
+        &vault,
+        &transmit(&challenges),
+        circuit_source,
+        prover_secrets,
+        inputs.clone(),
+        test_setup.parameters.clone(),
+    )
+    .unwrap();
+
+    // Tampered messages are rejected, and leave the session untouched.
+    let mut verifier_session =
+        SetupSession::restore(&verifier_dir, &challenges.session_id).unwrap();
+    let mut tampered = transmit(&proposal);
+    if let SetupPayload::Proposal { ref mut setup, .. } = tampered.payload {
+        setup.parameters.amount = bitcoin::Amount::from_sat(1);
+    }
+    assert!(verifier_session
+        .receive_proposal(&secp, &verifier_dir, &vault, &tampered)
+        .is_err());
+    assert!(verifier_session.finalized_setup().is_none());
+
+    // A proposal Paul did sign is rejected too when it falls short of Vicky's terms.
+    let mut parameters = test_setup.parameters.clone();
+    parameters.collateral = bitcoin::Amount::from_sat(5_000);
+    parameters.timelocks.response_timeout = 1;
+    let (_, _, short_proposal) = SetupSession::join(
+        &secp,
+        &working_dir.join("short-prover"),
+        &vault,
+        &transmit(&challenges),
+        circuit_source,
+        ProverSecrets::generate(&circuit, prover_keypair.secret_key()),
+        inputs.clone(),
+        parameters,
+    )
+    .unwrap();
+    let Err(e) =
+        verifier_session.receive_proposal(&secp, &verifier_dir, &vault, &transmit(&short_proposal))
+    else {
+        panic!("proposal below the terms accepted");
+    };
+    assert_eq!(e, "proposal does not meet the terms of this session: collateral 0.00005 BTC is below the minimum of 0.0001 BTC; response timeout of 1 blocks is below the minimum of 6");
+    assert!(verifier_session.finalized_setup().is_none());
+
+    let (mut verifier, acceptance) = verifier_session
+        .receive_proposal(&secp, &verifier_dir, &vault, &transmit(&proposal))
+        .unwrap();
+    assert_eq!(
+        verifier_session.finalized_setup(),
+        prover_session.setup.as_ref()
+    );
+    assert!(verifier_session
+        .receive_proposal(&secp, &verifier_dir, &vault, &transmit(&proposal))
+        .is_err());
+
+    prover_session
+        .receive_acceptance(&secp, &transmit(&acceptance), &mut prover)
+        .unwrap();
+    assert_eq!(prover_session.step, SetupStep::Finalized);
+    assert_eq!(prover.contract_id(), verifier.contract_id());
+    assert!(!prover
+        .handle(&ContractEvent::SetupCompleted)
+        .unwrap()
+        .is_empty());
+    verifier.handle(&ContractEvent::SetupCompleted).unwrap();
+    let _ = std::fs::remove_dir_all(&working_dir);
+}
@@ -0,0 +1,815 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::PathBuf;
+
+use std::sync::mpsc::Receiver;
+
+use bitcoin::secp256k1::{All, KeyPair, Secp256k1, SecretKey};
+use bitcoin::{Amount, ScriptBuf, Transaction, TxOut, Txid};
+use bitvm_types::{Circuit, ExecutionTrace, GateId, WireId};
+use rand::{thread_rng, Rng};
+
+use crate::chain::mock::MockChain;
+use crate::chain::{ChainBlock, ChainEvent};
+use crate::equivocation::EquivocationDetector;
+use crate::fees::{fee_rate, sign_key_spend, FeePolicy, FeeWallet};
+use crate::protocol::{
+    ContractAction, ContractEvent, ContractMachine, ContractParameters, ContractSetup,
+    FundingInput, Participants, Role, TimelockParameters, TransactionGraph, TransactionKind,
+};
+use crate::prover::{Prover, ProverSecrets};
+use crate::vault::Vault;
+use crate::verifier::{Verifier, VerifierSecrets};
+
+/// Upper bound on the blocks a simulated dispute may take.
+const MAX_SIMULATED_BLOCKS: u32 = 100_000;
+
+/// A deviation from the protocol, injected to rehearse disputes against a dishonest Paul.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fault {
+    /// Flips the output of the gate when evaluating the circuit.
+    FlipGate(GateId),
+    /// Opens the opposite value of the wire whenever a gate response reveals it.
+    EquivocateWire(WireId),
+    /// Opens the opposite value of the wire whenever a bisection response reveals it.
+    EquivocateState(WireId),
+    /// Stops answering challenges once the given number of rounds were answered.
+    StopAfterRound(usize),
+    /// Commits to the opposite value of the input wire, while keeping the outputs computed
+    /// on his actual inputs.
+    WrongInput(WireId),
+}
+
+/// Paul's execution trace on `inputs`, once `faults` are applied. The inputs of the trace are
+/// the ones he commits to.
+pub fn evaluate_with_faults(
+    circuit: &Circuit,
+    inputs: &BTreeMap<WireId, bool>,
+    faults: &[Fault],
+) -> Result<ExecutionTrace, String> {
+    let flipped = |gate_id: GateId| faults.contains(&Fault::FlipGate(gate_id));
+    let trace = circuit.evaluate_with(inputs, |gate_id, value| value ^ flipped(gate_id))?;
+    let wrong_inputs = faults
+        .iter()
+        .filter_map(|fault| match fault {
+            Fault::WrongInput(wire_id) => Some(*wire_id),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>();
+    if wrong_inputs.is_empty() {
+        return Ok(trace);
+    }
+    let committed_inputs = inputs
+        .iter()
+        .map(|(wire_id, value)| (*wire_id, value ^ wrong_inputs.contains(wire_id)))
+        .collect();
+    let outputs = circuit.collect_output_wires_ids();
+    circuit.evaluate_with(&committed_inputs, |gate_id, value| {
+        match outputs.contains(&gate_id) {
+            true => trace.value(&gate_id).unwrap_or(value),
+            false => value ^ flipped(gate_id),
+        }
+    })
+}
+
+#[derive(Debug, Clone)]
+pub struct SimulationOptions {
+    /// Where both parties persist their state.
+    pub working_dir: PathBuf,
+    /// Paul's inputs. Drawn at random when missing.
+    pub inputs: Option<BTreeMap<WireId, bool>>,
+    /// How Paul deviates from the protocol.
+    pub faults: Vec<Fault>,
+    pub amount: Amount,
+    pub collateral: Amount,
+    pub fee_per_transaction: Amount,
+    pub response_timeout: u16,
+    /// Minimum fee rate of blocks, in sat/vB, from each height on.
+    pub fee_rates: BTreeMap<u32, u64>,
+    /// How both parties bump their transactions stuck below the minimum fee rate. Without
+    /// one, they wait for the fee rate to drop.
+    pub fee_policy: Option<FeePolicy>,
+    /// Coins each party sets aside for fee bumps.
+    pub fee_reserve: Amount,
+}
+
+impl Default for SimulationOptions {
+    fn default() -> Self {
+        SimulationOptions {
+            working_dir: std::env::temp_dir()
+                .join(format!("bitvm-simulation-{}", rand::random::<u64>())),
+            inputs: None,
+            faults: vec![],
+            amount: Amount::from_sat(1_000_000),
+            collateral: Amount::from_sat(100_000),
+            fee_per_transaction: Amount::from_sat(2_000),
+            response_timeout: 6,
+            fee_rates: BTreeMap::new(),
+            fee_policy: None,
+            fee_reserve: Amount::from_sat(10_000_000),
+        }
+    }
+}
+
+/// A transaction confirmed during the simulation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulatedTransaction {
+    pub height: u32,
+    pub kind: TransactionKind,
+    pub broadcaster: Role,
+    pub txid: Txid,
+    pub vsize: usize,
+    pub witness_size: usize,
+    pub fee: Amount,
+    /// Fee of the children bumping the transaction.
+    pub cpfp_fee: Amount,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimulationReport {
+    pub inputs: BTreeMap<WireId, bool>,
+    pub rounds: usize,
+    pub transactions: Vec<SimulatedTransaction>,
+    /// Transaction through which the amount at stake was claimed.
+    pub outcome: TransactionKind,
+    pub winner: Role,
+    /// Party who ended up with Paul's collateral.
+    pub collateral_winner: Role,
+    /// Whether Paul's committed outputs differ from the circuit evaluated on his committed
+    /// inputs, or he never committed.
+    pub false_claim: bool,
+    /// Whether Paul opened both values of a wire on-chain.
+    pub equivocated: bool,
+}
+
+impl SimulationReport {
+    pub fn total_fees(&self, role: Role) -> Amount {
+        self.transactions
+            .iter()
+            .filter(|tx| tx.broadcaster == role)
+            .map(|tx| tx.fee + tx.cpfp_fee)
+            .sum()
+    }
+
+    /// Checks that Vicky won the amount at stake if and only if Paul's claim was false, and
+    /// his collateral if and only if he equivocated.
+    pub fn check_punishment(&self) -> Result<(), String> {
+        match (self.false_claim, self.winner) {
+            (true, Role::Prover) => {
+                return Err(format!(
+                    "Paul claimed a false result and won through {}",
+                    self.outcome
+                ))
+            }
+            (false, Role::Verifier) => {
+                return Err(format!(
+                    "Paul claimed a correct result and lost through {}",
+                    self.outcome
+                ))
+            }
+            _ => {}
+        }
+        match (self.equivocated, self.collateral_winner) {
+            (true, Role::Prover) => Err("Paul equivocated and kept his collateral".to_string()),
+            (false, Role::Verifier) => {
+                Err("Paul lost his collateral without equivocating".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Who claims the amount at stake, or the collateral, through `kind`.
+pub fn closing_party(kind: &TransactionKind) -> Option<Role> {
+    match kind {
+        TransactionKind::ChallengeTimeout(_)
+        | TransactionKind::SlashTimeout
+        | TransactionKind::CooperativeClose
+        | TransactionKind::CollateralReclaim => Some(Role::Prover),
+        TransactionKind::CommitTimeout
+        | TransactionKind::ResponseTimeout(_)
+        | TransactionKind::GateResponseTimeout
+        | TransactionKind::Slash
+        | TransactionKind::CollateralSlash => Some(Role::Verifier),
+        _ => None,
+    }
+}
+
+fn is_collateral_claim(kind: &TransactionKind) -> bool {
+    matches!(
+        kind,
+        TransactionKind::CollateralReclaim | TransactionKind::CollateralSlash
+    )
+}
+
+/// Runs a whole contract between Paul and Vicky against an in-memory chain: setup,
+/// funding, the dispute if any, and the release of the collateral.
+pub fn run_simulation(
+    circuit_source: &str,
+    options: &SimulationOptions,
+) -> Result<SimulationReport, String> {
+    let secp = Secp256k1::new();
+    let mut rng = thread_rng();
+    let prover_keypair = KeyPair::from_secret_key(&secp, &SecretKey::new(&mut rng));
+    let verifier_keypair = KeyPair::from_secret_key(&secp, &SecretKey::new(&mut rng));
+
+    let circuit = crate::bristol::parser::read_circuit(circuit_source)?;
+    let inputs = match options.inputs {
+        Some(ref inputs) => inputs.clone(),
+        None => circuit
+            .collect_input_wires_ids()
+            .into_iter()
+            .map(|wire_id| (*wire_id, rng.gen::<bool>()))
+            .collect(),
+    };
+
+    let prover_secrets = ProverSecrets::generate(&circuit, prover_keypair.secret_key());
+    let verifier_secrets = VerifierSecrets::generate(&circuit, verifier_keypair.secret_key());
+
+    let mut chain = MockChain::new();
+    let events = chain.subscribe();
+    let wallet_script = ScriptBuf::new_v1_p2tr(&secp, prover_keypair.x_only_public_key().0, None);
+    let wallet_output = TxOut {
+        value: (options.amount + options.collateral + options.fee_per_transaction * 2).to_sat(),
+        script_pubkey: wallet_script.clone(),
+    };
+    let wallet_outpoint = chain.mint(wallet_output.clone());
+    let setup = ContractSetup {
+        circuit_source: circuit_source.to_string(),
+        participants: Participants {
+            prover: prover_keypair.public_key(),
+            verifier: verifier_keypair.public_key(),
+        },
+        parameters: ContractParameters {
+            amount: options.amount,
+            collateral: options.collateral,
+            fee_per_transaction: options.fee_per_transaction,
+            timelocks: TimelockParameters {
+                response_timeout: options.response_timeout,
+            },
+            funding_inputs: vec![FundingInput {
+                outpoint: wallet_outpoint,
+                txout: wallet_output,
+            }],
+            change_script_pubkey: wallet_script,
+        },
+        commitments: prover_secrets
+            .compute_commitments(verifier_secrets.challenge_store.compute_hashes()),
+    };
+    let graph_commitments = setup.commitments.commitment_set.clone();
+
+    let vault = Vault::ephemeral();
+    let mut prover = Prover::new_dishonest(
+        &options.working_dir.join("prover"),
+        &vault,
+        setup.clone(),
+        prover_secrets,
+        inputs.clone(),
+        options.faults.clone(),
+    )?;
+    let mut verifier = Verifier::new(
+        &options.working_dir.join("verifier"),
+        &vault,
+        setup,
+        verifier_secrets,
+    )?;
+    prover.receive_presignatures(&verifier.presignatures())?;
+    verifier.receive_presignatures(&prover.presignatures())?;
+
+    let graph = prover.graph().clone();
+    let mut fee_wallets = vec![];
+    for _ in [Role::Prover, Role::Verifier] {
+        let mut fee_wallet = FeeWallet::new(
+            &secp,
+            KeyPair::from_secret_key(&secp, &SecretKey::new(&mut rng)),
+        );
+        let txout = TxOut {
+            value: options.fee_reserve.to_sat(),
+            script_pubkey: fee_wallet.script_pubkey().clone(),
+        };
+        fee_wallet.add_utxo(chain.mint(txout.clone()), txout);
+        fee_wallets.push(fee_wallet);
+    }
+    let verifier_fee_wallet = fee_wallets.pop().expect("minted above");
+    let prover_fee_wallet = fee_wallets.pop().expect("minted above");
+    let mut simulation = Simulation {
+        secp,
+        wallet: prover_keypair,
+        chain,
+        events,
+        graph: graph.clone(),
+        fee_rates: options.fee_rates.clone(),
+        fee_policy: options.fee_policy,
+        prover_fee_wallet,
+        verifier_fee_wallet,
+        pending: HashMap::new(),
+        bumps: HashMap::new(),
+        transactions: vec![],
+    };
+
+    simulation.dispatch(&mut prover, &mut verifier, &ContractEvent::SetupCompleted)?;
+    simulation.mine_block(&mut prover, &mut verifier)?;
+    simulation.dispatch(&mut prover, &mut verifier, &ContractEvent::FundingConfirmed)?;
+
+    loop {
+        if simulation.is_settled() {
+            break;
+        }
+        if simulation.chain.height() > MAX_SIMULATED_BLOCKS {
+            return Err("simulation did not terminate".to_string());
+        }
+        if simulation.chain.mempool().is_empty() {
+            // Nothing to confirm: let the parties know how close their deadlines are, and
+            // skip ahead to the next one when nobody moves.
+            let mut next_deadline: Option<u32> = None;
+            for (stage, output) in graph.outputs.iter() {
+                let Some(confirmed) = simulation.chain.confirmation_height(&output.outpoint) else {
+                    continue;
+                };
+                let timeout = graph.output_timeout(stage)? as u32;
+                let blocks_left =
+                    (confirmed + timeout).saturating_sub(simulation.chain.height() + 1);
+                let event = ContractEvent::TimeoutNear {
+                    output: *stage,
+                    blocks_left,
+                };
+                simulation.dispatch(&mut prover, &mut verifier, &event)?;
+                next_deadline = Some(next_deadline.map_or(blocks_left, |d| d.min(blocks_left)));
+            }
+            if simulation.chain.mempool().is_empty() {
+                let idle_blocks = next_deadline.ok_or("contract stalled".to_string())?;
+                simulation.mine_blocks(&mut prover, &mut verifier, idle_blocks)?;
+                continue;
+            }
+        }
+        simulation.mine_block(&mut prover, &mut verifier)?;
+    }
+
+    let (outcome, winner) = simulation
+        .find_claim(false)
+        .ok_or("amount at stake never claimed".to_string())?;
+    let (_, collateral_winner) = simulation
+        .find_claim(true)
+        .ok_or("collateral never released".to_string())?;
+
+    let committed_values = &verifier.state.committed_values;
+    let committed_inputs = circuit
+        .collect_input_wires_ids()
+        .into_iter()
+        .filter_map(|wire_id| {
+            committed_values
+                .get(wire_id)
+                .map(|value| (*wire_id, *value))
+        })
+        .collect::<BTreeMap<_, _>>();
+    let false_claim = match circuit.evaluate(&committed_inputs) {
+        Ok(trace) => circuit
+            .collect_output_wires_ids()
+            .iter()
+            .any(|wire_id| trace.value(wire_id) != committed_values.get(wire_id).cloned()),
+        Err(_) => true,
+    };
+    let mut detector = EquivocationDetector::new(&graph_commitments);
+    let equivocated = verifier
+        .state
+        .revealed_preimages
+        .iter()
+        .any(|preimage| detector.observe_preimage(preimage).is_some());
+
+    Ok(SimulationReport {
+        inputs,
+        rounds: graph.rounds,
+        transactions: simulation.transactions,
+        outcome,
+        winner,
+        collateral_winner,
+        false_claim,
+        equivocated,
+    })
+}
+
+struct Simulation {
+    secp: Secp256k1<All>,
+    /// Paul's wallet key, signing the funding transaction.
+    wallet: KeyPair,
+    chain: MockChain,
+    events: Receiver<ChainEvent>,
+    graph: TransactionGraph,
+    fee_rates: BTreeMap<u32, u64>,
+    fee_policy: Option<FeePolicy>,
+    prover_fee_wallet: FeeWallet,
+    verifier_fee_wallet: FeeWallet,
+    /// Transactions in the mempool, with who sent them and the fee they pay.
+    pending: HashMap<Txid, (TransactionKind, Role, Amount)>,
+    /// Children in the mempool, with the transaction they bump and the fee they pay.
+    bumps: HashMap<Txid, (Txid, Amount)>,
+    transactions: Vec<SimulatedTransaction>,
+}
+
+impl Simulation {
+    fn dispatch(
+        &mut self,
+        prover: &mut Prover,
+        verifier: &mut Verifier,
+        event: &ContractEvent,
+    ) -> Result<(), String> {
+        let prover_actions = prover.handle(event)?;
+        let verifier_actions = verifier.handle(event)?;
+        for (role, action) in prover_actions
+            .into_iter()
+            .map(|action| (Role::Prover, action))
+            .chain(
+                verifier_actions
+                    .into_iter()
+                    .map(|action| (Role::Verifier, action)),
+            )
+        {
+            let (kind, tx) = match action {
+                ContractAction::Fund(tx) => (TransactionKind::Funding, self.sign_funding(tx)?),
+                ContractAction::Broadcast { kind, tx } => (kind, tx),
+            };
+            let txid = tx.txid();
+            // The loser of a race, or a move made moot by a confirmed one, is dropped.
+            if self.pending.contains_key(&txid) || self.chain.conflicts(&tx) {
+                continue;
+            }
+            let fee = self
+                .chain
+                .broadcast(tx)
+                .map_err(|e| format!("{} rejected: {}", kind, e))?;
+            self.pending.insert(txid, (kind, role, fee));
+        }
+        Ok(())
+    }
+
+    /// Signs the wallet inputs of the funding transaction, through their key path.
+    fn sign_funding(&self, mut tx: Transaction) -> Result<Transaction, String> {
+        let prevouts = tx
+            .input
+            .iter()
+            .map(|input| {
+                self.chain
+                    .get_txout(&input.previous_output)
+                    .cloned()
+                    .ok_or(format!("funding input {} missing", input.previous_output))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let witnesses = (0..tx.input.len())
+            .map(|input_index| {
+                sign_key_spend(&self.secp, &tx, input_index, &prevouts, &self.wallet)
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        for (input, witness) in tx.input.iter_mut().zip(witnesses) {
+            input.witness = witness;
+        }
+        Ok(tx)
+    }
+
+    fn mine_block(&mut self, prover: &mut Prover, verifier: &mut Verifier) -> Result<(), String> {
+        self.mine_blocks(prover, verifier, 1)
+    }
+
+    /// Mines `count` blocks, and lets both parties observe them. Before each block, parties
+    /// bump their transactions paying less than the fee rate it requires.
+    fn mine_blocks(
+        &mut self,
+        prover: &mut Prover,
+        verifier: &mut Verifier,
+        count: u32,
+    ) -> Result<(), String> {
+        for _ in 0..count {
+            let height = self.chain.height() + 1;
+            let market_fee_rate = self
+                .fee_rates
+                .range(..=height)
+                .next_back()
+                .map_or(0, |(_, fee_rate)| *fee_rate);
+            self.chain.set_min_fee_rate(market_fee_rate);
+            self.bump_stuck_transactions(prover, verifier)?;
+            self.chain.mine_block();
+        }
+        let mut blocks = vec![];
+        for event in self.events.try_iter() {
+            match event {
+                ChainEvent::ChainUpdatedWithBlocks { new_blocks } => blocks.extend(new_blocks),
+                ChainEvent::ChainUpdatedWithReorg { .. } => {
+                    return Err("unexpected reorg".to_string())
+                }
+            }
+        }
+        for block in blocks {
+            self.observe_block(prover, verifier, block)?;
+        }
+        Ok(())
+    }
+
+    fn observe_block(
+        &mut self,
+        prover: &mut Prover,
+        verifier: &mut Verifier,
+        block: ChainBlock,
+    ) -> Result<(), String> {
+        for tx in block.transactions.iter() {
+            let txid = tx.txid();
+            if let Some((parent_txid, fee)) = self.bumps.remove(&txid) {
+                let parent = self
+                    .transactions
+                    .iter_mut()
+                    .find(|parent| parent.txid == parent_txid)
+                    .ok_or(format!("{} mined before the transaction it bumps", txid))?;
+                parent.cpfp_fee += fee;
+                continue;
+            }
+            let (kind, broadcaster, fee) = self
+                .pending
+                .remove(&txid)
+                .ok_or(format!("unknown transaction {} mined", txid))?;
+            self.transactions.push(SimulatedTransaction {
+                height: block.height,
+                kind,
+                broadcaster,
+                txid,
+                vsize: tx.vsize(),
+                witness_size: tx
+                    .input
+                    .iter()
+                    .map(|input| input.witness.serialized_len())
+                    .sum(),
+                fee,
+                cpfp_fee: Amount::ZERO,
+            });
+        }
+        for tx in block.transactions.into_iter() {
+            self.dispatch(prover, verifier, &ContractEvent::SpendObserved(tx))?;
+        }
+        Ok(())
+    }
+
+    /// Has the broadcaster of every pending transaction paying less than the minimum fee
+    /// rate bump it once, at the rate set by the fee policy.
+    fn bump_stuck_transactions(
+        &mut self,
+        prover: &Prover,
+        verifier: &Verifier,
+    ) -> Result<(), String> {
+        let Some(policy) = self.fee_policy else {
+            return Ok(());
+        };
+        let market_fee_rate = self.chain.min_fee_rate();
+        for tx in self.chain.mempool().to_vec() {
+            let txid = tx.txid();
+            let Some((kind, broadcaster, fee)) = self.pending.get(&txid).cloned() else {
+                continue;
+            };
+            if fee_rate(fee, tx.vsize()) >= market_fee_rate
+                || self.bumps.values().any(|(parent, _)| *parent == txid)
+                || self.graph.anchor(&kind).is_err()
+            {
+                continue;
+            }
+            let fee_rate = policy.fee_rate(market_fee_rate, self.blocks_left(&kind)?);
+            let child = match broadcaster {
+                Role::Prover => prover.bump_fee(&tx, fee, fee_rate, &mut self.prover_fee_wallet),
+                Role::Verifier => {
+                    verifier.bump_fee(&tx, fee, fee_rate, &mut self.verifier_fee_wallet)
+                }
+            }
+            .map_err(|e| format!("unable to bump {}: {}", kind, e))?;
+            let child_txid = child.txid();
+            let child_fee = self
+                .chain
+                .broadcast(child)
+                .map_err(|e| format!("bump of {} rejected: {}", kind, e))?;
+            self.bumps.insert(child_txid, (txid, child_fee));
+        }
+        Ok(())
+    }
+
+    /// Blocks left before the other party can claim the output spent by `kind`.
+    fn blocks_left(&self, kind: &TransactionKind) -> Result<u32, String> {
+        let Some(stage) = self.graph.transaction(kind)?.spent_output else {
+            return Ok(u32::MAX);
+        };
+        let Some(confirmed) = self
+            .chain
+            .confirmation_height(&self.graph.output(&stage)?.outpoint)
+        else {
+            return Ok(u32::MAX);
+        };
+        let timeout = self.graph.output_timeout(&stage)? as u32;
+        Ok((confirmed + timeout).saturating_sub(self.chain.height() + 1))
+    }
+
+    /// First confirmed claim on the collateral, or on the amount at stake.
+    fn find_claim(&self, collateral: bool) -> Option<(TransactionKind, Role)> {
+        self.transactions
+            .iter()
+            .filter(|tx| is_collateral_claim(&tx.kind) == collateral)
+            .find_map(|tx| closing_party(&tx.kind).map(|role| (tx.kind, role)))
+    }
+
+    fn is_settled(&self) -> bool {
+        self.find_claim(false).is_some() && self.find_claim(true).is_some()
+    }
+}
+
+#[cfg(test)]
+fn run_test_simulation(source: &str, options: SimulationOptions) -> SimulationReport {
+    let report = run_simulation(source, &options).unwrap();
+    std::fs::remove_dir_all(&options.working_dir).unwrap();
+    report.check_punishment().unwrap();
+    report
+}
+
+#[cfg(test)]
+fn run_faulty_simulation(source: &str, faults: Vec<Fault>) -> SimulationReport {
+    let options = SimulationOptions {
+        faults,
+        ..Default::default()
+    };
+    run_test_simulation(source, options)
+}
+
+#[test]
+fn test_simulated_disputes() {
+    let source = include_str!("../bristol/fixtures/test_vector_1.bristol");
+    let report = run_faulty_simulation(source, vec![]);
+    assert_eq!(report.outcome, TransactionKind::ChallengeTimeout(0));
+    assert_eq!(report.winner, Role::Prover);
+    assert_eq!(report.collateral_winner, Role::Prover);
+
+    let circuit = crate::bristol::parser::read_circuit(source).unwrap();
+    let output = circuit.collect_output_wires_ids()[0];
+    let report = run_faulty_simulation(source, vec![Fault::FlipGate(output)]);
+    assert_eq!(report.outcome, TransactionKind::Slash);
+    assert_eq!(report.winner, Role::Verifier);
+    assert_eq!(report.collateral_winner, Role::Prover);
+}
+
+#[test]
+fn test_injected_faults_are_punished() {
+    let source = include_str!("../bristol/fixtures/test_vector_1.bristol");
+    let circuit = crate::bristol::parser::read_circuit(source).unwrap();
+    let output = circuit.collect_output_wires_ids()[0];
+    let output_input = circuit.gates[&output].inputs()[0];
+    let input = *circuit.collect_input_wires_ids()[0];
+
+    // Lying about an output is settled on the faulty gate.
+    let report = run_faulty_simulation(source, vec![Fault::FlipGate(output)]);
+    assert_eq!(report.outcome, TransactionKind::Slash);
+
+    // Opening the honest output when challenged contradicts the commitment: Vicky takes
+    // the collateral, and still slashes with the value Paul committed to.
+    let faults = vec![Fault::FlipGate(output), Fault::EquivocateWire(output)];
+    let report = run_faulty_simulation(source, faults);
+    assert!(report.equivocated);
+    assert_eq!(report.outcome, TransactionKind::Slash);
+    assert_eq!(report.collateral_winner, Role::Verifier);
+
+    // Lying about an input of the faulty gate does not make it consistent either.
+    let faults = vec![Fault::FlipGate(output), Fault::EquivocateWire(output_input)];
+    let report = run_faulty_simulation(source, faults);
+    assert_eq!(report.winner, Role::Verifier);
+
+    let faults = vec![Fault::FlipGate(output), Fault::StopAfterRound(1)];
+    let report = run_faulty_simulation(source, faults);
+    assert_eq!(report.outcome, TransactionKind::ResponseTimeout(1));
+    let faults = vec![
+        Fault::FlipGate(output),
+        Fault::StopAfterRound(report.rounds),
+    ];
+    let report = run_faulty_simulation(source, faults);
+    assert_eq!(report.outcome, TransactionKind::GateResponseTimeout);
+
+    // Whether the claim is false depends on the inputs drawn.
+    run_faulty_simulation(source, vec![Fault::WrongInput(input)]);
+
+    // Without a dispute, faults in the dispute itself go unnoticed.
+    let faults = vec![Fault::StopAfterRound(0), Fault::EquivocateWire(input)];
+    let report = run_faulty_simulation(source, faults);
+    assert_eq!(report.outcome, TransactionKind::ChallengeTimeout(0));
+
+    let source = include_str!("../bristol/fixtures/test_vector_2.bristol");
+    let circuit = crate::bristol::parser::read_circuit(source).unwrap();
+    let output = circuit.collect_output_wires_ids()[0];
+    let input = *circuit.collect_input_wires_ids()[0];
+    let faults = vec![Fault::FlipGate(output), Fault::EquivocateWire(output)];
+    let report = run_faulty_simulation(source, faults);
+    assert_eq!(report.outcome, TransactionKind::Slash);
+    assert_eq!(report.collateral_winner, Role::Verifier);
+    run_faulty_simulation(source, vec![Fault::WrongInput(input)]);
+
+    // Faults deep inside the circuit: flipping an intermediate gate, then equivocating on the
+    // wires around it, when challenged or during the bisection.
+    for source in [
+        include_str!("../bristol/fixtures/test_vector_1.bristol"),
+        include_str!("../bristol/fixtures/test_vector_2.bristol"),
+    ] {
+        let circuit = crate::bristol::parser::read_circuit(source).unwrap();
+        let outputs = circuit.collect_output_wires_ids();
+        let order = circuit.topological_order().unwrap();
+        let intermediates = order
+            .iter()
+            .filter(|gate_id| !outputs.contains(gate_id))
+            .collect::<Vec<_>>();
+        for gate_id in [intermediates[0], intermediates[intermediates.len() / 2]] {
+            let gate_input = circuit.gates[gate_id].inputs()[0];
+            for faults in [
+                vec![Fault::FlipGate(*gate_id), Fault::EquivocateWire(gate_input)],
+                vec![Fault::FlipGate(*gate_id), Fault::EquivocateState(*gate_id)],
+                vec![
+                    Fault::FlipGate(*gate_id),
+                    Fault::EquivocateWire(*gate_id),
+                    Fault::EquivocateState(gate_input),
+                ],
+            ] {
+                run_faulty_simulation(source, faults);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_fee_spikes_are_bumped_through_anchors() {
+    let source = include_str!("../bristol/fixtures/test_vector_1.bristol");
+    let circuit = crate::bristol::parser::read_circuit(source).unwrap();
+    // Pre-signed fees fall short from the block after funding on.
+    let options = SimulationOptions {
+        faults: vec![Fault::FlipGate(circuit.collect_output_wires_ids()[0])],
+        fee_rates: BTreeMap::from([(2, 20)]),
+        fee_policy: Some(FeePolicy::default()),
+        ..Default::default()
+    };
+    let report = run_test_simulation(source, options.clone());
+    assert_eq!(report.outcome, TransactionKind::Slash);
+    for tx in report.transactions.iter().skip(1) {
+        assert!(tx.cpfp_fee > Amount::ZERO, "{} was not bumped", tx.kind);
+        assert!(fee_rate(tx.fee, tx.vsize) < 20);
+    }
+    let commit = &report.transactions[1];
+    assert_eq!((commit.kind, commit.height), (TransactionKind::Commit, 2));
+    assert!(
+        report.total_fees(Role::Verifier)
+            > options.fee_per_transaction * report.transactions.len() as u64 / 2
+    );
+
+    // Without bumps, nothing confirms until the spike is over.
+    let options = SimulationOptions {
+        fee_rates: BTreeMap::from([(2, 20), (20, 0)]),
+        fee_policy: None,
+        ..options
+    };
+    let report = run_test_simulation(source, options);
+    assert_eq!(report.transactions[1].height, 20);
+    assert!(report
+        .transactions
+        .iter()
+        .all(|tx| tx.cpfp_fee == Amount::ZERO));
+}
+
+#[test]
+fn test_gate_openings_are_bound_to_the_bisected_states() {
+    let source = include_str!("../bristol/fixtures/test_vector_1.bristol");
+    let circuit = crate::bristol::parser::read_circuit(source).unwrap();
+    let inputs = circuit
+        .collect_input_wires_ids()
+        .into_iter()
+        .map(|wire_id| (*wire_id, *wire_id == 100))
+        .collect::<BTreeMap<_, _>>();
+    // Paul flips a gate, and opens a false input to make it look consistent when challenged.
+    for faults in [
+        vec![Fault::FlipGate(3), Fault::EquivocateWire(101)],
+        vec![Fault::FlipGate(101), Fault::EquivocateWire(105)],
+    ] {
+        let options = SimulationOptions {
+            inputs: Some(inputs.clone()),
+            faults,
+            ..Default::default()
+        };
+        let report = run_test_simulation(source, options);
+        assert!(report.false_claim);
+        assert!(report.equivocated);
+        assert_eq!(report.outcome, TransactionKind::Slash);
+    }
+}
+
+#[test]
+fn test_equivocated_states_are_punished_when_the_gate_is_consistent() {
+    let source = include_str!("../bristol/fixtures/test_vector_1.bristol");
+    let circuit = crate::bristol::parser::read_circuit(source).unwrap();
+    let inputs = circuit
+        .collect_input_wires_ids()
+        .into_iter()
+        .map(|wire_id| (*wire_id, *wire_id % 3 == 0))
+        .collect::<BTreeMap<_, _>>();
+    // Paul flips an output, then opens a wire differently across the bisection so the gate
+    // finally challenged looks consistent.
+    let options = SimulationOptions {
+        inputs: Some(inputs),
+        faults: vec![Fault::FlipGate(2), Fault::EquivocateState(106)],
+        ..Default::default()
+    };
+    let report = run_test_simulation(source, options);
+    assert!(report.false_claim);
+    assert!(report.equivocated);
+    assert_eq!(report.outcome, TransactionKind::Slash);
+}
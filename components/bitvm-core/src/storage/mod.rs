@@ -0,0 +1,196 @@
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use bitcoin::consensus::encode::{deserialize, serialize_hex};
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::{Transaction, Txid};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Version of the layout of `working_dir`. Newer layouts are refused when opened.
+pub const SCHEMA_VERSION: u32 = 1;
+
+const METADATA_FILE: &str = "storage.json";
+
+/// Suffix of files being written. They are renamed over their target once complete, so a
+/// crash never leaves a half-written file in place of a good one.
+const TMP_SUFFIX: &str = ".tmp";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StorageMetadata {
+    schema_version: u32,
+}
+
+/// Prepares `working_dir` for use: removes the leftovers of writes interrupted by a crash,
+/// and checks its layout is not newer than `SCHEMA_VERSION`.
+pub fn open(working_dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(working_dir)
+        .map_err(|e| format!("unable to create {}: {}", working_dir.display(), e))?;
+    remove_interrupted_writes(working_dir)?;
+    let metadata_path = working_dir.join(METADATA_FILE);
+    if !metadata_path.exists() {
+        return write_json(
+            &metadata_path,
+            &StorageMetadata {
+                schema_version: SCHEMA_VERSION,
+            },
+        );
+    }
+    let version = read_json::<StorageMetadata>(&metadata_path)?.schema_version;
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "{} uses storage schema {}, newer than the supported {}",
+            working_dir.display(),
+            version,
+            SCHEMA_VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// Contracts are stored under `<working_dir>/contracts/<funding txid>`.
+pub fn contract_dir(working_dir: &Path, contract_id: &Txid) -> PathBuf {
+    working_dir.join("contracts").join(contract_id.to_string())
+}
+
+/// Ids of the contracts stored in `working_dir`.
+pub fn list_contracts(working_dir: &Path) -> Result<Vec<Txid>, String> {
+    let contracts_dir = working_dir.join("contracts");
+    if !contracts_dir.exists() {
+        return Ok(vec![]);
+    }
+    let entries = fs::read_dir(&contracts_dir)
+        .map_err(|e| format!("unable to read {}: {}", contracts_dir.display(), e))?;
+    let mut contract_ids = vec![];
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| format!("unable to read {}: {}", contracts_dir.display(), e))?;
+        if let Some(contract_id) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        {
+            contract_ids.push(contract_id);
+        }
+    }
+    contract_ids.sort();
+    Ok(contract_ids)
+}
+
+pub fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let bytes = serde_json::to_vec(value)
+        .map_err(|e| format!("unable to serialize {}: {}", path.display(), e))?;
+    write_atomic(path, &bytes)
+}
+
+/// Replaces `path` with `bytes`, all at once: readers, and restarts after a crash, find
+/// either the previous content or the new one.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let parent = path
+        .parent()
+        .ok_or(format!("invalid path {}", path.display()))?;
+    fs::create_dir_all(parent)
+        .map_err(|e| format!("unable to create {}: {}", parent.display(), e))?;
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(TMP_SUFFIX);
+    let tmp_path = PathBuf::from(tmp_path);
+    let mut file = fs::File::create(&tmp_path)
+        .map_err(|e| format!("unable to write {}: {}", path.display(), e))?;
+    file.write_all(bytes)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("unable to write {}: {}", path.display(), e))?;
+    fs::rename(&tmp_path, path)
+        .map_err(|e| format!("unable to write {}: {}", path.display(), e))?;
+    // Persist the rename itself.
+    #[cfg(unix)]
+    fs::File::open(parent)
+        .and_then(|dir| dir.sync_all())
+        .map_err(|e| format!("unable to sync {}: {}", parent.display(), e))?;
+    Ok(())
+}
+
+pub fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let bytes = fs::read(path).map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+    serde_json::from_slice(&bytes)
+        .map_err(|e| format!("unable to deserialize {}: {}", path.display(), e))
+}
+
+/// Keeps a contract transaction seen on chain, under `<contract dir>/transactions/<txid>.hex`.
+pub fn write_transaction(
+    working_dir: &Path,
+    contract_id: &Txid,
+    tx: &Transaction,
+) -> Result<(), String> {
+    let path = transaction_path(working_dir, contract_id, &tx.txid());
+    write_atomic(&path, serialize_hex(tx).as_bytes())
+}
+
+pub fn read_transaction(
+    working_dir: &Path,
+    contract_id: &Txid,
+    txid: &Txid,
+) -> Result<Transaction, String> {
+    let path = transaction_path(working_dir, contract_id, txid);
+    let hex = fs::read_to_string(&path)
+        .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+    let bytes = Vec::<u8>::from_hex(hex.trim())
+        .map_err(|e| format!("unable to decode {}: {}", path.display(), e))?;
+    deserialize(&bytes).map_err(|e| format!("unable to decode {}: {}", path.display(), e))
+}
+
+fn transaction_path(working_dir: &Path, contract_id: &Txid, txid: &Txid) -> PathBuf {
+    contract_dir(working_dir, contract_id)
+        .join("transactions")
+        .join(format!("{}.hex", txid))
+}
+
+/// Deletes the temporary files of writes that never completed.
+fn remove_interrupted_writes(dir: &Path) -> Result<(), String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("unable to read {}: {}", dir.display(), e))?;
+    for entry in entries {
+        let path = entry
+            .map_err(|e| format!("unable to read {}: {}", dir.display(), e))?
+            .path();
+        if path.is_dir() {
+            remove_interrupted_writes(&path)?;
+        } else if path.to_string_lossy().ends_with(TMP_SUFFIX) {
+            fs::remove_file(&path)
+                .map_err(|e| format!("unable to remove {}: {}", path.display(), e))?;
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_open_recovers_working_dir() {
+    let working_dir = std::env::temp_dir().join(format!("bitvm-storage-{}", rand::random::<u64>()));
+    let contract_id = Txid::from_raw_hash(bitcoin::hashes::Hash::all_zeros());
+    let contract_dir = contract_dir(&working_dir, &contract_id);
+    write_json(
+        &contract_dir.join("prover.json"),
+        &serde_json::json!({ "phase": "Setup" }),
+    )
+    .unwrap();
+    fs::write(contract_dir.join("prover.json.tmp"), b"{\"phase\":").unwrap();
+
+    open(&working_dir).unwrap();
+    assert!(!contract_dir.join("prover.json.tmp").exists());
+    let state: serde_json::Value = read_json(&contract_dir.join("prover.json")).unwrap();
+    assert_eq!(state, serde_json::json!({ "phase": "Setup" }));
+    let metadata: StorageMetadata = read_json(&working_dir.join(METADATA_FILE)).unwrap();
+    assert_eq!(metadata.schema_version, SCHEMA_VERSION);
+
+    // Opening again is a no-op, and newer layouts are refused.
+    open(&working_dir).unwrap();
+    write_json(
+        &working_dir.join(METADATA_FILE),
+        &StorageMetadata {
+            schema_version: SCHEMA_VERSION + 1,
+        },
+    )
+    .unwrap();
+    assert!(open(&working_dir).is_err());
+    let _ = fs::remove_dir_all(&working_dir);
+}
@@ -1,227 +1,151 @@
-
-use bitcoin::{script::Builder, opcodes, ScriptBuf, taproot::{TaprootBuilder, LeafVersion}, bip32::DerivationPath};
-use bitcoin::secp256k1::{self, Secp256k1};
 use bitcoin::secp256k1::PublicKey;
-use bitcoin::bip32::ExtendedPrivKey;
-use bitvm_types::{Circuit, BitCommitmentHashes};
-
-pub fn compute_challenge_address(circuit: &Circuit, secp: &Secp256k1<secp256k1::All>, public_key: &PublicKey, other_public_key: &PublicKey) {
-
-    
-
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::{script::Builder, taproot::TaprootSpendInfo, ScriptBuf};
+use bitvm_types::{BitCommitmentHashes, Circuit, CommitmentSet, Gate, GateId};
+
+use super::anti_contradiction_address::build_equivocation_condition;
+use super::challenge_hashlock::augment_with_hashlock;
+use super::{
+    augment_with_multisig, augment_with_timelock, build_taproot_spend_info, seal_with_multisig,
+};
+
+/// Output created once Paul opened the challenged gate. Vicky can spend it with one of the
+/// defectuous gate leaves if the values Paul opened are inconsistent, or with an equivocation
+/// leaf if he opened both values of a wire, otherwise Paul takes it back after `timeout` blocks.
+pub fn compute_challenge_address(
+    circuit: &Circuit,
+    commitment_set: &CommitmentSet,
+    secp: &Secp256k1<secp256k1::All>,
+    public_key: &PublicKey,
+    other_public_key: &PublicKey,
+    timeout: u16,
+) -> Result<TaprootSpendInfo, String> {
+    let mut scripts = vec![];
+    let mut gates_ids = circuit.gates.keys().cloned().collect::<Vec<_>>();
+    gates_ids.sort();
+    for gate_id in gates_ids.iter() {
+        for (_, _, condition) in build_tap_scripts_for_defectuous_gate(
+            *gate_id,
+            &circuit.gates[gate_id],
+            commitment_set,
+        )? {
+            scripts.push(seal_with_multisig(condition, public_key, other_public_key));
+        }
+    }
+    for bit_commitment in commitment_set.hashes.values() {
+        scripts.push(seal_with_multisig(
+            build_equivocation_condition(bit_commitment),
+            public_key,
+            other_public_key,
+        ));
+    }
+    scripts.push(
+        augment_with_multisig(
+            augment_with_timelock(Builder::new(), timeout),
+            public_key,
+            other_public_key,
+        )
+        .into_script(),
+    );
+    build_taproot_spend_info(secp, scripts)
 }
 
-pub fn build_tap_scripts_for_defectuous_inv_gate(input: &BitCommitmentHashes, output: &BitCommitmentHashes) -> Vec<ScriptBuf> {
-    let case_input_0_output_0: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_TOALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_NOT)
-        .push_opcode(opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(output.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .into_script();
-
-    let case_input_1_output_1: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_TOALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_NOT)
-        .push_opcode(opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(output.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .into_script();
-
-    return vec![case_input_0_output_0, case_input_1_output_1]
+/// Every combination of input values paired with the wrong output value, along with the leaf
+/// condition proving it. Each condition consumes the preimages of the inputs, then the output.
+pub fn build_tap_scripts_for_defectuous_gate(
+    gate_id: GateId,
+    gate: &Gate,
+    commitment_set: &CommitmentSet,
+) -> Result<Vec<(Vec<bool>, bool, ScriptBuf)>, String> {
+    let lookup = |wire_id: &u64| {
+        commitment_set
+            .get(wire_id)
+            .ok_or(format!("wire {} missing from the commitment set", wire_id))
+    };
+    let output = lookup(&gate_id)?;
+    let scripts = match gate {
+        Gate::Inv(input) => build_tap_scripts_for_defectuous_inv_gate(lookup(input)?, output),
+        Gate::And(input_a, input_b) => {
+            build_tap_scripts_for_defectuous_and_gate(lookup(input_a)?, lookup(input_b)?, output)
+        }
+        Gate::Nand(input_a, input_b) => {
+            build_tap_scripts_for_defectuous_nand_gate(lookup(input_a)?, lookup(input_b)?, output)
+        }
+        Gate::Xor(input_a, input_b) => {
+            build_tap_scripts_for_defectuous_xor_gate(lookup(input_a)?, lookup(input_b)?, output)
+        }
+    };
+    Ok(scripts)
 }
 
-pub fn build_tap_scripts_for_defectuous_and_gate(input_a: &BitCommitmentHashes, input_b: &BitCommitmentHashes, output: &BitCommitmentHashes) -> Vec<ScriptBuf> {
-    let case_input_a_1_input_b_1_output_0: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_TOALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_a.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_SWAP)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_b.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_BOOLAND)
-        .push_opcode(opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(output.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .into_script();
-
-    let case_input_a_0_input_b_1_output_1: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_TOALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_a.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_SWAP)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_b.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_BOOLAND)
-        .push_opcode(opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(output.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .into_script();
-
-    let case_input_a_1_input_b_0_output_1: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_TOALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_a.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_SWAP)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_b.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_BOOLAND)
-        .push_opcode(opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(output.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .into_script();
-
-    let case_input_a_0_input_b_0_output_1: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_TOALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_a.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_SWAP)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_b.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_BOOLAND)
-        .push_opcode(opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(output.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .into_script();
-
-    return vec![case_input_a_1_input_b_1_output_0, case_input_a_0_input_b_1_output_1, case_input_a_1_input_b_0_output_1, case_input_a_0_input_b_0_output_1]
+pub fn build_tap_scripts_for_defectuous_inv_gate(
+    input: &BitCommitmentHashes,
+    output: &BitCommitmentHashes,
+) -> Vec<(Vec<bool>, bool, ScriptBuf)> {
+    [false, true]
+        .into_iter()
+        .map(|a| {
+            (
+                vec![a],
+                a,
+                build_defectuous_gate_condition(&[input], &[a], output, a),
+            )
+        })
+        .collect()
 }
 
-pub fn build_tap_scripts_for_defectuous_xor_gate(input_a: &BitCommitmentHashes, input_b: &BitCommitmentHashes, output: &BitCommitmentHashes) -> Vec<ScriptBuf> {
-
-    let case_input_a_1_input_b_0_output_0: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_TOALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_a.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_SWAP)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_b.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(output.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .into_script();
+pub fn build_tap_scripts_for_defectuous_and_gate(
+    input_a: &BitCommitmentHashes,
+    input_b: &BitCommitmentHashes,
+    output: &BitCommitmentHashes,
+) -> Vec<(Vec<bool>, bool, ScriptBuf)> {
+    build_tap_scripts_for_defectuous_binary_gate(input_a, input_b, output, |a, b| a && b)
+}
 
-    let case_input_a_0_input_b_1_output_0: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_TOALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_a.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_SWAP)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_b.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(output.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .into_script();
+pub fn build_tap_scripts_for_defectuous_nand_gate(
+    input_a: &BitCommitmentHashes,
+    input_b: &BitCommitmentHashes,
+    output: &BitCommitmentHashes,
+) -> Vec<(Vec<bool>, bool, ScriptBuf)> {
+    build_tap_scripts_for_defectuous_binary_gate(input_a, input_b, output, |a, b| !(a && b))
+}
 
-    let case_input_a_1_input_b_1_output_1: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_TOALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_a.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_SWAP)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_b.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(output.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .into_script();
+pub fn build_tap_scripts_for_defectuous_xor_gate(
+    input_a: &BitCommitmentHashes,
+    input_b: &BitCommitmentHashes,
+    output: &BitCommitmentHashes,
+) -> Vec<(Vec<bool>, bool, ScriptBuf)> {
+    build_tap_scripts_for_defectuous_binary_gate(input_a, input_b, output, |a, b| a ^ b)
+}
 
-    let case_input_a_0_input_b_0_output_1: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_TOALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_a.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_SWAP)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(input_b.0)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_FROMALTSTACK)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(output.1)
-        .push_opcode(opcodes::all::OP_EQUALVERIFY)
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1)
-        .push_opcode(opcodes::all::OP_NUMNOTEQUAL)
-        .push_opcode(opcodes::all::OP_VERIFY)
-        .into_script();
+fn build_tap_scripts_for_defectuous_binary_gate<F: Fn(bool, bool) -> bool>(
+    input_a: &BitCommitmentHashes,
+    input_b: &BitCommitmentHashes,
+    output: &BitCommitmentHashes,
+    truth_table: F,
+) -> Vec<(Vec<bool>, bool, ScriptBuf)> {
+    let mut scripts = vec![];
+    for a in [false, true] {
+        for b in [false, true] {
+            let wrong_output = !truth_table(a, b);
+            let script =
+                build_defectuous_gate_condition(&[input_a, input_b], &[a, b], output, wrong_output);
+            scripts.push((vec![a, b], wrong_output, script));
+        }
+    }
+    scripts
+}
 
-    return vec![case_input_a_1_input_b_0_output_0, case_input_a_0_input_b_1_output_0, case_input_a_1_input_b_1_output_1, case_input_a_0_input_b_0_output_1]
+/// Checks that the stack holds Paul's preimages for `inputs_values` and `output_value`.
+pub fn build_defectuous_gate_condition(
+    inputs: &[&BitCommitmentHashes],
+    inputs_values: &[bool],
+    output: &BitCommitmentHashes,
+    output_value: bool,
+) -> ScriptBuf {
+    let mut builder = Builder::new();
+    for (input, value) in inputs.iter().zip(inputs_values.iter()) {
+        builder = augment_with_hashlock(builder, input.hash_for_bit(*value));
+    }
+    augment_with_hashlock(builder, output.hash_for_bit(output_value)).into_script()
 }
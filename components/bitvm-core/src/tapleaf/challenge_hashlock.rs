@@ -0,0 +1,237 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use bitcoin::{opcodes, script::Builder, ScriptBuf};
+use bitvm_types::{BitCommitmentHashes, BitCommitmentPreimages, GateId};
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use zeroize::Zeroize;
+
+use super::commitment_address::augment_with_bit_commitment_leaf;
+use crate::bisection::Challenge;
+
+/// Vicky's secrets. Revealing one of them is how she selects a branch of the dispute:
+/// a verdict per bisection round (preimage 0 when she disagrees with Paul's state, 1 when
+/// she agrees), and a selector per gate for the final single-gate challenge. They are never
+/// printed, and are wiped from memory on drop.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChallengeStore {
+    pub verdicts: Vec<BitCommitmentPreimages>,
+    pub gates: BTreeMap<GateId, [u8; 32]>,
+}
+
+impl fmt::Debug for ChallengeStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ChallengeStore")
+            .field("rounds", &self.verdicts.len())
+            .field("gates", &self.gates.keys().collect::<Vec<_>>())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for ChallengeStore {
+    fn drop(&mut self) {
+        self.verdicts.iter_mut().for_each(Zeroize::zeroize);
+        self.gates.values_mut().for_each(Zeroize::zeroize);
+    }
+}
+
+impl ChallengeStore {
+    pub fn new(gates_ids: &[GateId], rounds: usize) -> ChallengeStore {
+        let mut rng = thread_rng();
+        ChallengeStore {
+            verdicts: (0..rounds).map(|_| BitCommitmentPreimages::new()).collect(),
+            gates: gates_ids
+                .iter()
+                .map(|gate_id| (*gate_id, rng.gen::<[u8; 32]>()))
+                .collect(),
+        }
+    }
+
+    pub fn compute_hashes(&self) -> ChallengeHashes {
+        ChallengeHashes {
+            verdicts: self
+                .verdicts
+                .iter()
+                .map(|preimages| preimages.compute_bit_commitment_hashes())
+                .collect(),
+            gates: self
+                .gates
+                .iter()
+                .map(|(gate_id, preimage)| (*gate_id, Sha256::digest(preimage).into()))
+                .collect(),
+        }
+    }
+
+    /// Preimages to publish in the witness of the transaction carrying `challenge`.
+    pub fn reveal(&self, challenge: &Challenge) -> Result<Vec<[u8; 32]>, String> {
+        let (round, verdict) = match challenge {
+            Challenge::Bisect { round, verdict, .. } => (*round, verdict),
+            Challenge::Gate { verdict, .. } => (self.verdicts.len(), verdict),
+        };
+        let mut preimages = vec![];
+        if let Some(agreed) = verdict {
+            let preimages_for_round = round
+                .checked_sub(1)
+                .and_then(|r| self.verdicts.get(r))
+                .ok_or(format!("no verdict secret for round {}", round))?;
+            preimages.push(*preimages_for_round.preimage_for_bit(*agreed));
+        }
+        if let Challenge::Gate {
+            gate_id: Some(gate_id),
+            ..
+        } = challenge
+        {
+            let preimage = self
+                .gates
+                .get(gate_id)
+                .ok_or(format!("no challenge secret for gate {}", gate_id))?;
+            preimages.push(*preimage);
+        }
+        Ok(preimages)
+    }
+}
+
+/// A challenge secret, as recognized by Paul once Vicky published it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RevealedChallenge {
+    Verdict { round: usize, agreed: bool },
+    Gate(GateId),
+}
+
+/// Public side of Vicky's challenge secrets, embedded in the hashlocks of the dispute leaves.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChallengeHashes {
+    pub verdicts: Vec<BitCommitmentHashes>,
+    pub gates: BTreeMap<GateId, [u8; 32]>,
+}
+
+impl ChallengeHashes {
+    pub fn identify(&self, preimage: &[u8]) -> Option<RevealedChallenge> {
+        if preimage.len() != 32 {
+            return None;
+        }
+        let hash: [u8; 32] = Sha256::digest(preimage).into();
+        for (round, hashes) in self.verdicts.iter().enumerate() {
+            if hashes.0 == hash || hashes.1 == hash {
+                return Some(RevealedChallenge::Verdict {
+                    round,
+                    agreed: hashes.1 == hash,
+                });
+            }
+        }
+        self.gates
+            .iter()
+            .find(|(_, gate_hash)| **gate_hash == hash)
+            .map(|(gate_id, _)| RevealedChallenge::Gate(*gate_id))
+    }
+}
+
+/// Consumes one stack element, which must be the preimage of `hash`.
+pub fn augment_with_hashlock(builder: Builder, hash: &[u8; 32]) -> Builder {
+    builder
+        .push_opcode(opcodes::all::OP_SHA256)
+        .push_slice(hash)
+        .push_opcode(opcodes::all::OP_EQUALVERIFY)
+}
+
+/// Condition of the leaves through which Vicky publishes her challenges: her verdict on
+/// the previous round, if any, then the selected gate for the final challenge.
+/// Consumes `<verdict_preimage>` then `<gate_preimage>`, as returned by [`ChallengeStore::reveal`].
+pub fn build_challenge_condition(
+    verdict: Option<&BitCommitmentHashes>,
+    gate_challenge_hash: Option<&[u8; 32]>,
+) -> ScriptBuf {
+    let mut builder = Builder::new();
+    if let Some(verdict) = verdict {
+        builder = augment_with_bit_commitment_leaf(builder, verdict);
+    }
+    if let Some(gate_challenge_hash) = gate_challenge_hash {
+        builder = augment_with_hashlock(builder, gate_challenge_hash);
+    }
+    builder.into_script()
+}
+
+/// Condition of the response leaf opening the wires of a single gate. Locked by the gate's
+/// challenge hash, so Paul can only use it once Vicky challenged that gate.
+/// Consumes `<challenge_preimage>`, then one preimage per wire in `wires_bit_commitments` order.
+pub fn build_gate_response_condition(
+    gate_challenge_hash: &[u8; 32],
+    wires_bit_commitments: &[&BitCommitmentHashes],
+) -> ScriptBuf {
+    let mut builder = augment_with_hashlock(Builder::new(), gate_challenge_hash);
+    for bit_commitment in wires_bit_commitments.iter() {
+        builder = augment_with_bit_commitment_leaf(builder, bit_commitment);
+    }
+    builder.into_script()
+}
+
+/// Condition of the response leaf opening the state at one midpoint of a bisection round.
+/// Locked by Vicky's verdicts on the previous rounds, so Paul can only open the state at the
+/// midpoint she selected.
+/// Consumes one preimage per verdict in `verdict_hashes`, then one preimage per wire in
+/// `wires_bit_commitments` order.
+pub fn build_state_response_condition(
+    verdict_hashes: &[&[u8; 32]],
+    wires_bit_commitments: &[&BitCommitmentHashes],
+) -> ScriptBuf {
+    let mut builder = Builder::new();
+    for verdict_hash in verdict_hashes.iter() {
+        builder = augment_with_hashlock(builder, verdict_hash);
+    }
+    for bit_commitment in wires_bit_commitments.iter() {
+        builder = augment_with_bit_commitment_leaf(builder, bit_commitment);
+    }
+    builder.into_script()
+}
+
+#[test]
+fn test_challenge_reveal_and_identify() {
+    let store = ChallengeStore::new(&[97, 99, 101], 3);
+    let hashes = store.compute_hashes();
+    assert_eq!(
+        format!("{:?}", store),
+        "ChallengeStore { rounds: 3, gates: [97, 99, 101], .. }"
+    );
+
+    let challenge = Challenge::Bisect {
+        round: 2,
+        verdict: Some(true),
+        midpoint: 6,
+    };
+    let preimages = store.reveal(&challenge).unwrap();
+    assert_eq!(
+        hashes.identify(&preimages[0]),
+        Some(RevealedChallenge::Verdict {
+            round: 1,
+            agreed: true
+        })
+    );
+
+    let challenge = Challenge::Gate {
+        verdict: Some(false),
+        position: 4,
+        gate_id: Some(99),
+    };
+    let preimages = store.reveal(&challenge).unwrap();
+    assert_eq!(preimages.len(), 2);
+    assert_eq!(
+        hashes.identify(&preimages[0]),
+        Some(RevealedChallenge::Verdict {
+            round: 2,
+            agreed: false
+        })
+    );
+    assert_eq!(
+        hashes.identify(&preimages[1]),
+        Some(RevealedChallenge::Gate(99))
+    );
+    assert_eq!(hashes.identify(&[0; 32]), None);
+
+    let first_round = Challenge::Bisect {
+        round: 0,
+        verdict: None,
+        midpoint: 4,
+    };
+    assert_eq!(store.reveal(&first_round), Ok(vec![]));
+}
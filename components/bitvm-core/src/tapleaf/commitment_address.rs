@@ -1,106 +1,81 @@
-use std::str::FromStr;
-
-use bitcoin::{script::Builder, opcodes, ScriptBuf, taproot::{TaprootBuilder, LeafVersion}, bip32::DerivationPath};
-use bitcoin::secp256k1::{self, Secp256k1};
 use bitcoin::secp256k1::PublicKey;
-use bitcoin::bip32::ExtendedPrivKey;
-use bitvm_types::{Circuit, BitCommitmentHashes};
-
-pub fn compute_commitment_address(circuit: &Circuit, secp: &Secp256k1<secp256k1::All>, public_key: &PublicKey, other_public_key: &PublicKey) {
-    let input_wires_ids = circuit.collect_input_wires_ids();
-    let bit_commitment_preimages =
-        circuit.collect_gates_bit_commitments_preimages(&input_wires_ids);
-
-    let subsequent_commitment_preimages =
-        circuit.collect_subsequent_gates_bit_commitments_preimages(&input_wires_ids);
-
-    let mut tap_script_builder = Builder::new();
-    let bit_commitments_hashes = circuit.compute_bit_commitments_hashes(&bit_commitment_preimages);
-    for (_, bit_commitment_hash) in bit_commitments_hashes.iter() {
-        tap_script_builder = augment_with_bit_commitment_leaf(tap_script_builder, bit_commitment_hash);
-    }
-    
-    let subsequent_bit_commitments_hashes = circuit.compute_bit_commitments_hashes(&subsequent_commitment_preimages);
-    for (_, bit_commitment_hash) in subsequent_bit_commitments_hashes.iter() {
-        tap_script_builder = augment_with_bit_commitment_leaf(tap_script_builder, bit_commitment_hash);
-    }
-    let root_script = tap_script_builder
-        .push_opcode(opcodes::all::OP_PUSHBYTES_1) // TODO: incomplete
-        .into_script();
-
-    // Leaf 1: Allows Vicky to spend the inputs of the bit commitment address after 10 blocks have passed.
-    let script_1 = build_leaf_1(public_key);
-    // Leaf 2: Allows Paul and Vicky to cooperatively sign a 2/2 multisig to spend the inputs at any time.
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::{opcodes, script::Builder, taproot::TaprootSpendInfo, ScriptBuf};
+use bitvm_types::{BitCommitmentHashes, CommitmentSet, WireId};
+
+use super::{augment_with_multisig, augment_with_timelock, build_taproot_spend_info};
+
+pub fn compute_commitment_address(
+    commitment_set: &CommitmentSet,
+    committed_wires: &[WireId],
+    secp: &Secp256k1<secp256k1::All>,
+    public_key: &PublicKey,
+    other_public_key: &PublicKey,
+    timeout: u16,
+) -> Result<TaprootSpendInfo, String> {
+    // Leaf 1: Contains the actual bit commitment. Allows Paul to spend the inputs if he reveals the values of the inputs and outputs of the circuit.
+    let root_script = build_commit_leaf(
+        commitment_set,
+        committed_wires,
+        public_key,
+        other_public_key,
+    )?;
+    // Leaf 2: Allows Vicky to spend the inputs of the bit commitment address after `timeout` blocks have passed.
+    let script_1 = build_leaf_1(public_key, other_public_key, timeout);
+    // Leaf 3: Allows Paul and Vicky to cooperatively sign a 2/2 multisig to spend the inputs at any time.
     let script_2 = build_leaf_2(public_key, other_public_key);
-    // Leaf 3: Contains the actual bit commitment. Allows Paul to spend the inputs if he provides a valid execution trace.
-
 
-    let xpriv= ExtendedPrivKey::new_master(bitcoin::Network::Regtest, &[0]).unwrap();
-    let derivation_path = DerivationPath::from_str(&format!("m/101/1/0/0/1")).unwrap();
-    let internal_keypair =
-        xpriv.derive_priv(&secp, &derivation_path).unwrap().to_keypair(&secp);
-
-    let taproot_builder = TaprootBuilder::new();
-    let tree_info = taproot_builder
-        .add_leaf(1, root_script.clone()).unwrap()
-        .add_leaf(2, script_1.clone()).unwrap()
-        .add_leaf(2, script_2.clone()).unwrap()
-        .finalize(&secp, internal_keypair.x_only_public_key().0).unwrap();
-
-    let output_key = tree_info.output_key();
+    build_taproot_spend_info(secp, vec![root_script, script_1, script_2])
+}
 
-    for script in vec![root_script, script_1, script_2] {
-        let ver_script = (script, LeafVersion::TapScript);
-        let ctrl_block = tree_info.control_block(&ver_script).unwrap();
-        assert!(ctrl_block.verify_taproot_commitment(
-            &secp,
-            output_key.to_inner(),
-            &ver_script.0
-        ))
+/// Witness: `<vicky_signature> <paul_signature> <preimage_n> .. <preimage_1>`, one preimage
+/// per committed wire, opening the value Paul claims for it.
+pub fn build_commit_leaf(
+    commitment_set: &CommitmentSet,
+    committed_wires: &[WireId],
+    public_key: &PublicKey,
+    other_public_key: &PublicKey,
+) -> Result<ScriptBuf, String> {
+    let mut tap_script_builder = Builder::new();
+    for wire_id in committed_wires.iter() {
+        let bit_commitment_hash = commitment_set
+            .get(wire_id)
+            .ok_or(format!("wire {} missing from the commitment set", wire_id))?;
+        tap_script_builder =
+            augment_with_bit_commitment_leaf(tap_script_builder, bit_commitment_hash);
     }
-
-    println!("-> {:?}", output_key);
-
+    Ok(augment_with_multisig(tap_script_builder, public_key, other_public_key).into_script())
 }
 
-
-pub fn build_leaf_1(public_key: &PublicKey) -> ScriptBuf {
-    let script = Builder::new()
-        .push_opcode(opcodes::all::OP_PUSHNUM_10) 
-        .push_opcode(opcodes::all::OP_CSV)
-        .push_slice(public_key.serialize())
-        .push_opcode(opcodes::all::OP_CHECKSIG)
-        .into_script();
-
-        // if key.compressed {
-        //     self.push_slice(key.inner.serialize())
-        // } else {
-        //     self.push_slice(key.inner.serialize_uncompressed())
-        // }
-    return script
+pub fn build_leaf_1(
+    public_key: &PublicKey,
+    other_public_key: &PublicKey,
+    timeout: u16,
+) -> ScriptBuf {
+    augment_with_multisig(
+        augment_with_timelock(Builder::new(), timeout),
+        public_key,
+        other_public_key,
+    )
+    .into_script()
 }
 
 pub fn build_leaf_2(public_key: &PublicKey, other_public_key: &PublicKey) -> ScriptBuf {
-    let script: ScriptBuf = Builder::new()
-        .push_opcode(opcodes::all::OP_PUSHBYTES_0)
-        .push_slice(public_key.serialize())
-        .push_opcode(opcodes::all::OP_CHECKSIG)
-        .push_slice(other_public_key.serialize())
-        .push_opcode(opcodes::all::OP_CHECKSIG)
-        .push_opcode(opcodes::all::OP_PUSHNUM_2)
-        .push_opcode(opcodes::all::OP_EQUAL)
-        .into_script();
-    return script
+    augment_with_multisig(Builder::new(), public_key, other_public_key).into_script()
 }
 
-pub fn augment_with_bit_commitment_leaf(mut builder: Builder, bit_commitment: &BitCommitmentHashes) -> Builder {
+/// Consumes one stack element, which must open `bit_commitment` to either value.
+pub fn augment_with_bit_commitment_leaf(
+    builder: Builder,
+    bit_commitment: &BitCommitmentHashes,
+) -> Builder {
     builder
         .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(&bit_commitment.0)
+        .push_opcode(opcodes::all::OP_DUP)
+        .push_slice(bit_commitment.0)
         .push_opcode(opcodes::all::OP_EQUAL)
         .push_opcode(opcodes::all::OP_SWAP)
-        .push_opcode(opcodes::all::OP_SHA256)
-        .push_slice(&bit_commitment.1)
+        .push_slice(bit_commitment.1)
         .push_opcode(opcodes::all::OP_EQUAL)
         .push_opcode(opcodes::all::OP_BOOLOR)
         .push_opcode(opcodes::all::OP_VERIFY)
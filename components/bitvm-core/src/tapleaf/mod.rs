@@ -1,3 +1,71 @@
-pub mod commitment_address;
+use bitcoin::secp256k1::{self, PublicKey, Secp256k1, XOnlyPublicKey};
+use bitcoin::taproot::{TaprootBuilder, TaprootSpendInfo};
+use bitcoin::{opcodes, script::Builder, ScriptBuf, Sequence};
+
 pub mod anti_contradiction_address;
-pub mod challenge_address;
\ No newline at end of file
+pub mod challenge_address;
+pub mod challenge_hashlock;
+pub mod commitment_address;
+
+/// BIP341 "nothing up my sleeve" point: nobody knows its discrete log, which
+/// disables the key path and forces every spend through a script leaf.
+const UNSPENDABLE_INTERNAL_KEY: [u8; 32] = [
+    0x50, 0x92, 0x9b, 0x74, 0xc1, 0xa0, 0x49, 0x54, 0xb7, 0x8b, 0x4b, 0x60, 0x35, 0xe9, 0x7a, 0x5e,
+    0x07, 0x8a, 0x5a, 0x0f, 0x28, 0xec, 0x96, 0xd5, 0x47, 0xbf, 0xee, 0x9a, 0xce, 0x80, 0x3a, 0xc0,
+];
+
+pub fn unspendable_internal_key() -> XOnlyPublicKey {
+    XOnlyPublicKey::from_slice(&UNSPENDABLE_INTERNAL_KEY).expect("valid NUMS point")
+}
+
+/// Builds a balanced taproot tree holding `scripts`, behind an unspendable internal key.
+pub fn build_taproot_spend_info(
+    secp: &Secp256k1<secp256k1::All>,
+    scripts: Vec<ScriptBuf>,
+) -> Result<TaprootSpendInfo, String> {
+    if scripts.is_empty() {
+        return Err("unable to build taproot tree: no leaves".to_string());
+    }
+    let taproot_builder = TaprootBuilder::with_huffman_tree(scripts.into_iter().map(|s| (1, s)))
+        .map_err(|e| format!("unable to build taproot tree: {}", e))?;
+    taproot_builder
+        .finalize(secp, unspendable_internal_key())
+        .map_err(|_| "unable to finalize taproot tree".to_string())
+}
+
+/// Requires signatures from both participants. Appended to every leaf of the pre-signed
+/// transaction graph, so that an output can only be spent by the transactions both parties
+/// signed during setup. Witness: `<verifier_signature> <prover_signature>`.
+pub fn augment_with_multisig(
+    builder: Builder,
+    prover_public_key: &PublicKey,
+    verifier_public_key: &PublicKey,
+) -> Builder {
+    builder
+        .push_slice(prover_public_key.x_only_public_key().0.serialize())
+        .push_opcode(opcodes::all::OP_CHECKSIGVERIFY)
+        .push_slice(verifier_public_key.x_only_public_key().0.serialize())
+        .push_opcode(opcodes::all::OP_CHECKSIG)
+}
+
+/// Makes the leaf spendable only `blocks` blocks after the output confirmed.
+pub fn augment_with_timelock(builder: Builder, blocks: u16) -> Builder {
+    builder
+        .push_sequence(Sequence::from_height(blocks))
+        .push_opcode(opcodes::all::OP_CSV)
+        .push_opcode(opcodes::all::OP_DROP)
+}
+
+/// Turns a condition script into a leaf of the pre-signed graph.
+pub fn seal_with_multisig(
+    condition: ScriptBuf,
+    prover_public_key: &PublicKey,
+    verifier_public_key: &PublicKey,
+) -> ScriptBuf {
+    augment_with_multisig(
+        Builder::from(condition.into_bytes()),
+        prover_public_key,
+        verifier_public_key,
+    )
+    .into_script()
+}
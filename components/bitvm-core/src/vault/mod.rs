@@ -0,0 +1,241 @@
+use std::fmt;
+use std::path::Path;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use bitcoin::hashes::hex::FromHex;
+use bitcoin::secp256k1::rand;
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use zeroize::Zeroizing;
+
+use crate::storage;
+
+/// Version of the vault file format. Files of any other version are rejected.
+pub const VAULT_FORMAT_VERSION: u16 = 1;
+
+pub const VAULT_FILE: &str = "vault.json";
+
+const KEY_LENGTH: usize = 32;
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 24;
+
+/// Sealed in the vault file, so that a wrong passphrase is caught on unlock rather than when
+/// the first secrets are read.
+const CHECK_PLAINTEXT: &[u8] = b"BitVM/vault";
+
+/// Cost of the Argon2id derivation of the vault key from the passphrase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KdfParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        KdfParams {
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 1,
+        }
+    }
+}
+
+/// A value encrypted with the vault key.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Sealed {
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// Public parameters of the vault, persisted in `working_dir`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct VaultFile {
+    version: u16,
+    salt: String,
+    kdf: KdfParams,
+    check: Sealed,
+}
+
+/// Encrypts secrets at rest, with a key derived from a passphrase. The key only lives in
+/// memory, and is wiped when the vault is dropped.
+#[derive(Clone)]
+pub struct Vault {
+    key: Zeroizing<[u8; KEY_LENGTH]>,
+}
+
+impl fmt::Debug for Vault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Vault(..)")
+    }
+}
+
+impl Vault {
+    /// A vault with a random key, for secrets that need not outlive the process.
+    pub fn ephemeral() -> Vault {
+        Vault {
+            key: Zeroizing::new(rand::random()),
+        }
+    }
+
+    pub fn exists(working_dir: &Path) -> bool {
+        working_dir.join(VAULT_FILE).exists()
+    }
+
+    /// Creates the vault of `working_dir`, locked by `passphrase`.
+    pub fn create(working_dir: &Path, passphrase: &str, kdf: KdfParams) -> Result<Vault, String> {
+        let path = working_dir.join(VAULT_FILE);
+        if path.exists() {
+            return Err(format!("{} already exists", path.display()));
+        }
+        let salt = rand::random::<[u8; SALT_LENGTH]>();
+        let vault = Vault {
+            key: derive_key(passphrase, &salt, &kdf)?,
+        };
+        let file = VaultFile {
+            version: VAULT_FORMAT_VERSION,
+            salt: encode_hex(&salt),
+            kdf,
+            check: vault.seal_bytes(CHECK_PLAINTEXT)?,
+        };
+        storage::write_json(&path, &file)?;
+        Ok(vault)
+    }
+
+    /// Derives the key of the vault of `working_dir` from `passphrase`, and checks it.
+    pub fn unlock(working_dir: &Path, passphrase: &str) -> Result<Vault, String> {
+        let file: VaultFile = storage::read_json(&working_dir.join(VAULT_FILE))?;
+        if file.version != VAULT_FORMAT_VERSION {
+            return Err(format!(
+                "unsupported vault format version {} (expected {})",
+                file.version, VAULT_FORMAT_VERSION
+            ));
+        }
+        let salt = decode_hex(&file.salt)?;
+        let vault = Vault {
+            key: derive_key(passphrase, &salt, &file.kdf)?,
+        };
+        match vault.open_bytes(&file.check) {
+            Ok(check) if check.as_slice() == CHECK_PLAINTEXT => Ok(vault),
+            _ => Err("wrong passphrase".to_string()),
+        }
+    }
+
+    pub fn seal<T: Serialize>(&self, value: &T) -> Result<Sealed, String> {
+        let plaintext = Zeroizing::new(
+            serde_json::to_vec(value).map_err(|e| format!("unable to serialize secrets: {}", e))?,
+        );
+        self.seal_bytes(&plaintext)
+    }
+
+    pub fn open<T: DeserializeOwned>(&self, sealed: &Sealed) -> Result<T, String> {
+        let plaintext = self.open_bytes(sealed)?;
+        serde_json::from_slice(&plaintext).map_err(|e| format!("unable to parse secrets: {}", e))
+    }
+
+    pub fn write_secrets<T: Serialize>(&self, path: &Path, value: &T) -> Result<(), String> {
+        storage::write_json(path, &self.seal(value)?)
+    }
+
+    /// Reads secrets written by `write_secrets`. Files not sealed by the vault are refused.
+    pub fn read_secrets<T: DeserializeOwned>(&self, path: &Path) -> Result<T, String> {
+        let sealed: Sealed = storage::read_json(path)
+            .map_err(|e| format!("{} is not sealed by the vault: {}", path.display(), e))?;
+        self.open(&sealed)
+            .map_err(|e| format!("unable to read {}: {}", path.display(), e))
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(Key::from_slice(self.key.as_slice()))
+    }
+
+    fn seal_bytes(&self, plaintext: &[u8]) -> Result<Sealed, String> {
+        let nonce = rand::random::<[u8; NONCE_LENGTH]>();
+        let ciphertext = self
+            .cipher()
+            .encrypt(XNonce::from_slice(&nonce), plaintext)
+            .map_err(|_| "unable to encrypt secrets".to_string())?;
+        Ok(Sealed {
+            nonce: encode_hex(&nonce),
+            ciphertext: encode_hex(&ciphertext),
+        })
+    }
+
+    fn open_bytes(&self, sealed: &Sealed) -> Result<Zeroizing<Vec<u8>>, String> {
+        let nonce = decode_hex(&sealed.nonce)?;
+        if nonce.len() != NONCE_LENGTH {
+            return Err("invalid nonce".to_string());
+        }
+        self.cipher()
+            .decrypt(
+                XNonce::from_slice(&nonce),
+                decode_hex(&sealed.ciphertext)?.as_slice(),
+            )
+            .map(Zeroizing::new)
+            .map_err(|_| "unable to decrypt secrets".to_string())
+    }
+}
+
+fn derive_key(
+    passphrase: &str,
+    salt: &[u8],
+    kdf: &KdfParams,
+) -> Result<Zeroizing<[u8; KEY_LENGTH]>, String> {
+    let params = Params::new(
+        kdf.memory_kib,
+        kdf.iterations,
+        kdf.parallelism,
+        Some(KEY_LENGTH),
+    )
+    .map_err(|e| format!("invalid key derivation parameters: {}", e))?;
+    let mut key = Zeroizing::new([0; KEY_LENGTH]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, key.as_mut())
+        .map_err(|e| format!("unable to derive vault key: {}", e))?;
+    Ok(key)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, String> {
+    Vec::<u8>::from_hex(hex).map_err(|e| format!("invalid hex: {}", e))
+}
+
+#[test]
+fn test_secrets_are_sealed_with_the_passphrase() {
+    let working_dir = std::env::temp_dir().join(format!("bitvm-vault-{}", rand::random::<u64>()));
+    let test_kdf_params = || KdfParams {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+    let vault = Vault::create(&working_dir, "correct horse", test_kdf_params()).unwrap();
+    assert!(Vault::create(&working_dir, "correct horse", test_kdf_params()).is_err());
+    let path = working_dir.join("secrets.json");
+    let secrets = vec![[7u8; 32], [9u8; 32]];
+    vault.write_secrets(&path, &secrets).unwrap();
+    let on_disk = std::fs::read_to_string(&path).unwrap();
+    assert!(!on_disk.contains(&serde_json::to_string(&secrets[0]).unwrap()));
+
+    assert!(Vault::unlock(&working_dir, "wrong horse").is_err());
+    let unlocked = Vault::unlock(&working_dir, "correct horse").unwrap();
+    assert_eq!(
+        unlocked.read_secrets::<Vec<[u8; 32]>>(&path).unwrap(),
+        secrets
+    );
+
+    // Secrets written in the clear are refused, and left as they are.
+    let cleartext = working_dir.join("cleartext.json");
+    storage::write_json(&cleartext, &secrets).unwrap();
+    assert!(unlocked
+        .read_secrets::<Vec<[u8; 32]>>(&cleartext)
+        .unwrap_err()
+        .contains("is not sealed by the vault"));
+    let on_disk: Vec<[u8; 32]> = storage::read_json(&cleartext).unwrap();
+    assert_eq!(on_disk, secrets);
+    let _ = std::fs::remove_dir_all(&working_dir);
+}
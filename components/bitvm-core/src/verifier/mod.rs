@@ -0,0 +1,870 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use bitcoin::bip32::KeySource;
+use bitcoin::secp256k1::{self, KeyPair, Secp256k1, SecretKey};
+use bitcoin::{Amount, Transaction, Txid};
+use bitvm_types::{Circuit, ExecutionTrace, GateId, WireId};
+
+use crate::bisection::{compute_bisection_rounds, BisectionVerifier, Challenge, Response};
+use crate::equivocation::{build_slashing_transaction, EquivocationDetector};
+use crate::fees::FeeWallet;
+use crate::protocol::contract::Contract;
+use crate::protocol::{
+    collect_committed_wires_ids, ContractAction, ContractEvent, ContractMachine, ContractSetup,
+    PreSignature, Role, StageOutput, TransactionGraph, TransactionKind, TranscriptEntry,
+};
+use crate::storage;
+use crate::tapleaf::anti_contradiction_address::build_equivocation_condition;
+use crate::tapleaf::challenge_address::build_tap_scripts_for_defectuous_gate;
+use crate::tapleaf::challenge_hashlock::ChallengeStore;
+use crate::tapleaf::seal_with_multisig;
+use crate::vault::Vault;
+use crate::witness::{LeafSpend, WitnessParser};
+
+const VERIFIER_STATE_FILE: &str = "verifier.json";
+const VERIFIER_SECRETS_FILE: &str = "verifier-secrets.json";
+
+/// Vicky's secrets: her signing key, and the preimages selecting her challenges.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct VerifierSecrets {
+    pub secret_key: SecretKey,
+    /// Origin of `secret_key` when it was derived from a key store.
+    #[serde(default)]
+    pub key_source: Option<KeySource>,
+    pub challenge_store: ChallengeStore,
+}
+
+impl Drop for VerifierSecrets {
+    fn drop(&mut self) {
+        self.secret_key.non_secure_erase();
+    }
+}
+
+impl VerifierSecrets {
+    /// Draws a challenge preimage for every gate of `circuit` and every bisection round.
+    pub fn generate(circuit: &Circuit, secret_key: SecretKey) -> VerifierSecrets {
+        let mut gates_ids = circuit.gates.keys().cloned().collect::<Vec<_>>();
+        gates_ids.sort();
+        let rounds = compute_bisection_rounds(circuit.gates.len());
+        VerifierSecrets {
+            secret_key,
+            key_source: None,
+            challenge_store: ChallengeStore::new(&gates_ids, rounds),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum VerifierPhase {
+    /// Collecting Paul's pre-signatures.
+    Setup,
+    /// Waiting for Paul to fund the contract.
+    AwaitingFunding,
+    /// Waiting for Paul to commit to the inputs and outputs of the circuit.
+    AwaitingCommit,
+    /// Challenge of the given round published, waiting for Paul's response.
+    AwaitingResponse(usize),
+    /// Gate challenged, waiting for Paul to open it.
+    AwaitingGateResponse(GateId),
+    /// Vicky found nothing to dispute, and lets Paul claim the funds.
+    Accepted,
+    /// The amount at stake was claimed by the given transaction.
+    Closed(TransactionKind),
+}
+
+/// Persisted state of a contract, from Vicky's side.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct VerifierState {
+    pub setup: ContractSetup,
+    pub prover_presignatures: Vec<PreSignature>,
+    pub phase: VerifierPhase,
+    /// Values Paul committed to for the inputs and outputs of the circuit.
+    pub committed_values: BTreeMap<WireId, bool>,
+    /// Paul's bisection responses, replayed on restore.
+    pub responses: Vec<Response>,
+    /// Wire preimages Paul opened so far, replayed into the equivocation detector on restore.
+    pub revealed_preimages: Vec<[u8; 32]>,
+    /// Contract transactions seen on chain so far.
+    #[serde(default)]
+    pub transcript: Vec<TranscriptEntry>,
+    /// Last transaction Vicky broadcast, kept to rebroadcast it until it confirms.
+    pub last_broadcast: Option<(TransactionKind, Transaction)>,
+    pub collateral_slashed: bool,
+}
+
+/// Vicky's side of a contract: checks Paul's claim against her own evaluation of the
+/// circuit, bisects his execution trace down to a single gate, and slashes him when he
+/// equivocates, lies about a gate, or stops answering.
+pub struct Verifier {
+    pub state: VerifierState,
+    secrets: VerifierSecrets,
+    working_dir: PathBuf,
+    secp: Secp256k1<secp256k1::All>,
+    keypair: KeyPair,
+    circuit: Circuit,
+    graph: TransactionGraph,
+    parser: WitnessParser<StageOutput>,
+    detector: EquivocationDetector,
+    /// Vicky's evaluation of the circuit on Paul's committed inputs.
+    trace: Option<ExecutionTrace>,
+    bisection: Option<BisectionVerifier>,
+}
+
+impl Verifier {
+    pub fn new(
+        working_dir: &Path,
+        vault: &Vault,
+        setup: ContractSetup,
+        secrets: VerifierSecrets,
+    ) -> Result<Verifier, String> {
+        let state = VerifierState {
+            setup,
+            prover_presignatures: vec![],
+            phase: VerifierPhase::Setup,
+            committed_values: BTreeMap::new(),
+            responses: vec![],
+            revealed_preimages: vec![],
+            transcript: vec![],
+            last_broadcast: None,
+            collateral_slashed: false,
+        };
+        let verifier = Verifier::from_state(working_dir, state, secrets)?;
+        vault.write_secrets(
+            &verifier.contract_file(VERIFIER_SECRETS_FILE),
+            &verifier.secrets,
+        )?;
+        verifier.save()?;
+        Ok(verifier)
+    }
+
+    /// Resumes a contract from its persisted state, with the secrets sealed in `vault`.
+    pub fn restore(
+        working_dir: &Path,
+        vault: &Vault,
+        contract_id: &Txid,
+    ) -> Result<Verifier, String> {
+        let contract_dir = storage::contract_dir(working_dir, contract_id);
+        Verifier::from_state(
+            working_dir,
+            storage::read_json(&contract_dir.join(VERIFIER_STATE_FILE))?,
+            vault.read_secrets(&contract_dir.join(VERIFIER_SECRETS_FILE))?,
+        )
+    }
+
+    /// Resumes every contract of `working_dir` this party still has to play.
+    pub fn restore_active(working_dir: &Path, vault: &Vault) -> Result<Vec<Verifier>, String> {
+        let mut contracts = vec![];
+        for contract_id in storage::list_contracts(working_dir)? {
+            let path = storage::contract_dir(working_dir, &contract_id).join(VERIFIER_STATE_FILE);
+            if !path.exists() {
+                continue;
+            }
+            let contract = Verifier::restore(working_dir, vault, &contract_id)?;
+            if contract.is_active() {
+                contracts.push(contract);
+            }
+        }
+        Ok(contracts)
+    }
+
+    fn from_state(
+        working_dir: &Path,
+        state: VerifierState,
+        secrets: VerifierSecrets,
+    ) -> Result<Verifier, String> {
+        let secp = Secp256k1::new();
+        let keypair = KeyPair::from_secret_key(&secp, &secrets.secret_key);
+        if keypair.public_key() != state.setup.participants.verifier {
+            return Err("secret key does not match the verifier of the contract".to_string());
+        }
+        if secrets.challenge_store.compute_hashes() != state.setup.commitments.challenge_hashes {
+            return Err("secrets do not match the challenge hashes of the contract".to_string());
+        }
+        let circuit = state.setup.read_circuit()?;
+
+        let mut graph = state.setup.build_graph(&secp, &circuit)?;
+        let presignatures = graph.presign(&secp, &keypair)?;
+        graph.add_presignatures(&secp, Role::Verifier, &presignatures)?;
+        graph.add_presignatures(&secp, Role::Prover, &state.prover_presignatures)?;
+        let parser = graph.build_witness_parser(&state.setup.commitments);
+        let mut detector = EquivocationDetector::new(&state.setup.commitments.commitment_set);
+        for preimage in state.revealed_preimages.iter() {
+            detector.observe_preimage(preimage);
+        }
+
+        let mut verifier = Verifier {
+            state,
+            secrets,
+            working_dir: working_dir.to_path_buf(),
+            secp,
+            keypair,
+            circuit,
+            graph,
+            parser,
+            detector,
+            trace: None,
+            bisection: None,
+        };
+        verifier.replay()?;
+        Ok(verifier)
+    }
+
+    /// Rebuilds the bisection from the commitments and responses Paul published.
+    fn replay(&mut self) -> Result<(), String> {
+        if self.state.committed_values.is_empty() {
+            return Ok(());
+        }
+        let inputs = self
+            .circuit
+            .collect_input_wires_ids()
+            .into_iter()
+            .map(|wire_id| {
+                self.state
+                    .committed_values
+                    .get(wire_id)
+                    .map(|value| (*wire_id, *value))
+                    .ok_or(format!("no committed value for input wire {}", wire_id))
+            })
+            .collect::<Result<BTreeMap<_, _>, String>>()?;
+        let trace = self.circuit.evaluate(&inputs)?;
+        let mut bisection = BisectionVerifier::new(
+            &self.circuit,
+            &trace,
+            &self.state.setup.commitments.commitment_set,
+            &self.state.committed_values,
+        )?;
+        for response in self.state.responses.iter() {
+            bisection.next_challenge()?;
+            bisection.receive_response(response)?;
+        }
+        if matches!(
+            self.state.phase,
+            VerifierPhase::AwaitingResponse(_) | VerifierPhase::AwaitingGateResponse(_)
+        ) {
+            bisection.next_challenge()?;
+        }
+        self.trace = Some(trace);
+        self.bisection = Some(bisection);
+        Ok(())
+    }
+
+    /// Whether the contract still needs watching: until the amount at stake is claimed.
+    pub fn is_active(&self) -> bool {
+        !matches!(self.state.phase, VerifierPhase::Closed(_))
+    }
+
+    /// Contracts are identified by the txid of their funding transaction.
+    pub fn contract_id(&self) -> Txid {
+        self.graph.transactions[&TransactionKind::Funding].tx.txid()
+    }
+
+    pub fn graph(&self) -> &TransactionGraph {
+        &self.graph
+    }
+
+    pub fn trace(&self) -> Option<&ExecutionTrace> {
+        self.trace.as_ref()
+    }
+
+    /// Persists the state of the contract. Secrets are written once, when the contract is
+    /// created, and kept apart from the state.
+    pub fn save(&self) -> Result<(), String> {
+        storage::write_json(&self.contract_file(VERIFIER_STATE_FILE), &self.state)
+    }
+
+    fn contract_file(&self, name: &str) -> PathBuf {
+        storage::contract_dir(&self.working_dir, &self.contract_id()).join(name)
+    }
+
+    /// Vicky's pre-signatures, to be sent to Paul.
+    pub fn presignatures(&self) -> Vec<PreSignature> {
+        self.graph.collect_presignatures(Role::Verifier)
+    }
+
+    pub fn receive_presignatures(&mut self, presignatures: &[PreSignature]) -> Result<(), String> {
+        self.graph
+            .add_presignatures(&self.secp, Role::Prover, presignatures)?;
+        self.state
+            .prover_presignatures
+            .extend(presignatures.iter().cloned());
+        self.save()
+    }
+
+    /// Advances the contract, returning the transactions to publish. The new state is
+    /// persisted before returning.
+    pub fn handle(&mut self, event: &ContractEvent) -> Result<Vec<ContractAction>, String> {
+        let actions = match event {
+            ContractEvent::SetupCompleted => {
+                if self.state.phase == VerifierPhase::Setup {
+                    let missing = self.graph.count_missing_presignatures();
+                    if missing > 0 {
+                        return Err(format!("{} pre-signatures missing", missing));
+                    }
+                    self.state.phase = VerifierPhase::AwaitingFunding;
+                    Contract::from_graph(&self.state.setup, &self.graph)?
+                        .save(&self.working_dir)?;
+                }
+                vec![]
+            }
+            ContractEvent::FundingConfirmed => {
+                if self.state.phase == VerifierPhase::AwaitingFunding {
+                    self.state.phase = VerifierPhase::AwaitingCommit;
+                }
+                vec![]
+            }
+            ContractEvent::SpendObserved(tx) => self.observe_spend(tx)?,
+            ContractEvent::TimeoutNear {
+                output,
+                blocks_left,
+            } => self.handle_timeout(output, *blocks_left)?,
+        };
+        self.save()?;
+        Ok(actions)
+    }
+
+    fn observe_spend(&mut self, tx: &Transaction) -> Result<Vec<ContractAction>, String> {
+        let mut actions = self.slash_equivocations(tx)?;
+        let Some(kind) = self.graph.identify_transaction(tx) else {
+            return Ok(actions);
+        };
+        let txid = tx.txid();
+        if !self.state.transcript.iter().any(|entry| entry.txid == txid) {
+            storage::write_transaction(&self.working_dir, &self.contract_id(), tx)?;
+            self.state.transcript.push(TranscriptEntry { kind, txid });
+        }
+        let mut next_actions = match kind {
+            TransactionKind::Commit if self.state.phase == VerifierPhase::AwaitingCommit => {
+                self.check_commitment(tx)?
+            }
+            TransactionKind::Response(round)
+                if self.state.phase == VerifierPhase::AwaitingResponse(round) =>
+            {
+                self.check_response(round, tx)?
+            }
+            TransactionKind::GateResponse => match self.state.phase {
+                VerifierPhase::AwaitingGateResponse(gate_id) => self.judge_gate(gate_id, tx)?,
+                _ => vec![],
+            },
+            TransactionKind::CommitTimeout
+            | TransactionKind::CooperativeClose
+            | TransactionKind::ChallengeTimeout(_)
+            | TransactionKind::ResponseTimeout(_)
+            | TransactionKind::GateResponseTimeout
+            | TransactionKind::Slash
+            | TransactionKind::SlashTimeout => {
+                self.state.phase = VerifierPhase::Closed(kind);
+                self.state.last_broadcast = None;
+                vec![]
+            }
+            _ => vec![],
+        };
+        actions.append(&mut next_actions);
+        Ok(actions)
+    }
+
+    /// Claims Paul's collateral the first time he opens both values of a wire.
+    fn slash_equivocations(&mut self, tx: &Transaction) -> Result<Vec<ContractAction>, String> {
+        for input in tx.input.iter() {
+            for revealed in self.parser.extract_preimages(&input.witness.to_vec()) {
+                if !self.state.revealed_preimages.contains(&revealed.preimage) {
+                    self.state.revealed_preimages.push(revealed.preimage);
+                }
+            }
+        }
+        let equivocations = self.detector.observe_transaction(tx);
+        let Some(equivocation) = equivocations.first() else {
+            return Ok(vec![]);
+        };
+        if self.state.collateral_slashed {
+            return Ok(vec![]);
+        }
+        let collateral = self.graph.output(&StageOutput::Collateral)?;
+        let tx = build_slashing_transaction(
+            &self.secp,
+            equivocation,
+            &collateral.spend_info,
+            collateral.outpoint,
+            &collateral.txout,
+            &self.keypair,
+            self.state.setup.parameters.fee_per_transaction,
+        )?;
+        self.state.collateral_slashed = true;
+        Ok(vec![ContractAction::Broadcast {
+            kind: TransactionKind::CollateralSlash,
+            tx,
+        }])
+    }
+
+    /// Re-evaluates the circuit on Paul's inputs, and challenges him if his outputs differ.
+    fn check_commitment(&mut self, tx: &Transaction) -> Result<Vec<ContractAction>, String> {
+        let spend = self.parse_spend(tx)?;
+        for revealed in spend.revealed_preimages.iter() {
+            self.state
+                .committed_values
+                .insert(revealed.wire_id, revealed.bit);
+        }
+        for wire_id in collect_committed_wires_ids(&self.circuit) {
+            if !self.state.committed_values.contains_key(&wire_id) {
+                return Err(format!("commit transaction does not open wire {}", wire_id));
+            }
+        }
+        self.replay()?;
+        let trace = self.trace.as_ref().expect("trace evaluated on replay");
+        let disputed = self
+            .circuit
+            .collect_output_wires_ids()
+            .iter()
+            .any(|wire_id| {
+                trace.value(wire_id) != self.state.committed_values.get(wire_id).cloned()
+            });
+        if !disputed {
+            self.state.phase = VerifierPhase::Accepted;
+            return Ok(vec![]);
+        }
+        self.challenge()
+    }
+
+    /// Compares the state Paul revealed with Vicky's own, and issues the next challenge. The
+    /// response leaf first consumes Vicky's verdicts so far, then the state.
+    fn check_response(
+        &mut self,
+        round: usize,
+        tx: &Transaction,
+    ) -> Result<Vec<ContractAction>, String> {
+        let spend = self.parse_spend(tx)?;
+        let preimages = self
+            .graph
+            .extract_conditions(&TransactionKind::Response(round), &spend.stack)?
+            .iter()
+            .skip(round)
+            .map(|element| {
+                <[u8; 32]>::try_from(element.as_slice())
+                    .map_err(|_| "malformed state preimage".to_string())
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        let response = Response { round, preimages };
+        self.bisection
+            .as_mut()
+            .ok_or("response received before the commitment".to_string())?
+            .receive_response(&response)?;
+        self.state.responses.push(response);
+        self.challenge()
+    }
+
+    /// Publishes the next challenge: the next bisection round, or the disputed gate.
+    fn challenge(&mut self) -> Result<Vec<ContractAction>, String> {
+        let challenge = self
+            .bisection
+            .as_mut()
+            .ok_or("challenge issued before the commitment".to_string())?
+            .next_challenge()?;
+        let (kind, leaf) = match challenge {
+            Challenge::Bisect { round, .. } => {
+                let kind = TransactionKind::Challenge(round);
+                self.state.phase = VerifierPhase::AwaitingResponse(round);
+                (kind, self.graph.transaction(&kind)?.leaves[0].clone())
+            }
+            Challenge::Gate {
+                gate_id: Some(gate_id),
+                ..
+            } => {
+                let kind = TransactionKind::GateChallenge;
+                self.state.phase = VerifierPhase::AwaitingGateResponse(gate_id);
+                (kind, self.graph.gate_leaf(&kind, &gate_id)?.clone())
+            }
+            Challenge::Gate { gate_id: None, .. } => {
+                // The disagreement lies in the padding of the trace: nothing to dispute.
+                self.state.phase = VerifierPhase::Accepted;
+                return Ok(vec![]);
+            }
+        };
+        let conditions = self
+            .secrets
+            .challenge_store
+            .reveal(&challenge)?
+            .iter()
+            .map(|preimage| preimage.to_vec())
+            .collect();
+        let tx = self.graph.finalize_transaction(&kind, &leaf, conditions)?;
+        Ok(vec![self.broadcast(kind, tx)])
+    }
+
+    /// Slashes Paul if the values he opened for the challenged gate are inconsistent. Values
+    /// he opened in earlier transactions count too, so equivocating does not save him: failing
+    /// a defect, both values of any wire he opened are enough.
+    fn judge_gate(
+        &mut self,
+        gate_id: GateId,
+        tx: &Transaction,
+    ) -> Result<Vec<ContractAction>, String> {
+        let spend = self.parse_spend(tx)?;
+        let gate = self
+            .circuit
+            .gates
+            .get(&gate_id)
+            .ok_or(format!("unknown gate {}", gate_id))?;
+        let mut wires = gate.inputs();
+        wires.push(gate_id);
+        for wire_id in wires.iter() {
+            if !spend
+                .revealed_preimages
+                .iter()
+                .any(|revealed| revealed.wire_id == *wire_id)
+            {
+                return Err(format!("gate response does not open wire {}", wire_id));
+            }
+        }
+        let stack = self
+            .state
+            .revealed_preimages
+            .iter()
+            .map(|preimage| preimage.to_vec())
+            .collect::<Vec<_>>();
+        let revealed = self.parser.extract_preimages(&stack);
+        let opened = |wire_id: &WireId, bit: bool| {
+            revealed
+                .iter()
+                .find(|revealed| revealed.wire_id == *wire_id && revealed.bit == bit)
+                .map(|revealed| revealed.preimage.to_vec())
+        };
+
+        let commitment_set = &self.state.setup.commitments.commitment_set;
+        let defect = build_tap_scripts_for_defectuous_gate(gate_id, gate, commitment_set)?
+            .into_iter()
+            .find_map(|(values, wrong_output, condition)| {
+                let mut conditions = gate
+                    .inputs()
+                    .iter()
+                    .zip(values.iter())
+                    .map(|(wire_id, bit)| opened(wire_id, *bit))
+                    .collect::<Option<Vec<_>>>()?;
+                conditions.push(opened(&gate_id, wrong_output)?);
+                Some((condition, conditions))
+            });
+        // The gate may look consistent when Paul opened a wire differently across the bisection:
+        // the values he opened then contradict each other.
+        let equivocation = || {
+            commitment_set.hashes.iter().find_map(|(wire_id, hashes)| {
+                let conditions = vec![opened(wire_id, false)?, opened(wire_id, true)?];
+                Some((build_equivocation_condition(hashes), conditions))
+            })
+        };
+        let Some((condition, conditions)) = defect.or_else(equivocation) else {
+            // Paul opened a consistent gate: Vicky lost the dispute.
+            self.state.phase = VerifierPhase::Accepted;
+            return Ok(vec![]);
+        };
+        let participants = &self.state.setup.participants;
+        let leaf = seal_with_multisig(condition, &participants.prover, &participants.verifier);
+        let kind = TransactionKind::Slash;
+        let tx = self.graph.finalize_transaction(&kind, &leaf, conditions)?;
+        Ok(vec![self.broadcast(kind, tx)])
+    }
+
+    fn handle_timeout(
+        &mut self,
+        output: &StageOutput,
+        blocks_left: u32,
+    ) -> Result<Vec<ContractAction>, String> {
+        let expired = blocks_left == 0;
+        let kind = match (output, &self.state.phase) {
+            (StageOutput::Funding, VerifierPhase::AwaitingCommit) if expired => {
+                TransactionKind::CommitTimeout
+            }
+            (StageOutput::Response(round), VerifierPhase::AwaitingResponse(current))
+                if expired && round == current =>
+            {
+                TransactionKind::ResponseTimeout(*round)
+            }
+            (StageOutput::GateResponse, VerifierPhase::AwaitingGateResponse(_)) if expired => {
+                TransactionKind::GateResponseTimeout
+            }
+            (StageOutput::Challenge(_) | StageOutput::Judgement, _) => {
+                return Ok(self.rebroadcast(output));
+            }
+            _ => return Ok(vec![]),
+        };
+        let leaf = self.graph.transaction(&kind)?.leaves[0].clone();
+        let tx = self.graph.finalize_transaction(&kind, &leaf, vec![])?;
+        Ok(vec![self.broadcast(kind, tx)])
+    }
+
+    /// Vicky's pending move, if it spends `output` and has not confirmed yet.
+    fn rebroadcast(&self, output: &StageOutput) -> Vec<ContractAction> {
+        match self.state.last_broadcast {
+            Some((kind, ref tx))
+                if self.graph.transactions[&kind].spent_output == Some(*output) =>
+            {
+                vec![ContractAction::Broadcast {
+                    kind,
+                    tx: tx.clone(),
+                }]
+            }
+            _ => vec![],
+        }
+    }
+
+    fn broadcast(&mut self, kind: TransactionKind, tx: Transaction) -> ContractAction {
+        self.state.last_broadcast = Some((kind, tx.clone()));
+        ContractAction::Broadcast { kind, tx }
+    }
+
+    fn parse_spend(&self, tx: &Transaction) -> Result<LeafSpend<StageOutput>, String> {
+        self.parser
+            .parse_transaction(tx)
+            .into_iter()
+            .next()
+            .ok_or(format!("{} does not spend a contract output", tx.txid()))
+    }
+}
+
+impl ContractMachine for Verifier {
+    fn contract_id(&self) -> Txid {
+        Verifier::contract_id(self)
+    }
+
+    fn role(&self) -> Role {
+        Role::Verifier
+    }
+
+    fn phase(&self) -> String {
+        format!("{:?}", self.state.phase)
+    }
+
+    fn graph(&self) -> &TransactionGraph {
+        &self.graph
+    }
+
+    fn presignatures(&self) -> Vec<PreSignature> {
+        Verifier::presignatures(self)
+    }
+
+    fn receive_presignatures(&mut self, presignatures: &[PreSignature]) -> Result<(), String> {
+        Verifier::receive_presignatures(self, presignatures)
+    }
+
+    fn key_source(&self) -> Option<&KeySource> {
+        self.secrets.key_source.as_ref()
+    }
+
+    fn transcript(&self) -> &[TranscriptEntry] {
+        &self.state.transcript
+    }
+
+    fn bump_fee(
+        &self,
+        parent: &Transaction,
+        parent_fee: Amount,
+        fee_rate: u64,
+        wallet: &mut FeeWallet,
+    ) -> Result<Transaction, String> {
+        self.graph.build_cpfp(
+            &self.secp,
+            Role::Verifier,
+            &self.keypair,
+            parent,
+            parent_fee,
+            fee_rate,
+            wallet,
+        )
+    }
+
+    fn handle(&mut self, event: &ContractEvent) -> Result<Vec<ContractAction>, String> {
+        Verifier::handle(self, event)
+    }
+
+    fn snapshot(&self) -> Result<serde_json::Value, String> {
+        serde_json::to_value(&self.state).map_err(|e| format!("unable to snapshot state: {}", e))
+    }
+
+    fn rollback(&mut self, snapshot: serde_json::Value) -> Result<(), String> {
+        let state: VerifierState = serde_json::from_value(snapshot)
+            .map_err(|e| format!("unable to restore snapshot: {}", e))?;
+        let mut detector = EquivocationDetector::new(&state.setup.commitments.commitment_set);
+        for preimage in state.revealed_preimages.iter() {
+            detector.observe_preimage(preimage);
+        }
+        self.detector = detector;
+        self.state = state;
+        self.trace = None;
+        self.bisection = None;
+        self.replay()?;
+        self.save()
+    }
+}
+
+#[test]
+fn test_verifier_bisects_and_slashes_a_faulty_gate() {
+    use crate::bisection::BisectionProver;
+    use crate::protocol::build_test_setup;
+    use crate::tapleaf::challenge_hashlock::RevealedChallenge;
+
+    let secp = Secp256k1::new();
+    let prover_keypair = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+    let verifier_keypair = KeyPair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let (circuit, setup, challenge_store) = build_test_setup(
+        include_str!("../bristol/fixtures/test_vector_1.bristol"),
+        &prover_keypair,
+        &verifier_keypair,
+    );
+    let secrets = VerifierSecrets {
+        secret_key: verifier_keypair.secret_key(),
+        key_source: None,
+        challenge_store,
+    };
+    let working_dir =
+        std::env::temp_dir().join(format!("bitvm-verifier-{}", rand::random::<u64>()));
+    let vault = Vault::ephemeral();
+    let mut verifier = Verifier::new(&working_dir, &vault, setup.clone(), secrets).unwrap();
+
+    let mut graph = setup.build_graph(&secp, &circuit).unwrap();
+    let prover_presignatures = graph.presign(&secp, &prover_keypair).unwrap();
+    graph
+        .add_presignatures(&secp, Role::Prover, &prover_presignatures)
+        .unwrap();
+    graph
+        .add_presignatures(&secp, Role::Verifier, &verifier.presignatures())
+        .unwrap();
+    verifier
+        .receive_presignatures(&prover_presignatures)
+        .unwrap();
+    verifier.handle(&ContractEvent::SetupCompleted).unwrap();
+    verifier.handle(&ContractEvent::FundingConfirmed).unwrap();
+
+    // Paul lies about the first output of the circuit.
+    let inputs = circuit
+        .collect_input_wires_ids()
+        .into_iter()
+        .map(|wire_id| (*wire_id, wire_id % 3 == 0))
+        .collect::<BTreeMap<_, _>>();
+    let faulty_gate = circuit.collect_output_wires_ids()[0];
+    let paul_trace = circuit
+        .evaluate_with(&inputs, |gate_id, value| value ^ (gate_id == faulty_gate))
+        .unwrap();
+    let open = |wire_id: &WireId| {
+        circuit.gates_bit_commitments_preimages[wire_id]
+            .preimage_for_bit(paul_trace.values[wire_id])
+            .to_vec()
+    };
+    let kind = TransactionKind::Commit;
+    let leaf = graph.transaction(&kind).unwrap().leaves[0].clone();
+    let conditions = collect_committed_wires_ids(&circuit)
+        .iter()
+        .map(open)
+        .collect();
+    let mut observed = graph
+        .finalize_transaction(&kind, &leaf, conditions)
+        .unwrap();
+
+    let wire_preimages = circuit
+        .gates_bit_commitments_preimages
+        .iter()
+        .map(|(wire_id, preimages)| (*wire_id, preimages.clone()))
+        .collect();
+    let mut paul = BisectionProver::new(&circuit, &paul_trace, wire_preimages).unwrap();
+    let challenge_hashes = &setup.commitments.challenge_hashes;
+    let mut verdict_preimages = vec![];
+    let contract_id = verifier.contract_id();
+    loop {
+        // Restart before every move: the dispute resumes from the working directory.
+        drop(verifier);
+        verifier = Verifier::restore(&working_dir, &vault, &contract_id).unwrap();
+        let actions = verifier
+            .handle(&ContractEvent::SpendObserved(observed.clone()))
+            .unwrap();
+        let [ContractAction::Broadcast { kind, ref tx }] = actions[..] else {
+            panic!("expected a single move from Vicky");
+        };
+        let revealed = tx.input[0]
+            .witness
+            .iter()
+            .filter_map(|element| challenge_hashes.identify(element))
+            .collect::<Vec<_>>();
+        let verdict = revealed.iter().find_map(|challenge| match challenge {
+            RevealedChallenge::Verdict { agreed, .. } => Some(*agreed),
+            _ => None,
+        });
+        verdict_preimages.extend(tx.input[0].witness.iter().filter_map(|element| {
+            match challenge_hashes.identify(element) {
+                Some(RevealedChallenge::Verdict { .. }) => Some(element.to_vec()),
+                _ => None,
+            }
+        }));
+        let mut bisection = paul.bisection.clone();
+        if let Some(agreed) = verdict {
+            bisection.record_verdict(agreed).unwrap();
+        }
+        match kind {
+            TransactionKind::Challenge(round) => {
+                let response = paul
+                    .respond(&Challenge::Bisect {
+                        round,
+                        verdict,
+                        midpoint: bisection.midpoint().unwrap(),
+                    })
+                    .unwrap()
+                    .unwrap();
+                let kind = TransactionKind::Response(round);
+                let leaf = graph
+                    .response_leaf(round, &paul.bisection.verdicts())
+                    .unwrap()
+                    .clone();
+                let mut conditions = verdict_preimages.clone();
+                conditions.extend(response.preimages.iter().map(|p| p.to_vec()));
+                observed = graph
+                    .finalize_transaction(&kind, &leaf, conditions)
+                    .unwrap();
+            }
+            TransactionKind::GateChallenge => {
+                let gate_id = revealed
+                    .iter()
+                    .find_map(|challenge| match challenge {
+                        RevealedChallenge::Gate(gate_id) => Some(*gate_id),
+                        _ => None,
+                    })
+                    .unwrap();
+                assert_eq!(gate_id, faulty_gate);
+                let gate_preimage = tx.input[0]
+                    .witness
+                    .iter()
+                    .find(|element| {
+                        challenge_hashes.identify(element) == Some(RevealedChallenge::Gate(gate_id))
+                    })
+                    .unwrap()
+                    .to_vec();
+                let mut wires = circuit.gates[&gate_id].inputs();
+                wires.push(gate_id);
+                let mut conditions = vec![gate_preimage];
+                conditions.extend(wires.iter().map(open));
+                let kind = TransactionKind::GateResponse;
+                let leaf = graph.gate_leaf(&kind, &gate_id).unwrap().clone();
+                observed = graph
+                    .finalize_transaction(&kind, &leaf, conditions)
+                    .unwrap();
+            }
+            TransactionKind::Slash => break,
+            kind => panic!("unexpected {}", kind),
+        }
+    }
+
+    // Opening the honest value of the faulty gate afterwards is an equivocation.
+    let honest_preimage = circuit.gates_bit_commitments_preimages[&faulty_gate]
+        .preimage_for_bit(!paul_trace.values[&faulty_gate])
+        .to_vec();
+    let mut equivocating = observed.clone();
+    equivocating.output[0].value -= 1;
+    equivocating.input[0].witness = bitcoin::Witness::from_slice(&[honest_preimage]);
+    let mut verifier = Verifier::restore(&working_dir, &vault, &contract_id).unwrap();
+    let actions = verifier
+        .handle(&ContractEvent::SpendObserved(equivocating))
+        .unwrap();
+    assert!(matches!(
+        actions[..],
+        [ContractAction::Broadcast {
+            kind: TransactionKind::CollateralSlash,
+            ..
+        }]
+    ));
+    std::fs::remove_dir_all(&working_dir).unwrap();
+}
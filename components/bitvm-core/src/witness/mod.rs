@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use bitcoin::secp256k1::{self, Secp256k1};
+use bitcoin::taproot::{
+    ControlBlock, LeafVersion, TapLeafHash, TaprootSpendInfo, TAPROOT_ANNEX_PREFIX,
+};
+use bitcoin::{OutPoint, ScriptBuf, Transaction, Witness};
+use bitvm_types::{CommitmentSet, WireId};
+use sha2::{Digest, Sha256};
+
+use crate::tapleaf::challenge_hashlock::{ChallengeHashes, RevealedChallenge};
+
+/// A preimage revealed in a witness, mapped back to the wire and bit value it opens.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RevealedPreimage {
+    pub wire_id: WireId,
+    pub bit: bool,
+    pub preimage: [u8; 32],
+}
+
+/// A script path spend of one of the registered BitVM outputs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafSpend<L> {
+    pub txid: bitcoin::Txid,
+    pub input_index: usize,
+    pub previous_output: OutPoint,
+    pub leaf: L,
+    pub leaf_script: ScriptBuf,
+    /// Witness elements consumed by the leaf script, without the script and control block.
+    pub stack: Vec<Vec<u8>>,
+    pub revealed_preimages: Vec<RevealedPreimage>,
+    pub revealed_challenges: Vec<RevealedChallenge>,
+}
+
+struct RegisteredLeaf<L> {
+    label: L,
+    output_key: secp256k1::XOnlyPublicKey,
+}
+
+/// Extracts revealed preimages from transactions spending BitVM outputs.
+///
+/// Outputs are registered with a label per leaf; spends are identified through their
+/// control block, and every 32-byte stack element is matched against the commitment set.
+pub struct WitnessParser<L> {
+    secp: Secp256k1<secp256k1::All>,
+    leaves: HashMap<TapLeafHash, Vec<RegisteredLeaf<L>>>,
+    commitments_index: HashMap<[u8; 32], (WireId, bool)>,
+    challenge_hashes: Option<ChallengeHashes>,
+}
+
+impl<L: Clone> WitnessParser<L> {
+    pub fn new(commitment_set: &CommitmentSet) -> WitnessParser<L> {
+        WitnessParser {
+            secp: Secp256k1::new(),
+            leaves: HashMap::new(),
+            commitments_index: commitment_set.build_reverse_index(),
+            challenge_hashes: None,
+        }
+    }
+
+    /// Also recognize Vicky's challenge secrets when they show up in a witness.
+    pub fn register_challenge_hashes(&mut self, challenge_hashes: &ChallengeHashes) {
+        self.challenge_hashes = Some(challenge_hashes.clone());
+    }
+
+    /// Registers every leaf of a taproot output, labelled with `label_leaf(script)`.
+    pub fn register_output<F>(&mut self, spend_info: &TaprootSpendInfo, label_leaf: F)
+    where
+        F: Fn(&ScriptBuf) -> L,
+    {
+        let output_key = spend_info.output_key().to_inner();
+        for (script, leaf_version) in spend_info.as_script_map().keys() {
+            let leaf_hash = TapLeafHash::from_script(script, *leaf_version);
+            self.leaves
+                .entry(leaf_hash)
+                .or_default()
+                .push(RegisteredLeaf {
+                    label: label_leaf(script),
+                    output_key,
+                });
+        }
+    }
+
+    /// Returns the registered leaf spent by `witness`, along with its script and stack.
+    pub fn identify_leaf(&self, witness: &Witness) -> Option<(L, ScriptBuf, Vec<Vec<u8>>)> {
+        let mut elements = witness.to_vec();
+        if elements.len() >= 2 {
+            let last = elements.last()?;
+            if !last.is_empty() && last[0] == TAPROOT_ANNEX_PREFIX {
+                elements.pop();
+            }
+        }
+        if elements.len() < 2 {
+            return None;
+        }
+        let control_block = ControlBlock::decode(&elements.pop()?).ok()?;
+        if control_block.leaf_version != LeafVersion::TapScript {
+            return None;
+        }
+        let leaf_script = ScriptBuf::from_bytes(elements.pop()?);
+        let leaf_hash = TapLeafHash::from_script(&leaf_script, LeafVersion::TapScript);
+        let registered_leaf = self.leaves.get(&leaf_hash)?.iter().find(|leaf| {
+            control_block.verify_taproot_commitment(&self.secp, leaf.output_key, &leaf_script)
+        })?;
+        Some((registered_leaf.label.clone(), leaf_script, elements))
+    }
+
+    pub fn extract_preimages(&self, stack: &[Vec<u8>]) -> Vec<RevealedPreimage> {
+        let mut revealed_preimages = vec![];
+        for element in stack.iter() {
+            let Ok(preimage) = <[u8; 32]>::try_from(element.as_slice()) else {
+                continue;
+            };
+            let hash: [u8; 32] = Sha256::digest(preimage).into();
+            if let Some((wire_id, bit)) = self.commitments_index.get(&hash) {
+                revealed_preimages.push(RevealedPreimage {
+                    wire_id: *wire_id,
+                    bit: *bit,
+                    preimage,
+                });
+            }
+        }
+        revealed_preimages
+    }
+
+    pub fn extract_challenges(&self, stack: &[Vec<u8>]) -> Vec<RevealedChallenge> {
+        let Some(ref challenge_hashes) = self.challenge_hashes else {
+            return vec![];
+        };
+        stack
+            .iter()
+            .filter_map(|element| challenge_hashes.identify(element))
+            .collect()
+    }
+
+    /// Scans the inputs of `tx`, returning the spends of registered leaves.
+    pub fn parse_transaction(&self, tx: &Transaction) -> Vec<LeafSpend<L>> {
+        let txid = tx.txid();
+        let mut spends = vec![];
+        for (input_index, input) in tx.input.iter().enumerate() {
+            let Some((leaf, leaf_script, stack)) = self.identify_leaf(&input.witness) else {
+                continue;
+            };
+            let revealed_preimages = self.extract_preimages(&stack);
+            let revealed_challenges = self.extract_challenges(&stack);
+            spends.push(LeafSpend {
+                txid,
+                input_index,
+                previous_output: input.previous_output,
+                leaf,
+                leaf_script,
+                stack,
+                revealed_preimages,
+                revealed_challenges,
+            });
+        }
+        spends
+    }
+}
+
+#[test]
+fn test_witness_parser_identifies_leaf_and_preimages() {
+    use crate::equivocation::{build_slashing_transaction, Equivocation};
+    use crate::tapleaf::anti_contradiction_address::compute_anti_contradiction_address;
+    use bitcoin::hashes::Hash;
+    use bitcoin::secp256k1::KeyPair;
+    use bitcoin::{Amount, TxOut};
+
+    let circuit = crate::bristol::parser::read_circuit(include_str!(
+        "../bristol/fixtures/test_vector_1.bristol"
+    ))
+    .expect("unable to parse bristol");
+    let commitment_set = circuit.compute_commitment_set();
+    let secp = Secp256k1::new();
+    let prover_keypair = KeyPair::from_seckey_slice(&secp, &[3; 32]).unwrap();
+    let verifier_keypair = KeyPair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+    let spend_info = compute_anti_contradiction_address(
+        &commitment_set,
+        &secp,
+        &prover_keypair.public_key(),
+        &verifier_keypair.public_key(),
+        144,
+    )
+    .unwrap();
+
+    let mut parser = WitnessParser::new(&commitment_set);
+    parser.register_output(&spend_info, |script| script.len());
+
+    let wire_id = 97;
+    let preimages = circuit
+        .gates_bit_commitments_preimages
+        .get(&wire_id)
+        .unwrap();
+    let output = TxOut {
+        value: 10_000,
+        script_pubkey: ScriptBuf::new_v1_p2tr_tweaked(spend_info.output_key()),
+    };
+    let tx = build_slashing_transaction(
+        &secp,
+        &Equivocation {
+            wire_id,
+            preimages: preimages.clone(),
+        },
+        &spend_info,
+        OutPoint::new(bitcoin::Txid::all_zeros(), 1),
+        &output,
+        &verifier_keypair,
+        Amount::from_sat(500),
+    )
+    .unwrap();
+
+    let spends = parser.parse_transaction(&tx);
+    assert_eq!(spends.len(), 1);
+    assert_eq!(spends[0].leaf, spends[0].leaf_script.len());
+    assert_eq!(spends[0].stack.len(), 3);
+    assert!(spends[0].revealed_challenges.is_empty());
+    assert_eq!(
+        spends[0].revealed_preimages,
+        vec![
+            RevealedPreimage {
+                wire_id,
+                bit: true,
+                preimage: preimages.1
+            },
+            RevealedPreimage {
+                wire_id,
+                bit: false,
+                preimage: preimages.0
+            },
+        ]
+    );
+
+    // Spends of unregistered outputs are ignored.
+    let other_parser: WitnessParser<usize> = WitnessParser::new(&commitment_set);
+    assert!(other_parser.parse_transaction(&tx).is_empty());
+}
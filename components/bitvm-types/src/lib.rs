@@ -1,38 +1,99 @@
+#[macro_use]
+extern crate serde_derive;
+
 use core::fmt;
 use rand::{thread_rng, Rng};
 use sha2::{Digest, Sha256};
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use zeroize::{Zeroize, ZeroizeOnDrop};
 
-type CircuitId = u64;
-type GateId = u64;
-type WireId = u64;
+pub type CircuitId = u64;
+pub type GateId = u64;
+pub type WireId = u64;
 
-#[derive(Debug, PartialEq)]
+/// Opening of a bit commitment. Revealing both preimages is equivocating, so they are never
+/// printed, and are wiped from memory on drop.
+#[derive(Clone, PartialEq, Serialize, Deserialize, Zeroize, ZeroizeOnDrop)]
 pub struct BitCommitmentPreimages(pub [u8; 32], pub [u8; 32]);
 
+impl fmt::Debug for BitCommitmentPreimages {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BitCommitmentPreimages(..)")
+    }
+}
+
 impl BitCommitmentPreimages {
+    /// Draws both preimages from the thread rng. They must carry their full 256 bits of
+    /// entropy: anyone recovering them from the public hashes can forge an equivocation.
     pub fn new() -> Self {
         let mut rng = thread_rng();
-
-        let rng_0: u32 = rng.gen();
-        let preimage_0 = Sha256::digest(rng_0.to_be_bytes());
-
-        let rng_1: u32 = rng.gen();
-        let preimage_1 = Sha256::digest(rng_1.to_be_bytes());
-
-        Self(preimage_0.into(), preimage_1.into())
+        Self(rng.gen::<[u8; 32]>(), rng.gen::<[u8; 32]>())
     }
 
     pub fn compute_bit_commitment_hashes(&self) -> BitCommitmentHashes {
-        let hash_0 = Sha256::digest(&self.0);
-        let hash_1 = Sha256::digest(&self.1);
+        let hash_0 = Sha256::digest(self.0);
+        let hash_1 = Sha256::digest(self.1);
         BitCommitmentHashes(hash_0.into(), hash_1.into())
     }
+
+    pub fn preimage_for_bit(&self, bit: bool) -> &[u8; 32] {
+        if bit {
+            &self.1
+        } else {
+            &self.0
+        }
+    }
 }
 
-#[derive(Debug, PartialEq)]
+impl Default for BitCommitmentPreimages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct BitCommitmentHashes(pub [u8; 32], pub [u8; 32]);
 
+impl BitCommitmentHashes {
+    pub fn hash_for_bit(&self, bit: bool) -> &[u8; 32] {
+        if bit {
+            &self.1
+        } else {
+            &self.0
+        }
+    }
+}
+
+/// Public side of the prover's bit commitments: the pair of hashes committed
+/// for every wire of a circuit. Shared with the verifier during setup.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct CommitmentSet {
+    pub hashes: BTreeMap<WireId, BitCommitmentHashes>,
+}
+
+impl CommitmentSet {
+    pub fn new() -> CommitmentSet {
+        CommitmentSet {
+            hashes: BTreeMap::new(),
+        }
+    }
+
+    pub fn get(&self, wire_id: &WireId) -> Option<&BitCommitmentHashes> {
+        self.hashes.get(wire_id)
+    }
+
+    /// Index every committed hash, so that a revealed preimage can be mapped back
+    /// to the wire and bit value it opens.
+    pub fn build_reverse_index(&self) -> HashMap<[u8; 32], (WireId, bool)> {
+        let mut index = HashMap::new();
+        for (wire_id, hashes) in self.hashes.iter() {
+            index.insert(hashes.0, (*wire_id, false));
+            index.insert(hashes.1, (*wire_id, true));
+        }
+        index
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Circuit {
     pub circuit_id: CircuitId,
@@ -41,6 +102,12 @@ pub struct Circuit {
     pub reverse_lookup: HashMap<WireId, HashSet<GateId>>,
 }
 
+impl Default for Circuit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Circuit {
     pub fn new() -> Circuit {
         Circuit {
@@ -56,41 +123,37 @@ impl Circuit {
             Gate::Nand(input_1, input_2)
             | Gate::And(input_1, input_2)
             | Gate::Xor(input_1, input_2) => {
-                if !self.gates_bit_commitments_preimages.contains_key(&input_1) {
-                    self.gates_bit_commitments_preimages
-                        .insert(input_1, BitCommitmentPreimages::new());
-                }
-                if !self.gates_bit_commitments_preimages.contains_key(&input_2) {
-                    self.gates_bit_commitments_preimages
-                        .insert(input_2, BitCommitmentPreimages::new());
-                }
+                self.gates_bit_commitments_preimages
+                    .entry(input_1)
+                    .or_default();
+                self.gates_bit_commitments_preimages
+                    .entry(input_2)
+                    .or_default();
 
                 self.reverse_lookup
                     .entry(input_1)
-                    .or_insert_with(HashSet::new)
+                    .or_default()
                     .insert(gate_id);
 
                 self.reverse_lookup
                     .entry(input_2)
-                    .or_insert_with(HashSet::new)
+                    .or_default()
                     .insert(gate_id);
             }
             Gate::Inv(input) => {
-                if !self.gates_bit_commitments_preimages.contains_key(&input) {
-                    self.gates_bit_commitments_preimages
-                        .insert(input, BitCommitmentPreimages::new());
-                }
+                self.gates_bit_commitments_preimages
+                    .entry(input)
+                    .or_default();
 
                 self.reverse_lookup
                     .entry(input)
-                    .or_insert_with(HashSet::new)
+                    .or_default()
                     .insert(gate_id);
             }
         }
-        if !self.gates_bit_commitments_preimages.contains_key(&gate_id) {
-            self.gates_bit_commitments_preimages
-                .insert(gate_id, BitCommitmentPreimages::new());
-        }
+        self.gates_bit_commitments_preimages
+            .entry(gate_id)
+            .or_default();
         self.gates.insert(gate_id, gate);
     }
 
@@ -121,7 +184,7 @@ impl Circuit {
     }
 
     pub fn collect_intermediaries_wires_ids(&self) -> Vec<GateId> {
-        let inputs = self.collect_input_wires_ids();        
+        let inputs = self.collect_input_wires_ids();
         let mut hash_set = HashSet::new();
         for (gate_id, _gate) in self.gates.iter() {
             if !inputs.contains(&gate_id) {
@@ -138,7 +201,7 @@ impl Circuit {
         gates_ids: &'a Vec<&'a GateId>,
     ) -> BTreeMap<&'a u64, &'a BitCommitmentPreimages> {
         let mut collected = BTreeMap::new();
-        for gate_id in gates_ids.into_iter() {
+        for gate_id in gates_ids.iter() {
             let Some(preimage) = self.gates_bit_commitments_preimages.get(gate_id) else {
                 continue;
             };
@@ -152,7 +215,7 @@ impl Circuit {
         gates_ids: &'a Vec<&'a GateId>,
     ) -> BTreeMap<&'a u64, &'a BitCommitmentPreimages> {
         let mut collected = BTreeMap::new();
-        for gate_id in gates_ids.into_iter() {
+        for gate_id in gates_ids.iter() {
             let Some(subsequent_gates) = self.reverse_lookup.get(gate_id) else {
                 continue;
             };
@@ -172,11 +235,118 @@ impl Circuit {
         preimages: &'a BTreeMap<&'a u64, &'a BitCommitmentPreimages>,
     ) -> BTreeMap<u64, BitCommitmentHashes> {
         let mut hashes = BTreeMap::new();
-        for (gate_id, preimage) in preimages.into_iter() {
+        for (gate_id, preimage) in preimages.iter() {
             hashes.insert(**gate_id, preimage.compute_bit_commitment_hashes());
         }
         hashes
     }
+
+    /// Computes the public commitment set covering every wire of the circuit.
+    pub fn compute_commitment_set(&self) -> CommitmentSet {
+        let mut commitment_set = CommitmentSet::new();
+        for (wire_id, preimages) in self.gates_bit_commitments_preimages.iter() {
+            commitment_set
+                .hashes
+                .insert(*wire_id, preimages.compute_bit_commitment_hashes());
+        }
+        commitment_set
+    }
+
+    /// Wires produced by a gate and consumed by none.
+    pub fn collect_output_wires_ids(&self) -> Vec<WireId> {
+        let mut gates_ids = self
+            .gates
+            .keys()
+            .filter(|gate_id| !self.reverse_lookup.contains_key(gate_id))
+            .cloned()
+            .collect::<Vec<_>>();
+        gates_ids.sort();
+        gates_ids
+    }
+
+    /// Orders gates so that every gate comes after the gates producing its inputs.
+    /// Ties are broken by gate id, so that both parties derive the same order.
+    pub fn topological_order(&self) -> Result<Vec<GateId>, String> {
+        let mut pending_inputs = HashMap::new();
+        let mut ready = BTreeSet::new();
+        for (gate_id, gate) in self.gates.iter() {
+            let count = gate
+                .inputs()
+                .iter()
+                .filter(|input| self.gates.contains_key(input))
+                .count();
+            if count == 0 {
+                ready.insert(*gate_id);
+            } else {
+                pending_inputs.insert(*gate_id, count);
+            }
+        }
+
+        let mut order = Vec::with_capacity(self.gates.len());
+        while let Some(gate_id) = ready.pop_first() {
+            order.push(gate_id);
+            let Some(subsequent_gates) = self.reverse_lookup.get(&gate_id) else {
+                continue;
+            };
+            for subsequent_gate in subsequent_gates.iter() {
+                let Some(gate) = self.gates.get(subsequent_gate) else {
+                    continue;
+                };
+                // A gate reading the same wire twice is notified once per input.
+                let occurrences = gate.inputs().iter().filter(|i| **i == gate_id).count();
+                let Some(count) = pending_inputs.get_mut(subsequent_gate) else {
+                    continue;
+                };
+                *count -= occurrences;
+                if *count == 0 {
+                    pending_inputs.remove(subsequent_gate);
+                    ready.insert(*subsequent_gate);
+                }
+            }
+        }
+
+        if order.len() != self.gates.len() {
+            return Err("circuit contains a cycle".to_string());
+        }
+        Ok(order)
+    }
+
+    pub fn evaluate(&self, inputs: &BTreeMap<WireId, bool>) -> Result<ExecutionTrace, String> {
+        self.evaluate_with(inputs, |_, value| value)
+    }
+
+    /// Evaluates the circuit in topological order, letting `tamper` replace the output of
+    /// each gate before it is propagated. Used to model a dishonest prover.
+    pub fn evaluate_with<F>(
+        &self,
+        inputs: &BTreeMap<WireId, bool>,
+        tamper: F,
+    ) -> Result<ExecutionTrace, String>
+    where
+        F: Fn(GateId, bool) -> bool,
+    {
+        let mut values = BTreeMap::new();
+        for wire_id in self.collect_input_wires_ids() {
+            let Some(value) = inputs.get(wire_id) else {
+                return Err(format!("missing value for input wire {}", wire_id));
+            };
+            values.insert(*wire_id, *value);
+        }
+        let inputs = values.clone();
+
+        let order = self.topological_order()?;
+        for gate_id in order.iter() {
+            let gate = &self.gates[gate_id];
+            let value = gate.evaluate(&values)?;
+            values.insert(*gate_id, tamper(*gate_id, value));
+        }
+
+        Ok(ExecutionTrace {
+            inputs,
+            order,
+            values,
+        })
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -187,6 +357,58 @@ pub enum Gate {
     Xor(WireId, WireId),
 }
 
+impl Gate {
+    pub fn inputs(&self) -> Vec<WireId> {
+        match self {
+            Gate::Nand(input_1, input_2)
+            | Gate::And(input_1, input_2)
+            | Gate::Xor(input_1, input_2) => vec![*input_1, *input_2],
+            Gate::Inv(input) => vec![*input],
+        }
+    }
+
+    pub fn evaluate(&self, values: &BTreeMap<WireId, bool>) -> Result<bool, String> {
+        let value = |wire_id: &WireId| {
+            values
+                .get(wire_id)
+                .cloned()
+                .ok_or(format!("wire {} evaluated before its inputs", wire_id))
+        };
+        let result = match self {
+            Gate::Nand(input_1, input_2) => !(value(input_1)? && value(input_2)?),
+            Gate::And(input_1, input_2) => value(input_1)? && value(input_2)?,
+            Gate::Xor(input_1, input_2) => value(input_1)? ^ value(input_2)?,
+            Gate::Inv(input) => !value(input)?,
+        };
+        Ok(result)
+    }
+}
+
+/// Result of evaluating a circuit: the value of every wire, and the order in which gates ran.
+///
+/// The trace is bisected as a sequence of states: state `i` holds the values computed before
+/// the `i`-th gate that it, or a later gate, still reads. State 0 holds the inputs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionTrace {
+    pub inputs: BTreeMap<WireId, bool>,
+    pub order: Vec<GateId>,
+    pub values: BTreeMap<WireId, bool>,
+}
+
+impl ExecutionTrace {
+    pub fn len(&self) -> usize {
+        self.order.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    pub fn value(&self, wire_id: &WireId) -> Option<bool> {
+        self.values.get(wire_id).cloned()
+    }
+}
+
 impl fmt::Display for Circuit {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mut stats_nand = 0;
@@ -208,3 +430,16 @@ impl fmt::Display for Circuit {
         )
     }
 }
+
+#[test]
+fn test_preimages_are_not_derived_from_a_small_seed() {
+    // Preimages derived from 32-bit seeds collide among this many draws with near certainty.
+    let draws = 200_000;
+    let mut preimages = HashSet::new();
+    for _ in 0..draws {
+        let bit_commitment = BitCommitmentPreimages::new();
+        preimages.insert(bit_commitment.0);
+        preimages.insert(bit_commitment.1);
+    }
+    assert_eq!(preimages.len(), 2 * draws);
+}